@@ -1,22 +1,35 @@
 //! A generic bot framework designed for allowing bot components to be cleanly and modularly
 //! combined.
 
+#[doc(inline)] pub use sylphie_core::assets;
+#[doc(inline)] pub use sylphie_core::cli;
+#[doc(inline)] pub use sylphie_core::config;
 #[doc(inline)] pub use sylphie_core::core;
 #[doc(inline)] pub use sylphie_core::errors;
+#[doc(inline)] pub use sylphie_core::health;
 #[doc(inline)] pub use sylphie_core::interface;
+#[doc(inline)] pub use sylphie_core::metrics;
 #[doc(inline)] pub use sylphie_core::timer;
+#[doc(inline)] pub use sylphie_core::tokens;
 #[doc(inline)] pub use sylphie_core::module;
+#[doc(inline)] pub use sylphie_core::services;
+#[doc(inline)] pub use sylphie_core::stats;
+#[doc(inline)] pub use sylphie_core::tasks;
+#[doc(inline)] pub use sylphie_core::testing;
+#[doc(inline)] pub use sylphie_core::watchdog;
 
 /// A module containing the command system.
 pub mod commands {
-    #[doc(inline)] pub use sylphie_commands::{commands, ctx, manager};
+    #[doc(inline)] pub use sylphie_commands::{commands, connector, ctx, manager, progress};
 }
 
 /// A module containing types used for storing data persistantly.
 pub mod database {
     #[doc(inline)] pub use sylphie_database::{
-        connection, config, kvs, migrations, serializable, singleton,
+        backup, blobs, connection, config, event_log, flags, kvs, migrations, search,
+        serializable, singleton, timeseries, transient,
     };
+    #[cfg(feature = "sqlcipher")] #[doc(inline)] pub use sylphie_database::encryption;
 }
 
 /// A module containing types used for managing connections.
@@ -73,6 +86,7 @@ pub mod __macro_export {
     pub use sylphie_commands;
     pub use sylphie_core;
     pub use sylphie_database;
+    pub use sylphie_utils;
 
     #[derive(sylphie_derive::CoreModule)]
     #[module(integral)]
@@ -103,7 +117,7 @@ pub mod derives {
     #[doc(inline)] pub use sylphie_derive::{
         SylphieModule as Module,
         module_impl_sylphie as module_impl,
-        command, config,
+        command, config, module_hook,
     };
     #[doc(inline)] pub use static_events::handlers::event_handler;
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+//! Tests feature flags on a bot that stores its data in memory.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use sylphie::cli::CliArgs;
+use sylphie::core::{ReadyEvent, Subsystem};
+use sylphie::database::config::GLOBAL_SCOPE;
+use sylphie::database::connection::SylphieCoreDatabaseExt;
+use sylphie::database::flags::{FeatureFlags, FlagChangedEvent};
+use sylphie::prelude::*;
+
+static PASSED: AtomicBool = AtomicBool::new(false);
+static FLAG_CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+async fn check_flags(target: &Handler<impl Events>) -> Result<()> {
+    let flags = target.get_service::<FeatureFlags>();
+    let server = Scope::new("server", ScopeArgs::Long(1));
+    let scopes = [Scope::new("channel", ScopeArgs::Long(2)), server.clone()];
+
+    ensure!(!flags.is_enabled("test_flag").await?, "Flags are disabled by default.");
+    flags.set(target, "test_flag", GLOBAL_SCOPE, Some(true)).await?;
+    ensure!(flags.is_enabled("test_flag").await?, "The flag was enabled globally.");
+    ensure!(flags.is_enabled_in("test_flag", &scopes).await?, "The global setting is used.");
+
+    flags.set(target, "test_flag", server.clone(), Some(false)).await?;
+    ensure!(!flags.is_enabled_in("test_flag", &scopes).await?, "The server setting is used.");
+    ensure!(flags.is_enabled("test_flag").await?, "The global setting is unchanged.");
+    let list = flags.list().await?;
+    ensure!(list.get("test_flag").map(Vec::len) == Some(2), "The flag is set in two scopes.");
+
+    flags.set(target, "test_flag", server, None).await?;
+    ensure!(flags.is_enabled_in("test_flag", &scopes).await?, "The server setting was reset.");
+    ensure!(
+        flags.set(target, "test flag", GLOBAL_SCOPE, Some(true)).await.is_err(),
+        "Flag names cannot contain spaces.",
+    );
+    Ok(())
+}
+
+#[derive(Module)]
+pub struct FlagsTestModule {
+    #[module_info] info: ModuleInfo,
+}
+#[module_impl]
+impl FlagsTestModule {
+    #[event_handler]
+    async fn flag_changed(&self, _: &FlagChangedEvent) {
+        FLAG_CHANGES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[event_handler]
+    async fn ready(&self, target: &Handler<impl Events>, _: &ReadyEvent) {
+        match check_flags(target).await {
+            Ok(()) => PASSED.store(true, Ordering::SeqCst),
+            Err(e) => e.report_error(),
+        }
+        target.shutdown_bot();
+    }
+}
+
+sylphie_root_module! {
+    module FlagsTestBot {
+        test: FlagsTestModule,
+    }
+}
+
+#[test]
+fn feature_flags() {
+    let root = std::env::temp_dir().join(format!("sylphie_flags_test_{}", std::process::id()));
+    let result = SylphieCore::<FlagsTestBot>::with_args("flags_test", CliArgs::default())
+        .with_root_path(&root)
+        .without(Subsystem::Terminal)
+        .without(Subsystem::Signals)
+        .without(Subsystem::Metrics)
+        .without(Subsystem::HealthServer)
+        .without(Subsystem::Watchdog)
+        .without(Subsystem::ConfigWatcher)
+        .with_in_memory_database()
+        .start();
+    let _ = std::fs::remove_dir_all(&root);
+    result.unwrap();
+    assert!(PASSED.load(Ordering::SeqCst), "Feature flags did not behave as expected.");
+    assert_eq!(FLAG_CHANGES.load(Ordering::SeqCst), 3);
+}
@@ -19,11 +19,15 @@ use sylphie_utils::disambiguate::EntryName;
 pub struct CommandInfo {
     /// The name of the command.
     pub name: Cow<'static, str>,
+    /// The values offered by tab completion in the terminal for each argument of the command,
+    /// starting with the first argument.
+    pub completions: Option<&'static [&'static [&'static str]]>,
 }
 impl CommandInfo {
     pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
         CommandInfo {
             name: name.into(),
+            completions: None,
         }
     }
 }
@@ -43,6 +47,52 @@ pub trait CommandImpl: Send + Sync + 'static {
     ) -> BoxFuture<'a, Result<()>>;
 }
 
+/// Wraps a command so that it can only be run from the terminal.
+///
+/// This is meant for commands that can damage the bot or its data, such as restoring a backup.
+pub struct TerminalOnly<C>(pub C);
+impl <C: CommandImpl> CommandImpl for TerminalOnly<C> {
+    fn can_access<'a>(
+        &'a self, cmd: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<bool>> {
+        if ctx.is_terminal() {
+            self.0.can_access(cmd, ctx)
+        } else {
+            async { Ok(false) }.boxed()
+        }
+    }
+
+    fn execute<'a>(
+        &'a self, cmd: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.0.execute(cmd, ctx)
+    }
+}
+
+/// Wraps a command so that it can only be run by the bot's owners, or from the terminal.
+///
+/// This is meant for commands that change how the bot behaves, such as configuration commands.
+/// These stay limited to owners even on connectors where
+/// [`Connector::allows_everyone`](`crate::connector::Connector::allows_everyone`) is set.
+pub struct OwnerOnly<C>(pub C);
+impl <C: CommandImpl> CommandImpl for OwnerOnly<C> {
+    fn can_access<'a>(
+        &'a self, cmd: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<bool>> {
+        if ctx.is_owner() {
+            self.0.can_access(cmd, ctx)
+        } else {
+            async { Ok(false) }.boxed()
+        }
+    }
+
+    fn execute<'a>(
+        &'a self, cmd: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.0.execute(cmd, ctx)
+    }
+}
+
 /// A fully resolved command.
 #[derive(Clone)]
 pub struct Command(Arc<CommandData>);
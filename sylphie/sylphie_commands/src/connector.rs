@@ -0,0 +1,428 @@
+//! A common interface for the platforms the bot talks to, such as Discord or the terminal.
+//!
+//! Each platform is implemented as a [`Connector`]. Connectors report the messages they receive
+//! with [`receive_message`], which dispatches [`MessageReceivedEvent`] and then runs the command
+//! the connector found in the message, if any. Responses go back through the connector, so
+//! commands and modules do not need to know which platform they are running on.
+//!
+//! Only the users listed in `owners` in the configuration file can run commands through a
+//! connector, unless [`Connector::allows_everyone`] returns `true`. Owners are named by
+//! [`MessageAuthor::id`], such as `discord:123456789`. Commands wrapped in
+//! [`OwnerOnly`](`crate::commands::OwnerOnly`) can only be run by owners on every connector.
+
+use async_trait::*;
+use crate::ctx::{CommandCtx, CommandCtxImpl};
+use crate::manager::CommandManager;
+use crate::progress::*;
+use enumset::*;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Duration;
+use sylphie_core::config::Config;
+use sylphie_core::prelude::*;
+use sylphie_utils::scopes::{Scope, ScopeArgs, ScopedEvent, USER_SCOPE_TYPE};
+
+/// A feature that a connector's platform supports.
+#[derive(EnumSetType, Debug)]
+#[non_exhaustive]
+pub enum ConnectorCapability {
+    /// Messages the bot sent can be edited. Progress reports are shown by editing one message.
+    EditMessages,
+    /// Messages can be sent as replies to another message. Responses to commands reply to the
+    /// message the command was sent in.
+    Replies,
+    /// A status line can be shown. Progress reports are shown in the status line.
+    StatusLine,
+}
+
+/// A platform the bot receives messages from and sends messages to.
+///
+/// Channels and messages are identified by strings in a format specific to the connector, such
+/// as the numeric IDs used by Discord.
+#[async_trait]
+pub trait Connector: Send + Sync + 'static {
+    /// Returns the name of the platform, such as `discord`.
+    fn platform(&self) -> &str;
+
+    /// Returns the features the platform supports.
+    fn capabilities(&self) -> EnumSet<ConnectorCapability> {
+        EnumSet::empty()
+    }
+
+    /// Returns the longest message the platform accepts, in characters, or `None` if there is no
+    /// limit. Longer responses are split into several messages.
+    fn message_limit(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the minimum time between updates to a progress report.
+    fn progress_interval(&self) -> Duration {
+        DEFAULT_PROGRESS_INTERVAL
+    }
+
+    /// Returns whether users other than the bot's owners can run commands.
+    ///
+    /// This does not allow them to run commands wrapped in
+    /// [`OwnerOnly`](`crate::commands::OwnerOnly`).
+    fn allows_everyone(&self) -> bool {
+        false
+    }
+
+    /// Connects to the platform. Connectors should reconnect on their own if the connection is
+    /// lost afterwards.
+    ///
+    /// For connectors belonging to a connection, this is called by the connection manager once
+    /// the connection is created.
+    async fn connect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Disconnects from the platform.
+    ///
+    /// For connectors belonging to a connection, this is called by the connection manager when
+    /// the connection is destroyed.
+    async fn disconnect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the scopes of a channel, from most to least specific.
+    fn channel_scopes(&self, channel: &str) -> Vec<Scope>;
+
+    /// Sends a message to a channel, and returns its ID if the platform has message IDs.
+    async fn send_message(&self, channel: &str, text: &str) -> Result<Option<String>>;
+
+    /// Sends a message as a reply to another message.
+    ///
+    /// By default, this sends the message normally.
+    async fn reply(&self, channel: &str, _message: &str, text: &str) -> Result<Option<String>> {
+        self.send_message(channel, text).await
+    }
+
+    /// Replaces the text of a message the bot sent. This is only called if the connector has
+    /// [`ConnectorCapability::EditMessages`].
+    async fn edit_message(&self, _channel: &str, _message: &str, _text: &str) -> Result<()> {
+        cmd_error!("This connector cannot edit messages.")
+    }
+
+    /// Shows a status line, or hides it if `status` is `None`. This is only called if the
+    /// connector has [`ConnectorCapability::StatusLine`].
+    async fn set_status(&self, _channel: &str, _status: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The user who sent a message.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct MessageAuthor {
+    /// An ID for the user that is unique across platforms, such as `discord:123456789`.
+    pub id: String,
+    /// The name of the user, as shown in logs.
+    pub name: String,
+}
+impl MessageAuthor {
+    /// Creates a new message author.
+    pub fn new(id: impl Into<String>, name: impl Into<String>) -> Self {
+        MessageAuthor { id: id.into(), name: name.into() }
+    }
+
+    /// Returns the scope of this user.
+    pub fn scope(&self) -> Scope {
+        Scope::new(USER_SCOPE_TYPE, ScopeArgs::String(self.id.clone().into()))
+    }
+}
+
+/// A message received by a connector.
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct IncomingMessage {
+    /// The connector the message was received by.
+    pub connector: Arc<dyn Connector>,
+    /// The channel the message was sent in.
+    pub channel: String,
+    /// The ID of the message, if the platform has message IDs.
+    pub id: Option<String>,
+    /// The user who sent the message.
+    pub author: MessageAuthor,
+    /// The text of the message.
+    pub text: String,
+    /// The command in the message, such as the text after the bot's prefix, or `None` if the
+    /// message is not a command.
+    pub command: Option<String>,
+    /// Whether the message was sent directly to the bot, rather than in a shared channel.
+    pub is_direct: bool,
+    /// The scopes the message was sent in, from most to least specific, starting with the scope
+    /// of its author.
+    pub scopes: Vec<Scope>,
+    platform_data: Option<Arc<dyn Any + Send + Sync>>,
+}
+impl IncomingMessage {
+    /// Creates a new message that is not a command, in the scope of its author followed by the
+    /// scopes of its channel.
+    pub fn new(
+        connector: Arc<dyn Connector>, channel: impl Into<String>, author: MessageAuthor,
+        text: impl Into<String>,
+    ) -> Self {
+        let channel = channel.into();
+        let mut scopes = vec![author.scope()];
+        scopes.extend(connector.channel_scopes(&channel));
+        IncomingMessage {
+            connector,
+            channel,
+            id: None,
+            author,
+            text: text.into(),
+            command: None,
+            is_direct: false,
+            scopes,
+            platform_data: None,
+        }
+    }
+
+    /// Sets the ID of the message.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the command in the message.
+    pub fn with_command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    /// Marks the message as sent directly to the bot.
+    pub fn with_direct(mut self, is_direct: bool) -> Self {
+        self.is_direct = is_direct;
+        self
+    }
+
+    /// Overrides the scopes of the message, given from most to least specific.
+    pub fn with_scopes(mut self, scopes: Vec<Scope>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// Attaches information specific to the platform, such as the original message object.
+    pub fn with_platform_data(mut self, data: impl Any + Send + Sync) -> Self {
+        self.platform_data = Some(Arc::new(data));
+        self
+    }
+
+    /// Returns the information specific to the platform attached to this message, if it has
+    /// the given type.
+    pub fn platform_data<T: Any>(&self) -> Option<&T> {
+        self.platform_data.as_ref()?.downcast_ref()
+    }
+}
+
+/// Dispatched for every message a connector receives, before any command in it is run.
+pub struct MessageReceivedEvent {
+    /// The message.
+    pub message: IncomingMessage,
+}
+simple_event!(MessageReceivedEvent);
+impl ScopedEvent for MessageReceivedEvent {
+    fn scopes(&self) -> &[Scope] {
+        &self.message.scopes
+    }
+}
+
+/// Dispatches [`MessageReceivedEvent`] for a message, then runs the command in it, if any.
+pub async fn receive_message(
+    target: &Handler<impl Events>, message: IncomingMessage,
+) -> Result<()> {
+    target.dispatch_async(MessageReceivedEvent { message: message.clone() }).await;
+
+    let command = match &message.command {
+        Some(command) if !command.trim().is_empty() => command.clone(),
+        _ => return Ok(()),
+    };
+    if !message.connector.allows_everyone() {
+        let owners = &target.get_service::<Config>().core().owners;
+        if !owners.contains(&message.author.id) {
+            return Ok(())
+        }
+    }
+
+    let ctx = CommandCtx::new(target, ConnectorContext {
+        message,
+        command,
+        progress_messages: Mutex::new(FxHashMap::default()),
+    });
+    target.get_service::<CommandManager>().execute(&ctx).await
+}
+
+/// Splits a message into parts of at most `limit` characters, at line breaks where possible.
+///
+/// # Panics
+///
+/// Panics if `limit` is `0`.
+pub fn split_message(text: &str, limit: usize) -> Vec<&str> {
+    assert!(limit > 0, "Messages cannot be split into parts of 0 characters.");
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some((hard_split, _)) = rest.char_indices().nth(limit) {
+        let split = match rest[..hard_split].rfind('\n') {
+            Some(i) if i > 0 => i,
+            _ => hard_split,
+        };
+        parts.push(&rest[..split]);
+        rest = rest[split..].trim_start_matches('\n');
+    }
+    parts.push(rest);
+    parts.retain(|x| !x.trim().is_empty());
+    parts
+}
+
+/// Returns the text of a message after the command prefix or a mention of the bot, or `None` if
+/// the message is not a command.
+///
+/// `mentions` are matched exactly at the start of the message, such as `<@123456789>` on
+/// Discord. `names` are names the bot is addressed by with a `:` or `,` after them, as in
+/// `bot: help`, and are matched case-insensitively. An empty prefix is never matched, and
+/// messages sent directly to the bot are commands even without a prefix.
+pub fn strip_command_prefix<'a>(
+    text: &'a str, prefix: &str, mentions: &[String], names: &[String], is_direct: bool,
+) -> Option<&'a str> {
+    let text = text.trim_start();
+    for mention in mentions {
+        if let Some(rest) = text.strip_prefix(mention.as_str()) {
+            return Some(rest.trim_start())
+        }
+    }
+    for name in names {
+        if name.is_empty() || text.len() <= name.len() || !text.is_char_boundary(name.len()) {
+            continue
+        }
+        let (start, rest) = text.split_at(name.len());
+        let is_addressed = rest.starts_with(':') || rest.starts_with(',');
+        if start.eq_ignore_ascii_case(name) && is_addressed {
+            return Some(rest[1..].trim_start())
+        }
+    }
+    match text.strip_prefix(prefix) {
+        Some(rest) if !prefix.is_empty() => Some(rest),
+        _ if is_direct => Some(text),
+        _ => None,
+    }
+}
+
+/// The context of a command received by a connector.
+pub(crate) struct ConnectorContext {
+    pub message: IncomingMessage,
+    command: String,
+    progress_messages: Mutex<FxHashMap<u64, String>>,
+}
+impl ConnectorContext {
+    async fn send(&self, text: &str) -> Result<Option<String>> {
+        let connector = &*self.message.connector;
+        let parts = match connector.message_limit() {
+            Some(limit) => split_message(text, limit),
+            None => vec![text],
+        };
+        let reply_to = match &self.message.id {
+            Some(id) if connector.capabilities().contains(ConnectorCapability::Replies) =>
+                Some(id.as_str()),
+            _ => None,
+        };
+        let mut last = None;
+        for (i, part) in parts.into_iter().enumerate() {
+            last = match reply_to {
+                Some(id) if i == 0 => connector.reply(&self.message.channel, id, part).await?,
+                _ => connector.send_message(&self.message.channel, part).await?,
+            };
+        }
+        Ok(last)
+    }
+}
+#[async_trait]
+impl CommandCtxImpl for ConnectorContext {
+    fn scopes(&self) -> &[Scope] {
+        &self.message.scopes
+    }
+
+    fn raw_message(&self) -> &str {
+        &self.command
+    }
+
+    fn user_name(&self) -> Option<&str> {
+        Some(&self.message.author.name)
+    }
+
+    async fn respond<E: Events>(&self, _: &Handler<E>, msg: &str) -> Result<()> {
+        self.send(msg).await?;
+        Ok(())
+    }
+
+    fn progress_interval(&self) -> Duration {
+        self.message.connector.progress_interval()
+    }
+
+    async fn update_progress<E: Events>(
+        &self, _: &Handler<E>, update: &ProgressUpdate<'_>,
+    ) -> Result<()> {
+        let connector = &*self.message.connector;
+        let channel = &self.message.channel;
+        let capabilities = connector.capabilities();
+        let text = update.state.to_string();
+        if capabilities.contains(ConnectorCapability::StatusLine) {
+            if update.state.is_finished {
+                connector.set_status(channel, None).await?;
+                self.send(&text).await?;
+            } else {
+                connector.set_status(channel, Some(&text)).await?;
+            }
+        } else if capabilities.contains(ConnectorCapability::EditMessages) {
+            let existing = self.progress_messages.lock().get(&update.id).cloned();
+            match existing {
+                Some(id) => connector.edit_message(channel, &id, &text).await?,
+                None => if let Some(id) = self.send(&text).await? {
+                    self.progress_messages.lock().insert(update.id, id);
+                },
+            }
+            if update.state.is_finished {
+                self.progress_messages.lock().remove(&update.id);
+            }
+        } else if update.is_first || update.state.is_finished {
+            self.send(&text).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_messages() {
+        assert_eq!(split_message("abc", 5), vec!["abc"]);
+        assert_eq!(split_message("", 5), Vec::<&str>::new());
+        assert_eq!(split_message("abc\ndef\nghi", 8), vec!["abc\ndef", "ghi"]);
+        assert_eq!(split_message("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(split_message("ab\n\ncd", 3), vec!["ab", "cd"]);
+        assert_eq!(split_message("ééééé", 2), vec!["éé", "éé", "é"]);
+    }
+
+    #[test]
+    fn command_prefixes() {
+        let mentions = ["<@42>".to_string()];
+        let names = ["bot".to_string(), String::new()];
+        let strip = |text, prefix, is_direct| {
+            strip_command_prefix(text, prefix, &mentions, &names, is_direct)
+        };
+        assert_eq!(strip("!help", "!", false), Some("help"));
+        assert_eq!(strip("hello", "!", false), None);
+        assert_eq!(strip("hello", "!", true), Some("hello"));
+        assert_eq!(strip("<@42> help", "!", false), Some("help"));
+        assert_eq!(strip("<@43> help", "!", false), None);
+        assert_eq!(strip("Bot: help", "!", false), Some("help"));
+        assert_eq!(strip("bot, help", "!", false), Some("help"));
+        assert_eq!(strip("bother", "!", false), None);
+        assert_eq!(strip("help", "", false), None);
+    }
+}
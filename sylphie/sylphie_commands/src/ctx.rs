@@ -1,8 +1,12 @@
 use async_trait::*;
+use crate::connector::{ConnectorContext, IncomingMessage};
+use crate::progress::*;
 use crate::raw_args::*;
 use static_events::prelude_async::*;
 use std::any::Any;
 use std::sync::Arc;
+use std::time::Duration;
+use sylphie_core::config::Config;
 use sylphie_core::prelude::*;
 use sylphie_utils::scopes::*;
 
@@ -22,8 +26,53 @@ pub trait CommandCtxImpl: Sync + Send + 'static {
     /// This should return the same value for every call.
     fn raw_message(&self) -> &str;
 
+    /// Returns whether this command was run by one of the bot's owners.
+    ///
+    /// Commands run from the terminal are always treated as run by an owner. Commands received
+    /// by a connector are run by an owner if the author is listed in `owners` in the
+    /// configuration file.
+    pub fn is_owner(&self) -> bool {
+        if self.is_terminal() {
+            return true
+        }
+        match self.message() {
+            Some(message) => {
+                let owners = &self.handler().get_service::<Config>().core().owners;
+                owners.contains(&message.author.id)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns a name for the user who sent this command, if there is one.
+    ///
+    /// This is used to identify the user in logs.
+    fn user_name(&self) -> Option<&str> {
+        None
+    }
+
     /// Responds to the user with a given string.
     async fn respond<E: Events>(&self, target: &Handler<E>, msg: &str) -> Result<()>;
+
+    /// Returns the minimum time between updates to a progress report. This defaults to two
+    /// seconds.
+    fn progress_interval(&self) -> Duration {
+        DEFAULT_PROGRESS_INTERVAL
+    }
+
+    /// Shows an update to a progress report created with [`CommandCtx::progress`].
+    ///
+    /// By default, only the first and final updates are sent, as responses. Contexts that can
+    /// edit messages should instead send a single message, and edit it for each update.
+    async fn update_progress<E: Events>(
+        &self, target: &Handler<E>, update: &ProgressUpdate<'_>,
+    ) -> Result<()> {
+        if update.is_first || update.state.is_finished {
+            self.respond(target, &update.state.to_string()).await
+        } else {
+            Ok(())
+        }
+    }
 }
 
 /// An argument to a command.
@@ -68,6 +117,12 @@ impl <E: Events> CommandCtx<E> {
         self.0.ctx_impl.as_any().downcast_ref::<T>()
     }
 
+    /// Returns the message the command was sent in, if it was received by a
+    /// [`Connector`](`crate::connector::Connector`).
+    pub fn message(&self) -> Option<&IncomingMessage> {
+        self.downcast_ref::<ConnectorContext>().map(|x| &x.message)
+    }
+
     /// Returns the raw text of the command.
     pub fn raw_message(&self) -> &str {
         self.0.ctx_impl.raw_message()
@@ -103,10 +158,55 @@ impl <E: Events> CommandCtx<E> {
         self.0.ctx_impl.scopes()
     }
 
+    /// Returns whether this command was run from the terminal.
+    pub fn is_terminal(&self) -> bool {
+        self.scopes().iter().any(|x| &*x.scope_type == "terminal")
+    }
+
+    /// Returns whether this command was run by one of the bot's owners.
+    ///
+    /// Commands run from the terminal are always treated as run by an owner. Commands received
+    /// by a connector are run by an owner if the author is listed in `owners` in the
+    /// configuration file.
+    pub fn is_owner(&self) -> bool {
+        if self.is_terminal() {
+            return true
+        }
+        match self.message() {
+            Some(message) => {
+                let owners = &self.handler().get_service::<Config>().core().owners;
+                owners.contains(&message.author.id)
+            }
+            None => false,
+        }
+    }
+
+    /// Returns a name for the user who sent this command, if there is one.
+    pub fn user_name(&self) -> Option<&str> {
+        self.0.ctx_impl.user_name()
+    }
+
     /// Responds to the user with a given string.
     pub async fn respond(&self, msg: &str) -> Result<()> {
         self.0.ctx_impl.respond(&self.0.handle, msg).await
     }
+
+    /// Starts reporting the progress of a long running task to the user.
+    ///
+    /// `total` is the amount of work to be done, or 0 if it is not known. How the progress is
+    /// shown depends on the context: the terminal shows it in a status line, while connectors
+    /// that support editing messages edit a single message as the work progresses.
+    pub async fn progress(&self, message: &str, total: u64) -> Result<Progress<E>> {
+        Progress::new(self, message, total).await
+    }
+
+    pub(crate) fn progress_interval(&self) -> Duration {
+        self.0.ctx_impl.progress_interval()
+    }
+
+    pub(crate) async fn update_progress(&self, update: &ProgressUpdate<'_>) -> Result<()> {
+        self.0.ctx_impl.update_progress(&self.0.handle, update).await
+    }
 }
 impl <E: Events> Clone for CommandCtx<E> {
     fn clone(&self) -> Self {
@@ -121,7 +221,12 @@ trait CommandCtxImplWrapper<E: Events>: Sync + Send + 'static {
     fn raw_message(&self) -> &str;
 
     fn scopes(&self) -> &[Scope];
+    fn user_name(&self) -> Option<&str>;
     async fn respond(&self, target: &Handler<E>, msg: &str) -> Result<()>;
+    fn progress_interval(&self) -> Duration;
+    async fn update_progress(
+        &self, target: &Handler<E>, update: &ProgressUpdate<'_>,
+    ) -> Result<()>;
 }
 #[async_trait]
 impl <E: Events, T: CommandCtxImpl> CommandCtxImplWrapper<E> for T {
@@ -129,7 +234,14 @@ impl <E: Events, T: CommandCtxImpl> CommandCtxImplWrapper<E> for T {
     fn raw_message(&self) -> &str { self.raw_message() }
 
     fn scopes(&self) -> &[Scope] { self.scopes() }
+    fn user_name(&self) -> Option<&str> { self.user_name() }
     async fn respond(&self, target: &Handler<E>, msg: &str) -> Result<()> {
         self.respond(target, msg).await
     }
+    fn progress_interval(&self) -> Duration { self.progress_interval() }
+    async fn update_progress(
+        &self, target: &Handler<E>, update: &ProgressUpdate<'_>,
+    ) -> Result<()> {
+        self.update_progress(target, update).await
+    }
 }
\ No newline at end of file
@@ -2,9 +2,11 @@
 
 pub mod args;
 pub mod commands;
+pub mod connector;
 pub mod ctx;
 pub mod manager;
 mod module;
+pub mod progress;
 mod raw_args;
 
 pub use module::CommandsModule;
@@ -13,6 +15,7 @@ pub use module::CommandsModule;
 pub mod prelude {
     pub use crate::commands::{Command, CommandInfo};
     pub use crate::ctx::{CommandCtx, CommandArg};
+    pub use crate::progress::Progress;
 }
 
 /// Reexports of various types for macros. Not public API.
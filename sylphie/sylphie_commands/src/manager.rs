@@ -1,10 +1,16 @@
 use arc_swap::ArcSwapOption;
 use crate::commands::Command;
 use crate::ctx::CommandCtx;
+use fxhash::FxHashMap;
+use parking_lot::Mutex;
 use static_events::prelude_async::*;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use sylphie_core::errors::*;
+use sylphie_core::stats::CoreStats;
 use sylphie_utils::disambiguate::{DisambiguatedSet, Disambiguated, LookupResult};
+use sylphie_utils::scopes::USER_SCOPE_TYPE;
+use tracing_futures::Instrument;
 
 /// The event used to register commands.
 #[derive(Debug, Default)]
@@ -22,6 +28,27 @@ impl RegisterCommandsEvent {
 /// The result of a command lookup.
 pub type CommandLookupResult = LookupResult<Command>;
 
+/// Statistics about how a command has been used since the bot was started.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CommandStats {
+    /// How many times the command was executed.
+    pub executions: u64,
+    /// How many executions failed with an error other than a command error, or panicked.
+    pub errors: u64,
+    /// The total time spent executing the command.
+    pub total_time: Duration,
+}
+impl CommandStats {
+    /// Returns the average time spent executing the command.
+    pub fn average_time(&self) -> Duration {
+        if self.executions == 0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_nanos((self.total_time.as_nanos() / self.executions as u128) as u64)
+        }
+    }
+}
+
 /// The service used to lookup commands.
 #[derive(Clone, Debug)]
 pub struct CommandManager(Arc<CommandManagerData>);
@@ -29,12 +56,14 @@ pub struct CommandManager(Arc<CommandManagerData>);
 struct CommandManagerData {
     null: DisambiguatedSet<Command>,
     data: ArcSwapOption<DisambiguatedSet<Command>>,
+    stats: Mutex<FxHashMap<Arc<str>, CommandStats>>,
 }
 impl CommandManager {
     pub(crate) fn new() -> Self {
         CommandManager(Arc::new(CommandManagerData {
             null: DisambiguatedSet::new("command", Vec::new()),
             data: ArcSwapOption::new(None),
+            stats: Mutex::new(FxHashMap::default()),
         }))
     }
 
@@ -84,6 +113,24 @@ impl CommandManager {
         Ok(CommandLookupResult::new(valid_commands))
     }
 
+    /// Returns the statistics of every command that has been executed, sorted by name.
+    pub fn command_stats(&self) -> Vec<(Arc<str>, CommandStats)> {
+        let mut stats: Vec<_> =
+            self.0.stats.lock().iter().map(|(name, stats)| (name.clone(), *stats)).collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    fn record_execution(&self, cmd: &Command, time: Duration, is_error: bool) {
+        let mut stats = self.0.stats.lock();
+        let stats = stats.entry(cmd.full_name().into()).or_default();
+        stats.executions += 1;
+        stats.total_time += time;
+        if is_error {
+            stats.errors += 1;
+        }
+    }
+
     /// Executes a command immediately.
     pub async fn execute(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
         if ctx.args_count() == 0 {
@@ -93,7 +140,23 @@ impl CommandManager {
             match command {
                 CommandLookupResult::NoneFound => ctx.respond("No such command found.").await?,
                 CommandLookupResult::Found(cmd) => {
-                    match Error::catch_panic_async(cmd.execute(ctx)).await {
+                    let scope = ctx.scopes().iter()
+                        .find(|x| &*x.scope_type != USER_SCOPE_TYPE)
+                        .map_or("", |x| &*x.scope_type);
+                    let span = info_span!(
+                        "command",
+                        name = %cmd.full_name(),
+                        scope,
+                        user = ctx.user_name().unwrap_or("-"),
+                    );
+                    ctx.handler().get_service::<CoreStats>().record_command();
+                    let start_time = Instant::now();
+                    let result = Error::catch_panic_async(cmd.execute(ctx)).instrument(span).await;
+                    self.record_execution(&cmd, start_time.elapsed(), match &result {
+                        Err(e) => !matches!(e.error_kind(), ErrorKind::CommandError(_)),
+                        Ok(()) => false,
+                    });
+                    match result {
                         Ok(()) => { }
                         Err(e) => {
                             // split to avoid saving a `&ErrorKind` which is !Send
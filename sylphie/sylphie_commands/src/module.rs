@@ -1,14 +1,20 @@
 use async_trait::*;
 use crate::commands::*;
-use crate::ctx::*;
+use crate::connector::*;
 use crate::manager::*;
-use std::time::Instant;
-use sylphie_core::core::{SylphieEvents, InitEvent};
+use enumset::EnumSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sylphie_core::core::{SylphieEvents, InitEvent, ReloadEvent};
 use sylphie_core::derives::*;
-use sylphie_core::interface::{TerminalCommandEvent, SetupLoggerEvent};
+use sylphie_core::interface::{
+    Interface, SetupLoggerEvent, TerminalCommandEvent, TerminalCompleteEvent,
+};
+use sylphie_core::metrics::CollectMetricsEvent;
 use sylphie_core::prelude::*;
+use sylphie_utils::disambiguate::LookupResult;
 use sylphie_utils::scopes::*;
-use sylphie_utils::strings::StringWrapper;
+use tracing::level_filters::LevelFilter;
 
 /// The module containing the implementation of Sylphie commands.
 #[derive(Module)]
@@ -29,15 +35,25 @@ impl <R: Module> CommandsModule<R> {
         target.get_service::<CommandManager>().reload(target).await;
     }
 
+    #[event_handler]
+    async fn reload_commands(target: &Handler<impl Events>, _: &ReloadEvent) {
+        target.get_service::<CommandManager>().reload(target).await;
+    }
+
     #[event_handler]
     async fn run_terminal_command(
         &self, target: &Handler<impl Events>, command: &TerminalCommandEvent,
     ) {
-        let ctx = CommandCtx::new(target, TerminalContext {
-            raw_message: command.0.clone(),
-        });
+        let connector = Arc::new(TerminalConnector(target.get_service::<Interface>().clone()));
+        let author = MessageAuthor::new("terminal", "terminal");
+        // the terminal is not a user that configuration can be set for
+        let scopes = connector.channel_scopes("terminal");
+        let message = IncomingMessage::new(connector, "terminal", author, command.0.clone())
+            .with_scopes(scopes)
+            .with_command(command.0.clone())
+            .with_direct(true);
         let start_time = Instant::now();
-        if let Err(e) = target.get_service::<CommandManager>().execute(&ctx).await {
+        if let Err(e) = receive_message(target, message).await {
             e.report_error();
         } else {
             let total_time = (Instant::now() - start_time).as_millis();
@@ -45,31 +61,76 @@ impl <R: Module> CommandsModule<R> {
         }
     }
 
+    #[event_handler]
+    fn complete_terminal_command(target: &Handler<impl Events>, ev: &mut TerminalCompleteEvent) {
+        let manager = target.get_service::<CommandManager>();
+        let words: Vec<String> = ev.previous_words().into_iter().map(|x| x.to_string()).collect();
+        match words.as_slice() {
+            [] => for command in &*manager.command_list() {
+                for name in &*command.allowed_names {
+                    ev.add_completion(&*name.full_name);
+                }
+            },
+            [command, args @ ..] => {
+                if let Ok(LookupResult::Found(command)) = manager.lookup_command_raw(command) {
+                    let values = command.value.info().completions
+                        .and_then(|x| x.get(args.len()));
+                    for value in values.into_iter().flat_map(|x| x.iter()) {
+                        ev.add_completion(*value);
+                    }
+                }
+            }
+        }
+    }
+
+    #[event_handler]
+    fn collect_metrics(&self, target: &Handler<impl Events>, ev: &mut CollectMetricsEvent) {
+        for (name, stats) in target.get_service::<CommandManager>().command_stats() {
+            let labels = [("command", &*name)];
+            ev.labeled_counter(&self.info, "command_executions", &labels, stats.executions);
+            ev.labeled_counter(&self.info, "command_errors", &labels, stats.errors);
+            let time = stats.total_time.as_millis() as u64;
+            ev.labeled_counter(&self.info, "command_time_ms", &labels, time);
+        }
+    }
+
     #[event_handler]
     fn setup_logger(ev: &mut SetupLoggerEvent) {
-        ev.add_console_directive("sylphie_commands=debug");
+        ev.set_crate_level("sylphie_commands", LevelFilter::DEBUG);
     }
 }
 
-struct TerminalContext {
-    raw_message: String,
-}
+/// The terminal, as a connector.
+struct TerminalConnector(Interface);
 #[async_trait]
-impl CommandCtxImpl for TerminalContext {
-    fn scopes(&self) -> &[Scope] {
-        const SCOPES: [Scope; 1] = [Scope {
-            scope_type: StringWrapper::Static("terminal"),
-            args: ScopeArgs::None,
-        }];
-        &SCOPES
+impl Connector for TerminalConnector {
+    fn platform(&self) -> &str {
+        "terminal"
+    }
+
+    fn capabilities(&self) -> EnumSet<ConnectorCapability> {
+        EnumSet::only(ConnectorCapability::StatusLine)
+    }
+
+    fn progress_interval(&self) -> Duration {
+        Duration::from_millis(100)
     }
 
-    fn raw_message(&self) -> &str {
-        &self.raw_message
+    fn allows_everyone(&self) -> bool {
+        true
     }
 
-    async fn respond<E: Events>(&self, _: &Handler<E>, msg: &str) -> Result<()> {
-        info!(target: "[term]", "{}", msg);
+    fn channel_scopes(&self, _: &str) -> Vec<Scope> {
+        vec![Scope::new("terminal", ScopeArgs::None)]
+    }
+
+    async fn send_message(&self, _: &str, text: &str) -> Result<Option<String>> {
+        self.0.print_terminal_response(text);
+        Ok(None)
+    }
+
+    async fn set_status(&self, _: &str, status: Option<&str>) -> Result<()> {
+        self.0.set_terminal_status(status);
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,162 @@
+//! Reports the progress of long running commands to the user.
+
+use crate::ctx::CommandCtx;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use sylphie_core::prelude::*;
+
+/// The width of the bar drawn by [`ProgressState`]'s `Display` implementation.
+const BAR_WIDTH: usize = 20;
+
+/// The default interval between progress updates.
+pub(crate) const DEFAULT_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The current state of a progress report.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ProgressState {
+    /// What is being done, such as `Importing...`.
+    pub message: String,
+    /// How much of the work has been done.
+    pub current: u64,
+    /// How much work there is in total, or 0 if this is not known.
+    pub total: u64,
+    /// Whether the work has finished.
+    pub is_finished: bool,
+}
+
+/// Formats the state as a single line, such as `Importing... [#####     ] 50/100 (50%)`.
+impl fmt::Display for ProgressState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.total == 0 {
+            write!(f, "{} {}", self.message, self.current)?;
+        } else {
+            let current = self.current.min(self.total);
+            let filled = (current as u128 * BAR_WIDTH as u128 / self.total as u128) as usize;
+            write!(
+                f, "{} [{}{}] {}/{} ({}%)",
+                self.message, "#".repeat(filled), " ".repeat(BAR_WIDTH - filled),
+                current, self.total, current as u128 * 100 / self.total as u128,
+            )?;
+        }
+        if self.is_finished {
+            write!(f, " done")?;
+        }
+        Ok(())
+    }
+}
+
+/// An update to a progress report, passed to
+/// [`CommandCtxImpl::update_progress`](`crate::ctx::CommandCtxImpl::update_progress`).
+#[non_exhaustive]
+pub struct ProgressUpdate<'a> {
+    /// An identifier for the progress report, which is unique for as long as the bot runs.
+    ///
+    /// Contexts that edit a message for each update can use this to find the message.
+    pub id: u64,
+    /// Whether this is the first update of the report.
+    pub is_first: bool,
+    /// The new state of the report.
+    pub state: &'a ProgressState,
+}
+
+struct ProgressData {
+    state: ProgressState,
+    last_update: Option<Instant>,
+}
+
+/// A handle to a progress report, created with [`CommandCtx::progress`].
+///
+/// Updates are sent to the context at most once every
+/// [`CommandCtxImpl::progress_interval`](`crate::ctx::CommandCtxImpl::progress_interval`), except
+/// for the first and final updates. [`Progress::finish`] should be called once the work is done.
+pub struct Progress<E: Events> {
+    ctx: CommandCtx<E>,
+    id: u64,
+    data: Mutex<ProgressData>,
+}
+impl <E: Events> Progress<E> {
+    pub(crate) async fn new(ctx: &CommandCtx<E>, message: &str, total: u64) -> Result<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let progress = Progress {
+            ctx: ctx.clone(),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            data: Mutex::new(ProgressData {
+                state: ProgressState {
+                    message: message.to_string(),
+                    current: 0,
+                    total,
+                    is_finished: false,
+                },
+                last_update: None,
+            }),
+        };
+        progress.update(|_| { }).await?;
+        Ok(progress)
+    }
+
+    async fn update(&self, func: impl FnOnce(&mut ProgressState)) -> Result<()> {
+        let interval = self.ctx.progress_interval();
+        let (state, is_first) = {
+            let mut data = self.data.lock();
+            func(&mut data.state);
+            let now = Instant::now();
+            let is_first = data.last_update.is_none();
+            let is_due = data.last_update.map_or(true, |x| now - x >= interval);
+            if !data.state.is_finished && !is_due {
+                return Ok(())
+            }
+            data.last_update = Some(now);
+            (data.state.clone(), is_first)
+        };
+        self.ctx.update_progress(&ProgressUpdate { id: self.id, is_first, state: &state }).await
+    }
+
+    /// Sets how much of the work has been done.
+    pub async fn set(&self, current: u64) -> Result<()> {
+        self.update(|x| x.current = current).await
+    }
+
+    /// Adds to how much of the work has been done.
+    pub async fn inc(&self, by: u64) -> Result<()> {
+        self.update(|x| x.current = x.current.saturating_add(by)).await
+    }
+
+    /// Changes the message shown with the progress.
+    pub async fn set_message(&self, message: &str) -> Result<()> {
+        self.update(|x| x.message = message.to_string()).await
+    }
+
+    /// Marks the work as finished, and sends the final update.
+    pub async fn finish(self) -> Result<()> {
+        self.update(|x| {
+            x.is_finished = true;
+            if x.total != 0 {
+                x.current = x.total;
+            }
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_progress() {
+        let mut state = ProgressState {
+            message: "Importing...".to_string(),
+            current: 50,
+            total: 100,
+            is_finished: false,
+        };
+        assert_eq!(state.to_string(), "Importing... [##########          ] 50/100 (50%)");
+        state.total = 0;
+        assert_eq!(state.to_string(), "Importing... 50");
+        state.is_finished = true;
+        assert_eq!(state.to_string(), "Importing... 50 done");
+    }
+}
@@ -0,0 +1,73 @@
+//! Implements the `connections` command, which adds and removes connections.
+
+use crate::{ConnectionManager, ConnectionStatus};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, TerminalOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::prelude::*;
+
+fn status_name(status: ConnectionStatus) -> &'static str {
+    match status {
+        ConnectionStatus::Connected => "connected",
+        ConnectionStatus::PartlyConnected => "partly connected",
+        ConnectionStatus::Disconnected => "disconnected",
+        ConnectionStatus::Deactivated => "deactivated",
+    }
+}
+
+struct ConnectionsCommand;
+impl ConnectionsCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let target = ctx.handler();
+        let manager = target.get_service::<ConnectionManager>();
+        let action = match ctx.args_count() {
+            1 => "list".to_string(),
+            _ => ctx.arg(1).text.to_ascii_lowercase(),
+        };
+        match (action.as_str(), ctx.args_count()) {
+            ("list", 1) | ("list", 2) => {
+                let connections = manager.list_connections(target).await;
+                if connections.is_empty() {
+                    ctx.respond("No connections have been added.").await?;
+                }
+                for (name, kind, status) in connections {
+                    ctx.respond(&format!("* {} ({}): {}", name, kind, status_name(status))).await?;
+                }
+                let types = manager.connection_types();
+                ctx.respond(&format!("Connection types: {}", types.join(", "))).await?;
+            }
+            ("add", 4) => {
+                let (name, kind) = (ctx.arg(2).text, ctx.arg(3).text);
+                let conn_type = manager.connection_type(kind)
+                    .cmd_error(|| format!("No such connection type '{}' exists.", kind))?;
+                manager.add_connection(target, name, &conn_type).await?;
+                ctx.respond(&format!("Added connection '{}'.", name)).await?;
+            }
+            ("remove", 3) => {
+                let name = ctx.arg(2).text;
+                manager.remove_connection(target, name).await?;
+                ctx.respond(&format!("Removed connection '{}'.", name)).await?;
+            }
+            _ => cmd_error!("Usage: connections [list | add <name> <type> | remove <name>]"),
+        }
+        Ok(())
+    }
+}
+impl CommandImpl for ConnectionsCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `connections` command.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("connections").completions(&[&["list", "add", "remove"]]);
+    // removing the connection a command was sent through would cut off its response.
+    ev.register_command(Command::new(target, module, info, TerminalOnly(ConnectionsCommand)));
+}
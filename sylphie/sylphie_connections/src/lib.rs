@@ -3,17 +3,24 @@ use fxhash::FxHashMap;
 use serde::*;
 use static_events::prelude_async::*;
 use std::sync::Arc;
-use sylphie_core::core::InitEvent;
+use sylphie_core::core::{ConnectEvent, ShutdownEvent};
 use sylphie_core::derives::*;
+use sylphie_core::health::HealthReport;
+use sylphie_core::interface::LogAlertEvent;
+use sylphie_core::metrics::{CONNECTED_METRIC, CollectMetricsEvent};
+use sylphie_commands::manager::RegisterCommandsEvent;
 use sylphie_core::prelude::*;
-use sylphie_utils::scopes::{Scope, ScopeArgs};
+use sylphie_utils::scopes::{CONNECTION_SCOPE_TYPE, Scope, ScopeArgs};
 use sylphie_database::config::*;
 use sylphie_database::serializable::*;
 use sylphie_database::singleton::SingletonStore;
 use sylphie_utils::strings::InternString;
 use tokio::sync::RwLock;
 
+mod commands;
+mod reconnect;
 mod types;
+pub use reconnect::*;
 pub use types::*;
 
 /// The internal identifier of a connection.
@@ -28,7 +35,7 @@ struct ConnectionInfo {
 }
 impl ConnectionInfo {
     fn scope(&self) -> Scope {
-        Scope::new("sylphie_connections:connection", ScopeArgs::Long(self.id.0))
+        Scope::new(CONNECTION_SCOPE_TYPE, ScopeArgs::Long(self.id.0))
     }
 }
 
@@ -115,8 +122,15 @@ pub struct ConnectionManager {
 }
 #[module_impl]
 impl ConnectionManager {
+    /// The channel warnings and errors are relayed to, as `<connection name>:<channel>`, or an
+    /// empty string to not relay them.
+    #[config]
+    pub const CFG_LOG_ALERT_CHANNEL: ConfigKey<String> = config_option!(
+        Global, "sylphie_connections.log_alert_channel", || String::new(),
+    );
+
     #[event_handler]
-    async fn init(&self, target: &Handler<impl Events>, _: &InitEvent) -> Result<()> {
+    async fn init(&self, target: &Handler<impl Events>, _: &ConnectEvent) -> Result<()> {
         let types = target.dispatch_sync(InitConnectionTypesEvent {
             types: Default::default(),
         })?.types;
@@ -124,6 +138,85 @@ impl ConnectionManager {
         self.update(target).await
     }
 
+    #[event_handler]
+    fn register_commands(&self, target: &Handler<impl Events>, ev: &mut RegisterCommandsEvent) {
+        crate::commands::register_commands(target, self, ev);
+    }
+
+    #[module_hook(health)]
+    async fn check_health(&self, target: &Handler<impl Events>) -> HealthReport {
+        let live_state = self.live_state.read().await;
+        let mut disconnected = Vec::new();
+        for (id, instance) in &live_state.instances {
+            match instance.status(target).await {
+                ConnectionStatus::Connected | ConnectionStatus::Deactivated => { }
+                ConnectionStatus::PartlyConnected | ConnectionStatus::Disconnected => {
+                    match live_state.current.by_id.get(id) {
+                        Some(info) => disconnected.push(info.name.to_string()),
+                        None => disconnected.push(format!("#{}", id.0)),
+                    }
+                }
+            }
+        }
+        if disconnected.is_empty() {
+            HealthReport::ok()
+        } else {
+            disconnected.sort();
+            HealthReport::degraded(format!("Not connected: {}", disconnected.join(", ")))
+        }
+    }
+
+    #[event_handler]
+    async fn collect_metrics(&self, target: &Handler<impl Events>, ev: &mut CollectMetricsEvent) {
+        let live_state = self.live_state.read().await;
+        for (id, instance) in &live_state.instances {
+            let name = match live_state.current.by_id.get(id) {
+                Some(info) => info.name.to_string(),
+                None => format!("#{}", id.0),
+            };
+            let connected = match instance.status(target).await {
+                ConnectionStatus::Connected => 1.0,
+                ConnectionStatus::PartlyConnected => 0.5,
+                ConnectionStatus::Disconnected | ConnectionStatus::Deactivated => 0.0,
+            };
+            let labels = [("connection", &*name), ("type", instance.conn_type().name())];
+            ev.labeled_gauge(&self.info, CONNECTED_METRIC, &labels, connected);
+        }
+    }
+
+    #[event_handler]
+    async fn relay_log_alerts(
+        &self, target: &Handler<impl Events>, ev: &LogAlertEvent,
+    ) -> Result<()> {
+        let config = target.get_service::<ConfigManager>();
+        let channel = config.get(target, GLOBAL_SCOPE, Self::CFG_LOG_ALERT_CHANNEL).await?;
+        if channel.is_empty() {
+            return Ok(())
+        }
+        let (name, channel) = match channel.find(':') {
+            Some(i) => (&channel[..i], &channel[i + 1..]),
+            None => cmd_error!("Invalid log alert channel '{}'.", channel),
+        };
+
+        let live_state = self.live_state.read().await;
+        let instance = live_state.current.by_name.get(name)
+            .and_then(|id| live_state.instances.get(id));
+        match instance {
+            Some(instance) => instance.send_message(target, channel, &ev.to_text()).await,
+            None => cmd_error!("No such connection named '{}' exists.", name),
+        }
+    }
+
+    #[event_handler]
+    async fn close_connections(&self, target: &Handler<impl Events>, _: &ShutdownEvent) {
+        let mut live_state = self.live_state.write().await;
+        for (_, instance) in live_state.instances.drain() {
+            if let Err(err) = instance.destroy(target).await {
+                err.report_error();
+            }
+        }
+    }
+
     async fn update(&self, target: &Handler<impl Events>) -> Result<()> {
         let state = self.state.get().await;
         let mut live_state = self.live_state.write().await;
@@ -170,9 +263,41 @@ impl ConnectionManager {
         Ok(())
     }
 
+    /// Returns the connection type with a given name, if it exists.
+    pub fn connection_type(&self, name: &str) -> Option<ConnectionType> {
+        self.types.load().as_ref().and_then(|types| types.get(name).cloned())
+    }
+
+    /// Returns the names of every connection type, sorted by name.
+    pub fn connection_types(&self) -> Vec<Arc<str>> {
+        let mut types: Vec<_> = match self.types.load().as_ref() {
+            Some(types) => types.keys().cloned().collect(),
+            None => Vec::new(),
+        };
+        types.sort();
+        types
+    }
+
+    /// Returns the name, type and status of every connection, sorted by name.
+    pub async fn list_connections(
+        &self, target: &Handler<impl Events>,
+    ) -> Vec<(Arc<str>, Arc<str>, ConnectionStatus)> {
+        let live_state = self.live_state.read().await;
+        let mut list = Vec::new();
+        for (id, info) in &live_state.current.by_id {
+            let status = match live_state.instances.get(id) {
+                Some(instance) => instance.status(target).await,
+                None => ConnectionStatus::Disconnected,
+            };
+            list.push((info.name.clone(), info.kind.clone(), status));
+        }
+        list.sort_by(|a, b| a.0.cmp(&b.0));
+        list
+    }
+
     /// Creates a new connection.
     pub async fn add_connection(
-        &mut self, target: &Handler<impl Events>, name: &str, kind: &ConnectionType,
+        &self, target: &Handler<impl Events>, name: &str, kind: &ConnectionType,
     ) -> Result<()> {
         let mut state = self.state.get_mut().await?;
         state.add_connection(name, kind).await?;
@@ -183,7 +308,7 @@ impl ConnectionManager {
 
     /// Removes an old connection.
     pub async fn remove_connection(
-        &mut self, target: &Handler<impl Events>, name: &str,
+        &self, target: &Handler<impl Events>, name: &str,
     ) -> Result<()> {
         let mut state = self.state.get_mut().await?;
         state.remove_connection(name).await?;
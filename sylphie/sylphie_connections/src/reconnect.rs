@@ -0,0 +1,95 @@
+//! Helpers for connections that reconnect on their own after losing their connection.
+
+use std::time::Duration;
+use sylphie_core::prelude::*;
+
+/// How long a connection must last before the delay before reconnecting is reset.
+const STABLE_TIME: Duration = Duration::from_secs(60);
+
+/// Returns whether an error can never be fixed by reconnecting, such as an invalid token.
+///
+/// Connections report problems with their settings as command errors, so these are treated as
+/// fatal. Connections should stop retrying and return the error when this returns `true`.
+pub fn is_fatal_error(err: &Error) -> bool {
+    match err.error_kind() {
+        ErrorKind::CommandError(_) => true,
+        _ => false,
+    }
+}
+
+/// Logs an error that caused a connection to be lost.
+///
+/// Network failures are expected from time to time, so they are logged as warnings rather than
+/// reported as errors. Panics are reported as usual, as they are bugs.
+pub fn log_disconnect(name: &str, err: &Error) {
+    match err.error_kind() {
+        ErrorKind::Panicked(..) => {
+            warn!("The connection to {} failed.", name);
+            err.report_error();
+        }
+        _ => warn!("The connection to {} failed: {}", name, err),
+    }
+}
+
+/// The delay before a connection tries to reconnect, which doubles after each failure.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+impl Backoff {
+    /// Creates a new backoff, starting at `initial` and doubling up to `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff { initial, max, current: initial }
+    }
+
+    /// Returns the delay the next call to [`wait`](Self::wait) waits for.
+    pub fn delay(&self) -> Duration {
+        self.current
+    }
+
+    /// Resets the delay, after the connection succeeds.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    /// Resets the delay if a connection lasted long enough to be considered stable.
+    pub fn reset_if_stable(&mut self, connected_for: Duration) {
+        if connected_for >= STABLE_TIME {
+            self.reset();
+        }
+    }
+
+    /// Waits for the current delay, and then doubles it.
+    pub async fn wait(&mut self, name: &str) {
+        debug!("Reconnecting to {} in {} seconds.", name, self.current.as_secs());
+        tokio::time::delay_for(self.advance()).await;
+    }
+
+    fn advance(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (0..4).map(|_| backoff.advance().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5]);
+
+        backoff.reset_if_stable(Duration::from_secs(1));
+        assert_eq!(backoff.delay().as_secs(), 5);
+        backoff.reset_if_stable(STABLE_TIME);
+        assert_eq!(backoff.delay().as_secs(), 1);
+
+        assert!(is_fatal_error(&Error::new(ErrorKind::CommandError("bad".into()))));
+        assert!(!is_fatal_error(&Error::new(ErrorKind::InternalError("bad".into()))));
+    }
+}
@@ -3,6 +3,7 @@ use std::any::Any;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use sylphie_commands::connector::Connector;
 use sylphie_core::prelude::*;
 use sylphie_utils::scopes::Scope;
 use sylphie_database::utils::ScopeId;
@@ -30,7 +31,27 @@ pub trait Connection<E: Events>: Send + Sync + 'static {
     async fn update_connection(&self, target: &Handler<E>) -> Result<()>;
 
     /// An event that is triggered when an connection is destroyed.
+    ///
+    /// The connector returned by [`Connection::connector`] is disconnected after this returns.
     async fn destroy(&self, target: &Handler<E>) -> Result<()>;
+
+    /// Sends a message to a channel of this connection, identified in a way specific to the
+    /// connection type.
+    ///
+    /// By default, this returns an error, as the connection can not send messages.
+    async fn send_message(
+        &self, _target: &Handler<E>, _channel: &str, _message: &str,
+    ) -> Result<()> {
+        cmd_error!("This connection can not send messages.")
+    }
+
+    /// Returns the connector this connection receives messages through, if it has one.
+    ///
+    /// The connector is connected once the connection is created, and disconnected when the
+    /// connection is destroyed.
+    fn connector(&self) -> Option<Arc<dyn Connector>> {
+        None
+    }
 }
 
 #[async_trait]
@@ -53,6 +74,14 @@ trait ErasedConnection: Send + Sync + 'static {
 
     /// An event that is triggered when an event is destroyed.
     async fn destroy(&self, target: &(dyn Any + Send + Sync)) -> Result<()>;
+
+    /// Sends a message to a channel of this connection.
+    async fn send_message(
+        &self, target: &(dyn Any + Send + Sync), channel: &str, message: &str,
+    ) -> Result<()>;
+
+    /// Returns the connector of this connection.
+    fn connector(&self) -> Option<Arc<dyn Connector>>;
 }
 struct ConnectionWrapper<E: Events, C: Connection<E>>(C, PhantomData<E>);
 #[async_trait]
@@ -69,6 +98,15 @@ impl <E: Events, C: Connection<E>> ErasedConnection for ConnectionWrapper<E, C>
         let target = target.downcast_ref().expect("Wrong Dispatch type passed!");
         self.0.destroy(target).await
     }
+    async fn send_message(
+        &self, target: &(dyn Any + Send + Sync), channel: &str, message: &str,
+    ) -> Result<()> {
+        let target = target.downcast_ref().expect("Wrong Dispatch type passed!");
+        self.0.send_message(target, channel, message).await
+    }
+    fn connector(&self) -> Option<Arc<dyn Connector>> {
+        self.0.connector()
+    }
 }
 
 #[async_trait]
@@ -125,6 +163,14 @@ impl ConnectionType {
     ) -> Result<ConnectionInstance> {
         let conn = self.0.inner.create(target, id, scope.clone()).await?;
         let scope_id = ScopeId::intern(target, scope.clone()).await?;
+        if let Some(connector) = conn.connector() {
+            if let Err(e) = connector.connect().await {
+                if let Err(e) = conn.destroy(target).await {
+                    e.report_error();
+                }
+                return Err(e)
+            }
+        }
         Ok(ConnectionInstance(Arc::new(ConnectionInstanceData {
             id,
             scope,
@@ -183,7 +229,23 @@ impl ConnectionInstance {
         self.0.inner.update_connection(target).await
     }
 
+    /// Sends a message to a channel of this connection.
+    pub async fn send_message(
+        &self, target: &Handler<impl Events>, channel: &str, message: &str,
+    ) -> Result<()> {
+        self.0.inner.send_message(target, channel, message).await
+    }
+
+    /// Returns the connector this connection receives messages through, if it has one.
+    pub fn connector(&self) -> Option<Arc<dyn Connector>> {
+        self.0.inner.connector()
+    }
+
     pub(crate) async fn destroy(&self, target: &Handler<impl Events>) -> Result<()> {
-        self.0.inner.destroy(target).await
+        let result = self.0.inner.destroy(target).await;
+        if let Some(connector) = self.connector() {
+            connector.disconnect().await?;
+        }
+        result
     }
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+//! Static files bundled into modules at compile time.
+
+use crate::errors::*;
+use crate::module::ModuleInfo;
+use arc_swap::ArcSwapOption;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A static file embedded into a module.
+///
+/// This is usually created with the [`module_asset!`] macro.
+#[derive(Copy, Clone, Debug)]
+pub struct AssetData {
+    /// The name used to look up this asset.
+    pub name: &'static str,
+    /// The contents of the asset.
+    pub data: &'static [u8],
+}
+impl AssetData {
+    /// Returns the file name of this asset, without any directories.
+    pub fn file_name(&self) -> &'static str {
+        self.name.rsplit('/').next().unwrap()
+    }
+
+    /// Returns the contents of this asset as a string.
+    pub fn as_str(&self) -> Result<&'static str> {
+        std::str::from_utf8(self.data)
+            .internal_err(|| format!("Asset '{}' is not valid UTF-8.", self.name))
+    }
+}
+
+/// Embeds a file into the module as an asset.
+///
+/// The asset is named after the file name of the path, unless a name is explicitly given as the
+/// first parameter.
+#[macro_export]
+macro_rules! module_asset_7c2e5d1f03b94e0c8a6f1d2b9e4a3c76 {
+    ($name:expr, $source:expr $(,)?) => {
+        $crate::assets::AssetData {
+            name: $name,
+            data: include_bytes!($source),
+        }
+    };
+    ($source:expr $(,)?) => {
+        $crate::assets::AssetData {
+            name: $source,
+            data: include_bytes!($source),
+        }
+    };
+}
+
+#[doc(inline)]
+pub use crate::{module_asset_7c2e5d1f03b94e0c8a6f1d2b9e4a3c76 as module_asset};
+
+type AssetMap = HashMap<Arc<str>, HashMap<&'static str, &'static AssetData>>;
+
+/// An event used to register the assets used by modules.
+///
+/// This is only fired once during early initialization, before [`EarlyInitEvent`].
+///
+/// [`EarlyInitEvent`]: crate::core::EarlyInitEvent
+pub struct RegisterAssetsEvent {
+    assets: AssetMap,
+}
+failable_self_event!(RegisterAssetsEvent, Error);
+impl RegisterAssetsEvent {
+    pub(crate) fn new() -> Self {
+        RegisterAssetsEvent { assets: HashMap::new() }
+    }
+
+    /// Registers an asset under the namespace of the given module.
+    pub fn add_asset(&mut self, module: &ModuleInfo, asset: &'static AssetData) -> Result<()> {
+        let namespace = self.assets.entry(module.arc_name()).or_insert_with(HashMap::new);
+        let name = asset.file_name();
+        ensure!(
+            !namespace.contains_key(name),
+            "Duplicate asset '{}' in module '{}'.", name, module.name(),
+        );
+        namespace.insert(name, asset);
+        Ok(())
+    }
+}
+
+/// Allows modules to look up static assets at runtime.
+///
+/// Assets are namespaced by the name of the module that registered them. This can be retrieved
+/// using `get_service`.
+#[derive(Default)]
+pub struct Assets {
+    assets: ArcSwapOption<AssetMap>,
+}
+impl Assets {
+    pub(crate) fn set_assets(&self, ev: RegisterAssetsEvent) {
+        self.assets.store(Some(Arc::new(ev.assets)));
+    }
+
+    /// Looks up an asset by its namespace and name.
+    pub fn get(&self, namespace: &str, name: &str) -> Option<&'static AssetData> {
+        let assets = self.assets.load();
+        let assets = assets.as_ref().expect("Assets are not yet initialized.");
+        assets.get(namespace).and_then(|x| x.get(name)).map(|x| *x)
+    }
+
+    /// Looks up an asset by a path of the form `module_name:asset_name`.
+    pub fn lookup(&self, path: &str) -> Option<&'static AssetData> {
+        let mut split = path.rsplitn(2, ':');
+        let name = split.next().unwrap();
+        let namespace = split.next()?;
+        self.get(namespace, name)
+    }
+
+    /// Looks up an asset registered by a particular module.
+    pub fn get_for_module(&self, module: &ModuleInfo, name: &str) -> Option<&'static AssetData> {
+        self.get(module.name(), name)
+    }
+
+    /// Returns the full paths of all registered assets.
+    pub fn list(&self) -> Vec<String> {
+        let assets = self.assets.load();
+        let assets = assets.as_ref().expect("Assets are not yet initialized.");
+        let mut list = Vec::new();
+        for (namespace, items) in assets.iter() {
+            for name in items.keys() {
+                list.push(format!("{}:{}", namespace, name));
+            }
+        }
+        list.sort();
+        list
+    }
+}
@@ -0,0 +1,196 @@
+//! Parses the command line arguments accepted by every bot.
+//!
+//! [`SylphieCore::new`](`crate::SylphieCore::new`) parses the arguments the bot was started
+//! with, printing [`USAGE`] and exiting if they are invalid or `--help` is given. Arguments after
+//! a lone `--` are not parsed, and are left in [`CliArgs::extra`] for the bot's own use.
+
+use crate::errors::*;
+use std::path::PathBuf;
+
+/// The help text printed for `--help`.
+pub const USAGE: &str = "\
+Options:
+    --data-dir <dir>          Stores the bot's files in <dir>, instead of the usual directories.
+    --config-dir <dir>        Reads config.toml from <dir>.
+    --cache-dir <dir>         Stores cached files in <dir>.
+    --config <file>           Reads the configuration from <file> instead of config.toml.
+    --log-level <level>       Sets the log level for the bot's modules, such as `debug`, or
+                              adds a filtering directive, such as `sylphie_core=trace`.
+    --migrate-only            Applies database migrations and other init tasks, then exits.
+    --check                   Checks that the configuration and database are valid, then exits.
+    --check-migrations        Checks that database migrations can be applied, then exits.
+    --restore-backup <file>   Restores the database from a backup before starting.
+    --exec-script <file>      Runs the terminal commands in <file> after starting.
+    --daemon                  Runs the bot in the background, without a terminal.
+    --supervise               Restarts the bot if it crashes.
+    --help                    Prints this message.";
+
+/// The command line arguments the bot was started with.
+///
+/// This can be retrieved using `get_service`.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct CliArgs {
+    /// The directory the bot's data is stored in, instead of the default root path.
+    pub data_dir: Option<PathBuf>,
+    /// The directory the configuration file is found in.
+    pub config_dir: Option<PathBuf>,
+    /// The directory cached files are stored in.
+    pub cache_dir: Option<PathBuf>,
+    /// The configuration file, instead of `config.toml` in the configuration directory.
+    pub config: Option<PathBuf>,
+    /// The log level or filtering directive for the bot's modules.
+    pub log_level: Option<String>,
+    /// Whether the bot should exit once its init tasks have finished.
+    pub migrate_only: bool,
+    /// Whether the bot should exit once its configuration has been checked.
+    pub check: bool,
+    /// Whether the bot should exit once its database migrations have been checked.
+    pub check_migrations: bool,
+    /// A backup to restore the database from.
+    pub restore_backup: Option<PathBuf>,
+    /// A script of terminal commands to run once the bot has started.
+    pub exec_script: Option<PathBuf>,
+    /// Whether the bot should run in the background.
+    pub daemon: bool,
+    /// Whether the bot should be restarted if it crashes.
+    pub supervise: bool,
+    /// Whether `--help` was given.
+    pub help: bool,
+    /// The arguments following `--`.
+    pub extra: Vec<String>,
+}
+impl CliArgs {
+    /// Parses the arguments the bot was started with.
+    pub fn from_env() -> Result<CliArgs> {
+        let mut args = Vec::new();
+        for arg in std::env::args_os().skip(1) {
+            match arg.into_string() {
+                Ok(x) => args.push(x),
+                Err(x) => cmd_error!("Argument is not valid Unicode: {}", x.to_string_lossy()),
+            }
+        }
+        CliArgs::parse(args)
+    }
+
+    /// Returns arguments that would be parsed back into these arguments.
+    pub fn to_args(&self) -> Vec<String> {
+        fn path(path: &Option<PathBuf>) -> Option<String> {
+            path.as_ref().map(|x| x.to_string_lossy().into_owned())
+        }
+        let values = [
+            ("--data-dir", path(&self.data_dir)),
+            ("--config-dir", path(&self.config_dir)),
+            ("--cache-dir", path(&self.cache_dir)),
+            ("--config", path(&self.config)),
+            ("--log-level", self.log_level.clone()),
+            ("--restore-backup", path(&self.restore_backup)),
+            ("--exec-script", path(&self.exec_script)),
+        ];
+        let flags = [
+            ("--migrate-only", self.migrate_only),
+            ("--check", self.check),
+            ("--check-migrations", self.check_migrations),
+            ("--daemon", self.daemon),
+            ("--supervise", self.supervise),
+            ("--help", self.help),
+        ];
+
+        let mut args = Vec::new();
+        for (name, value) in values.iter() {
+            if let Some(value) = value {
+                args.push(name.to_string());
+                args.push(value.clone());
+            }
+        }
+        for (name, is_set) in flags.iter() {
+            if *is_set {
+                args.push(name.to_string());
+            }
+        }
+        if !self.extra.is_empty() {
+            args.push("--".to_string());
+            args.extend(self.extra.iter().cloned());
+        }
+        args
+    }
+
+    /// Parses a list of arguments, not including the name of the executable.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<CliArgs> {
+        let mut parsed = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || match inline.clone().or_else(|| args.next()) {
+                Some(x) => Ok(x),
+                None => cmd_error!("Missing value for {}.", name),
+            };
+            match name {
+                "--data-dir" => parsed.data_dir = Some(PathBuf::from(value()?)),
+                "--config-dir" => parsed.config_dir = Some(PathBuf::from(value()?)),
+                "--cache-dir" => parsed.cache_dir = Some(PathBuf::from(value()?)),
+                "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "--log-level" => parsed.log_level = Some(value()?),
+                "--restore-backup" => parsed.restore_backup = Some(PathBuf::from(value()?)),
+                "--exec-script" => parsed.exec_script = Some(PathBuf::from(value()?)),
+                "--migrate-only" | "--check" | "--check-migrations" | "--daemon" |
+                "--supervise" | "--help" => {
+                    if inline.is_some() {
+                        cmd_error!("{} does not take a value.", name);
+                    }
+                    match name {
+                        "--migrate-only" => parsed.migrate_only = true,
+                        "--check" => parsed.check = true,
+                        "--check-migrations" => parsed.check_migrations = true,
+                        "--daemon" => parsed.daemon = true,
+                        "--supervise" => parsed.supervise = true,
+                        _ => parsed.help = true,
+                    }
+                }
+                "--" => {
+                    parsed.extra.extend(args);
+                    break
+                }
+                _ => cmd_error!("Unknown argument: {}", arg),
+            }
+        }
+        if parsed.migrate_only && (parsed.check || parsed.check_migrations) {
+            cmd_error!("--migrate-only cannot be used with --check or --check-migrations.");
+        }
+        if parsed.supervise && (parsed.migrate_only || parsed.check || parsed.check_migrations) {
+            cmd_error!("--supervise cannot be used with options that exit after starting.");
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs> {
+        CliArgs::parse(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = parse(&[
+            "--data-dir", "state", "--log-level=debug", "--check", "--", "--bot-flag",
+        ]).unwrap();
+        assert_eq!(args.data_dir, Some(PathBuf::from("state")));
+        assert_eq!(args.log_level.as_deref(), Some("debug"));
+        assert!(args.check);
+        assert!(!args.migrate_only);
+        assert_eq!(args.extra, vec!["--bot-flag".to_string()]);
+        assert_eq!(CliArgs::parse(args.to_args()).unwrap().to_args(), args.to_args());
+
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--check=yes"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert!(parse(&["--migrate-only", "--check"]).is_err());
+        assert!(parse(&["--supervise", "--check"]).is_err());
+    }
+}
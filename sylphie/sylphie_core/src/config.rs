@@ -0,0 +1,352 @@
+//! Loads the bot's configuration file.
+//!
+//! `config.toml` in the bot's configuration directory is read when the bot starts, and read
+//! again whenever it changes on disk, or the bot is asked to reload with the `reload` command or
+//! `SIGHUP`. Modules that keep state derived from the configuration, such as API clients, should
+//! rebuild it when [`ConfigReloadedEvent`] is dispatched. Some settings, such as the paths of the
+//! database, are only used when the bot starts, and changes to them take effect once it is
+//! restarted.
+//!
+//! This is unlike the configuration options in `sylphie::database::config`, which are stored in
+//! the database and are changed with commands.
+//!
+//! Settings are resolved in this order, with later sources taking precedence:
+//!
+//! 1. The defaults built into the bot and its modules.
+//! 2. `config.toml` in the bot's configuration directory.
+//! 3. Environment variables named after the setting's key, in upper case with dots replaced by
+//!    underscores, and prefixed with `SYLPHIE_`. For example, `discord.token` is overridden by
+//!    `SYLPHIE_DISCORD_TOKEN`. Values are parsed as TOML values where possible, such as
+//!    `["a", "b"]` for a list, and used as plain strings otherwise.
+//! 4. Command line arguments, for the few settings that have one, such as `--log-level`. These
+//!    are described in [`cli`](`crate::cli`).
+//!
+//! A different configuration file can be used by starting the bot with `--config <file>`.
+//!
+//! The settings used by the core are described by [`CoreConfig`]. Other tables, such as the
+//! credentials for a connector, are read by modules with [`Config::get`]:
+//!
+//! ```toml
+//! bot_name = "Sylphie"
+//! owners = ["discord:123456789"]
+//!
+//! [database]
+//! path = "db/sylphie.db"
+//! transient_path = "db/sylphie.transient.db"
+//!
+//! [log]
+//! level = "debug"
+//! directives = ["sylphie_database::connection=trace"]
+//!
+//! [health]
+//! listen = "127.0.0.1:8080"
+//!
+//! [metrics]
+//! listen = "127.0.0.1:9100"
+//!
+//! [watchdog]
+//! timeout = 120
+//!
+//! [discord]
+//! token = "..."
+//! ```
+
+use arc_swap::ArcSwap;
+use crate::errors::*;
+use serde::de::DeserializeOwned;
+use static_events::prelude_async::*;
+#[cfg(test)] use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use toml::Value;
+use toml::value::Table;
+
+/// The name of the configuration file in the bot's configuration directory.
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// How often the configuration file is checked for changes.
+pub const CONFIG_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Dispatched after the configuration file has been reloaded, either because it changed on disk
+/// or because the bot was asked to reload.
+///
+/// [`Config`] already returns the new settings when this is dispatched. If the new file could
+/// not be parsed, the error is reported, the previous settings are kept, and this is not
+/// dispatched.
+pub struct ConfigReloadedEvent(());
+simple_event!(ConfigReloadedEvent);
+
+/// The settings in the configuration file that are used by the core.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct CoreConfig {
+    /// Overrides the name of the bot given to [`SylphieCore::new`](`crate::SylphieCore::new`).
+    pub bot_name: Option<String>,
+    /// The users who own the bot, in a format specific to the connector they use.
+    pub owners: Vec<String>,
+    /// Where the database is stored.
+    pub database: DatabaseConfig,
+    /// How log messages are filtered.
+    pub log: LogConfig,
+}
+
+/// The `[database]` table of the configuration file.
+///
+/// Relative paths are resolved against the bot's root path. By default, the databases are
+/// stored in the `db` directory of the root path, and named after the bot.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct DatabaseConfig {
+    /// The path of the persistent database.
+    pub path: Option<PathBuf>,
+    /// The path of the transient database.
+    pub transient_path: Option<PathBuf>,
+}
+
+/// The `[log]` table of the configuration file.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct LogConfig {
+    /// The level of log messages shown for crates containing loaded modules, such as `debug`.
+    pub level: Option<String>,
+    /// Additional `env_logger` style filtering directives, applied after `level`.
+    pub directives: Vec<String>,
+}
+
+/// Returns the environment variable that overrides a setting.
+fn env_var_name(key: &str) -> String {
+    format!("SYLPHIE_{}", key.to_uppercase().replace('.', "_"))
+}
+
+/// Where the environment variables that override settings are read from.
+enum Environment {
+    /// The environment of the process.
+    Process,
+    /// A fixed set of variables, so tests do not change the environment of the process.
+    #[cfg(test)]
+    Fixed(HashMap<String, String>),
+}
+impl Environment {
+    fn var(&self, var: &str) -> Result<Option<String>> {
+        match self {
+            Environment::Process => match std::env::var(var) {
+                Ok(raw) => Ok(Some(raw)),
+                Err(std::env::VarError::NotUnicode(_)) =>
+                    cmd_error!("Environment variable {} is not valid Unicode.", var),
+                Err(std::env::VarError::NotPresent) => Ok(None),
+            },
+            #[cfg(test)]
+            Environment::Fixed(vars) => Ok(vars.get(var).cloned()),
+        }
+    }
+}
+
+/// Returns when a file was last modified, or `None` if it does not exist.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// The parsed contents of the configuration file, which are replaced when it is reloaded.
+struct ConfigData {
+    table: Table,
+    core: Arc<CoreConfig>,
+}
+
+/// The contents of the configuration file.
+///
+/// This can be retrieved using `get_service`.
+pub struct Config {
+    path: PathBuf,
+    env: Environment,
+    data: ArcSwap<ConfigData>,
+}
+impl Config {
+    /// Loads a configuration file. A missing file is treated as empty.
+    pub(crate) fn load(path: PathBuf) -> Result<Config> {
+        let env = Environment::Process;
+        let data = Config::read(&path, &env)?;
+        Ok(Config { path, env, data: ArcSwap::from_pointee(data) })
+    }
+
+    /// Reads the configuration file again, keeping the current settings if it is invalid.
+    pub(crate) fn reload(&self) -> Result<()> {
+        let data = Config::read(&self.path, &self.env)?;
+        self.data.store(Arc::new(data));
+        Ok(())
+    }
+
+    fn read(path: &Path, env: &Environment) -> Result<ConfigData> {
+        let text = if path.exists() {
+            std::fs::read_to_string(path)
+                .internal_err(|| format!("Could not read '{}'.", path.display()))?
+        } else {
+            String::new()
+        };
+        Config::parse_data(path, env, &text)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn parse(path: PathBuf, text: &str) -> Result<Config> {
+        Config::parse_with_env(path, text, &[])
+    }
+
+    #[cfg(test)]
+    fn parse_with_env(path: PathBuf, text: &str, vars: &[(&str, &str)]) -> Result<Config> {
+        let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let env = Environment::Fixed(vars);
+        let data = Config::parse_data(&path, &env, text)?;
+        Ok(Config { path, env, data: ArcSwap::from_pointee(data) })
+    }
+
+    fn parse_data(path: &Path, env: &Environment, text: &str) -> Result<ConfigData> {
+        let table: Table = match toml::from_str(text) {
+            Ok(x) => x,
+            Err(e) => cmd_error!("Could not parse '{}': {}", path.display(), e),
+        };
+        let core = CoreConfig {
+            bot_name: Config::lookup(path, env, &table, "bot_name")?,
+            owners: Config::lookup(path, env, &table, "owners")?.unwrap_or_default(),
+            database: DatabaseConfig {
+                path: Config::lookup(path, env, &table, "database.path")?,
+                transient_path: Config::lookup(path, env, &table, "database.transient_path")?,
+            },
+            log: LogConfig {
+                level: Config::lookup(path, env, &table, "log.level")?,
+                directives: Config::lookup(path, env, &table, "log.directives")?
+                    .unwrap_or_default(),
+            },
+        };
+        Ok(ConfigData { table, core: Arc::new(core) })
+    }
+
+    /// Parses the value of an environment variable that overrides a setting.
+    fn parse_env<T: DeserializeOwned>(var: &str, raw: &str) -> Result<T> {
+        if let Ok(Value::Table(mut table)) = format!("value = {}", raw).parse::<Value>() {
+            if let Some(value) = table.remove("value") {
+                if let Ok(x) = value.try_into() {
+                    return Ok(x)
+                }
+            }
+        }
+        match Value::String(raw.to_string()).try_into() {
+            Ok(x) => Ok(x),
+            Err(e) => cmd_error!("Invalid value for environment variable {}: {}", var, e),
+        }
+    }
+
+    /// Returns the path of the configuration file, whether or not it exists.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the settings used by the core.
+    pub fn core(&self) -> Arc<CoreConfig> {
+        self.data.load().core.clone()
+    }
+
+    /// Returns the value of a setting by its dotted key, such as `discord.token`, or `None` if
+    /// it is not set.
+    ///
+    /// The setting's environment variable is checked first, and then the configuration file.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        Config::lookup(&self.path, &self.env, &self.data.load().table, key)
+    }
+
+    fn lookup<T: DeserializeOwned>(
+        path: &Path, env: &Environment, table: &Table, key: &str,
+    ) -> Result<Option<T>> {
+        let var = env_var_name(key);
+        if let Some(raw) = env.var(&var)? {
+            return Self::parse_env(&var, &raw).map(Some)
+        }
+
+        let mut parts = key.split('.');
+        let mut value = match table.get(parts.next().unwrap()) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        for part in parts {
+            value = match value.get(part) {
+                Some(x) => x,
+                None => return Ok(None),
+            };
+        }
+        match value.clone().try_into() {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => cmd_error!("Invalid value for '{}' in '{}': {}", key, path.display(), e),
+        }
+    }
+}
+
+/// Reloads the configuration file whenever it changes on disk, until the bot shuts down.
+pub(crate) async fn watch_config_task(target: Handler<impl Events>) -> Result<()> {
+    let path = target.get_service::<Config>().path().to_owned();
+    let mut last_modified = modified_time(&path);
+    let mut interval = tokio::time::interval(CONFIG_WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let modified = modified_time(&path);
+        if modified == last_modified {
+            continue
+        }
+        last_modified = modified;
+        info!("The configuration file has changed. Reloading it.");
+        if let Err(e) = reload_config(&target).await {
+            e.report_error();
+        }
+    }
+}
+
+/// Reloads the configuration file and the logger, then dispatches [`ConfigReloadedEvent`].
+pub(crate) async fn reload_config(target: &Handler<impl Events>) -> Result<()> {
+    target.get_service::<Config>().reload()?;
+    if let Err(e) = target.get_service::<crate::interface::Interface>().reload_logger(target) {
+        e.report_error();
+    }
+    target.dispatch_async(ConfigReloadedEvent(())).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_values() {
+        let config = Config::parse(PathBuf::from(CONFIG_FILE_NAME), r#"
+            owners = ["discord:1"]
+            [log]
+            level = "debug"
+            [discord]
+            token = "abc"
+            shards = 2
+        "#).unwrap();
+        assert_eq!(config.core().owners, vec!["discord:1".to_string()]);
+        assert_eq!(config.core().log.level.as_deref(), Some("debug"));
+        assert_eq!(config.get::<String>("discord.token").unwrap().as_deref(), Some("abc"));
+        assert_eq!(config.get::<u32>("discord.shards").unwrap(), Some(2));
+        assert_eq!(config.get::<String>("discord.missing").unwrap(), None);
+        assert!(config.get::<u32>("discord.token").is_err());
+    }
+
+    #[test]
+    fn env_overrides() {
+        let config = Config::parse_with_env(PathBuf::from(CONFIG_FILE_NAME), r#"
+            [env_test]
+            name = "file"
+        "#, &[
+            ("SYLPHIE_ENV_TEST_NAME", "env"),
+            ("SYLPHIE_ENV_TEST_LIST", r#"["a", "b"]"#),
+            ("SYLPHIE_ENV_TEST_COUNT", "3"),
+        ]).unwrap();
+        assert_eq!(env_var_name("env_test.name"), "SYLPHIE_ENV_TEST_NAME");
+        assert_eq!(config.get::<String>("env_test.name").unwrap().as_deref(), Some("env"));
+        assert_eq!(
+            config.get::<Vec<String>>("env_test.list").unwrap(),
+            Some(vec!["a".to_string(), "b".to_string()]),
+        );
+        assert_eq!(config.get::<u32>("env_test.count").unwrap(), Some(3));
+        assert_eq!(config.get::<String>("env_test.count").unwrap().as_deref(), Some("3"));
+    }
+}
@@ -0,0 +1,60 @@
+//! Runs the bot in the background, detached from the terminal it was started from.
+
+use crate::core::BotInfo;
+use crate::errors::*;
+use std::path::PathBuf;
+
+/// The environment variable a daemon passes its PID file to itself in when it restarts.
+pub(in super) const PID_FILE_VAR: &str = "SYLPHIE_DAEMON_PID_FILE";
+
+/// Returns the path of the file the daemon's PID is written to.
+fn pid_file_path(info: &BotInfo) -> PathBuf {
+    info.root_path.join(format!("{}.pid", info.bot_name))
+}
+
+/// Returns the PID file of the daemon this process was restarted from, if any.
+///
+/// A restarted daemon keeps the same PID, so it takes over the PID file and removes it when it
+/// exits. This must be called before any threads are started, as it changes the environment.
+pub(in super) fn inherited_pid_file() -> Option<PathBuf> {
+    let path = std::env::var_os(PID_FILE_VAR)?;
+    std::env::remove_var(PID_FILE_VAR);
+    Some(path.into())
+}
+
+/// Forks the bot into the background, exiting the original process.
+///
+/// The PID of the daemon is written to `<bot name>.pid` in the root path, and its standard
+/// output and error are appended to `logs/<bot name>.daemon.log`. Standard input is closed, so
+/// the terminal runs without the line editor. Returns the path of the PID file.
+///
+/// This must be called before any threads are started, as only the calling thread survives.
+#[cfg(unix)]
+pub(in super) fn daemonize(info: &BotInfo) -> Result<PathBuf> {
+    use daemonize::Daemonize;
+    use std::fs::{self, OpenOptions};
+
+    let log_dir = info.root_path.join("logs");
+    fs::create_dir_all(&log_dir)?;
+    let output = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_dir.join(format!("{}.daemon.log", info.bot_name)))
+        .internal_err(|| "Could not open daemon log file.")?;
+
+    // the working directory is kept so relative paths given on the command line still work.
+    let pid_path = pid_file_path(info);
+    Daemonize::new()
+        .pid_file(&pid_path)
+        .working_directory(std::env::current_dir()?)
+        .stdout(output.try_clone()?)
+        .stderr(output)
+        .start()
+        .internal_err(|| "Could not start the bot in the background.")?;
+    Ok(pid_path)
+}
+
+#[cfg(not(unix))]
+pub(in super) fn daemonize(_: &BotInfo) -> Result<PathBuf> {
+    cmd_error!("--daemon is only supported on Unix systems.")
+}
@@ -1,8 +1,19 @@
 use crate::core::{ShutdownStartedEvent, SylphieCoreHandlerExt};
-use crate::interface::{TerminalCommandEvent, Interface, SetupLoggerEvent};
+use crate::interface::{TerminalCommandEvent, TerminalCompleteEvent, Interface, SetupLoggerEvent};
 use crate::module::{Module, ModuleManager};
+use crate::stats::CoreStats;
+use crate::tasks::TaskManager;
 use static_events::prelude_async::*;
 use std::marker::PhantomData;
+use tracing::level_filters::LevelFilter;
+
+/// The built-in commands offered by tab completion. `.abort!!` is left out so it is never
+/// completed by accident.
+const COMPLETED_BUILTINS: &[&str] = &[
+    ".help", ".info", ".health", ".stats", ".tasks", ".loglevel", ".source", ".shutdown",
+    ".restart",
+];
+const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "reset"];
 
 #[derive(Events)]
 pub struct SylphieEventsImpl<R: Module>(pub PhantomData<R>);
@@ -10,7 +21,7 @@ pub struct SylphieEventsImpl<R: Module>(pub PhantomData<R>);
 #[events_impl]
 impl <R: Module> SylphieEventsImpl<R> {
     #[event_handler(EvBeforeEvent)]
-    fn builtin_commands(
+    async fn builtin_commands(
         &self, target: &Handler<impl Events>, command: &TerminalCommandEvent,
     ) -> EventResult {
         match command.0.to_ascii_lowercase().as_str().trim() {
@@ -18,8 +29,25 @@ impl <R: Module> SylphieEventsImpl<R> {
                 info!(target: "[term]", "Built-in commands:");
                 info!(target: "[term]", ".help - Shows this help message.");
                 info!(target: "[term]", ".info - Prints information about the bot.");
+                info!(target: "[term]", ".health - Checks the health of all modules.");
+                info!(target: "[term]", ".stats - Shows the uptime and resource usage of the bot.");
+                info!(target: "[term]", ".tasks - Lists running background tasks.");
+                info!(
+                    target: "[term]",
+                    ".loglevel [<target> <level|reset>] - Changes which log messages are shown.",
+                );
+                info!(
+                    target: "[term]",
+                    ".source <file> - Runs each line of a file as a terminal command.",
+                );
                 info!(target: "[term]", ".shutdown - Shuts down the bot.");
+                info!(target: "[term]", ".restart - Shuts down the bot, then starts it again.");
                 info!(target: "[term]", ".abort!! - Forcefully shuts down the bot.");
+                info!(
+                    target: "[term]",
+                    "Press Tab to complete commands and Ctrl-R to search previous input. End a \
+                     line with '\\' to continue it on the next line.",
+                );
             }
             ".info" => {
                 info!(target: "[term]", "Loaded modules:");
@@ -30,7 +58,65 @@ impl <R: Module> SylphieEventsImpl<R> {
                     info!(target: "[term]", "{}", info_line);
                 }
             }
+            ".health" => {
+                let health = crate::health::check_health(target).await;
+                info!(target: "[term]", "Bot health: {}", health.status);
+                for module in &health.modules {
+                    match &module.report.message {
+                        Some(message) => info!(
+                            target: "[term]", "    {}: {} ({})",
+                            module.module.name(), module.report.status, message,
+                        ),
+                        None => info!(
+                            target: "[term]", "    {}: {}",
+                            module.module.name(), module.report.status,
+                        ),
+                    }
+                }
+            }
+            ".stats" => {
+                for line in target.get_service::<CoreStats>().summary() {
+                    info!(target: "[term]", "{}", line);
+                }
+            }
+            ".tasks" => {
+                let tasks = target.get_service::<TaskManager>().list_tasks();
+                info!(target: "[term]", "Running tasks: {}", tasks.len());
+                for task in tasks {
+                    info!(
+                        target: "[term]", "    {}/{} (running for {}s)",
+                        task.module.name(), task.name, task.running_for.as_secs(),
+                    );
+                }
+            }
+            x if x == ".loglevel" || x.starts_with(".loglevel ") => {
+                let args: Vec<&str> = command.0.split_whitespace().skip(1).collect();
+                let interface = target.get_service::<Interface>();
+                let result = match args.as_slice() {
+                    [] => {
+                        let log_levels = interface.log_levels();
+                        if log_levels.is_empty() {
+                            info!(target: "[term]", "No log levels have been overridden.");
+                        }
+                        for (log_target, level) in log_levels {
+                            info!(target: "[term]", "    {} = {}", log_target, level);
+                        }
+                        Ok(())
+                    }
+                    [log_target, "reset"] => interface.set_log_level(target, log_target, None),
+                    [log_target, level] =>
+                        interface.set_log_level(target, log_target, Some(*level)),
+                    _ => {
+                        error!(target: "[term]", "Usage: .loglevel [<target> <level|reset>]");
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    error!(target: "[term]", "{}", e);
+                }
+            }
             ".shutdown" => target.shutdown_bot(),
+            ".restart" => target.restart_bot(),
             ".abort!!" => {
                 eprintln!("(abort)");
                 ::std::process::abort()
@@ -52,6 +138,23 @@ impl <R: Module> SylphieEventsImpl<R> {
         EvCancel
     }
 
+    #[event_handler]
+    fn complete_builtin_commands(ev: &mut TerminalCompleteEvent) {
+        let (is_command, is_log_level) = {
+            let words = ev.previous_words();
+            (words.is_empty(), words.len() == 2 && words[0].eq_ignore_ascii_case(".loglevel"))
+        };
+        if is_command {
+            for name in COMPLETED_BUILTINS {
+                ev.add_completion(*name);
+            }
+        } else if is_log_level {
+            for level in LOG_LEVELS {
+                ev.add_completion(*level);
+            }
+        }
+    }
+
     #[event_handler]
     fn shutdown_handler(&self, target: &Handler<impl Events>, _: &ShutdownStartedEvent) {
         target.get_service::<Interface>().shutdown();
@@ -59,6 +162,6 @@ impl <R: Module> SylphieEventsImpl<R> {
 
     #[event_handler]
     fn setup_logger(ev: &mut SetupLoggerEvent) {
-        ev.add_console_directive("sylphie_core=debug");
+        ev.set_crate_level("sylphie_core", LevelFilter::DEBUG);
     }
 }
\ No newline at end of file
@@ -0,0 +1,135 @@
+use crate::errors::*;
+use crate::module::ModuleInfo;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use futures::stream::FuturesUnordered;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+
+struct InitTask {
+    name: Arc<str>,
+    priority: i32,
+    deps: Vec<Arc<str>>,
+    future: BoxFuture<'static, Result<()>>,
+}
+
+/// Dispatched after [`EarlyInitEvent`](`crate::core::EarlyInitEvent`) to collect module
+/// initialization tasks, such as database migrations. This is the migrations phase of startup.
+///
+/// The tasks registered by this event are run concurrently on the tokio runtime, with each task
+/// waiting only on the tasks it declares as dependencies, and on the tasks of any module with a
+/// higher [`init_priority`](`crate::module::ModuleMetadata::init_priority`). All tasks finish
+/// before [`InitEvent`](`crate::core::InitEvent`) is dispatched.
+///
+/// This event is dispatched synchronously.
+pub struct RegisterInitTasksEvent {
+    tasks: Vec<InitTask>,
+}
+self_event!(RegisterInitTasksEvent);
+impl RegisterInitTasksEvent {
+    pub(crate) fn new() -> Self {
+        RegisterInitTasksEvent { tasks: Vec::new() }
+    }
+
+    /// Registers a new initialization task.
+    ///
+    /// The task's full name is `module_name:name`. Dependencies may either be the name of
+    /// another task in the same module, or the full name of a task in another module.
+    pub fn add_task(
+        &mut self,
+        module: &ModuleInfo,
+        name: &str,
+        deps: &[&str],
+        task: impl Future<Output = Result<()>> + Send + 'static,
+    ) {
+        let module_name = module.name();
+        let full_name = |name: &str| -> Arc<str> {
+            if name.contains(':') {
+                name.into()
+            } else {
+                format!("{}:{}", module_name, name).into()
+            }
+        };
+        self.tasks.push(InitTask {
+            name: full_name(name),
+            priority: module.metadata().init_priority,
+            deps: deps.iter().map(|x| full_name(x)).collect(),
+            future: task.boxed(),
+        });
+    }
+}
+
+fn check_tasks(tasks: &[InitTask]) -> Result<()> {
+    let mut names = HashSet::new();
+    for task in tasks {
+        ensure!(names.insert(task.name.clone()), "Duplicate init task '{}'.", task.name);
+    }
+    for task in tasks {
+        for dep in &task.deps {
+            ensure!(
+                names.contains(dep),
+                "Init task '{}' depends on '{}', which does not exist.", task.name, dep,
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn run_init_tasks(ev: RegisterInitTasksEvent) -> Result<()> {
+    let tasks = ev.tasks;
+    check_tasks(&tasks)?;
+
+    let mut waiting: HashMap<Arc<str>, InitTask> =
+        tasks.into_iter().map(|x| (x.name.clone(), x)).collect();
+    let mut finished = HashSet::new();
+    let mut running_priority = HashMap::new();
+    let mut running = FuturesUnordered::new();
+    loop {
+        // tasks can only start once every task with a higher priority is done
+        let current_priority = waiting.values().map(|x| x.priority)
+            .chain(running_priority.values().cloned())
+            .max();
+        let ready: Vec<_> = waiting.values()
+            .filter(|x| Some(x.priority) == current_priority)
+            .filter(|x| x.deps.iter().all(|dep| finished.contains(dep)))
+            .map(|x| x.name.clone())
+            .collect();
+        for name in ready {
+            let task = waiting.remove(&name).unwrap();
+            trace!("Starting init task {}", task.name);
+            running_priority.insert(name.clone(), task.priority);
+            let handle = tokio::spawn(task.future);
+            running.push(async move { (name, handle.await) });
+        }
+
+        match running.next().await {
+            Some((name, result)) => {
+                let result = match result {
+                    Ok(result) => result.internal_err(|| format!("Init task '{}' failed.", name)),
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    // tokio cannot cancel spawned tasks, so the other running tasks are waited on
+                    // instead of being left to run while the bot shuts down
+                    while running.next().await.is_some() { }
+                    return Err(e)
+                }
+                trace!("Finished init task {}", name);
+                running_priority.remove(&name);
+                finished.insert(name);
+            }
+            None => break,
+        }
+    }
+
+    if !waiting.is_empty() {
+        let mut names: Vec<_> = waiting.keys().map(|x| x.to_string()).collect();
+        names.sort();
+        bail!(
+            "Init tasks have circular dependencies, or depend on tasks with a lower priority: {}",
+            names.join(", "),
+        );
+    }
+    Ok(())
+}
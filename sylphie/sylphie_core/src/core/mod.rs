@@ -1,86 +1,154 @@
+use crate::assets::{Assets, RegisterAssetsEvent};
+use crate::cli::{CliArgs, USAGE};
+use crate::config::{Config, CONFIG_FILE_NAME, LogConfig};
 use crate::errors::*;
-use crate::global_instance::*;
 use crate::interface::*;
-use crate::module::{Module, ModuleManager};
+use crate::metrics::MetricsRegistry;
+use crate::module::{Module, ModuleInfo, ModuleManager};
+use crate::services::Services;
+use crate::stats::CoreStats;
+use crate::tasks::TaskManager;
+use enumset::*;
 use fs2::*;
-use lazy_static::*;
 use static_events::prelude_async::*;
 use std::env;
 use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
+use tokio::runtime::{Handle, Runtime};
 
+mod daemon;
 mod events;
+mod init_tasks;
+mod paths;
+mod signals;
+pub mod supervisor;
 
-fn check_lock(path: impl AsRef<Path>) -> Result<File> {
-    let mut options = OpenOptions::new();
-    options.create(true).read(true).write(true);
-    let lock_file = options.open(path)
-        .internal_err(|| "Could not open lock file")?;
-    lock_file.try_lock_exclusive()
-        .internal_err(|| "Could not acquire exclusive lock on database.")?;
-    Ok(lock_file)
-}
-fn get_exe_dir() -> PathBuf {
-    let mut path = env::current_exe().expect("cannot get current exe path");
-    path.pop();
-    path
-}
-fn get_dir_from_cargo(path: PathBuf) -> Option<PathBuf> {
-    // Check for other cargo-related env vars to be safe.
-    if env::var_os("CARGO").is_none() ||
-        env::var_os("CARGO_PKG_NAME").is_none() ||
-        env::var_os("CARGO_PKG_VERSION").is_none()
-    {
-        return None
-    }
-
-    // Check for a Cargo.toml
-    let mut cur_path = path.clone();
-    cur_path.push("Cargo.toml");
-    if !(cur_path.exists() || cur_path.is_file()) {
-        return None
-    }
-    cur_path.pop();
-    cur_path.push(".git");
-    if cur_path.exists() && cur_path.is_dir() {
-        // We found a .git directory. Assume there is no workspace setup.
-        return None
-    }
-
-    // Check for the most typical workspace setup.
-    cur_path.pop();
-    cur_path.pop();
-    cur_path.push("Cargo.toml");
-    if cur_path.exists() && cur_path.is_file() {
-        cur_path.pop();
-        Some(cur_path)
-    } else {
-        Some(path)
-    }
-}
-fn get_root_path() -> PathBuf {
-    env::var_os("CARGO_MANIFEST_DIR")
-        .and_then(|x| get_dir_from_cargo(PathBuf::from(x)))
-        .unwrap_or_else(|| get_exe_dir())
-}
-
-/// Dispatched when the bot is started, before [`InitEvent`].
+pub use init_tasks::RegisterInitTasksEvent;
+
+use paths::{BotDirs, DirOverrides};
+
+/// An exclusive lock on the bot's root path, held for as long as the bot runs.
+///
+/// The lock file contains the PID of the process holding it, so that a second copy of the bot
+/// can say which process it is waiting on.
+struct InstanceLock(File);
+impl InstanceLock {
+    fn acquire(path: &Path) -> Result<InstanceLock> {
+        let mut options = OpenOptions::new();
+        options.create(true).read(true).write(true);
+        let mut lock_file = options.open(path)
+            .internal_err(|| format!("Could not open lock file '{}'.", path.display()))?;
+        if lock_file.try_lock_exclusive().is_err() {
+            // the holder's PID can't be read on platforms with mandatory locking.
+            let mut contents = String::new();
+            let _ = lock_file.read_to_string(&mut contents);
+            match contents.trim().parse::<u32>() {
+                Ok(pid) => cmd_error!(
+                    "Another copy of the bot is already running in '{}' (PID {}).",
+                    path.parent().unwrap_or(path).display(), pid,
+                ),
+                Err(_) => cmd_error!(
+                    "Another copy of the bot is already running in '{}'.",
+                    path.parent().unwrap_or(path).display(),
+                ),
+            }
+        }
+        lock_file.set_len(0)?;
+        write!(lock_file, "{}", std::process::id())?;
+        lock_file.sync_all()?;
+        Ok(InstanceLock(lock_file))
+    }
+}
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // the lock itself is released when the file is closed.
+        let _ = self.0.set_len(0);
+    }
+}
+fn usage() -> String {
+    let exe = env::args().next().unwrap_or_else(|| "bot".to_string());
+    format!("Usage: {} [options] [-- <bot arguments>]\n\n{}", exe, USAGE)
+}
+
+/// Dispatched when the bot is started, before anything else has been set up. This is the
+/// first phase of startup.
+///
+/// Handlers should only check settings and open resources such as the database here. The
+/// next phase is [`MigrationsEvent`], which runs database migrations and other tasks.
 ///
 /// This event is dispatched synchronously.
 pub struct EarlyInitEvent(());
 failable_event!(EarlyInitEvent, (), Error);
 
-/// Dispatched when the bot is started, before user interface is initialized.
+/// Dispatched in the migrations phase, after [`EarlyInitEvent`], once the tasks registered
+/// with [`RegisterInitTasksEvent`] have finished.
+///
+/// Modules should bring stored data up to date here, such as by migrating data that the
+/// database's migration scripts cannot. Nothing else has been set up yet. The bot exits after
+/// this phase if it was started with `--migrate-only`.
+pub struct MigrationsEvent(());
+failable_event!(MigrationsEvent, (), Error);
+
+/// Dispatched once the migrations phase has finished, after [`MigrationsEvent`].
+///
+/// Modules should set themselves up here, such as by registering settings and commands. No
+/// connections to chat services have been made yet, so they see everything set up in this
+/// phase once they are made in [`ConnectEvent`].
 pub struct InitEvent(());
 failable_event!(InitEvent, (), Error);
 
+/// Dispatched after [`InitEvent`], once the core's background tasks are running.
+///
+/// Connectors and other modules that talk to outside services should connect to them here.
+pub struct ConnectEvent(());
+failable_event!(ConnectEvent, (), Error);
+
+/// Dispatched after [`ConnectEvent`], once the bot has fully started. This is the last phase of
+/// startup, after which commands are read from the terminal.
+pub struct ReadyEvent(());
+simple_event!(ReadyEvent);
+
+/// How long the bot waits for [`ShutdownEvent`] to finish before stopping anyway.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Dispatched after shutdown is initialized, and after the user interface is killed.
+///
+/// This is dispatched asynchronously while background tasks are still running, so handlers can
+/// flush caches, wait for in-flight jobs, and close connections before the runtime stops. No new
+/// tasks can be spawned at this point, and the remaining tasks are aborted once every handler
+/// has finished. Handlers in the `EvAfterEvent` phase run after the others, for work that relies
+/// on them having finished.
+///
+/// If this takes longer than [`SHUTDOWN_TIMEOUT`], the bot stops without waiting for the
+/// remaining handlers.
 pub struct ShutdownEvent(());
 simple_event!(ShutdownEvent);
 
+/// Dispatched when the bot is asked to reload its settings, such as by `SIGHUP` or the `reload`
+/// command.
+///
+/// The configuration file and logger have already been reloaded when this is dispatched, and
+/// [`ConfigReloadedEvent`](`crate::config::ConfigReloadedEvent`) has been dispatched. Modules
+/// that keep state derived from their settings in the database should rebuild it here.
+pub struct ReloadEvent(());
+simple_event!(ReloadEvent);
+
+/// Reloads the bot's settings, as is done for `SIGHUP`.
+///
+/// This reloads the configuration file and the logger, then dispatches [`ReloadEvent`]. If the
+/// configuration file is invalid, an error is returned and nothing else is reloaded.
+pub async fn reload_bot(target: &Handler<impl Events>) -> Result<()> {
+    crate::config::reload_config(target).await?;
+    target.dispatch_async(ReloadEvent(())).await;
+    Ok(())
+}
+
 struct ShutdownStartedEvent;
 simple_event!(ShutdownStartedEvent);
 
@@ -92,19 +160,66 @@ pub struct SylphieEvents<R: Module> {
     #[service] module_manager: ModuleManager,
     #[service] interface: Interface,
     #[service] bot_info: BotInfo,
+    #[service] cli_args: CliArgs,
+    #[service] config: Config,
+    #[service] services: Services,
+    #[service] tasks: TaskManager,
+    #[service] assets: Assets,
+    #[service] metrics: MetricsRegistry,
+    #[service] stats: CoreStats,
+}
+
+/// The number of bot cores running in this process.
+static RUNNING_INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether bot cores other than the current one are running in this process.
+fn is_process_shared() -> bool {
+    RUNNING_INSTANCES.load(Ordering::SeqCst) > 1
 }
 
-lazy_static! {
-    static ref SYLPHIE_RUNNING_GUARD: GlobalInstance<()> = GlobalInstance::new();
+/// Counts a bot core as running until it is dropped.
+struct RunningGuard;
+impl RunningGuard {
+    fn new() -> RunningGuard {
+        RUNNING_INSTANCES.fetch_add(1, Ordering::SeqCst);
+        RunningGuard
+    }
+}
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        RUNNING_INSTANCES.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// Stores information related to the bot.
 ///
 /// This can be retrieved using `get_service`.
+///
+/// The bot's files are kept in three directories: the configuration directory, the data
+/// directory, which is the root path, and the cache directory.
+///
+/// When the bot is run with `cargo run`, or a `run` directory exists next to its executable,
+/// everything is kept together in that directory, as is convenient while developing a bot or
+/// when it is deployed as a single folder. Otherwise, the platform's usual directories are
+/// used, in a subdirectory named after the bot:
+///
+/// * On Linux, the XDG base directories, such as `~/.config/<bot>` for the configuration,
+///   `~/.local/share/<bot>` for data and `~/.cache/<bot>` for the cache.
+/// * On macOS, `~/Library/Application Support/<bot>` for the configuration and data, and
+///   `~/Library/Caches/<bot>` for the cache.
+/// * On Windows, `%APPDATA%\<bot>` for the configuration and data, and `%LOCALAPPDATA%\<bot>`
+///   for the cache.
+///
+/// Each directory can be overridden with `--data-dir`, `--config-dir` and `--cache-dir`, or
+/// with the matching methods on [`SylphieCore`](`crate::SylphieCore`). A data directory given
+/// this way also holds the configuration, and the cache in its `cache` subdirectory, unless those
+/// are overridden too.
 #[derive(Clone)]
 pub struct BotInfo {
     bot_name: String,
     root_path: PathBuf,
+    config_dir: PathBuf,
+    cache_dir: PathBuf,
 }
 impl BotInfo {
     /// Returns the name of the bot.
@@ -112,35 +227,269 @@ impl BotInfo {
         &self.bot_name
     }
 
-    /// Returns the path where the bot's state is stored.
+    /// Returns the path where the bot's data is stored, such as its database and logs.
     pub fn root_path(&self) -> &Path {
         &self.root_path
     }
+
+    /// Returns the directory the configuration file is found in by default.
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    /// Returns the directory for files that can be deleted without losing anything, such as
+    /// downloaded assets. This is not created until it is used.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+}
+
+/// How the tokio runtime the bot runs on is created.
+#[derive(Default)]
+struct RuntimeOptions {
+    worker_threads: Option<usize>,
+    thread_name: Option<String>,
+    blocking_threads: Option<usize>,
+    handle: Option<Handle>,
+}
+impl RuntimeOptions {
+    /// Builds a runtime whose threads all use the given interface.
+    fn build(&self, interface: &Interface) -> Result<Runtime> {
+        let mut builder = tokio::runtime::Builder::new();
+        builder.threaded_scheduler().enable_all();
+        let worker_threads = self.worker_threads.unwrap_or_else(num_cpus::get);
+        ensure!(worker_threads > 0, "The bot's runtime must have at least one worker thread.");
+        builder.core_threads(worker_threads);
+        if let Some(blocking_threads) = self.blocking_threads {
+            builder.max_threads(worker_threads + blocking_threads);
+        }
+        if let Some(thread_name) = &self.thread_name {
+            builder.thread_name(thread_name.clone());
+        }
+        let interface = interface.clone();
+        builder.on_thread_start(move || std::mem::forget(interface.enter_thread()));
+        Ok(builder.build()?)
+    }
 }
 
+/// A part of the core that can be turned off with [`SylphieCore::without`].
+#[derive(EnumSetType, Debug)]
+#[non_exhaustive]
+pub enum Subsystem {
+    /// Reading commands from the terminal. Log messages are still written to standard output.
+    Terminal,
+    /// Handling `SIGTERM`, `SIGINT` and `SIGHUP`, for bots embedded in a program that handles
+    /// signals itself.
+    Signals,
+    /// Collecting metrics over time, and serving them at `metrics.listen`.
+    Metrics,
+    /// Serving health checks at `health.listen`.
+    HealthServer,
+    /// The [`watchdog`](`crate::watchdog`).
+    Watchdog,
+    /// Reloading the configuration file when it changes on disk. It is still reloaded when the
+    /// bot is asked to reload.
+    ConfigWatcher,
+}
+
+type EarlyInitHook<R> = Box<dyn FnOnce(&Handler<SylphieEvents<R>>) -> Result<()> + Send>;
+
+/// A bot, which is set up by chaining the `with_*` methods and then run with
+/// [`SylphieCore::start`].
+///
+/// ```rust,ignore
+/// SylphieCore::<MyBot>::new("my_bot")
+///     .with_root_path("/var/lib/my_bot")
+///     .with_log_level("debug")
+///     .without(Subsystem::Terminal)
+///     .with_in_memory_database()
+///     .start()?;
+/// ```
+///
+/// Other crates add further options through extension traits, such as the database backend
+/// with `SylphieCoreDatabaseExt` in `sylphie_database`. Paths and log settings given here are
+/// defaults, which the configuration file and command line arguments take precedence over.
 pub struct SylphieCore<R: Module> {
     info: BotInfo,
+    args: CliArgs,
+    dirs: DirOverrides,
+    config_path: Option<PathBuf>,
+    log_defaults: LogConfig,
+    disabled: EnumSet<Subsystem>,
+    early_init: Vec<EarlyInitHook<R>>,
+    services: Services,
+    runtime: RuntimeOptions,
+    #[cfg(feature = "error_reporter")]
+    error_reporter: Option<Box<dyn ErrorReporter>>,
     phantom: PhantomData<R>,
 }
 impl <R: Module> SylphieCore<R> {
+    /// Creates a new bot core, parsing the command line arguments the bot was started with.
+    ///
+    /// The arguments accepted are described in [`cli`](`crate::cli`). If they are invalid, or
+    /// `--help` is given, this prints how to use the bot and exits the process.
     pub fn new(bot_name: impl Into<String>) -> Self {
-        let mut root_path = get_root_path();
-        root_path.push("run");
+        let args = match CliArgs::from_env() {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, usage());
+                std::process::exit(2);
+            }
+        };
+        if args.help {
+            println!("{}", usage());
+            std::process::exit(0);
+        }
+        Self::with_args(bot_name, args)
+    }
+
+    /// Creates a new bot core with the given arguments, rather than the ones the process was
+    /// started with.
+    ///
+    /// This is mainly useful for running several bots in the same process, each of which needs
+    /// its own `data_dir`. `help` is ignored.
+    pub fn with_args(bot_name: impl Into<String>, args: CliArgs) -> Self {
         SylphieCore {
+            // the directories are found when the bot starts, once all overrides are known.
             info: BotInfo {
                 bot_name: bot_name.into(),
-                root_path,
+                root_path: PathBuf::new(),
+                config_dir: PathBuf::new(),
+                cache_dir: PathBuf::new(),
             },
+            args,
+            dirs: DirOverrides::default(),
+            config_path: None,
+            log_defaults: LogConfig::default(),
+            disabled: EnumSet::new(),
+            early_init: Vec::new(),
+            services: Services::default(),
+            runtime: RuntimeOptions::default(),
+            #[cfg(feature = "error_reporter")]
+            error_reporter: None,
             phantom: PhantomData,
         }
     }
-    fn lock(&mut self) -> Result<File> {
+
+    /// Sets the directory the bot's data is stored in, as described in [`BotInfo`]. This also
+    /// holds the configuration and cache unless they are set separately. `--data-dir` takes
+    /// precedence over this.
+    pub fn with_root_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dirs.data = Some(path.into());
+        self
+    }
+
+    /// Sets the directory the configuration file is found in. `--config-dir` takes precedence
+    /// over this.
+    pub fn with_config_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dirs.config = Some(path.into());
+        self
+    }
+
+    /// Sets the directory cached files are stored in. `--cache-dir` takes precedence over this.
+    pub fn with_cache_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dirs.cache = Some(path.into());
+        self
+    }
+
+    /// Sets the configuration file, instead of `config.toml` in the configuration directory.
+    /// Relative paths are resolved against that directory, and `--config` takes precedence over
+    /// this.
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Sets the default level of log messages shown for crates containing loaded modules, such
+    /// as `debug`.
+    pub fn with_log_level(mut self, level: impl Into<String>) -> Self {
+        self.log_defaults.level = Some(level.into());
+        self
+    }
+
+    /// Adds a default `env_logger` style filtering directive, such as `sylphie_core=trace`.
+    pub fn with_log_directive(mut self, directive: impl Into<String>) -> Self {
+        self.log_defaults.directives.push(directive.into());
+        self
+    }
+
+    /// Turns off a part of the core that the bot has no use for, or that the program it is
+    /// embedded in provides itself.
+    pub fn without(mut self, subsystem: Subsystem) -> Self {
+        self.disabled.insert(subsystem);
+        self
+    }
+
+    /// Adds a function that is called with the bot's event handler once [`EarlyInitEvent`] has
+    /// been dispatched, before any init tasks are run.
+    ///
+    /// This allows the bot to set up state that does not belong to any module, such as by
+    /// checking settings or publishing services that depend on the configuration file. Errors
+    /// stop the bot from starting, in the same way as in [`EarlyInitEvent`] handlers.
+    pub fn with_early_init(
+        mut self, hook: impl FnOnce(&Handler<SylphieEvents<R>>) -> Result<()> + Send + 'static,
+    ) -> Self {
+        self.early_init.push(Box::new(hook));
+        self
+    }
+
+    /// Publishes a service before the bot starts.
+    ///
+    /// This allows modules to be configured with implementations chosen by the bot itself, and
+    /// overrides any service of the same type already published this way.
+    pub fn with_service<T: ?Sized + Send + Sync + 'static>(self, service: Arc<T>) -> Self {
+        self.services.publish_override(&self.info.bot_name, service);
+        self
+    }
+
+    /// Sets a hook that forwards reported errors and panics to an external service.
+    ///
+    /// This replaces any reporter that was already set.
+    #[cfg(feature = "error_reporter")]
+    pub fn with_error_reporter(mut self, reporter: impl ErrorReporter) -> Self {
+        self.error_reporter = Some(Box::new(reporter));
+        self
+    }
+
+    /// Sets how many threads the bot's runtime uses to run tasks. This must be at least 1, and
+    /// defaults to the number of CPU cores. The bot fails to start if this is 0.
+    pub fn with_worker_threads(mut self, count: usize) -> Self {
+        self.runtime.worker_threads = Some(count);
+        self
+    }
+
+    /// Sets the name of the threads started by the bot's runtime.
+    pub fn with_thread_name(mut self, name: impl Into<String>) -> Self {
+        self.runtime.thread_name = Some(name.into());
+        self
+    }
+
+    /// Sets how many threads the bot's runtime may start in addition to its worker threads, to
+    /// run blocking operations such as database queries.
+    pub fn with_blocking_threads(mut self, count: usize) -> Self {
+        self.runtime.blocking_threads = Some(count);
+        self
+    }
+
+    /// Runs the bot on an existing runtime, rather than creating one. The other runtime options
+    /// are ignored if this is set.
+    ///
+    /// The runtime must use the threaded scheduler, and the bot must not be started from one
+    /// of its threads. Tasks spawned through [`TaskManager`] write log messages to the bot's
+    /// logger, but anything else running on the runtime's threads uses the logger of the
+    /// runtime's owner.
+    pub fn with_runtime(mut self, handle: Handle) -> Self {
+        self.runtime.handle = Some(handle);
+        self
+    }
+
+    fn lock(&mut self) -> Result<InstanceLock> {
         let mut lock_path = self.info.root_path.clone();
         if !lock_path.is_dir() {
             fs::create_dir_all(&lock_path)?;
         }
         lock_path.push(".lock");
-        check_lock(lock_path)
+        InstanceLock::acquire(&lock_path)
     }
 
     /// Starts the bot core, blocking the main thread until the bot returns.
@@ -151,38 +500,117 @@ impl <R: Module> SylphieCore<R> {
     ///
     /// This sets the panic hook to allow for better error reporting.
     ///
-    /// # Panics
+    /// Before anything else is started, the configuration file is loaded from the bot's
+    /// configuration directory, or the path given with `--config`, as described in
+    /// [`config`](`crate::config`).
+    ///
+    /// If the bot was started with `--daemon`, it then forks into the background and the original
+    /// process exits. The daemon writes its PID to `<bot name>.pid` in the root path, its output
+    /// to `logs/<bot name>.daemon.log`, and runs without an interactive terminal.
+    ///
+    /// Only one copy of a bot may use a root path at a time, as they would share a database.
+    /// This is checked with a `.lock` file in the root path, which holds the PID of the running
+    /// copy, and starting another copy fails with an error naming that PID.
+    ///
+    /// Once the bot's modules have been loaded, it starts up in phases, each of which has an
+    /// event dispatched for it: [`EarlyInitEvent`], [`MigrationsEvent`] after the tasks
+    /// registered with [`RegisterInitTasksEvent`] have run, [`InitEvent`], [`ConnectEvent`] and
+    /// finally [`ReadyEvent`]. Each phase starts only once every handler of the previous one
+    /// has finished.
     ///
-    /// Only one bot core may be started at one time. Any cores started while another core is
-    /// running
+    /// If the bot was started with `--supervise`, this process only restarts the bot when it
+    /// crashes, and the bot runs in a child process, as described in
+    /// [`supervisor`](`crate::core::supervisor`).
+    ///
+    /// Several bot cores may run in the same process at once, each started on its own thread
+    /// with its own root path, such as one set with [`SylphieCore::with_root_path`]. Each
+    /// core writes log messages and error reports for its own threads to its own log files, and
+    /// the first core started reads commands from the terminal. Cores that share a process can
+    /// not be started with `--daemon`, and restarting one of them only shuts it down, as the
+    /// whole process would be replaced.
     pub fn start(mut self) -> Result<()> {
-        // acquire the per-process lock
-        let _guard = SYLPHIE_RUNNING_GUARD.set_instance(());
+        let _running = RunningGuard::new();
+
+        // find the directories the bot uses
+        let dirs = BotDirs::resolve(&self.info.bot_name, &self.args, &self.dirs);
+        self.info.root_path = dirs.data;
+        self.info.config_dir = dirs.config;
+        self.info.cache_dir = dirs.cache;
+
+        // load the configuration file
+        let config_path = match (&self.args.config, &self.config_path) {
+            (Some(path), _) => path.clone(),
+            (None, Some(path)) => self.info.config_dir.join(path),
+            (None, None) => self.info.config_dir.join(CONFIG_FILE_NAME),
+        };
+        let config = Config::load(config_path)?;
+        if let Some(bot_name) = &config.core().bot_name {
+            self.info.bot_name = bot_name.clone();
+        }
+
+        // fork into the background, before any threads are started
+        let pid_file = if self.args.daemon {
+            if is_process_shared() {
+                cmd_error!("--daemon cannot be used while other bots run in the same process.");
+            }
+            if !self.info.root_path.is_dir() {
+                fs::create_dir_all(&self.info.root_path)?;
+            }
+            Some(daemon::daemonize(&self.info)?)
+        } else {
+            daemon::inherited_pid_file()
+        };
 
         // initialize early logging and related processes
         early_init();
 
-        // acquire the database lock
+        // run the bot in child processes instead, if asked to restart it when it crashes
+        if self.args.supervise {
+            if is_process_shared() {
+                cmd_error!("--supervise cannot be used while other bots run in the same process.");
+            }
+            let result = supervisor::supervise(&config, &self.args);
+            if let Some(pid_file) = pid_file {
+                if let Err(e) = fs::remove_file(&pid_file) {
+                    warn!("Could not remove PID file '{}': {}", pid_file.display(), e);
+                }
+            }
+            return result
+        }
+
+        // make sure no other copy of the bot is using the same root path
         let _lock = self.lock()?;
 
-        // initializes the tokio runtime
-        let runtime = tokio::runtime::Builder::new()
-            .threaded_scheduler()
-            .enable_all()
-            .build()?;
-        runtime.enter(move || -> Result<()> {
-            let runtime = tokio::runtime::Handle::current();
-
-            // initialize the interface system
-            let interface_info = InterfaceInfo {
-                bot_name: self.info.bot_name.clone(),
-                root_path: self.info.root_path.clone(),
-            };
-            let interface = Interface::new(interface_info)
-                .internal_err(|| "Could not initialize user interface.")?;
+        // initialize the interface system, and use it on every thread this core runs on
+        let interface_info = InterfaceInfo {
+            bot_name: self.info.bot_name.clone(),
+            root_path: self.info.root_path.clone(),
+            terminal: !self.disabled.contains(Subsystem::Terminal),
+            log_defaults: self.log_defaults.clone(),
+        };
+        let interface = Interface::new(interface_info)
+            .internal_err(|| "Could not initialize user interface.")?;
+        #[cfg(feature = "error_reporter")]
+        if let Some(reporter) = self.error_reporter.take() {
+            interface.set_error_reporter(reporter);
+        }
+        let _thread_guard = interface.enter_thread();
+
+        // initializes the tokio runtime, unless one was given
+        let (runtime, handle) = match self.runtime.handle.take() {
+            Some(handle) => (None, handle),
+            None => {
+                let runtime = self.runtime.build(&interface)?;
+                let handle = runtime.handle().clone();
+                (Some(runtime), handle)
+            }
+        };
+        let restart = handle.enter(move || -> Result<Option<CliArgs>> {
+            let runtime = Handle::current();
 
             // initialize the module tree and events dispatch
             let (module_manager, root_module) = ModuleManager::init::<R>();
+            let root_info = root_module.info().clone();
             interface.set_loaded_crates(module_manager.loaded_crates_list());
             let handler = Handler::new(SylphieEvents {
                 root_module,
@@ -190,13 +618,32 @@ impl <R: Module> SylphieCore<R> {
                 module_manager,
                 interface: interface.clone(),
                 bot_info: self.info.clone(),
+                cli_args: self.args.clone(),
+                config,
+                services: self.services,
+                tasks: TaskManager::default(),
+                assets: Assets::default(),
+                metrics: MetricsRegistry::default(),
+                stats: CoreStats::new(interface.handler_counters().clone()),
             });
 
-            // start the actual bot itself
-            handler.dispatch_sync(EarlyInitEvent(()))?;
-            runtime.block_on(handler.dispatch_async(InitEvent(())))?;
-            interface.start(&handler)?;
-            runtime.block_on(handler.dispatch_async(ShutdownEvent(())));
+            // start the actual bot itself, and shut it down again even if it failed to start
+            let result = run_bot(
+                &handler, &runtime, &root_info, &self.args, self.disabled, self.early_init,
+            );
+
+            // let modules finish their work before background tasks are stopped
+            info!("Shutting down...");
+            let tasks = handler.get_service::<TaskManager>();
+            tasks.stop_spawning();
+            let shutdown = handler.dispatch_async(ShutdownEvent(()));
+            if runtime.block_on(tokio::time::timeout(SHUTDOWN_TIMEOUT, shutdown)).is_err() {
+                warn!(
+                    "Shutdown handlers did not finish within {} seconds. Stopping anyway.",
+                    SHUTDOWN_TIMEOUT.as_secs(),
+                );
+            }
+            tasks.shutdown();
 
             // wait for shutdown
             let mut ct = 0;
@@ -211,13 +658,160 @@ impl <R: Module> SylphieCore<R> {
                 ct += 1;
                 thread::sleep(Duration::from_millis(10));
             }
+            result?;
+
+            if interface.is_restart() && is_process_shared() {
+                warn!("Other bots are running in this process. Not restarting.");
+                Ok(None)
+            } else if interface.is_restart() {
+                // the backup was restored already, and the data directory may have been found
+                // relative to an executable that is being replaced. a daemon is already detached,
+                // and keeps its PID and PID file across the restart.
+                let mut args = self.args.clone();
+                args.restore_backup = None;
+                args.data_dir = Some(self.info.root_path.clone());
+                args.config_dir = Some(self.info.config_dir.clone());
+                args.cache_dir = Some(self.info.cache_dir.clone());
+                args.daemon = false;
+                Ok(Some(args))
+            } else {
+                Ok(None)
+            }
+        });
 
-            Ok(())
-        })?;
+        // release the runtime and database lock before the new process needs them
+        drop(runtime);
+        drop(_lock);
+        if let Some(pid_file) = &pid_file {
+            if !matches!(restart, Ok(Some(_))) {
+                if let Err(e) = fs::remove_file(pid_file) {
+                    warn!("Could not remove PID file '{}': {}", pid_file.display(), e);
+                }
+            }
+        }
+        if let Some(args) = restart? {
+            restart_process(&args, pid_file.as_deref())?;
+        }
         Ok(())
     }
 }
 
+/// Runs the startup phases of the bot, then runs it until it is asked to shut down.
+///
+/// This returns early if the bot only checks its configuration or runs migrations. The caller
+/// shuts the bot down afterwards, whether or not this succeeded.
+fn run_bot<R: Module>(
+    handler: &Handler<SylphieEvents<R>>, runtime: &Handle, root_info: &ModuleInfo,
+    args: &CliArgs, disabled: EnumSet<Subsystem>, early_init: Vec<EarlyInitHook<R>>,
+) -> Result<()> {
+    let assets = handler.dispatch_sync(RegisterAssetsEvent::new())?;
+    handler.get_service::<Assets>().set_assets(assets);
+    debug!("Startup phase: early init");
+    handler.dispatch_sync(EarlyInitEvent(()))?;
+    for hook in early_init {
+        hook(handler)?;
+    }
+    if args.check {
+        info!("The configuration is valid. Exiting, as --check was given.");
+        return Ok(())
+    }
+    debug!("Startup phase: migrations");
+    let init_tasks = handler.dispatch_sync(RegisterInitTasksEvent::new());
+    runtime.block_on(init_tasks::run_init_tasks(init_tasks))?;
+    runtime.block_on(handler.dispatch_async(MigrationsEvent(())))?;
+    if args.migrate_only {
+        info!("Init tasks finished. Exiting, as --migrate-only was given.");
+        return Ok(())
+    }
+    debug!("Startup phase: init");
+    runtime.block_on(handler.dispatch_async(InitEvent(())))?;
+    let is_enabled = |subsystem| !disabled.contains(subsystem);
+    if is_enabled(Subsystem::Metrics) {
+        handler.get_service::<TaskManager>().spawn(
+            root_info, "collect_metrics",
+            crate::metrics::collect_metrics_task(handler.clone()),
+        );
+    }
+    handler.get_service::<TaskManager>().spawn(
+        root_info, "log_alerts", crate::interface::log_alerts_task(handler.clone()),
+    );
+    handler.get_service::<TaskManager>().spawn(
+        root_info, "log_dedup", crate::interface::dedup_task(handler.clone()),
+    );
+    if is_enabled(Subsystem::Signals) {
+        handler.get_service::<TaskManager>().spawn(
+            root_info, "signals", signals::signals_task(handler.clone()),
+        );
+    }
+    if is_enabled(Subsystem::HealthServer) {
+        if let Some(addr) = handler.get_service::<Config>().get("health.listen")? {
+            handler.get_service::<TaskManager>().spawn(
+                root_info, "health_server",
+                crate::health::health_server_task(handler.clone(), addr),
+            );
+        }
+    }
+    #[cfg(feature = "prometheus")]
+    if is_enabled(Subsystem::Metrics) {
+        if let Some(addr) = handler.get_service::<Config>().get("metrics.listen")? {
+            handler.get_service::<TaskManager>().spawn(
+                root_info, "metrics_server",
+                crate::metrics::metrics_server_task(handler.clone(), addr),
+            );
+        }
+    }
+    if is_enabled(Subsystem::Watchdog) {
+        crate::watchdog::start_watchdog(handler, root_info)?;
+    }
+    if is_enabled(Subsystem::ConfigWatcher) {
+        handler.get_service::<TaskManager>().spawn(
+            root_info, "config_watcher",
+            crate::config::watch_config_task(handler.clone()),
+        );
+    }
+    debug!("Startup phase: connect");
+    runtime.block_on(handler.dispatch_async(ConnectEvent(())))?;
+    debug!("Startup phase: ready");
+    runtime.block_on(handler.dispatch_async(ReadyEvent(())));
+    handler.get_service::<Interface>().start(handler)
+}
+
+/// Returns the command used to start a new copy of the bot.
+///
+/// The PID file of a daemon is passed on to the new copy, which removes it when it exits.
+fn restart_command(args: &CliArgs, pid_file: Option<&Path>) -> Result<std::process::Command> {
+    let exe = env::current_exe().internal_err(|| "Could not find the current executable.")?;
+    let mut command = std::process::Command::new(exe);
+    command.args(args.to_args());
+    if let Some(pid_file) = pid_file {
+        command.env(daemon::PID_FILE_VAR, pid_file);
+    }
+    Ok(command)
+}
+
+/// Replaces the current process with a new copy of the bot.
+#[cfg(unix)]
+fn restart_process(args: &CliArgs, pid_file: Option<&Path>) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    info!("Restarting...");
+    let err = restart_command(args, pid_file)?.exec();
+    Err::<(), _>(err).internal_err(|| "Could not restart the bot.")
+}
+
+/// Starts a new copy of the bot, which keeps running once this process exits.
+///
+/// A supervised bot instead exits with [`supervisor::RESTART_EXIT_CODE`], so the new copy is
+/// started by the supervisor and stays under it.
+#[cfg(not(unix))]
+fn restart_process(args: &CliArgs, pid_file: Option<&Path>) -> Result<()> {
+    info!("Restarting...");
+    if supervisor::crash_count().is_some() {
+        std::process::exit(supervisor::RESTART_EXIT_CODE);
+    }
+    restart_command(args, pid_file)?.spawn().internal_err(|| "Could not restart the bot.")?;
+    Ok(())
+}
+
 /// Contains extension functions defined directly on `Handler<impl Events>`.
 ///
 /// This is the main way to access a lot of core bot functionality. Most of the functions in this
@@ -225,11 +819,29 @@ impl <R: Module> SylphieCore<R> {
 pub trait SylphieCoreHandlerExt {
     /// Shuts down the bot.
     fn shutdown_bot(&self);
+
+    /// Shuts down the bot, and then starts it again with the same arguments and data directory.
+    ///
+    /// This replaces the process with the bot's executable as it is on disk once shutdown has
+    /// finished, so an upgraded binary or changed configuration file is picked up.
+    fn restart_bot(&self);
+
+    /// Returns the registry of services published by modules.
+    fn services(&self) -> &Services;
 }
 impl <E: Events> SylphieCoreHandlerExt for Handler<E> {
     fn shutdown_bot(&self) {
         self.dispatch_sync(ShutdownStartedEvent);
     }
+
+    fn restart_bot(&self) {
+        self.get_service::<Interface>().set_restart();
+        self.dispatch_sync(ShutdownStartedEvent);
+    }
+
+    fn services(&self) -> &Services {
+        self.get_service::<Services>()
+    }
 }
 
 /// Initializes the compatibility layer between `log` and `tracing`, the fallback logger, and the
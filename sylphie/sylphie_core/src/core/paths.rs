@@ -0,0 +1,122 @@
+//! Finds the directories the bot stores its configuration, data and cache in.
+
+use crate::cli::CliArgs;
+use std::env;
+use std::path::PathBuf;
+
+fn get_exe_dir() -> PathBuf {
+    let mut path = env::current_exe().expect("cannot get current exe path");
+    path.pop();
+    path
+}
+fn get_dir_from_cargo(path: PathBuf) -> Option<PathBuf> {
+    // Check for other cargo-related env vars to be safe.
+    if env::var_os("CARGO").is_none() ||
+        env::var_os("CARGO_PKG_NAME").is_none() ||
+        env::var_os("CARGO_PKG_VERSION").is_none()
+    {
+        return None
+    }
+
+    // Check for a Cargo.toml
+    let mut cur_path = path.clone();
+    cur_path.push("Cargo.toml");
+    if !(cur_path.exists() || cur_path.is_file()) {
+        return None
+    }
+    cur_path.pop();
+    cur_path.push(".git");
+    if cur_path.exists() && cur_path.is_dir() {
+        // We found a .git directory. Assume there is no workspace setup.
+        return None
+    }
+
+    // Check for the most typical workspace setup.
+    cur_path.pop();
+    cur_path.pop();
+    cur_path.push("Cargo.toml");
+    if cur_path.exists() && cur_path.is_file() {
+        cur_path.pop();
+        Some(cur_path)
+    } else {
+        Some(path)
+    }
+}
+
+/// Directories chosen when the bot was built, which command line arguments take precedence over.
+#[derive(Default)]
+pub(in super) struct DirOverrides {
+    pub data: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub cache: Option<PathBuf>,
+}
+
+/// The directories the bot uses.
+pub(in super) struct BotDirs {
+    pub data: PathBuf,
+    pub config: PathBuf,
+    pub cache: PathBuf,
+}
+impl BotDirs {
+    /// Keeps everything in a single directory.
+    fn flat(path: PathBuf) -> BotDirs {
+        BotDirs { config: path.clone(), cache: path.join("cache"), data: path }
+    }
+
+    /// Finds the directories used when none are given.
+    fn default_dirs(bot_name: &str) -> BotDirs {
+        let cargo_dir = env::var_os("CARGO_MANIFEST_DIR")
+            .and_then(|x| get_dir_from_cargo(PathBuf::from(x)));
+        if let Some(path) = cargo_dir {
+            return BotDirs::flat(path.join("run"))
+        }
+        let portable = get_exe_dir().join("run");
+        if portable.is_dir() {
+            return BotDirs::flat(portable)
+        }
+        match (dirs::data_dir(), dirs::config_dir(), dirs::cache_dir()) {
+            (Some(data), Some(config), Some(cache)) => BotDirs {
+                data: data.join(bot_name),
+                config: config.join(bot_name),
+                cache: cache.join(bot_name),
+            },
+            // there is no home directory, such as for some service accounts.
+            _ => BotDirs::flat(portable),
+        }
+    }
+
+    /// Finds the directories a bot uses, from the most specific setting given.
+    pub fn resolve(bot_name: &str, args: &CliArgs, overrides: &DirOverrides) -> BotDirs {
+        let data = args.data_dir.as_ref().or(overrides.data.as_ref());
+        let mut dirs = match data {
+            Some(path) => BotDirs::flat(path.clone()),
+            None => BotDirs::default_dirs(bot_name),
+        };
+        if let Some(path) = args.config_dir.as_ref().or(overrides.config.as_ref()) {
+            dirs.config = path.clone();
+        }
+        if let Some(path) = args.cache_dir.as_ref().or(overrides.cache.as_ref()) {
+            dirs.cache = path.clone();
+        }
+        dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides() {
+        let args = CliArgs::parse(vec!["--data-dir".to_string(), "state".to_string()]).unwrap();
+        let overrides = DirOverrides {
+            data: Some(PathBuf::from("ignored")),
+            cache: Some(PathBuf::from("cache")),
+            ..Default::default()
+        };
+        let dirs = BotDirs::resolve("bot", &args, &overrides);
+        assert_eq!(dirs.data, PathBuf::from("state"));
+        assert_eq!(dirs.config, PathBuf::from("state"));
+        assert_eq!(dirs.cache, PathBuf::from("cache"));
+    }
+}
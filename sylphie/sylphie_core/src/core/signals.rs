@@ -0,0 +1,79 @@
+//! Handles the signals sent by service managers such as systemd and Docker.
+
+use crate::core::SylphieCoreHandlerExt;
+use crate::errors::*;
+use crate::interface::Interface;
+use static_events::prelude_async::*;
+
+/// Reloads the bot's settings, logging any error.
+#[cfg(unix)]
+async fn reload(target: &Handler<impl Events>) {
+    if let Err(e) = crate::core::reload_bot(target).await {
+        e.report_error();
+    }
+}
+
+/// Returns whether Ctrl+C is left to the terminal.
+fn uses_terminal_interrupt(target: &Handler<impl Events>) -> bool {
+    atty::is(atty::Stream::Stdin) && !target.get_service::<Interface>().is_terminal_disabled()
+}
+
+/// Starts a graceful shutdown, or exits immediately if one was already started.
+fn shutdown(target: &Handler<impl Events>, signal: &str, is_shutdown: &mut bool) {
+    if *is_shutdown {
+        warn!("Received {} again. Exiting without waiting for shutdown to finish.", signal);
+        std::process::exit(1);
+    }
+    info!("Received {}. Shutting down.", signal);
+    *is_shutdown = true;
+    target.shutdown_bot();
+}
+
+/// Waits for signals until the bot shuts down.
+///
+/// `SIGTERM` and `SIGINT` shut down the bot, and `SIGHUP` reloads it. `SIGINT` is left to the
+/// terminal if it is interactive, as Ctrl+C is handled there. This is also the case for
+/// instances that share a process with the one using the terminal, unless their own terminal
+/// was disabled.
+#[cfg(unix)]
+pub(crate) async fn signals_task(target: Handler<impl Events>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut interrupt = if uses_terminal_interrupt(&target) {
+        None
+    } else {
+        Some(signal(SignalKind::interrupt())?)
+    };
+    let mut is_shutdown = false;
+    loop {
+        let interrupted = async {
+            match &mut interrupt {
+                Some(interrupt) => interrupt.recv().await,
+                None => futures::future::pending::<Option<()>>().await,
+            }
+        };
+        tokio::select! {
+            _ = terminate.recv() => shutdown(&target, "SIGTERM", &mut is_shutdown),
+            _ = interrupted => shutdown(&target, "SIGINT", &mut is_shutdown),
+            _ = hangup.recv() => {
+                info!("Received SIGHUP. Reloading.");
+                reload(&target).await;
+            }
+        }
+    }
+}
+
+/// Waits for Ctrl+C until the bot shuts down, if standard input is not a terminal.
+#[cfg(not(unix))]
+pub(crate) async fn signals_task(target: Handler<impl Events>) -> Result<()> {
+    if uses_terminal_interrupt(&target) {
+        return Ok(())
+    }
+    let mut is_shutdown = false;
+    loop {
+        tokio::signal::ctrl_c().await?;
+        shutdown(&target, "Ctrl+C", &mut is_shutdown);
+    }
+}
@@ -0,0 +1,283 @@
+//! Restarts the bot when it crashes, for bots started with `--supervise`.
+//!
+//! The supervisor is the process the bot was started as. It runs the bot itself in a child
+//! process with the same arguments, and starts it again if it exits with an error, is killed by
+//! a signal, or aborts, such as after the [`watchdog`](`crate::watchdog`) finds it stalled. The
+//! delay before each restart doubles from [`INITIAL_BACKOFF`] up to [`MAX_BACKOFF`], and is reset
+//! once the bot has run for [`STABLE_RUN_TIME`].
+//!
+//! If the bot crashes too often, it is probably failing on startup, and the supervisor gives up
+//! rather than restarting it forever. This is configured in the `[supervisor]` table of the
+//! configuration file:
+//!
+//! ```toml
+//! [supervisor]
+//! max_crashes = 5     # how many crashes are allowed within the window
+//! crash_window = 600  # how long crashes are counted for, in seconds
+//! ```
+//!
+//! `SIGTERM` and `SIGHUP` are passed on to the bot, and the supervisor exits once the bot has
+//! shut down in response to them. On systems other than Unix, where the bot cannot replace its
+//! own process, a supervised bot restarts by exiting with [`RESTART_EXIT_CODE`], and the
+//! supervisor starts it again without counting a crash. The number of crashes so far is
+//! available to the bot from [`CoreStats::crash_count`](`crate::stats::CoreStats::crash_count`).
+
+use crate::cli::CliArgs;
+use crate::config::Config;
+use crate::errors::*;
+use std::collections::VecDeque;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// The delay before the bot is restarted after its first crash.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest delay before the bot is restarted.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How long the bot must run before the delay before restarting it is reset.
+pub const STABLE_RUN_TIME: Duration = Duration::from_secs(300);
+
+/// How many crashes are allowed within the crash window by default.
+pub const DEFAULT_MAX_CRASHES: u32 = 5;
+
+/// How long crashes are counted for by default.
+pub const DEFAULT_CRASH_WINDOW: Duration = Duration::from_secs(600);
+
+/// The exit code a supervised bot exits with to be started again by the supervisor.
+///
+/// This is only used on systems other than Unix.
+pub const RESTART_EXIT_CODE: i32 = 75;
+
+/// The environment variable the supervisor passes the number of crashes so far in.
+const CRASHES_VAR: &str = "SYLPHIE_SUPERVISOR_CRASHES";
+
+/// Returns the number of times the bot has crashed, or `None` if it is not supervised.
+pub(crate) fn crash_count() -> Option<u32> {
+    std::env::var(CRASHES_VAR).ok()?.parse().ok()
+}
+
+/// Describes how a child process exited, for log messages.
+fn describe_exit(status: &ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return format!("was killed by signal {}", signal)
+        }
+    }
+    match status.code() {
+        Some(code) => format!("exited with code {}", code),
+        None => "exited".to_string(),
+    }
+}
+
+/// A signal received by the supervisor.
+enum ReceivedSignal {
+    /// The bot should shut down, and not be restarted afterwards.
+    Stop { forward: bool },
+    /// The bot should reload its settings.
+    Reload,
+}
+
+#[cfg(unix)]
+struct Signals {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+    hangup: tokio::signal::unix::Signal,
+}
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Result<Signals> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Signals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+            hangup: signal(SignalKind::hangup())?,
+        })
+    }
+
+    async fn recv(&mut self) -> ReceivedSignal {
+        // Ctrl+C in a terminal is sent to the bot as well, so it should not get it twice.
+        tokio::select! {
+            _ = self.terminate.recv() => ReceivedSignal::Stop { forward: true },
+            _ = self.interrupt.recv() =>
+                ReceivedSignal::Stop { forward: !atty::is(atty::Stream::Stdin) },
+            _ = self.hangup.recv() => ReceivedSignal::Reload,
+        }
+    }
+
+    fn forward(child: &tokio::process::Child, signal: &ReceivedSignal) {
+        let signal = match signal {
+            ReceivedSignal::Stop { forward: false } => return,
+            ReceivedSignal::Stop { forward: true } => libc::SIGTERM,
+            ReceivedSignal::Reload => libc::SIGHUP,
+        };
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, signal);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Result<Signals> {
+        Ok(Signals)
+    }
+
+    async fn recv(&mut self) -> ReceivedSignal {
+        // Ctrl+C is sent to every process attached to the console, including the bot.
+        if tokio::signal::ctrl_c().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+        ReceivedSignal::Stop { forward: false }
+    }
+
+    fn forward(_: &tokio::process::Child, _: &ReceivedSignal) { }
+}
+
+/// Tracks recent crashes to decide how long to wait before restarting the bot.
+struct CrashTracker {
+    max_crashes: u32,
+    crash_window: Duration,
+    recent: VecDeque<Instant>,
+    total: u32,
+    backoff: Duration,
+}
+impl CrashTracker {
+    fn new(config: &Config) -> Result<CrashTracker> {
+        let crash_window = config.get("supervisor.crash_window")?.map(Duration::from_secs);
+        Ok(CrashTracker {
+            max_crashes: config.get("supervisor.max_crashes")?.unwrap_or(DEFAULT_MAX_CRASHES),
+            crash_window: crash_window.unwrap_or(DEFAULT_CRASH_WINDOW),
+            recent: VecDeque::new(),
+            total: 0,
+            backoff: INITIAL_BACKOFF,
+        })
+    }
+
+    /// Records a crash after the bot ran for `run_time`, and returns how long to wait before
+    /// restarting it, or `None` if it is crashing too often.
+    fn record_crash(&mut self, now: Instant, run_time: Duration) -> Option<Duration> {
+        self.total += 1;
+        if run_time >= STABLE_RUN_TIME {
+            self.backoff = INITIAL_BACKOFF;
+        }
+        self.recent.push_back(now);
+        while let Some(&first) = self.recent.front() {
+            if now.duration_since(first) < self.crash_window {
+                break
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() as u32 >= self.max_crashes {
+            return None
+        }
+        let backoff = self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        Some(backoff)
+    }
+}
+
+async fn supervise_async(config: &Config, args: &CliArgs) -> Result<()> {
+    let exe = std::env::current_exe()
+        .internal_err(|| "Could not find the current executable.")?;
+    let mut child_args = args.clone();
+    child_args.supervise = false;
+    child_args.daemon = false;
+    let mut tracker = CrashTracker::new(config)?;
+    let mut signals = Signals::new()?;
+
+    loop {
+        let started = Instant::now();
+        let mut child = tokio::process::Command::new(&exe)
+            .args(child_args.to_args())
+            .env(CRASHES_VAR, tracker.total.to_string())
+            .spawn()
+            .internal_err(|| "Could not start the bot.")?;
+        info!("Started the bot with PID {}.", child.id());
+        // the backup has been restored once it has started, and must not be restored again.
+        child_args.restore_backup = None;
+
+        let mut is_stopping = false;
+        let status = loop {
+            tokio::select! {
+                status = &mut child => break status?,
+                signal = signals.recv() => {
+                    is_stopping |= matches!(signal, ReceivedSignal::Stop { .. });
+                    Signals::forward(&child, &signal);
+                }
+            }
+        };
+        if status.success() || is_stopping {
+            return Ok(())
+        }
+        #[cfg(not(unix))]
+        {
+            if status.code() == Some(RESTART_EXIT_CODE) {
+                info!("Restarting the bot.");
+                continue
+            }
+        }
+
+        let run_time = started.elapsed();
+        let backoff = match tracker.record_crash(Instant::now(), run_time) {
+            Some(x) => x,
+            None => cmd_error!(
+                "The bot {}, and has crashed {} times within {} seconds. Not restarting it.",
+                describe_exit(&status), tracker.recent.len(), tracker.crash_window.as_secs(),
+            ),
+        };
+        error!(
+            "The bot {} after running for {} seconds. Restarting in {} seconds \
+             ({} crashes so far).",
+            describe_exit(&status), run_time.as_secs(), backoff.as_secs(), tracker.total,
+        );
+        tokio::select! {
+            _ = tokio::time::delay_for(backoff) => { }
+            _ = signals.recv() => return Ok(()),
+        }
+    }
+}
+
+/// Runs the bot in child processes until it exits on its own or is stopped, restarting it
+/// when it crashes.
+pub(in super) fn supervise(config: &Config, args: &CliArgs) -> Result<()> {
+    let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build()?;
+    runtime.block_on(supervise_async(config, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_tracker() -> CrashTracker {
+        CrashTracker {
+            max_crashes: 3,
+            crash_window: Duration::from_secs(60),
+            recent: VecDeque::new(),
+            total: 0,
+            backoff: INITIAL_BACKOFF,
+        }
+    }
+
+    #[test]
+    fn backoff_and_crash_loops() {
+        let start = Instant::now();
+        let quick = Duration::from_secs(1);
+        let mut tracker = new_tracker();
+        assert_eq!(tracker.record_crash(start, quick), Some(INITIAL_BACKOFF));
+        assert_eq!(tracker.record_crash(start + quick, quick), Some(INITIAL_BACKOFF * 2));
+        assert_eq!(tracker.record_crash(start + quick * 2, quick), None);
+
+        let mut tracker = new_tracker();
+        let later = Duration::from_secs(100);
+        assert_eq!(tracker.record_crash(start, quick), Some(INITIAL_BACKOFF));
+        assert_eq!(tracker.record_crash(start + later, quick), Some(INITIAL_BACKOFF * 2));
+        let stable = STABLE_RUN_TIME;
+        assert_eq!(tracker.record_crash(start + later * 2, stable), Some(INITIAL_BACKOFF));
+        assert_eq!(tracker.total, 3);
+    }
+}
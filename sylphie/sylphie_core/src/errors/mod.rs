@@ -10,7 +10,10 @@ pub(crate) use std::result::{Result as StdResult};
 
 mod panic;
 pub use panic::PanicLocation;
-pub(crate) use panic::init_panic_hook;
+pub(crate) use panic::{init_panic_hook, payload_to_str};
+
+#[cfg(feature = "error_reporter")] mod reporter;
+#[cfg(feature = "error_reporter")] pub use reporter::{ErrorReport, ErrorReporter};
 
 /// The type of error contained within an [`Error`].
 #[derive(Error, Debug)]
@@ -54,7 +54,7 @@ thread_local!(static PANIC_INFO: RefCell<PanicInfoStore> = RefCell::new(PanicInf
     info: None,
 }));
 
-fn payload_to_str(payload: &(dyn Any + Send)) -> Cow<'static, str> {
+pub(crate) fn payload_to_str(payload: &(dyn Any + Send)) -> Cow<'static, str> {
     if let Some(x) = payload.downcast_ref::<&'static str>() {
         (*x).into()
     } else if let Some(x) = payload.downcast_ref::<String>() {
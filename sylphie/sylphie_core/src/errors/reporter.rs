@@ -0,0 +1,32 @@
+//! Allows errors to be forwarded to an external error tracking service.
+
+use crate::errors::Error;
+use crate::module::CrateMetadata;
+
+/// Information about an error reported with [`Error::report_error`].
+#[non_exhaustive]
+pub struct ErrorReport<'a> {
+    /// The name of the bot the error occurred in.
+    pub bot_name: &'a str,
+    /// The error itself. Panics are reported as errors with [`ErrorKind::Panicked`].
+    ///
+    /// [`ErrorKind::Panicked`]: `crate::errors::ErrorKind::Panicked`
+    pub error: &'a Error,
+    /// The crates the bot was built from, or `None` if the module tree has not been loaded yet.
+    pub loaded_crates: Option<&'a [CrateMetadata]>,
+}
+
+/// A hook that forwards errors to a service such as Sentry.
+///
+/// Reporters are set with [`SylphieCore::with_error_reporter`], and are called for every error
+/// that is reported rather than shown to the user, after the error report file is written.
+/// Errors that occur while the bot is starting up or shutting down are not forwarded.
+///
+/// [`SylphieCore::with_error_reporter`]: `crate::core::SylphieCore::with_error_reporter`
+pub trait ErrorReporter: Send + Sync + 'static {
+    /// Forwards an error to the service.
+    ///
+    /// This is called from the thread the error was reported on, and should not block it for
+    /// long. Panics in this function are caught and logged.
+    fn report(&self, report: &ErrorReport<'_>);
+}
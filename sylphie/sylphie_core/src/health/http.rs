@@ -0,0 +1,48 @@
+//! Serves health checks over HTTP, for orchestrators such as Kubernetes.
+
+use crate::errors::*;
+use crate::health::{check_health, HealthStatus, HealthSummary};
+use crate::http_server::{self, Request, Response};
+use futures::FutureExt;
+use serde_json::{json, Map, Value};
+use static_events::prelude_async::*;
+
+/// Formats a health summary as the JSON returned by the endpoints.
+fn summary_json(summary: &HealthSummary) -> Value {
+    let mut modules = Map::new();
+    for module in &summary.modules {
+        modules.insert(module.module.name().to_string(), json!({
+            "status": module.report.status.to_string(),
+            "message": module.report.message.as_deref(),
+        }));
+    }
+    json!({ "status": summary.status.to_string(), "modules": modules })
+}
+
+async fn respond(target: &Handler<impl Events>, request: Request) -> Response {
+    let is_ready = match &*request.path {
+        "/healthz" => false,
+        "/readyz" => true,
+        _ => return Response::not_found(),
+    };
+    if request.method != "GET" {
+        return Response::method_not_allowed()
+    }
+
+    let summary = check_health(target).await;
+    let healthy = match summary.status {
+        HealthStatus::Ok => true,
+        HealthStatus::Degraded => !is_ready,
+        HealthStatus::Failed => false,
+    };
+    let code = if healthy { 200 } else { 503 };
+    Response::new(code, "application/json", summary_json(&summary).to_string())
+}
+
+/// Serves `/healthz` and `/readyz` on an address until the bot shuts down.
+pub(crate) async fn health_server_task(target: Handler<impl Events>, addr: String) -> Result<()> {
+    http_server::serve(&addr, "health check", move |request| {
+        let target = target.clone();
+        async move { respond(&target, request).await }.boxed()
+    }).await
+}
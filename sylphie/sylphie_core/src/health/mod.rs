@@ -0,0 +1,123 @@
+//! Health checks for modules.
+//!
+//! Modules may report their health by defining a `#[module_hook(health)]` method in their
+//! `#[module_impl]` block. The method may take either `&self` or `&self` and a handler, may be
+//! async, and must return something convertible into a [`HealthReport`].
+//!
+//! If `health.listen` is set in the configuration file to an address such as `127.0.0.1:8080`,
+//! the bot also serves its health over HTTP, so orchestrators can probe it:
+//!
+//! * `/healthz` responds with `200 OK` unless a module has failed, and `503` otherwise.
+//! * `/readyz` responds with `200 OK` only if every module is healthy, including connections.
+//!
+//! Both respond with a JSON object containing the overall `status`, and the `status` and
+//! `message` of each module. The server only starts once the bot has finished initializing.
+
+use crate::errors::*;
+use crate::module::ModuleInfo;
+use static_events::prelude_async::*;
+use std::borrow::Cow;
+use std::fmt;
+
+mod http;
+pub(crate) use http::health_server_task;
+
+/// The health of a module or of the bot as a whole.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HealthStatus {
+    /// The module is working correctly.
+    Ok,
+    /// The module is working, but some of its functionality is unavailable.
+    Degraded,
+    /// The module is not working.
+    Failed,
+}
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HealthStatus::Ok => "ok",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Failed => "failed",
+        })
+    }
+}
+
+/// The health reported by a single module.
+#[derive(Clone, Debug)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub message: Option<Cow<'static, str>>,
+}
+impl HealthReport {
+    /// Creates a report for a module that is working correctly.
+    pub fn ok() -> Self {
+        HealthReport { status: HealthStatus::Ok, message: None }
+    }
+
+    /// Creates a report for a module that is only partly working.
+    pub fn degraded(message: impl Into<Cow<'static, str>>) -> Self {
+        HealthReport { status: HealthStatus::Degraded, message: Some(message.into()) }
+    }
+
+    /// Creates a report for a module that is not working.
+    pub fn failed(message: impl Into<Cow<'static, str>>) -> Self {
+        HealthReport { status: HealthStatus::Failed, message: Some(message.into()) }
+    }
+
+    /// Sets the message for this report.
+    pub fn with_message(mut self, message: impl Into<Cow<'static, str>>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+}
+impl From<HealthStatus> for HealthReport {
+    fn from(status: HealthStatus) -> Self {
+        HealthReport { status, message: None }
+    }
+}
+impl From<Result<HealthReport>> for HealthReport {
+    fn from(result: Result<HealthReport>) -> Self {
+        match result {
+            Ok(report) => report,
+            Err(e) => HealthReport::failed(format!("Health check failed: {}", e)),
+        }
+    }
+}
+
+/// The health of a particular module.
+#[derive(Clone, Debug)]
+pub struct ModuleHealth {
+    pub module: ModuleInfo,
+    pub report: HealthReport,
+}
+
+/// The aggregated health of every module that defines a health check.
+#[derive(Clone, Debug)]
+pub struct HealthSummary {
+    /// The worst status reported by any module.
+    pub status: HealthStatus,
+    /// The reports of each module, sorted by module name.
+    pub modules: Vec<ModuleHealth>,
+}
+
+/// The event used to collect health reports from modules.
+///
+/// This is usually dispatched through [`check_health`] rather than directly.
+pub struct CheckHealthEvent {
+    reports: Vec<ModuleHealth>,
+}
+self_event!(CheckHealthEvent);
+impl CheckHealthEvent {
+    /// Adds a health report for a module.
+    pub fn report(&mut self, module: &ModuleInfo, report: impl Into<HealthReport>) {
+        self.reports.push(ModuleHealth { module: module.clone(), report: report.into() });
+    }
+}
+
+/// Checks the health of every module in the bot.
+pub async fn check_health(target: &Handler<impl Events>) -> HealthSummary {
+    let mut reports = target.dispatch_async(CheckHealthEvent { reports: Vec::new() }).await.reports;
+    reports.sort_by(|a, b| a.module.name().cmp(b.module.name()));
+    let status = reports.iter().map(|x| x.report.status).max().unwrap_or(HealthStatus::Ok);
+    HealthSummary { status, modules: reports }
+}
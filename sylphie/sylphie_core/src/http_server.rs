@@ -0,0 +1,143 @@
+//! A minimal HTTP server for the endpoints the bot serves to monitoring systems.
+//!
+//! Only what those endpoints need is supported: each connection carries a single request, and
+//! request bodies and headers are ignored.
+
+use crate::errors::*;
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest request that is read. Only the request line is needed, so this is generous.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// The method and path of a request, with any query string removed from the path.
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+}
+
+/// The response to a request.
+pub(crate) struct Response {
+    pub code: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+impl Response {
+    pub fn new(code: u16, content_type: &'static str, body: String) -> Self {
+        Response { code, content_type, body }
+    }
+
+    pub fn not_found() -> Self {
+        Response::new(404, "text/plain", "not found\n".to_string())
+    }
+
+    pub fn method_not_allowed() -> Self {
+        Response::new(405, "text/plain", "method not allowed\n".to_string())
+    }
+}
+
+type Route = Arc<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// Parses the method and path of a request from its request line.
+fn parse_request_line(request: &str) -> Option<Request> {
+    let mut parts = request.lines().next()?.split(' ');
+    let method = parts.next()?;
+    let path = parts.next()?;
+    let path = path.split('?').next().unwrap();
+    Some(Request { method: method.to_string(), path: path.to_string() })
+}
+
+fn reason_phrase(code: u16) -> &'static str {
+    match code {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// Reads the head of a request, up to the blank line that ends it.
+async fn read_request(stream: &mut (impl AsyncRead + Unpin)) -> Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+        ensure!(request.len() < MAX_REQUEST_LEN, "HTTP request is too long.");
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            break
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+async fn serve_connection(route: Route, mut stream: TcpStream) -> Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+        .internal_err(|| "HTTP request timed out.")??;
+    let response = match parse_request_line(&request) {
+        Some(request) => route(request).await,
+        None => Response::new(400, "text/plain", "bad request\n".to_string()),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.code, reason_phrase(response.code), response.content_type,
+        response.body.len(),
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown(std::net::Shutdown::Both)?;
+    Ok(())
+}
+
+/// Serves requests on an address until the task is aborted.
+///
+/// `name` describes the server in log messages, such as `health check`.
+pub(crate) async fn serve(
+    addr: &str, name: &str,
+    route: impl Fn(Request) -> BoxFuture<'static, Response> + Send + Sync + 'static,
+) -> Result<()> {
+    let addr: SocketAddr = match addr.parse() {
+        Ok(x) => x,
+        Err(_) => cmd_error!("Invalid address for the {} server: {}", name, addr),
+    };
+    let mut listener = tokio::net::TcpListener::bind(addr).await
+        .internal_err(|| format!("Could not bind the {} server to {}.", name, addr))?;
+    info!("Serving {} requests on http://{}/.", name, addr);
+    let route: Route = Arc::new(route);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let future = serve_connection(route.clone(), stream);
+                tokio::spawn(async move {
+                    if let Err(e) = Error::catch_panic_async(future).await {
+                        debug!("HTTP connection closed with error: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Could not accept {} connection: {}", name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        let request = parse_request_line("GET /readyz?verbose=1 HTTP/1.1\r\nHost: a\r\n\r\n");
+        let request = request.unwrap();
+        assert_eq!((&*request.method, &*request.path), ("GET", "/readyz"));
+        assert!(parse_request_line("GET").is_none());
+        assert!(parse_request_line("").is_none());
+    }
+}
@@ -0,0 +1,225 @@
+//! Formats log messages for the console with configurable colors and timestamps.
+
+use ansi_term::Colour;
+use chrono::Local;
+use crate::errors::*;
+use lazy_static::*;
+use std::fmt;
+use std::str::FromStr;
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A color used for text in the console.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Purple,
+    Cyan,
+    White,
+    /// A color from the 256 color palette.
+    Fixed(u8),
+    /// A 24-bit color.
+    Rgb(u8, u8, u8),
+}
+impl LogColor {
+    fn to_ansi(self) -> Colour {
+        match self {
+            LogColor::Black => Colour::Black,
+            LogColor::Red => Colour::Red,
+            LogColor::Green => Colour::Green,
+            LogColor::Yellow => Colour::Yellow,
+            LogColor::Blue => Colour::Blue,
+            LogColor::Purple => Colour::Purple,
+            LogColor::Cyan => Colour::Cyan,
+            LogColor::White => Colour::White,
+            LogColor::Fixed(n) => Colour::Fixed(n),
+            LogColor::Rgb(r, g, b) => Colour::RGB(r, g, b),
+        }
+    }
+}
+
+/// Parses a color name such as `red`, a palette number such as `208`, or a hex color such as
+/// `#ff8000`.
+impl FromStr for LogColor {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        Ok(match s.as_str() {
+            "black" => LogColor::Black,
+            "red" => LogColor::Red,
+            "green" => LogColor::Green,
+            "yellow" => LogColor::Yellow,
+            "blue" => LogColor::Blue,
+            "purple" | "magenta" => LogColor::Purple,
+            "cyan" => LogColor::Cyan,
+            "white" => LogColor::White,
+            _ => if let Some(hex) = s.strip_prefix('#') {
+                let channel = |i: usize| {
+                    hex.get(i..i + 2).and_then(|x| u8::from_str_radix(x, 16).ok())
+                };
+                match (hex.len(), channel(0), channel(2), channel(4)) {
+                    (6, Some(r), Some(g), Some(b)) => LogColor::Rgb(r, g, b),
+                    _ => cmd_error!("Invalid hex color: #{}", hex),
+                }
+            } else if let Ok(n) = s.parse::<u8>() {
+                LogColor::Fixed(n)
+            } else {
+                cmd_error!("Unknown color: {}", s)
+            },
+        })
+    }
+}
+
+/// Returns whether the console understands ANSI escape codes.
+///
+/// On Windows, this enables virtual terminal processing for the console the first time it is
+/// called, which fails on versions older than Windows 10.
+pub(in super) fn ansi_supported() -> bool {
+    lazy_static! {
+        static ref ANSI_SUPPORTED: bool = {
+            #[cfg(windows)]
+            {
+                ansi_term::enable_ansi_support().is_ok()
+            }
+            #[cfg(not(windows))]
+            {
+                true
+            }
+        };
+    }
+    *ANSI_SUPPORTED
+}
+
+/// When log messages in the console are colored.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Colors are used only if standard output is a terminal that supports them.
+    Auto,
+    Always,
+    Never,
+}
+
+/// The colors and timestamp format used for log messages in the console.
+///
+/// This is set with [`SetupLoggerEvent`](`crate::interface::SetupLoggerEvent`), and changes
+/// take effect when the logger is reloaded.
+#[derive(Clone, Debug)]
+pub struct ConsoleTheme {
+    /// The color of each log level, from `ERROR` to `TRACE`. `None` leaves a level uncolored.
+    pub level_colors: [Option<LogColor>; 5],
+    /// The format of timestamps, as a [`chrono` format string](`chrono::format::strftime`).
+    pub timestamp_format: String,
+    /// When colors are used.
+    pub color_mode: ColorMode,
+}
+impl ConsoleTheme {
+    fn level_index(level: Level) -> usize {
+        match level {
+            Level::ERROR => 0,
+            Level::WARN => 1,
+            Level::INFO => 2,
+            Level::DEBUG => 3,
+            _ => 4,
+        }
+    }
+
+    /// Returns the color of a log level.
+    pub fn level_color(&self, level: Level) -> Option<LogColor> {
+        self.level_colors[Self::level_index(level)]
+    }
+
+    /// Sets the color of a log level.
+    pub fn set_level_color(&mut self, level: Level, color: Option<LogColor>) {
+        self.level_colors[Self::level_index(level)] = color;
+    }
+
+    pub(in super) fn use_color(&self) -> bool {
+        match self.color_mode {
+            ColorMode::Auto => atty::is(atty::Stream::Stdout) && ansi_supported(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+impl Default for ConsoleTheme {
+    fn default() -> Self {
+        ConsoleTheme {
+            level_colors: [
+                Some(LogColor::Red),
+                Some(LogColor::Yellow),
+                Some(LogColor::Green),
+                Some(LogColor::Blue),
+                Some(LogColor::Purple),
+            ],
+            timestamp_format: "[%k:%M:%S]".to_string(),
+            color_mode: ColorMode::Auto,
+        }
+    }
+}
+
+/// Formats log messages for the console according to a [`ConsoleTheme`].
+pub(in super) struct ConsoleFormat {
+    theme: ConsoleTheme,
+    ansi: bool,
+}
+impl ConsoleFormat {
+    pub fn new(theme: ConsoleTheme, ansi: bool) -> Self {
+        ConsoleFormat { theme, ansi }
+    }
+}
+impl <S, N> FormatEvent<S, N> for ConsoleFormat
+where S: Subscriber + for<'a> LookupSpan<'a>, N: for<'a> FormatFields<'a> + 'static
+{
+    fn format_event(
+        &self, ctx: &FmtContext<'_, S, N>, writer: &mut dyn fmt::Write, event: &Event<'_>,
+    ) -> fmt::Result {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        write!(writer, "{} ", Local::now().format(&self.theme.timestamp_format))?;
+        let level = format!("{:>5}", metadata.level().to_string());
+        match self.theme.level_color(*metadata.level()) {
+            Some(color) if self.ansi => write!(writer, "{} ", color.to_ansi().paint(level))?,
+            _ => write!(writer, "{} ", level)?,
+        }
+        let mut has_spans = false;
+        ctx.visit_spans(|span| {
+            has_spans = true;
+            write!(writer, "{}", span.name())?;
+            let extensions = span.extensions();
+            if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                if !fields.is_empty() {
+                    write!(writer, "{{{}}}", fields)?;
+                }
+            }
+            write!(writer, ":")
+        })?;
+        if has_spans {
+            write!(writer, " ")?;
+        }
+        write!(writer, "{}: ", metadata.target())?;
+        ctx.format_fields(writer, event)?;
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!("Red".parse::<LogColor>().unwrap(), LogColor::Red);
+        assert_eq!("magenta".parse::<LogColor>().unwrap(), LogColor::Purple);
+        assert_eq!("208".parse::<LogColor>().unwrap(), LogColor::Fixed(208));
+        assert_eq!("#ff8000".parse::<LogColor>().unwrap(), LogColor::Rgb(255, 128, 0));
+        assert!("#ff80".parse::<LogColor>().is_err());
+        assert!("chartreuse".parse::<LogColor>().is_err());
+    }
+}
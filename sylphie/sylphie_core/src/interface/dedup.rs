@@ -0,0 +1,133 @@
+//! Collapses identical log messages that are emitted in quick succession.
+
+use crate::errors::*;
+use crate::interface::Interface;
+use crate::interface::system_log::MessageVisitor;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{Event, Level, Metadata};
+use tracing_log::NormalizeEvent;
+
+/// The default window used to collapse repeated log messages.
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(5);
+
+/// How often the summaries of collapsed messages are checked for.
+const DEDUP_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The most distinct messages tracked at once. Further messages are logged without collapsing.
+const MAX_TRACKED_MESSAGES: usize = 256;
+
+#[derive(Eq, PartialEq, Hash)]
+struct MessageKey {
+    level: Level,
+    target: String,
+    message: String,
+}
+impl MessageKey {
+    fn new(metadata: &Metadata<'_>, event: &Event<'_>) -> Self {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        MessageKey {
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.finish(),
+        }
+    }
+}
+
+struct RecentMessage {
+    first_seen: Instant,
+    repeated: usize,
+}
+
+/// Tracks recent log messages, to decide whether an event repeats one of them.
+///
+/// A message is suppressed if an identical message was first logged less than the window ago.
+/// Once the window has passed, a summary of how many copies were suppressed is logged, either
+/// by [`dedup_task`] or when the message is logged again.
+pub(in super) struct Dedup {
+    window: Duration,
+    recent: Mutex<HashMap<MessageKey, RecentMessage>>,
+}
+impl Dedup {
+    pub fn new(window: Duration) -> Self {
+        Dedup { window, recent: Mutex::new(HashMap::new()) }
+    }
+
+    /// Checks an event, returning whether it should be logged.
+    ///
+    /// This must not be called while the terminal is locked, as it may log a summary of the
+    /// messages it suppressed.
+    pub fn check(&self, event: &Event<'_>) -> bool {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        if metadata.target() == module_path!() || metadata.target() == "[term]" {
+            // our own summaries and responses to terminal commands are never collapsed.
+            return true
+        }
+
+        let key = MessageKey::new(metadata, event);
+        let now = Instant::now();
+        let summary = {
+            let mut recent = self.recent.lock();
+            if let Some(message) = recent.get_mut(&key) {
+                if now - message.first_seen < self.window {
+                    message.repeated += 1;
+                    return false
+                }
+            }
+            let expired = recent.remove(&key).filter(|x| x.repeated > 0);
+            if recent.len() < MAX_TRACKED_MESSAGES {
+                let message = RecentMessage { first_seen: now, repeated: 0 };
+                let summary = expired.map(|x| (key.level, key.message.clone(), x.repeated));
+                recent.insert(key, message);
+                summary
+            } else {
+                expired.map(|x| (key.level, key.message, x.repeated))
+            }
+        };
+        if let Some((level, message, repeated)) = summary {
+            log_summary(level, &message, repeated);
+        }
+        true
+    }
+
+    /// Forgets the messages whose window has passed, and logs a summary for each of them that
+    /// was repeated.
+    pub fn flush(&self) {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.recent.lock().retain(|key, message| {
+            let is_expired = now - message.first_seen >= self.window;
+            if is_expired && message.repeated > 0 {
+                expired.push((key.level, key.message.clone(), message.repeated));
+            }
+            !is_expired
+        });
+        for (level, message, repeated) in expired {
+            log_summary(level, &message, repeated);
+        }
+    }
+}
+
+fn log_summary(level: Level, message: &str, repeated: usize) {
+    let s = if repeated == 1 { "" } else { "s" };
+    match level {
+        Level::ERROR => error!("Message repeated {} more time{}: {}", repeated, s, message),
+        Level::WARN => warn!("Message repeated {} more time{}: {}", repeated, s, message),
+        Level::INFO => info!("Message repeated {} more time{}: {}", repeated, s, message),
+        Level::DEBUG => debug!("Message repeated {} more time{}: {}", repeated, s, message),
+        _ => trace!("Message repeated {} more time{}: {}", repeated, s, message),
+    }
+}
+
+/// Periodically logs the summaries of collapsed messages until the bot shuts down.
+pub(crate) async fn dedup_task(target: Handler<impl Events>) -> Result<()> {
+    let mut interval = tokio::time::interval(DEDUP_FLUSH_INTERVAL);
+    loop {
+        interval.tick().await;
+        target.get_service::<Interface>().0.logger.flush_dedup();
+    }
+}
@@ -2,19 +2,20 @@
 
 use backtrace::Backtrace;
 use crate::errors::*;
-use crate::global_instance::*;
 use crate::interface::InterfaceShared;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use lazy_static::*;
-use parking_lot::Once;
+use parking_lot::{Mutex, Once};
 use parking_lot::deadlock;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fmt::{self, Formatter};
 use std::fs;
 use std::fs::File;
 use std::io::{Write as IoWrite};
+use std::panic;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::Duration;
 
@@ -35,8 +36,57 @@ fn check_deadlock() -> Vec<Vec<DeadlockInfo>> {
     }).collect()).collect()
 }
 
+/// How many errors are kept for [`Interface::recent_errors`](`super::Interface::recent_errors`).
+pub(in super) const RECENT_ERRORS_COUNT: usize = 20;
+
+/// An error that was recently reported with [`Error::report_error`].
+#[derive(Clone, Debug)]
+pub struct RecentError {
+    /// When the error was reported.
+    pub time: DateTime<Utc>,
+    /// The first line of the error report.
+    pub summary: String,
+    /// The file the full error report was written to.
+    pub report_file: PathBuf,
+}
+
 lazy_static! {
-    static ref CURRENT_CTX: GlobalInstance<ErrorCtx> = GlobalInstance::new();
+    /// The contexts of the instances running in this process, oldest first.
+    static ref RUNNING_CTXS: Mutex<Vec<ErrorCtx>> = Mutex::new(Vec::new());
+}
+thread_local! {
+    /// The instance the current thread runs for, if it was set with [`ErrorCtx::enter_thread`].
+    static THREAD_CTX: RefCell<Weak<InterfaceShared>> = RefCell::new(Weak::new());
+}
+
+/// Returns the context errors on the current thread are reported to.
+///
+/// This is the instance the thread runs for, or the instance started most recently if the
+/// thread does not belong to a running instance.
+fn current_ctx() -> Option<ErrorCtx> {
+    let running = RUNNING_CTXS.lock();
+    let thread_ctx = THREAD_CTX.try_with(|x| x.borrow().upgrade()).ok().flatten();
+    thread_ctx
+        .and_then(|shared| running.iter().find(|x| Arc::ptr_eq(&x.0, &shared)))
+        .or_else(|| running.last())
+        .cloned()
+}
+
+/// Unregisters a context registered with [`ErrorCtx::activate`] when dropped.
+pub(in super) struct ActiveCtxGuard(Arc<InterfaceShared>);
+impl Drop for ActiveCtxGuard {
+    fn drop(&mut self) {
+        RUNNING_CTXS.lock().retain(|x| !Arc::ptr_eq(&x.0, &self.0));
+    }
+}
+
+/// Restores the context the current thread belonged to before [`ErrorCtx::enter_thread`].
+pub(in super) struct ThreadCtxGuard(Weak<InterfaceShared>);
+impl Drop for ThreadCtxGuard {
+    fn drop(&mut self) {
+        let previous = std::mem::replace(&mut self.0, Weak::new());
+        THREAD_CTX.with(|x| *x.borrow_mut() = previous);
+    }
 }
 
 #[derive(Clone)]
@@ -45,8 +95,18 @@ impl ErrorCtx {
     pub(in super) fn new(shared: Arc<InterfaceShared>) -> Self {
         ErrorCtx(shared)
     }
-    pub fn activate(self) -> InstanceScopeGuard<ErrorCtx> {
-        CURRENT_CTX.set_instance(self)
+
+    /// Registers this context as belonging to a running instance.
+    pub(in super) fn activate(self) -> ActiveCtxGuard {
+        let guard = ActiveCtxGuard(self.0.clone());
+        RUNNING_CTXS.lock().push(self);
+        guard
+    }
+
+    /// Reports errors and panics on the current thread to this context.
+    pub(in super) fn enter_thread(&self) -> ThreadCtxGuard {
+        let shared = Arc::downgrade(&self.0);
+        ThreadCtxGuard(THREAD_CTX.with(|x| std::mem::replace(&mut *x.borrow_mut(), shared)))
     }
 
     fn fmt_info(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -87,15 +147,40 @@ impl ErrorCtx {
         self.fmt_info(fmt)
     }
     fn fmt_logs(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: Logs
+        let lines = self.0.log_tail.lines();
+        if !lines.is_empty() {
+            write!(fmt, "\nRecent log messages:\n")?;
+            for line in lines {
+                writeln!(fmt, "    {}", line)?;
+            }
+        }
         Ok(())
     }
+
+    #[cfg(feature = "error_reporter")]
+    fn forward_error(&self, err: &Error) {
+        if let Some(reporter) = &*self.0.error_reporter.load() {
+            let loaded_crates = self.0.loaded_crates.load();
+            let report = ErrorReport {
+                bot_name: &self.0.info.bot_name,
+                error: err,
+                loaded_crates: loaded_crates.as_deref().map(|x| &**x),
+            };
+            let result = Error::catch_panic(|| {
+                reporter.report(&report);
+                Ok(())
+            });
+            if let Err(e) = result {
+                error!("Error reporter failed: {}", e);
+            }
+        }
+    }
 }
 fn fmt_error(fmt: &mut fmt::Formatter<'_>, e: &Error) -> fmt::Result {
     write!(fmt, "\nThread '{}' encountered an error: {}\n", e.backtrace_thread(), e)?;
     let mut current = e.source();
     while let Some(source) = current {
-        write!(fmt, "Caused by: {}\n", e)?;
+        write!(fmt, "Caused by: {}\n", source)?;
         current = source.source();
     }
     match e.backtrace() {
@@ -141,10 +226,10 @@ fn fmt_deadlock(fmt: &mut fmt::Formatter<'_>, data: &Vec<Vec<DeadlockInfo>>) ->
     Ok(())
 }
 
-fn write_report_file(logs_path: &Path, report: &str) -> Result<PathBuf> {
+fn write_report_file(logs_path: &Path, prefix: &str, report: &str) -> Result<PathBuf> {
     let mut path = PathBuf::from(logs_path);
     fs::create_dir_all(&path)?;
-    let file_name = format!("error_report_{}.log", Utc::now().format("%Y-%m-%d_%H%M%S%f"));
+    let file_name = format!("{}_{}.log", prefix, Utc::now().format("%Y-%m-%d_%H%M%S%f"));
     path.push(file_name);
 
     let mut out = File::create(&path)?;
@@ -154,10 +239,8 @@ fn write_report_file(logs_path: &Path, report: &str) -> Result<PathBuf> {
     Ok(path)
 }
 
-fn write_report(lock: InstanceGuard<ErrorCtx>, report: &str) -> Result<()> {
-    if !lock.is_loaded() {
-        error!("Error encounted during startup/shutdown:\n{}", report);
-    } else {
+fn write_report(ctx: Option<&ErrorCtx>, report: &str) -> Result<()> {
+    if let Some(ctx) = ctx {
         struct FormatErrorReport<'a>(&'a ErrorCtx, &'a str);
         impl <'a> fmt::Display for FormatErrorReport<'a> {
             fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -168,24 +251,82 @@ fn write_report(lock: InstanceGuard<ErrorCtx>, report: &str) -> Result<()> {
             }
         }
 
-        let full_error = FormatErrorReport(&*lock, report).to_string();
-        if let Some(line) = report.trim().split('\n').next() {
-            error!("{}", line);
+        let full_error = FormatErrorReport(ctx, report).to_string();
+        let summary = report.trim().split('\n').next().unwrap_or("").to_string();
+        if !summary.is_empty() {
+            error!("{}", summary);
         }
 
-        // TODO: Better logs dir handling
-        let mut logs_dir = lock.0.info.root_path.clone();
-        logs_dir.push("logs");
-        let report_file = write_report_file(&logs_dir, &full_error)?;
+        let logs_dir = crate::interface::logger::log_path(&ctx.0)?;
+        let report_file = write_report_file(&logs_dir, "error_report", &full_error)?;
+        {
+            let mut recent_errors = ctx.0.recent_errors.lock();
+            if recent_errors.len() == RECENT_ERRORS_COUNT {
+                recent_errors.pop_front();
+            }
+            recent_errors.push_back(RecentError {
+                time: Utc::now(),
+                summary,
+                report_file: report_file.clone(),
+            });
+        }
         error!(
             "Detailed information about this error can be found at '{}'.", report_file.display(),
         );
         // TODO: Proper way to handle error reporting URLs.
         error!("This is probably a bug. Please report it at [TODO] and include the error report.");
+    } else {
+        error!("Error encounted during startup/shutdown:\n{}", report);
     }
     Ok(())
 }
 
+fn write_crash_dump(ctx: &ErrorCtx, info: &panic::PanicInfo<'_>) -> Result<PathBuf> {
+    struct FormatCrash<'a>(&'a ErrorCtx, &'a panic::PanicInfo<'a>);
+    impl <'a> fmt::Display for FormatCrash<'a> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            self.0.fmt_header(f)?;
+            let thread = thread::current();
+            write!(
+                f, "\nThread '{}' panicked: {}",
+                thread.name().unwrap_or("<unnamed>"), payload_to_str(self.1.payload()),
+            )?;
+            if let Some(location) = self.1.location() {
+                write!(f, " at {}", location)?;
+            }
+            write!(f, "\n\n{:?}\n", Backtrace::new())?;
+            self.0.fmt_logs(f)?;
+            Ok(())
+        }
+    }
+
+    let logs_dir = crate::interface::logger::log_path(&ctx.0)?;
+    write_report_file(&logs_dir, "crash", &FormatCrash(ctx, info).to_string())
+}
+
+/// Installs a panic hook that writes a crash dump for panics that are not caught with
+/// [`Error::catch_panic`], before the previous hook runs.
+///
+/// This must be installed before the hook used by [`Error::catch_panic`], so that caught panics
+/// do not produce crash dumps.
+pub fn init_crash_dumps() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(ctx) = current_ctx() {
+                match write_crash_dump(&ctx, info) {
+                    Ok(path) => error!(
+                        "Thread panicked. A crash dump was written to '{}'.", path.display(),
+                    ),
+                    Err(e) => error!("Error while writing crash dump: {}", e),
+                }
+            }
+            default_hook(info)
+        }));
+    });
+}
+
 pub fn init_deadlock_detection() {
     struct FormatDeadlock<'a>(&'a Vec<Vec<DeadlockInfo>>);
     impl <'a> fmt::Display for FormatDeadlock<'a> {
@@ -202,7 +343,7 @@ pub fn init_deadlock_detection() {
                 let deadlock = check_deadlock();
                 if !deadlock.is_empty() {
                     if let Err(e) = write_report(
-                        CURRENT_CTX.load(), &FormatDeadlock(&deadlock).to_string(),
+                        current_ctx().as_ref(), &FormatDeadlock(&deadlock).to_string(),
                     ) {
                         error!("Error while reporting deadlock: {}", e);
                     }
@@ -220,11 +361,14 @@ pub fn report_error(err: &Error) {
         }
     }
 
-    if let Err(e) = write_report(
-        CURRENT_CTX.load(), &FormatError(err).to_string(),
-    ) {
+    let ctx = current_ctx();
+    if let Err(e) = write_report(ctx.as_ref(), &FormatError(err).to_string()) {
         error!("Error while reporting error: {}", e);
     }
+    #[cfg(feature = "error_reporter")]
+    if let Some(ctx) = ctx {
+        ctx.forward_error(err);
+    }
 }
 
 pub(crate) fn get_info_string() -> String {
@@ -234,5 +378,9 @@ pub(crate) fn get_info_string() -> String {
             self.0.fmt_info(f)
         }
     }
-    format!("{}", FormatInfo(&*CURRENT_CTX.load()))
+    match current_ctx() {
+        Some(ctx) => format!("{}", FormatInfo(&ctx)),
+        None => "No information is available, as no instance of Sylphie is running on this \
+                 thread.".to_string(),
+    }
 }
\ No newline at end of file
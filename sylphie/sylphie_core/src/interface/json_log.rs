@@ -0,0 +1,83 @@
+//! Writes log messages as JSON objects, one per line, for log collectors such as Loki.
+
+use chrono::Utc;
+use crate::interface::log_files::RotatingFile;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::Arc;
+use tracing::{Event, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::fmt::FormattedFields;
+use tracing_subscriber::fmt::format::DefaultFields;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+impl <'a> JsonVisitor<'a> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // these are added by the `log` compatibility layer, and are already in the metadata.
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+impl <'a> Visit for JsonVisitor<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+/// A layer that writes each event to a file as a JSON object.
+pub(in super) struct JsonLayer {
+    out: Arc<RotatingFile>,
+}
+impl JsonLayer {
+    pub fn new(out: Arc<RotatingFile>) -> Self {
+        JsonLayer { out }
+    }
+}
+impl <S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for JsonLayer {
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Utc::now().to_rfc3339().into());
+        line.insert("level".to_string(), metadata.level().to_string().into());
+        line.insert("target".to_string(), metadata.target().into());
+        let module_path = metadata.module_path().map_or(Value::Null, Into::into);
+        line.insert("module_path".to_string(), module_path);
+        line.insert("fields".to_string(), fields.into());
+
+        // the fields of each span are already formatted by the console layer.
+        let mut spans = Vec::new();
+        for span in ctx.scope() {
+            let mut span_obj = Map::new();
+            span_obj.insert("name".to_string(), span.name().into());
+            if let Some(fields) = span.extensions().get::<FormattedFields<DefaultFields>>() {
+                span_obj.insert("fields".to_string(), fields.fields.as_str().into());
+            }
+            spans.push(Value::Object(span_obj));
+        }
+        line.insert("spans".to_string(), spans.into());
+
+        // there is nowhere to report a failure to write a log message to.
+        let _ = self.out.write_message(format!("{}\n", Value::Object(line)).as_bytes());
+    }
+}
@@ -0,0 +1,120 @@
+//! Collects warnings and errors so they can be relayed to the bot's administrators in chat.
+
+use chrono::{DateTime, Utc};
+use crate::errors::*;
+use crate::interface::{Interface, InterfaceShared};
+use static_events::prelude_async::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How often collected warnings and errors are sent with a [`LogAlertEvent`].
+pub const LOG_ALERT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The largest number of messages sent in a single [`LogAlertEvent`]. Further messages logged
+/// in the same interval are counted, but not sent.
+pub const MAX_LOG_ALERTS: usize = 10;
+
+/// A warning or error that was logged.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct LogAlert {
+    pub time: DateTime<Utc>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// Dispatched periodically with the warnings and errors logged since it was last dispatched.
+///
+/// Handlers should relay the messages to wherever the bot's administrators will see them, such
+/// as a chat channel. Messages logged by terminal commands are not included, and this is not
+/// dispatched if nothing was logged.
+pub struct LogAlertEvent {
+    alerts: Vec<LogAlert>,
+    dropped: usize,
+}
+failable_event!(LogAlertEvent, (), Error);
+impl LogAlertEvent {
+    /// Returns the messages logged, oldest first.
+    pub fn alerts(&self) -> &[LogAlert] {
+        &self.alerts
+    }
+
+    /// Returns the number of further messages that were logged, but not included because of
+    /// [`MAX_LOG_ALERTS`].
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Formats the messages as text suitable for sending in a single chat message.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for alert in &self.alerts {
+            text.push_str(&format!(
+                "[{}] {} {}: {}\n",
+                alert.time.format("%Y-%m-%d %H:%M:%S UTC"), alert.level, alert.target,
+                alert.message,
+            ));
+        }
+        if self.dropped != 0 {
+            text.push_str(&format!("... and {} more messages.\n", self.dropped));
+        }
+        text
+    }
+}
+
+/// The messages waiting to be sent with the next [`LogAlertEvent`].
+#[derive(Default)]
+pub(in super) struct AlertQueue {
+    alerts: Vec<LogAlert>,
+    dropped: usize,
+}
+
+/// A layer that queues warnings and errors for the next [`LogAlertEvent`].
+pub(in super) struct LogAlertLayer(pub Arc<InterfaceShared>);
+impl <S: Subscriber> Layer<S> for LogAlertLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        // failures to deliver alerts are logged from this module, and must not be resent.
+        if *metadata.level() > Level::WARN ||
+            metadata.target() == "[term]" || metadata.target() == module_path!()
+        {
+            return
+        }
+
+        let mut queue = self.0.log_alerts.lock();
+        if queue.alerts.len() >= MAX_LOG_ALERTS {
+            queue.dropped += 1;
+        } else {
+            let mut visitor = super::MessageVisitor::default();
+            event.record(&mut visitor);
+            queue.alerts.push(LogAlert {
+                time: Utc::now(),
+                level: *metadata.level(),
+                target: metadata.target().to_string(),
+                message: visitor.finish(),
+            });
+        }
+    }
+}
+
+/// Periodically sends the queued warnings and errors until the bot shuts down.
+pub(crate) async fn log_alerts_task(target: Handler<impl Events>) -> Result<()> {
+    let mut interval = tokio::time::interval(LOG_ALERT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let shared = &target.get_service::<Interface>().0.shared;
+        let queue = std::mem::take(&mut *shared.log_alerts.lock());
+        if queue.alerts.is_empty() {
+            continue
+        }
+        let ev = LogAlertEvent { alerts: queue.alerts, dropped: queue.dropped };
+        if let Err(e) = target.dispatch_async(ev).await {
+            warn!("Could not relay warnings and errors: {}", e);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Writes log files that are rotated by date or size, keeping a limited number of old files.
+
+use chrono::{Local, NaiveDate};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The default number of old log files kept.
+pub const DEFAULT_LOG_RETENTION: usize = 14;
+
+/// The number of recent log lines included in error reports.
+const LOG_TAIL_LINES: usize = 100;
+
+/// When log files are rotated.
+///
+/// This can be set with
+/// [`SetupLoggerEvent::set_log_rotation`](`crate::interface::SetupLoggerEvent::set_log_rotation`).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogRotation {
+    /// Starts a new log file every day. Old files are named by the day they were written.
+    Daily,
+    /// Starts a new log file whenever the current file would grow past this many bytes. Old
+    /// files are named by the time they were rotated.
+    Size(u64),
+}
+impl Default for LogRotation {
+    fn default() -> Self {
+        LogRotation::Daily
+    }
+}
+
+struct FileState {
+    file: Option<File>,
+    size: u64,
+    date: NaiveDate,
+}
+
+/// A log file that moves itself aside when it is rotated.
+///
+/// The current file is named `<stem>.log`, and old files are named `<stem>.<time>.log`.
+pub(in super) struct RotatingFile {
+    dir: PathBuf,
+    stem: String,
+    rotation: LogRotation,
+    retain: usize,
+    state: Mutex<FileState>,
+}
+impl RotatingFile {
+    pub fn open(
+        dir: &Path, stem: &str, rotation: LogRotation, retain: usize,
+    ) -> io::Result<Arc<Self>> {
+        let file = RotatingFile {
+            dir: dir.to_owned(),
+            stem: stem.to_string(),
+            rotation,
+            retain,
+            state: Mutex::new(FileState { file: None, size: 0, date: today() }),
+        };
+        let path = file.current_path();
+        let mut state = file.state.lock();
+        state.file = Some(open_append(&path)?);
+        let metadata = fs::metadata(&path)?;
+        state.size = metadata.len();
+        if let Ok(modified) = metadata.modified() {
+            state.date = chrono::DateTime::<Local>::from(modified).naive_local().date();
+        }
+        std::mem::drop(state);
+        Ok(Arc::new(file))
+    }
+
+    fn current_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.log", self.stem))
+    }
+
+    fn needs_rotation(&self, state: &FileState, len: usize) -> bool {
+        match self.rotation {
+            LogRotation::Daily => state.date != today(),
+            LogRotation::Size(max) => state.size > 0 && state.size + len as u64 > max,
+        }
+    }
+
+    fn rotate(&self, state: &mut FileState) -> io::Result<()> {
+        let time = match self.rotation {
+            LogRotation::Daily => state.date.format("%Y-%m-%d").to_string(),
+            LogRotation::Size(_) => Local::now().format("%Y-%m-%d_%H-%M-%S").to_string(),
+        };
+        let mut rotated = self.dir.join(format!("{}.{}.log", self.stem, time));
+        let mut i = 1;
+        while rotated.exists() {
+            rotated = self.dir.join(format!("{}.{}-{}.log", self.stem, time, i));
+            i += 1;
+        }
+
+        // the file is closed first, as open files cannot be renamed on Windows.
+        state.file = None;
+        fs::rename(self.current_path(), rotated)?;
+        state.file = Some(open_append(&self.current_path())?);
+        state.size = 0;
+        state.date = today();
+        self.remove_old_files()
+    }
+
+    fn remove_old_files(&self) -> io::Result<()> {
+        let prefix = format!("{}.", self.stem);
+        let mut old_files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let name = match name.to_str() {
+                Some(name) => name,
+                None => continue,
+            };
+            // other sinks may share the stem, as in `bot.json.log`, but their old files do not
+            // have a time directly after the stem.
+            let is_rotated = name.ends_with(".log") && name.strip_prefix(&prefix)
+                .map_or(false, |x| x.starts_with(|c: char| c.is_ascii_digit()));
+            if is_rotated {
+                old_files.push(name.to_string());
+            }
+        }
+        old_files.sort();
+        let remove_count = old_files.len().saturating_sub(self.retain);
+        for name in &old_files[..remove_count] {
+            fs::remove_file(self.dir.join(name))?;
+        }
+        Ok(())
+    }
+
+    /// Writes a complete log message to the file, rotating it first if needed.
+    pub fn write_message(&self, buf: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock();
+        if state.file.is_some() && self.needs_rotation(&state, buf.len()) {
+            self.rotate(&mut state)?;
+        }
+        if state.file.is_none() {
+            // a failed rotation may have left the file closed.
+            state.file = Some(open_append(&self.current_path())?);
+        }
+        state.file.as_mut().unwrap().write_all(buf)?;
+        state.size += buf.len() as u64;
+        Ok(())
+    }
+}
+
+fn today() -> NaiveDate {
+    Local::today().naive_local()
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// The most recent lines written to the log file, kept in memory for error reports.
+#[derive(Default)]
+pub(in super) struct LogTail(Mutex<VecDeque<String>>);
+impl LogTail {
+    fn push(&self, buf: &[u8]) {
+        let mut lines = self.0.lock();
+        for line in String::from_utf8_lossy(buf).lines() {
+            if lines.len() == LOG_TAIL_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+
+    /// Returns the lines currently kept, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.0.lock().iter().cloned().collect()
+    }
+}
+
+/// The writer given to the `fmt` layer for each log message.
+pub(in super) struct RotatingFileWriter(Arc<RotatingFile>, Arc<LogTail>);
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.push(buf);
+        self.0.write_message(buf)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates writers for a [`RotatingFile`], which also copy messages into a [`LogTail`].
+#[derive(Clone)]
+pub(in super) struct MakeRotatingWriter(pub Arc<RotatingFile>, pub Arc<LogTail>);
+impl MakeWriter for MakeRotatingWriter {
+    type Writer = RotatingFileWriter;
+    fn make_writer(&self) -> Self::Writer {
+        RotatingFileWriter(self.0.clone(), self.1.clone())
+    }
+}
@@ -1,31 +1,104 @@
+use arc_swap::ArcSwap;
 use chrono::Local;
+use crate::cli::CliArgs;
+use crate::config::Config;
 use crate::errors::*;
 use crate::interface::InterfaceShared;
+use crate::interface::console_theme::*;
+use crate::interface::dedup::*;
+use crate::interface::json_log::JsonLayer;
+use crate::interface::log_alerts::LogAlertLayer;
+use crate::interface::log_files::*;
+use crate::interface::otlp::OtlpExporter;
+use crate::interface::remote_console::CaptureLayer;
+use crate::interface::system_log::*;
 use crate::interface::terminal::Terminal;
 use parking_lot::Once;
 use static_events::prelude_async::*;
+use std::any::TypeId;
+use std::collections::BTreeMap;
 use std::fmt::{Result as FmtResult, Write};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
-use tracing::{*, Metadata, Event};
+use std::time::Duration;
+use tracing::{*, Dispatch, Metadata, Event};
+use tracing::level_filters::LevelFilter;
 use tracing::span::{Attributes, Record};
-use tracing::subscriber::DefaultGuard;
-use tracing_subscriber::{FmtSubscriber, EnvFilter};
-use tracing_subscriber::fmt::format::{DefaultFields, Format, Full};
+use tracing::subscriber::{DefaultGuard, Interest};
+use tracing_subscriber::Registry;
 use tracing_subscriber::fmt::time::FormatTime;
 use tracing_subscriber::filter::Directive;
+use tracing_subscriber::layer::{Context, Layer, Layered, SubscriberExt};
 
-// TODO: Logging to file.
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
 
-type EnvSubscriber = FmtSubscriber<DefaultFields, Format<Full, ShortFormatTime>, EnvFilter>;
+/// The parts of an instance's logger that are replaced when it is reloaded.
+struct LoggerLayers {
+    dedup: Option<Dedup>,
+    layers: BoxedLayer,
+}
+impl LoggerLayers {
+    /// Returns the layers used before the logger is started and after it is stopped.
+    fn fallback() -> Self {
+        let layers = Layer::<Registry>::and_then(
+            tracing_subscriber::EnvFilter::new("debug"),
+            tracing_subscriber::fmt::layer().with_timer(ShortFormatTime),
+        );
+        LoggerLayers { dedup: None, layers: Box::new(layers) }
+    }
+}
+
+/// Forwards to the layers that are currently installed, so the logger can be reloaded without
+/// replacing the subscriber the instance's threads use.
+///
+/// Spans are stored in a single registry for the lifetime of the instance, so spans that were
+/// opened before a reload can still be closed after it. Interest in callsites is never cached,
+/// as the filter changes along with the layers.
+struct ReloadLayer(Arc<ArcSwap<LoggerLayers>>);
+impl Layer<Registry> for ReloadLayer {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.0.load().layers.register_callsite(metadata);
+        Interest::sometimes()
+    }
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, Registry>) -> bool {
+        self.0.load().layers.enabled(metadata, ctx)
+    }
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, Registry>) {
+        self.0.load().layers.new_span(attrs, id, ctx)
+    }
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_record(span, values, ctx)
+    }
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_follows_from(span, follows, ctx)
+    }
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_event(event, ctx)
+    }
+    fn on_enter(&self, id: &Id, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_enter(id, ctx)
+    }
+    fn on_exit(&self, id: &Id, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_exit(id, ctx)
+    }
+    fn on_close(&self, id: Id, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_close(id, ctx)
+    }
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, Registry>) {
+        self.0.load().layers.on_id_change(old, new, ctx)
+    }
+}
 
 struct LockingSubscriber {
-    shared: Arc<InterfaceShared>,
+    layers: Arc<ArcSwap<LoggerLayers>>,
     terminal: Arc<Terminal>,
-    underlying: EnvSubscriber,
+    underlying: Layered<ReloadLayer, Registry>,
 }
 impl Subscriber for LockingSubscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.underlying.register_callsite(metadata)
+    }
     fn enabled(&self, metadata: &Metadata<'_>) -> bool {
         self.underlying.enabled(metadata)
     }
@@ -45,9 +118,65 @@ impl Subscriber for LockingSubscriber {
         self.underlying.exit(span)
     }
     fn event(&self, event: &Event<'_>) {
+        if let Some(dedup) = &self.layers.load().dedup {
+            if !dedup.check(event) {
+                return
+            }
+        }
         let _guard = self.terminal.lock_write();
         self.underlying.event(event);
     }
+    fn clone_span(&self, id: &Id) -> Id {
+        self.underlying.clone_span(id)
+    }
+    fn try_close(&self, id: Id) -> bool {
+        self.underlying.try_close(id)
+    }
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.underlying.downcast_raw(id)
+        }
+    }
+}
+
+/// The subscriber used by every thread an instance runs on.
+///
+/// Each instance in the process has its own, so their messages are written to their own log
+/// files. Until the logger is started, messages are written to the console by a fallback.
+#[derive(Clone)]
+pub(in super) struct InstanceLogger {
+    dispatch: Dispatch,
+    layers: Arc<ArcSwap<LoggerLayers>>,
+}
+impl InstanceLogger {
+    pub fn new(terminal: Arc<Terminal>) -> Self {
+        let layers = Arc::new(ArcSwap::from_pointee(LoggerLayers::fallback()));
+        let subscriber = LockingSubscriber {
+            layers: layers.clone(),
+            terminal,
+            underlying: Registry::default().with(ReloadLayer(layers.clone())),
+        };
+        InstanceLogger { dispatch: Dispatch::new(subscriber), layers }
+    }
+
+    /// Makes this the logger of the current thread, until the returned guard is dropped.
+    pub fn enter_thread(&self) -> DefaultGuard {
+        tracing::dispatcher::set_default(&self.dispatch)
+    }
+
+    /// Logs the summaries of collapsed messages whose window has passed.
+    pub fn flush_dedup(&self) {
+        if let Some(dedup) = &self.layers.load().dedup {
+            dedup.flush();
+        }
+    }
+
+    fn install(&self, layers: LoggerLayers) {
+        self.layers.store(Arc::new(layers));
+        tracing::callsite::rebuild_interest_cache();
+    }
 }
 
 struct ShortFormatTime;
@@ -57,10 +186,22 @@ impl FormatTime for ShortFormatTime {
     }
 }
 
+struct FullFormatTime;
+impl FormatTime for FullFormatTime {
+    fn format_time(&self, w: &mut dyn Write) -> FmtResult {
+        write!(w, "{}", Local::now().format("[%Y-%m-%d %H:%M:%S]"))
+    }
+}
+
 pub struct Logger {
-    guard: Option<DefaultGuard>,
+    otlp: Option<OtlpExporter>,
     shared: Arc<InterfaceShared>,
-    terminal: Arc<Terminal>,
+    instance: InstanceLogger,
+}
+impl Drop for Logger {
+    fn drop(&mut self) {
+        self.instance.install(LoggerLayers::fallback());
+    }
 }
 
 pub fn activate_log_compat() {
@@ -71,20 +212,121 @@ pub fn activate_log_compat() {
 }
 
 /// An event that is sent by the logging framework to configure logging.
+///
+/// Log messages are written to the console and to `logs/<bot name>.log` in the bot's root path.
+/// This event is dispatched again whenever the logger is reloaded with
+/// [`Interface::reload_logger`](`crate::interface::Interface::reload_logger`), so settings such
+/// as log rotation can be changed while the bot is running.
+///
+/// By default, messages at the `INFO` level or above are shown for crates that contain loaded
+/// modules, and messages at the `WARN` level or above are shown for every other crate.
 pub struct SetupLoggerEvent {
-    console: tracing_subscriber::EnvFilter,
+    default_level: LevelFilter,
+    crate_levels: BTreeMap<String, LevelFilter>,
+    directives: Vec<Directive>,
+    json_log: bool,
+    rotation: LogRotation,
+    retain: usize,
+    theme: ConsoleTheme,
+    system_log: Option<SystemLog>,
+    dedup_window: Option<Duration>,
 }
 self_event!(SetupLoggerEvent);
 impl SetupLoggerEvent {
+    /// Sets the level of log messages shown for crates that do not contain any loaded modules.
+    /// This defaults to `WARN`.
+    pub fn set_default_level(&mut self, level: LevelFilter) {
+        self.default_level = level;
+    }
+
+    /// Sets the level of log messages shown for a crate, such as `sylphie_core`.
+    ///
+    /// This replaces the default level for the crate, whether or not it contains loaded modules.
+    pub fn set_crate_level(&mut self, crate_name: &str, level: LevelFilter) {
+        self.crate_levels.insert(crate_name.replace('-', "_"), level);
+    }
+
+    /// Adds a raw `env_logger` style filtering directive, such as `sylphie_core::tasks=trace`.
+    ///
+    /// Directives are applied after the levels set with [`SetupLoggerEvent::set_crate_level`],
+    /// and should only be needed for filtering that cannot be done per crate.
     pub fn add_console_directive(&mut self, directive: &str) {
-        let directive = match Directive::from_str(directive) {
-            Ok(x) => x,
-            Err(_) => {
-                error!("Failed to parse logging directive: {}", directive);
-                return
-            }
-        };
-        self.console = std::mem::take(&mut self.console).add_directive(directive);
+        match Directive::from_str(directive) {
+            Ok(x) => self.directives.push(x),
+            Err(_) => error!("Failed to parse logging directive: {}", directive),
+        }
+    }
+
+    /// Additionally writes log messages to `logs/<bot name>.json.log` in the bot's root path.
+    ///
+    /// Each message is written as a JSON object on its own line, with the `timestamp`, `level`,
+    /// `target`, `module_path` and `fields` of the message, and the `spans` it was logged in.
+    /// This is meant to be read by log collectors rather than people, and uses the same filter
+    /// as the console.
+    pub fn enable_json_log(&mut self) {
+        self.json_log = true;
+    }
+
+    /// Additionally forwards log messages to the system logger, using the bot's name to
+    /// identify them.
+    ///
+    /// Log levels are mapped to the matching syslog priorities, with `TRACE` messages sent at
+    /// the `debug` priority. This uses the same filter as the console, and is only available on
+    /// Unix systems.
+    pub fn enable_system_log(&mut self, kind: SystemLog) {
+        self.system_log = Some(kind);
+    }
+
+    /// Sets when log files are rotated. This defaults to [`LogRotation::Daily`].
+    pub fn set_log_rotation(&mut self, rotation: LogRotation) {
+        self.rotation = rotation;
+    }
+
+    /// Sets how many old log files are kept for each log, deleting the oldest files first. This
+    /// defaults to [`DEFAULT_LOG_RETENTION`].
+    pub fn set_log_retention(&mut self, count: usize) {
+        self.retain = count;
+    }
+
+    /// Sets how long identical log messages are collapsed for, or disables collapsing them if
+    /// `None` is given. This defaults to [`DEFAULT_DEDUP_WINDOW`].
+    ///
+    /// When a message is logged repeatedly within the window, only the first copy is logged,
+    /// and a `Message repeated N more times` summary is logged once the window has passed. This
+    /// applies to the console and every log file, but not to responses to terminal commands.
+    pub fn set_dedup_window(&mut self, window: Option<Duration>) {
+        self.dedup_window = window;
+    }
+
+    /// Sets the colors and timestamp format used for log messages in the console.
+    pub fn set_console_theme(&mut self, theme: ConsoleTheme) {
+        self.theme = theme;
+    }
+
+    /// Sets the color of a log level in the console, or leaves it uncolored if `None` is given.
+    pub fn set_level_color(&mut self, level: Level, color: Option<LogColor>) {
+        self.theme.set_level_color(level, color);
+    }
+
+    /// Sets the format of timestamps in the console, as a `chrono` format string. This defaults
+    /// to `[%k:%M:%S]`.
+    pub fn set_timestamp_format(&mut self, format: impl Into<String>) {
+        self.theme.timestamp_format = format.into();
+    }
+
+    /// Sets when log messages in the console are colored. By default, they are colored only if
+    /// standard output is a terminal, and on Windows, only if the console supports ANSI escape
+    /// codes.
+    pub fn set_color_mode(&mut self, mode: ColorMode) {
+        self.theme.color_mode = mode;
+    }
+}
+
+/// Checks that a logging directive can be parsed.
+pub(in super) fn check_directive(directive: &str) -> Result<()> {
+    match Directive::from_str(directive) {
+        Ok(_) => Ok(()),
+        Err(_) => cmd_error!("Invalid logging directive: {}", directive),
     }
 }
 
@@ -101,7 +343,8 @@ pub fn activate_fallback() {
     });
 }
 
-fn log_path(shared: &InterfaceShared) -> Result<PathBuf> {
+/// Returns the directory logs and error reports are written to, creating it if needed.
+pub(in super) fn log_path(shared: &InterfaceShared) -> Result<PathBuf> {
     let mut log_path = shared.info.root_path.clone();
     log_path.push("logs");
 
@@ -112,39 +355,136 @@ fn log_path(shared: &InterfaceShared) -> Result<PathBuf> {
 
     Ok(log_path)
 }
-fn make_logger(
-    core: &Handler<impl Events>, shared: &Arc<InterfaceShared>, terminal: &Arc<Terminal>,
-) -> Result<LockingSubscriber> {
+fn start_otlp(shared: &InterfaceShared) -> Result<Option<OtlpExporter>> {
+    match &*shared.otlp_endpoint.lock() {
+        Some(endpoint) => Ok(Some(OtlpExporter::start(&shared.info.bot_name, endpoint)?)),
+        None => Ok(None),
+    }
+}
+fn set_loaded_crate_levels(
+    ev: &mut SetupLoggerEvent, shared: &InterfaceShared, level: LevelFilter,
+) {
+    if let Some(crates) = &*shared.loaded_crates.load() {
+        for krate in crates.iter() {
+            ev.set_crate_level(krate.crate_path, level);
+        }
+    }
+}
+fn make_layers(
+    core: &Handler<impl Events>, shared: &Arc<InterfaceShared>, otlp: Option<&OtlpExporter>,
+) -> Result<LoggerLayers> {
     let log_path = log_path(shared)?;
 
-    let ev = core.dispatch_sync(SetupLoggerEvent {
-        console: tracing_subscriber::EnvFilter::new("info"),
+    let mut crate_levels = BTreeMap::new();
+    if let Some(crates) = &*shared.loaded_crates.load() {
+        for krate in crates.iter() {
+            crate_levels.insert(krate.crate_path.to_string(), LevelFilter::INFO);
+        }
+    }
+    let mut ev = core.dispatch_sync(SetupLoggerEvent {
+        default_level: LevelFilter::WARN,
+        crate_levels,
+        directives: Vec::new(),
+        json_log: false,
+        rotation: LogRotation::default(),
+        retain: DEFAULT_LOG_RETENTION,
+        theme: ConsoleTheme::default(),
+        system_log: None,
+        dedup_window: Some(DEFAULT_DEDUP_WINDOW),
     });
 
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_timer(ShortFormatTime)
-        .with_env_filter(ev.console)
-        .finish();
-    Ok(LockingSubscriber {
-        shared: shared.clone(),
-        terminal: terminal.clone(),
-        underlying: subscriber,
+    // the defaults set when the core was built take precedence over the ones set by modules,
+    // settings from the configuration file take precedence over both, and `--log-level` takes
+    // precedence over everything.
+    let core_config = core.get_service::<Config>().core();
+    let sources = [
+        (&shared.info.log_defaults, "the bot's defaults"),
+        (&core_config.log, "configuration file"),
+    ];
+    for (log_config, source) in sources.iter() {
+        if let Some(level) = &log_config.level {
+            match LevelFilter::from_str(level) {
+                Ok(level) => set_loaded_crate_levels(&mut ev, shared, level),
+                Err(_) => error!("Invalid log level in {}: {}", source, level),
+            }
+        }
+        for directive in &log_config.directives {
+            ev.add_console_directive(directive);
+        }
+    }
+    if let Some(level) = &core.get_service::<CliArgs>().log_level {
+        match LevelFilter::from_str(level) {
+            Ok(level) => set_loaded_crate_levels(&mut ev, shared, level),
+            Err(_) => ev.add_console_directive(level),
+        }
+    }
+    for (target, level) in &*shared.log_levels.lock() {
+        ev.add_console_directive(&format!("{}={}", target, level));
+    }
+    let mut console = tracing_subscriber::EnvFilter::default()
+        .add_directive(ev.default_level.into());
+    for (crate_name, level) in &ev.crate_levels {
+        match Directive::from_str(&format!("{}={}", crate_name, level)) {
+            Ok(x) => console = console.add_directive(x),
+            Err(_) => error!("Invalid crate name for logging: {}", crate_name),
+        }
+    }
+    for directive in ev.directives {
+        console = console.add_directive(directive);
+    }
+
+    let bot_name = &shared.info.bot_name;
+    let log_file = RotatingFile::open(&log_path, bot_name, ev.rotation, ev.retain)
+        .internal_err(|| "Could not open log file.")?;
+    let json_layer = if ev.json_log {
+        let stem = format!("{}.json", bot_name);
+        let file = RotatingFile::open(&log_path, &stem, ev.rotation, ev.retain)
+            .internal_err(|| "Could not open JSON log file.")?;
+        Some(JsonLayer::new(file))
+    } else {
+        None
+    };
+    let system_log_layer = match ev.system_log {
+        Some(kind) => Some(SystemLogLayer::new(kind, bot_name)?),
+        None => None,
+    };
+
+    let ansi = ev.theme.use_color();
+    let console_layer = tracing_subscriber::fmt::layer()
+        .with_ansi(ansi)
+        .event_format(ConsoleFormat::new(ev.theme, ansi));
+    let layers = Layer::<Registry>::and_then(console, console_layer)
+        .and_then(tracing_subscriber::fmt::layer()
+            .with_timer(FullFormatTime)
+            .with_ansi(false)
+            .with_writer(MakeRotatingWriter(log_file, shared.log_tail.clone())))
+        .and_then(json_layer)
+        .and_then(system_log_layer)
+        .and_then(CaptureLayer)
+        .and_then(LogAlertLayer(shared.clone()))
+        .and_then(otlp.map(|x| x.layer()));
+    Ok(LoggerLayers {
+        dedup: ev.dedup_window.map(Dedup::new),
+        layers: Box::new(layers),
     })
 }
 pub(in super) fn activate(
-    core: &Handler<impl Events>, shared: Arc<InterfaceShared>, terminal: Arc<Terminal>,
+    core: &Handler<impl Events>, shared: Arc<InterfaceShared>, instance: InstanceLogger,
 ) -> Result<Logger> {
     activate_log_compat();
-    let new_logger = make_logger(core, &shared, &terminal)?;
-    let guard = tracing::subscriber::set_default(new_logger);
-    Ok(Logger { guard: Some(guard), shared, terminal })
+    let otlp = start_otlp(&shared)?;
+    instance.install(make_layers(core, &shared, otlp.as_ref())?);
+    Ok(Logger { otlp, shared, instance })
 }
 pub fn reload(
     core: &Handler<impl Events>, guard: &mut Logger,
 ) -> Result<()> {
     activate_log_compat(); // More a procaution than anything
-    let new_logger = make_logger(core, &guard.shared, &guard.terminal)?;
-    guard.guard = None; // Drop the old guard first. The fallback will take over for a bit.
-    guard.guard = Some(tracing::subscriber::set_default(new_logger)); // Set the new logger.
+    // The old exporter is shut down first, so its pending spans are flushed before the new one
+    // starts exporting.
+    guard.otlp = None;
+    let otlp = start_otlp(&guard.shared)?;
+    guard.instance.install(make_layers(core, &guard.shared, otlp.as_ref())?);
+    guard.otlp = otlp;
     Ok(())
 }
\ No newline at end of file
@@ -1,39 +1,80 @@
 //! Handles logging, terminal input, error reporting and related concerns.
 
 use arc_swap::ArcSwapOption;
+use crate::config::LogConfig;
 use crate::errors::*;
-use crate::global_instance::InstanceScopeGuard;
+use crate::metrics::{HandlerCounters, HandlerCountersGuard};
 use crate::module::CrateMetadata;
 use parking_lot::Mutex;
 use static_events::prelude_async::*;
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::subscriber::DefaultGuard;
 
+mod console_theme;
+mod dedup;
 mod error_report;
+mod json_log;
+mod log_alerts;
+mod log_files;
 mod logger;
+mod otlp;
+mod remote_console;
+mod status_bar;
+mod system_log;
 mod terminal;
 
+pub use console_theme::{ColorMode, ConsoleTheme, LogColor};
+pub use dedup::DEFAULT_DEDUP_WINDOW;
+pub use error_report::RecentError;
+pub use log_alerts::{LOG_ALERT_INTERVAL, LogAlert, LogAlertEvent, MAX_LOG_ALERTS};
+pub use log_files::{DEFAULT_LOG_RETENTION, LogRotation};
 pub use logger::SetupLoggerEvent;
-pub use terminal::TerminalCommandEvent;
+pub use remote_console::RemoteConsole;
+pub use system_log::SystemLog;
+pub use terminal::{SetupTerminalEvent, TerminalCommandEvent, TerminalCompleteEvent};
+
+pub(crate) use dedup::dedup_task;
+pub(crate) use log_alerts::log_alerts_task;
+pub(crate) use logger::activate_log_compat;
+pub(crate) use system_log::MessageVisitor;
 
 // TODO: Replace with BotInfo
 pub(crate) struct InterfaceInfo {
     pub bot_name: String,
     pub root_path: PathBuf,
+    /// Whether commands may be read from the terminal at all.
+    pub terminal: bool,
+    /// Log settings that the configuration file and command line take precedence over.
+    pub log_defaults: LogConfig,
 }
 
 struct InterfaceShared {
     info: InterfaceInfo,
+    started: Instant,
     is_shutdown: AtomicBool,
+    is_restart: AtomicBool,
     loaded_crates: ArcSwapOption<Box<[CrateMetadata]>>,
+    log_levels: Mutex<Vec<(String, String)>>,
+    otlp_endpoint: Mutex<Option<String>>,
+    log_tail: Arc<log_files::LogTail>,
+    recent_errors: Mutex<VecDeque<error_report::RecentError>>,
+    log_alerts: Mutex<log_alerts::AlertQueue>,
+    #[cfg(feature = "error_reporter")]
+    error_reporter: ArcSwapOption<Box<dyn ErrorReporter>>,
 }
 
 struct InterfaceData {
     shared: Arc<InterfaceShared>,
     terminal: Arc<terminal::Terminal>,
+    logger: logger::InstanceLogger,
     current_logger: Arc<Mutex<Option<logger::Logger>>>,
-    scope_guard: InstanceScopeGuard<error_report::ErrorCtx>,
+    error_ctx: error_report::ErrorCtx,
+    scope_guard: error_report::ActiveCtxGuard,
+    handler_counters: Arc<HandlerCounters>,
 }
 struct LoggerLockGuard<'a>(&'a InterfaceData);
 impl <'a> Drop for LoggerLockGuard<'a> {
@@ -42,6 +83,13 @@ impl <'a> Drop for LoggerLockGuard<'a> {
     }
 }
 
+/// Keeps the current thread logging to an instance and reporting errors to it, until dropped.
+pub(crate) struct ThreadGuard {
+    _logger: DefaultGuard,
+    _error_ctx: error_report::ThreadCtxGuard,
+    _handler_counters: HandlerCountersGuard,
+}
+
 /// A handle to services related to logging, the user interface, and error reporting.
 #[derive(Clone)]
 pub struct Interface(Arc<InterfaceData>);
@@ -49,23 +97,54 @@ impl Interface {
     pub(crate) fn new(info: InterfaceInfo) -> Result<Interface> {
         let shared = Arc::new(InterfaceShared {
             info,
+            started: Instant::now(),
             is_shutdown: AtomicBool::new(false),
+            is_restart: AtomicBool::new(false),
             loaded_crates: ArcSwapOption::empty(),
+            log_levels: Mutex::new(Vec::new()),
+            otlp_endpoint: Mutex::new(None),
+            log_tail: Default::default(),
+            recent_errors: Mutex::new(VecDeque::new()),
+            log_alerts: Default::default(),
+            #[cfg(feature = "error_reporter")]
+            error_reporter: ArcSwapOption::empty(),
         });
-        let error_ctx = error_report::ErrorCtx::new(shared.clone()).activate();
+        let error_ctx = error_report::ErrorCtx::new(shared.clone());
+        let scope_guard = error_ctx.clone().activate();
         let terminal = Arc::new(terminal::Terminal::new(shared.clone())?);
         Ok(Interface(Arc::new(InterfaceData {
             shared,
+            logger: logger::InstanceLogger::new(terminal.clone()),
             terminal,
             current_logger: Arc::new(Mutex::new(None)),
-            scope_guard: error_ctx,
+            error_ctx,
+            scope_guard,
+            handler_counters: Default::default(),
         })))
     }
 
+    /// Makes the current thread write log messages to this instance's logger, and report errors
+    /// and panics to this instance, until the returned guard is dropped.
+    ///
+    /// This must be called on every thread the instance runs on, so that several instances can
+    /// run in the same process without their messages being mixed up.
+    pub(crate) fn enter_thread(&self) -> ThreadGuard {
+        ThreadGuard {
+            _logger: self.0.logger.enter_thread(),
+            _error_ctx: self.0.error_ctx.enter_thread(),
+            _handler_counters: self.0.handler_counters.enter_thread(),
+        }
+    }
+
+    /// Returns the counters of the event handlers called on this instance's threads.
+    pub(crate) fn handler_counters(&self) -> &Arc<HandlerCounters> {
+        &self.0.handler_counters
+    }
+
     pub(crate) fn start(&self, target: &Handler<impl Events>) -> Result<()> {
         let _lock_guard = {
             let mut lock = self.0.current_logger.lock();
-            let logger = logger::activate(target, self.0.shared.clone(), self.0.terminal.clone())?;
+            let logger = logger::activate(target, self.0.shared.clone(), self.0.logger.clone())?;
             *lock = Some(logger);
             LoggerLockGuard(&self.0)
         };
@@ -77,10 +156,37 @@ impl Interface {
         self.0.shared.is_shutdown.store(true, Ordering::Relaxed)
     }
 
+    pub(crate) fn set_restart(&self) {
+        self.0.shared.is_restart.store(true, Ordering::Relaxed)
+    }
+
+    /// Returns whether the terminal was disabled when the core was built.
+    pub(crate) fn is_terminal_disabled(&self) -> bool {
+        !self.0.shared.info.terminal
+    }
+
+    pub(crate) fn is_restart(&self) -> bool {
+        self.0.shared.is_restart.load(Ordering::Relaxed)
+    }
+
     pub(crate) fn set_loaded_crates(&self, crates: Arc<[CrateMetadata]>) {
         self.0.shared.loaded_crates.store(Some(Arc::new(crates.to_vec().into())));
     }
 
+    #[cfg(feature = "error_reporter")]
+    pub(crate) fn set_error_reporter(&self, reporter: Box<dyn ErrorReporter>) {
+        self.0.shared.error_reporter.store(Some(Arc::new(reporter)));
+    }
+
+    /// Returns whether terminal commands are read from an interactive terminal, rather than the
+    /// bot running without one, such as under a service manager.
+    ///
+    /// Only one instance in a process reads from the terminal. Any other instances, and ones
+    /// built with the terminal disabled, run as if standard input was not a terminal.
+    pub fn is_interactive(&self) -> bool {
+        self.0.terminal.is_interactive()
+    }
+
     /// Reloads the logger, to reflect any configuration changes that may have occurred since.
     ///
     /// If no logger is currently active, this method will return an error.
@@ -89,6 +195,93 @@ impl Interface {
         let handle = lock.as_mut().internal_err(|| "Logger is not running.")?;
         logger::reload(target, handle)
     }
+
+    /// Overrides the level of log messages shown for a target, such as a crate or module path,
+    /// and reloads the logger.
+    ///
+    /// The override is applied after the directives added by [`SetupLoggerEvent`], and lasts
+    /// until the bot is restarted. If `level` is `None`, any override for the target is removed.
+    pub fn set_log_level(
+        &self, target: &Handler<impl Events>, log_target: &str, level: Option<&str>,
+    ) -> Result<()> {
+        {
+            let mut log_levels = self.0.shared.log_levels.lock();
+            log_levels.retain(|(x, _)| x != log_target);
+            if let Some(level) = level {
+                let directive = format!("{}={}", log_target, level.to_ascii_lowercase());
+                logger::check_directive(&directive)?;
+                log_levels.push((log_target.to_string(), level.to_ascii_lowercase()));
+            }
+        }
+        self.reload_logger(target)
+    }
+
+    /// Returns the log level overrides set with [`Interface::set_log_level`].
+    pub fn log_levels(&self) -> Vec<(String, String)> {
+        self.0.shared.log_levels.lock().clone()
+    }
+
+    /// Returns the most recent lines written to the log file, oldest first.
+    pub fn recent_log_lines(&self) -> Vec<String> {
+        self.0.shared.log_tail.lines()
+    }
+
+    /// Returns the errors reported since the bot was started, oldest first.
+    ///
+    /// Only the last 20 errors are kept. Errors that occur while the bot is starting up or
+    /// shutting down are not included.
+    pub fn recent_errors(&self) -> Vec<RecentError> {
+        self.0.shared.recent_errors.lock().iter().cloned().collect()
+    }
+
+    /// Prints a response to a terminal command.
+    ///
+    /// Responses taller than the terminal are shown one page at a time once the command
+    /// finishes, with space to show the next page and `q` to skip the rest. Paged responses are
+    /// not written to the log file. Responses to commands from a remote console, or when standard
+    /// input is not a terminal, are never paged.
+    pub fn print_terminal_response(&self, text: &str) {
+        let is_remote = remote_console::is_capturing();
+        let is_paged = !is_remote && self.0.terminal.is_interactive();
+        if is_paged && text.lines().count() > terminal::page_height() {
+            self.0.terminal.page(text);
+        } else {
+            info!(target: "[term]", "{}", text);
+        }
+    }
+
+    /// Shows a status line below the terminal output, such as the progress of a long running
+    /// terminal command, replacing any status line shown before.
+    ///
+    /// The line is removed when `None` is given, or once the current terminal command finishes.
+    /// Log messages are printed above it. This does nothing for commands from a remote console.
+    pub fn set_terminal_status(&self, status: Option<&str>) {
+        if remote_console::is_capturing() {
+            return
+        }
+        if let Err(e) = self.0.terminal.set_status(status.map(|x| x.to_string())) {
+            e.report_error();
+        }
+    }
+
+    /// Sets the OTLP collector that spans are exported to, such as `http://localhost:4317`, and
+    /// reloads the logger. If `endpoint` is `None`, spans are no longer exported.
+    ///
+    /// Spans are filtered in the same way as log messages, so the `trace` level spans around
+    /// event handlers are only exported if that level is enabled for `sylphie_core`. Metrics are
+    /// not exported over OTLP, and are only served in the Prometheus format. This requires the
+    /// `otlp` feature.
+    pub fn set_otlp_endpoint(
+        &self, target: &Handler<impl Events>, endpoint: Option<&str>,
+    ) -> Result<()> {
+        *self.0.shared.otlp_endpoint.lock() = endpoint.map(|x| x.to_string());
+        self.reload_logger(target)
+    }
+
+    /// Returns the OTLP collector set with [`Interface::set_otlp_endpoint`].
+    pub fn otlp_endpoint(&self) -> Option<String> {
+        self.0.shared.otlp_endpoint.lock().clone()
+    }
 }
 
 impl Error {
@@ -102,6 +295,7 @@ pub(crate) fn init_interface() {
     logger::activate_log_compat();
     logger::activate_fallback();
     error_report::init_deadlock_detection();
+    error_report::init_crash_dumps();
 }
 pub(crate) fn get_info_string() -> String {
     error_report::get_info_string()
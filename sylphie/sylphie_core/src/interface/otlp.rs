@@ -0,0 +1,71 @@
+//! Exports spans to an OpenTelemetry collector over OTLP.
+//!
+//! Commands, event handlers and database queries each run in their own span, so their latencies
+//! can be read from the span durations reported to the collector.
+//!
+//! Only spans are exported. The metrics collected with
+//! [`CollectMetricsEvent`](crate::metrics::CollectMetricsEvent) are not sent over OTLP, as the
+//! OTLP exporter used here does not support metrics. They are served in the Prometheus format
+//! instead, as described in the [`metrics`](crate::metrics) module.
+
+use crate::errors::*;
+
+#[cfg(not(feature = "otlp"))]
+use tracing_subscriber::layer::Identity;
+#[cfg(feature = "otlp")]
+use opentelemetry::{KeyValue, sdk::Resource, sdk::trace::{self, Tracer, TracerProvider}};
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::TracerProvider as _;
+#[cfg(feature = "otlp")]
+use opentelemetry_otlp::{Exporter, ExporterConfig};
+#[cfg(feature = "otlp")]
+use tracing::Subscriber;
+#[cfg(feature = "otlp")]
+use tracing_opentelemetry::OpenTelemetryLayer;
+#[cfg(feature = "otlp")]
+use tracing_subscriber::registry::LookupSpan;
+
+/// A running OTLP exporter. Pending spans are flushed when this is dropped.
+///
+/// Each exporter has its own tracer provider rather than installing a global one, so several
+/// bots running in the same process can each export to their own collector.
+#[cfg(feature = "otlp")]
+pub(in super) struct OtlpExporter {
+    tracer: Tracer,
+    _provider: TracerProvider,
+}
+#[cfg(feature = "otlp")]
+impl OtlpExporter {
+    pub fn start(bot_name: &str, endpoint: &str) -> Result<OtlpExporter> {
+        let resource = Resource::new(vec![KeyValue::new("service.name", bot_name.to_string())]);
+        let exporter = Exporter::new(ExporterConfig {
+            endpoint: endpoint.to_string(),
+            ..Default::default()
+        });
+        let provider = TracerProvider::builder()
+            .with_exporter(exporter)
+            .with_config(trace::config().with_resource(resource))
+            .build();
+        let tracer = provider.get_tracer("sylphie", Some(env!("CARGO_PKG_VERSION")));
+        Ok(OtlpExporter { tracer, _provider: provider })
+    }
+
+    pub fn layer<S: Subscriber + for<'a> LookupSpan<'a>>(&self) -> OpenTelemetryLayer<S, Tracer> {
+        tracing_opentelemetry::layer().with_tracer(self.tracer.clone())
+    }
+}
+
+/// A placeholder for the exporter when Sylphie is built without OTLP support. As it has no
+/// values, an exporter can never be started.
+#[cfg(not(feature = "otlp"))]
+pub(in super) enum OtlpExporter { }
+#[cfg(not(feature = "otlp"))]
+impl OtlpExporter {
+    pub fn start(_: &str, _: &str) -> Result<OtlpExporter> {
+        cmd_error!("Sylphie was built without support for OTLP. Enable the `otlp` feature.")
+    }
+
+    pub fn layer(&self) -> Identity {
+        match *self { }
+    }
+}
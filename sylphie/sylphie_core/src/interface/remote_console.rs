@@ -0,0 +1,253 @@
+//! Accepts terminal commands from other processes over a socket.
+//!
+//! The protocol is line based, so the console can be used with tools such as `socat` or `nc`.
+//! A client first sends the token from `remote_console.token` in the bot's root path, and the
+//! bot answers with `ok`, or `error: <reason>` before closing the connection. Each line sent
+//! after that is run as a terminal command, and the bot answers with the output of the command
+//! followed by a line containing only `.`. Output lines that start with `.` have another `.`
+//! prepended to them, so they can not be mistaken for the end of the output.
+
+use crate::errors::*;
+use crate::interface::{InterfaceShared, TerminalCommandEvent};
+use crate::interface::system_log::MessageVisitor;
+use crate::interface::terminal::loggable_command;
+use crate::tokens::{load_token, tokens_match};
+use static_events::prelude_async::*;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{Event, Level, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How often connections check whether the bot is shutting down.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(500);
+
+/// Where the remote console accepts connections.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RemoteConsole {
+    /// A Unix domain socket at `remote_console/console.sock` in the bot's root path. Only the
+    /// user running the bot can open the directory it is in, so no one else can connect to it.
+    /// This is only available on Unix systems.
+    UnixSocket,
+    /// A TCP port on `127.0.0.1`.
+    Tcp(u16),
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Vec<String>>> = RefCell::new(None);
+}
+
+/// Returns whether log messages on this thread are being sent to a remote console.
+pub(in super) fn is_capturing() -> bool {
+    CAPTURE.with(|x| x.borrow().is_some())
+}
+
+/// Runs a function, collecting the output it logs for the remote console.
+fn capture(func: impl FnOnce()) -> Vec<String> {
+    CAPTURE.with(|x| *x.borrow_mut() = Some(Vec::new()));
+    func();
+    CAPTURE.with(|x| x.borrow_mut().take()).unwrap_or_default()
+}
+
+/// A layer that collects responses to terminal commands, and any warnings or errors logged
+/// while a remote console command runs.
+pub(in super) struct CaptureLayer;
+impl <S: Subscriber> Layer<S> for CaptureLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        CAPTURE.with(|capture| {
+            if let Some(capture) = &mut *capture.borrow_mut() {
+                let normalized = event.normalized_metadata();
+                let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+                let prefix = match *metadata.level() {
+                    _ if metadata.target() == "[term]" => "",
+                    Level::ERROR => "error: ",
+                    Level::WARN => "warning: ",
+                    _ => return,
+                };
+                let mut visitor = MessageVisitor::default();
+                event.record(&mut visitor);
+                capture.push(format!("{}{}", prefix, visitor.finish()));
+            }
+        })
+    }
+}
+
+/// Formats the output of a command for sending to a client.
+fn encode_output(output: &[String]) -> String {
+    let mut encoded = String::new();
+    for line in output.iter().flat_map(|x| x.lines()) {
+        if line.starts_with('.') {
+            encoded.push('.');
+        }
+        encoded.push_str(line);
+        encoded.push('\n');
+    }
+    encoded.push_str(".\n");
+    encoded
+}
+
+/// Runs a terminal command, returning the output logged for it.
+async fn run_command<E: Events>(target: &Handler<E>, line: String) -> Result<Vec<String>> {
+    let target = target.clone();
+    let output = tokio::task::spawn_blocking(move || capture(|| {
+        // the command is run on this thread, so everything it logs is captured.
+        let result = tokio::runtime::Handle::current().block_on(
+            Error::catch_panic_async(async {
+                target.dispatch_async(TerminalCommandEvent(line)).await;
+                Ok(())
+            }),
+        );
+        if let Err(e) = result {
+            e.report_error();
+        }
+    })).await.internal_err(|| "Remote console command could not be run.")?;
+    Ok(output)
+}
+
+/// The settings shared by every remote console connection.
+struct ConsoleSettings {
+    token: String,
+    secret_commands: Vec<String>,
+}
+
+async fn serve_connection<E: Events>(
+    target: Handler<E>, shared: Arc<InterfaceShared>, settings: Arc<ConsoleSettings>,
+    stream: impl AsyncRead + AsyncWrite,
+) -> Result<()> {
+    let (read, mut write) = tokio::io::split(stream);
+    let mut lines = BufReader::new(read).lines();
+    let mut is_authenticated = false;
+    while !shared.is_shutdown.load(Ordering::Relaxed) {
+        let line = match tokio::time::timeout(SHUTDOWN_POLL, lines.next_line()).await {
+            Ok(line) => match line? {
+                Some(line) => line,
+                None => break,
+            },
+            Err(_) => continue,
+        };
+        if !is_authenticated {
+            if tokens_match(line.trim(), &settings.token) {
+                is_authenticated = true;
+                write.write_all(b"ok\n").await?;
+                continue
+            } else {
+                warn!("Rejected a remote console connection with an invalid token.");
+                write.write_all(b"error: invalid token\n").await?;
+                break
+            }
+        }
+        if line.trim().is_empty() {
+            continue
+        }
+        info!("Remote console command: {}", loggable_command(&line, &settings.secret_commands));
+        let output = run_command(&target, line).await?;
+        write.write_all(encode_output(&output).as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn spawn_connection<E: Events>(
+    target: &Handler<E>, shared: &Arc<InterfaceShared>, settings: &Arc<ConsoleSettings>,
+    stream: impl AsyncRead + AsyncWrite + Send + 'static,
+) {
+    let future = serve_connection(target.clone(), shared.clone(), settings.clone(), stream);
+    tokio::spawn(async move {
+        if let Err(e) = Error::catch_panic_async(future).await {
+            debug!("Remote console connection closed with error: {}", e);
+        }
+    });
+}
+
+/// Returns the path of the socket used by [`RemoteConsole::UnixSocket`].
+fn socket_dir(shared: &InterfaceShared) -> PathBuf {
+    shared.info.root_path.join("remote_console")
+}
+fn socket_path(shared: &InterfaceShared) -> PathBuf {
+    socket_dir(shared).join("console.sock")
+}
+
+/// Starts accepting remote console connections.
+///
+/// Lines running one of `secret_commands` are logged without their arguments.
+pub(in super) fn start<E: Events>(
+    target: &Handler<E>, shared: Arc<InterfaceShared>, kind: RemoteConsole,
+    secret_commands: Vec<String>,
+) -> Result<()> {
+    let token_path = shared.info.root_path.join("remote_console.token");
+    let token = load_token(&token_path, "remote console")?;
+    let settings = Arc::new(ConsoleSettings { token, secret_commands });
+    let target = target.clone();
+    match kind {
+        #[cfg(unix)]
+        RemoteConsole::UnixSocket => {
+            use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+            // the socket is created with permissions from the umask, so it is bound inside a
+            // directory only the bot's user can open rather than relying on setting its
+            // permissions afterwards.
+            let dir = socket_dir(&shared);
+            if !dir.is_dir() {
+                std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+            }
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+            let path = socket_path(&shared);
+            if path.exists() {
+                // left over from a previous run of the bot.
+                std::fs::remove_file(&path)?;
+            }
+            let listener = std::os::unix::net::UnixListener::bind(&path)
+                .internal_err(|| format!("Could not bind to '{}'.", path.display()))?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+            listener.set_nonblocking(true)?;
+            let mut listener = tokio::net::UnixListener::from_std(listener)?;
+            info!("Remote console listening at '{}'.", path.display());
+            tokio::spawn(async move {
+                while !shared.is_shutdown.load(Ordering::Relaxed) {
+                    match tokio::time::timeout(SHUTDOWN_POLL, listener.accept()).await {
+                        Ok(Ok((stream, _))) =>
+                            spawn_connection(&target, &shared, &settings, stream),
+                        Ok(Err(e)) => warn!("Could not accept remote console connection: {}", e),
+                        Err(_) => { }
+                    }
+                }
+                let _ = std::fs::remove_file(socket_path(&shared));
+            });
+        }
+        #[cfg(not(unix))]
+        RemoteConsole::UnixSocket =>
+            cmd_error!("Remote consoles on Unix sockets are only available on Unix systems."),
+        RemoteConsole::Tcp(port) => {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+                .internal_err(|| format!("Could not bind to port {}.", port))?;
+            listener.set_nonblocking(true)?;
+            let mut listener = tokio::net::TcpListener::from_std(listener)?;
+            info!("Remote console listening on 127.0.0.1:{}.", port);
+            tokio::spawn(async move {
+                while !shared.is_shutdown.load(Ordering::Relaxed) {
+                    match tokio::time::timeout(SHUTDOWN_POLL, listener.accept()).await {
+                        Ok(Ok((stream, _))) =>
+                            spawn_connection(&target, &shared, &settings, stream),
+                        Ok(Err(e)) => warn!("Could not accept remote console connection: {}", e),
+                        Err(_) => { }
+                    }
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_encoding() {
+        let output = vec!["Loaded modules:\n    .hidden".to_string(), ".".to_string()];
+        assert_eq!(encode_output(&output), "Loaded modules:\n    .hidden\n..\n.\n");
+    }
+}
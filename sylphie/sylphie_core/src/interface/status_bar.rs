@@ -0,0 +1,77 @@
+//! Builds the status bar shown above the terminal prompt.
+
+use chrono::Local;
+use crate::interface::InterfaceShared;
+use crate::metrics::{CONNECTED_METRIC, MetricsRegistry, MetricsSnapshot};
+use crate::stats::format_uptime;
+use crate::tasks::TaskManager;
+use static_events::prelude_async::*;
+use std::time::Duration;
+
+/// How often the status bar is refreshed.
+pub(in super) const STATUS_BAR_REFRESH: Duration = Duration::from_secs(1);
+
+/// Returns the text of the status bar.
+///
+/// Connectors are read from the most recent metrics snapshot, so they may lag behind by up to
+/// [`COLLECT_METRICS_INTERVAL`](`crate::metrics::COLLECT_METRICS_INTERVAL`).
+pub(in super) fn status_text(target: &Handler<impl Events>, shared: &InterfaceShared) -> String {
+    let mut text = format!("up {}", format_uptime(shared.started.elapsed()));
+
+    let snapshot = target.get_service::<MetricsRegistry>().snapshot();
+    let connected = connected_names(&snapshot);
+    if connected.is_empty() {
+        text.push_str(" | no connectors");
+    } else {
+        text.push_str(&format!(" | connected: {}", connected.join(", ")));
+    }
+
+    let tasks = target.get_service::<TaskManager>().list_tasks().len();
+    text.push_str(&format!(" | tasks: {}", tasks));
+
+    if let Some(error) = shared.recent_errors.lock().back() {
+        text.push_str(&format!(
+            " | last error: [{}] {}",
+            error.time.with_timezone(&Local).format("%k:%M:%S"), error.summary,
+        ));
+    }
+    text
+}
+
+/// Returns the names of the connections that are currently connected, using the `connection`
+/// label of the gauge if it has one, and the name of the reporting module otherwise.
+fn connected_names(snapshot: &MetricsSnapshot) -> Vec<&str> {
+    snapshot.metrics.iter()
+        .filter(|x| x.name == CONNECTED_METRIC && x.value > 0.0)
+        .map(|x| match x.labels.iter().find(|(k, _)| k == "connection") {
+            Some((_, name)) => name.as_str(),
+            None => &*x.module,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::CollectMetricsEvent;
+    use crate::module::ModuleInfo;
+    use std::time::SystemTime;
+
+    #[test]
+    fn lists_connected_gauges() {
+        let connections = ModuleInfo::for_test("sylphie_connections");
+        let irc = ModuleInfo::for_test("sylphie_irc");
+        let mut ev = CollectMetricsEvent::new();
+        ev.labeled_gauge(&connections, CONNECTED_METRIC, &[("connection", "main")], 1.0);
+        ev.labeled_gauge(&connections, CONNECTED_METRIC, &[("connection", "backup")], 0.0);
+        ev.labeled_gauge(&connections, CONNECTED_METRIC, &[("connection", "partial")], 0.5);
+        ev.gauge(&irc, CONNECTED_METRIC, 1.0);
+        ev.gauge(&irc, "connected_channels", 3.0);
+
+        let snapshot = MetricsSnapshot {
+            collected_at: SystemTime::now(),
+            metrics: ev.into_metrics(),
+        };
+        assert_eq!(connected_names(&snapshot), ["main", "partial", "sylphie_irc"]);
+    }
+}
@@ -0,0 +1,193 @@
+//! Forwards log messages to the system logger, either a syslog daemon or systemd-journald.
+
+use crate::errors::*;
+use std::fmt::{self, Write};
+use tracing::{Event, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::layer::{Context, Layer};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use tracing::Level;
+#[cfg(unix)]
+use tracing_log::NormalizeEvent;
+
+/// A system logger that log messages can be forwarded to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SystemLog {
+    /// The local syslog daemon, through `/dev/log`. Messages are sent with the `daemon`
+    /// facility.
+    Syslog,
+    /// systemd-journald, through its native protocol. The target, source file and line of each
+    /// message are sent as the `TARGET`, `CODE_FILE` and `CODE_LINE` fields.
+    Journald,
+}
+#[cfg(unix)]
+impl SystemLog {
+    fn socket_path(self) -> &'static str {
+        match self {
+            SystemLog::Syslog => "/dev/log",
+            SystemLog::Journald => "/run/systemd/journal/socket",
+        }
+    }
+}
+
+/// Returns the syslog severity of a log level.
+///
+/// `TRACE` has no equivalent severity, so it is sent as `debug` like `DEBUG` messages.
+#[cfg(unix)]
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3, // err
+        Level::WARN => 4, // warning
+        Level::INFO => 6, // info
+        _ => 7, // debug
+    }
+}
+
+#[cfg(unix)]
+const FACILITY_DAEMON: u8 = 3;
+
+/// Collects the fields of an event into a single line, with the message first.
+#[derive(Default)]
+pub(crate) struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+impl MessageVisitor {
+    pub fn finish(mut self) -> String {
+        if !self.fields.is_empty() {
+            if !self.message.is_empty() {
+                self.message.push(' ');
+            }
+            self.message.push_str(&self.fields);
+        }
+        self.message
+    }
+}
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value)
+        }
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else if !field.name().starts_with("log.") {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Appends a field to a message in journald's native protocol.
+#[cfg(unix)]
+fn push_journal_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        // values containing newlines are sent with an explicit length.
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+/// A layer that sends each event to the system logger.
+///
+/// This can only be created on Unix systems.
+pub(in super) struct SystemLogLayer {
+    #[cfg(unix)]
+    kind: SystemLog,
+    #[cfg(unix)]
+    ident: String,
+    #[cfg(unix)]
+    socket: UnixDatagram,
+}
+impl SystemLogLayer {
+    #[cfg(unix)]
+    pub fn new(kind: SystemLog, ident: &str) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(kind.socket_path())
+            .internal_err(|| format!("Could not connect to {}.", kind.socket_path()))?;
+        Ok(SystemLogLayer { kind, ident: ident.to_string(), socket })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(kind: SystemLog, _: &str) -> Result<Self> {
+        cmd_error!("{:?} is only available on Unix systems.", kind)
+    }
+
+    #[cfg(unix)]
+    fn format(&self, event: &Event<'_>) -> Vec<u8> {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let message = visitor.finish();
+
+        let severity = severity(metadata.level());
+        match self.kind {
+            SystemLog::Syslog => {
+                let priority = FACILITY_DAEMON * 8 + severity;
+                format!(
+                    "<{}>{}[{}]: {}: {}",
+                    priority, self.ident, std::process::id(), metadata.target(), message,
+                ).into_bytes()
+            }
+            SystemLog::Journald => {
+                let mut buf = Vec::new();
+                push_journal_field(&mut buf, "PRIORITY", &severity.to_string());
+                push_journal_field(&mut buf, "SYSLOG_IDENTIFIER", &self.ident);
+                push_journal_field(&mut buf, "MESSAGE", &message);
+                push_journal_field(&mut buf, "TARGET", metadata.target());
+                if let Some(file) = metadata.file() {
+                    push_journal_field(&mut buf, "CODE_FILE", file);
+                }
+                if let Some(line) = metadata.line() {
+                    push_journal_field(&mut buf, "CODE_LINE", &line.to_string());
+                }
+                buf
+            }
+        }
+    }
+}
+impl <S: Subscriber> Layer<S> for SystemLogLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        #[cfg(unix)]
+        {
+            // there is nowhere to report a failure to send a log message to.
+            let _ = self.socket.send(&self.format(event));
+        }
+        #[cfg(not(unix))]
+        {
+            // the layer cannot be created on other systems.
+            let _ = event;
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_fields() {
+        let mut buf = Vec::new();
+        push_journal_field(&mut buf, "PRIORITY", "6");
+        push_journal_field(&mut buf, "MESSAGE", "a\nb");
+        let mut expected = b"PRIORITY=6\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(buf, expected);
+    }
+}
@@ -1,56 +1,648 @@
+use crate::cli::CliArgs;
 use crate::errors::*;
 use crate::interface::InterfaceShared;
+use crate::interface::console_theme::ansi_supported;
+use crate::interface::remote_console::{self, RemoteConsole};
+use crate::interface::status_bar::{self, STATUS_BAR_REFRESH};
 use linefeed::{
-    Interface as LinefeedInterface, DefaultTerminal, Signal, ReadResult, Writer,
+    Interface as LinefeedInterface, Command, DefaultTerminal, Function, Signal, ReadResult, Writer,
 };
+use linefeed::complete::{Completer, Completion};
+use linefeed::prompter::Prompter;
+use parking_lot::{Mutex, MutexGuard};
 use static_events::prelude_async::*;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, Write as IoWrite};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::*;
+use terminal_size::{Height, Width, terminal_size};
 
 pub struct TerminalCommandEvent(pub String);
 simple_event!(TerminalCommandEvent);
 
-pub struct TerminalLock<'a, 'b>(Writer<'a, 'b, DefaultTerminal>);
+/// An event that is sent when Tab is pressed in the terminal, to collect completions for the
+/// word under the cursor.
+pub struct TerminalCompleteEvent {
+    line: String,
+    word: String,
+    completions: Vec<String>,
+}
+self_event!(TerminalCompleteEvent);
+impl TerminalCompleteEvent {
+    /// Returns the input up to the start of the word being completed.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// Returns the part of the word being completed that has already been typed.
+    pub fn word(&self) -> &str {
+        &self.word
+    }
+
+    /// Returns the words before the word being completed.
+    ///
+    /// When completing a command name, this is empty. When completing its first argument, this
+    /// contains only the command name.
+    pub fn previous_words(&self) -> Vec<&str> {
+        self.line.split_whitespace().collect()
+    }
+
+    /// Offers a completion for the word, if it starts with what has already been typed.
+    pub fn add_completion(&mut self, completion: impl Into<String>) {
+        let completion = completion.into();
+        if completion.to_ascii_lowercase().starts_with(&self.word.to_ascii_lowercase()) {
+            self.completions.push(completion);
+        }
+    }
+}
+
+/// Returns whether a line runs one of the commands added with
+/// [`SetupTerminalEvent::add_secret_command`].
+pub(in super) fn is_secret_command(line: &str, secret_commands: &[String]) -> bool {
+    let name = line.split_whitespace().next().unwrap_or("");
+    secret_commands.iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+/// Returns a line as it is written to the log, without the arguments of secret commands.
+pub(in super) fn loggable_command<'a>(line: &'a str, secret_commands: &[String]) -> Cow<'a, str> {
+    if is_secret_command(line, secret_commands) {
+        let name = line.split_whitespace().next().unwrap_or("");
+        format!("{} (arguments hidden)", name).into()
+    } else {
+        line.into()
+    }
+}
+
+/// Creates the history file if needed, so that only the user running the bot can read it.
+fn secure_history_file(path: &Path) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        options.open(path)?;
+        // files created before this was done have the default permissions.
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    options.open(path)?;
+    Ok(())
+}
+
+struct EventCompleter<E: Events>(Handler<E>);
+impl <E: Events, T: linefeed::Terminal> Completer<T> for EventCompleter<E> {
+    fn complete(
+        &self, word: &str, prompter: &Prompter<T>, start: usize, _end: usize,
+    ) -> Option<Vec<Completion>> {
+        let ev = self.0.dispatch_sync(TerminalCompleteEvent {
+            line: prompter.buffer()[..start].to_string(),
+            word: word.to_string(),
+            completions: Vec::new(),
+        });
+        let mut completions = ev.completions;
+        completions.sort();
+        completions.dedup();
+        Some(completions.into_iter().map(Completion::simple).collect())
+    }
+}
+
+/// An event that is sent when the terminal starts, to configure it.
+///
+/// Input history is saved to `terminal_history.txt` in the bot's root path, and restored when
+/// the bot is next started. The file can only be read by the user running the bot.
+pub struct SetupTerminalEvent {
+    history_size: usize,
+    dedup_history: bool,
+    secret_commands: Vec<String>,
+    remote_console: Option<RemoteConsole>,
+    status_bar: bool,
+}
+self_event!(SetupTerminalEvent);
+impl SetupTerminalEvent {
+    /// Sets how many lines of input history are kept. This defaults to 100.
+    pub fn set_history_size(&mut self, size: usize) {
+        self.history_size = size;
+    }
+
+    /// Sets whether a line is left out of the history when it is the same as the line before
+    /// it. This defaults to `true`.
+    pub fn set_dedup_history(&mut self, dedup: bool) {
+        self.dedup_history = dedup;
+    }
+
+    /// Keeps lines running a command out of the input history, and out of the log when they
+    /// are run from a script or the remote console, as the command is given secrets such as
+    /// passwords.
+    pub fn add_secret_command(&mut self, name: impl Into<String>) {
+        self.secret_commands.push(name.into());
+    }
+
+    /// Additionally accepts terminal commands from other processes, so the bot can be
+    /// administered without access to its standard input.
+    ///
+    /// Clients authenticate with the token in `remote_console.token` in the bot's root path,
+    /// which is created when the console is first started. See [`RemoteConsole`] for where
+    /// connections are accepted. Scripts can not be run with `.source` from a remote console.
+    pub fn enable_remote_console(&mut self, console: RemoteConsole) {
+        self.remote_console = Some(console);
+    }
+
+    /// Shows a status bar above the prompt with the bot's uptime, the connectors that are
+    /// connected, the number of running tasks, and the last error reported.
+    ///
+    /// The bar is part of the prompt, so it is redrawn below new output rather than being left
+    /// in the scrollback. It is not shown if standard output is not a terminal.
+    pub fn enable_status_bar(&mut self) {
+        self.status_bar = true;
+    }
+}
+
+/// Holds the terminal while a log message is written. The status line is hidden while this is
+/// held, and drawn again once it is dropped.
+pub struct TerminalLock<'a, 'b> {
+    _writer: Option<Writer<'a, 'b, DefaultTerminal>>,
+    status: MutexGuard<'a, Option<String>>,
+}
+impl <'a, 'b> Drop for TerminalLock<'a, 'b> {
+    fn drop(&mut self) {
+        if let Some(status) = &*self.status {
+            draw_status(status);
+        }
+    }
+}
+
+/// Truncates a line of text so it fits within the width of the terminal.
+fn fit_to_width(text: &str) -> String {
+    let width = terminal_size().map_or(80, |(Width(width), _)| width as usize);
+    text.chars().take(width.saturating_sub(1)).collect()
+}
+
+/// Draws a status line below the output, leaving the cursor at its end.
+fn draw_status(status: &str) {
+    let status = fit_to_width(status);
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "{}", status);
+    let _ = stdout.flush();
+}
+/// Erases the status line drawn by [`draw_status`].
+fn erase_status() {
+    let mut stdout = io::stdout();
+    let _ = write!(stdout, "\r\x1b[K");
+    let _ = stdout.flush();
+}
+
+/// How often to check whether the bot has shut down when no input is read.
+const HEADLESS_POLL: Duration = Duration::from_millis(100);
+
+const PAGER_PROMPT: &str = "-- More -- (space to continue, q to quit) ";
+
+/// How many scripts may be nested inside each other with `.source`.
+const MAX_SCRIPT_DEPTH: usize = 16;
+
+/// Whether an instance in this process already reads commands from standard input, as only one
+/// of them can.
+static STDIN_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the path given to a `.source` command, if the line is one.
+fn source_arg(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let (command, path) = line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+    if command.eq_ignore_ascii_case(".source") && !path.trim().is_empty() {
+        Some(path.trim())
+    } else {
+        None
+    }
+}
+
+/// Splits a script into the commands it contains.
+///
+/// Blank lines and lines starting with `#` are skipped, and a line ending with `\` is continued
+/// on the next line, in the same way as in the terminal.
+fn script_commands(script: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current: Option<String> = None;
+    for line in script.lines() {
+        let is_comment = line.trim_start().starts_with('#');
+        if current.is_none() && (line.trim().is_empty() || is_comment) {
+            continue
+        }
+        let command = match current.take() {
+            Some(mut command) => {
+                command.push('\n');
+                command.push_str(line);
+                command
+            }
+            None => line.to_string(),
+        };
+        if let Some(command) = command.strip_suffix('\\') {
+            current = Some(command.to_string());
+        } else {
+            commands.push(command);
+        }
+    }
+    commands.extend(current.filter(|x| !x.trim().is_empty()));
+    commands
+}
+
+/// Returns the number of lines shown on each page of paged output.
+pub(in super) fn page_height() -> usize {
+    let height = terminal_size().map_or(24, |(_, Height(height))| height as usize);
+    height.saturating_sub(1).max(1)
+}
+
+/// Makes a key end the input line immediately while the pager is shown, and insert itself as
+/// normal otherwise.
+struct PagerKey(Arc<AtomicBool>);
+impl <T: linefeed::Terminal> Function<T> for PagerKey {
+    fn execute(&self, prompter: &mut Prompter<T>, count: i32, ch: char) -> io::Result<()> {
+        if self.0.load(Ordering::Relaxed) {
+            prompter.set_buffer(&ch.to_string())?;
+            prompter.accept_input()
+        } else {
+            prompter.insert(count.max(1) as usize, ch)
+        }
+    }
+}
+
+/// Tracks pastes in bracketed paste mode, in which the terminal sends `ESC [200~` before pasted
+/// text and `ESC [201~` after it.
+#[derive(Default)]
+struct PasteState {
+    is_pasting: AtomicBool,
+    /// Whether the last line of the paste had no line break, and was left in the line editor.
+    has_partial_line: AtomicBool,
+}
+
+/// Marks the start or end of a paste.
+struct PasteKey(Arc<PasteState>, bool);
+impl <T: linefeed::Terminal> Function<T> for PasteKey {
+    fn execute(&self, prompter: &mut Prompter<T>, _: i32, _: char) -> io::Result<()> {
+        let PasteKey(state, is_start) = self;
+        state.is_pasting.store(*is_start, Ordering::Relaxed);
+        if !is_start {
+            state.has_partial_line.store(!prompter.buffer().is_empty(), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Turns bracketed paste mode on for as long as this is alive.
+struct BracketedPasteGuard;
+impl BracketedPasteGuard {
+    fn enable() -> Self {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x1b[?2004h");
+        let _ = stdout.flush();
+        BracketedPasteGuard
+    }
+}
+impl Drop for BracketedPasteGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x1b[?2004l");
+        let _ = stdout.flush();
+    }
+}
+
+/// Creates the line editor used to read commands from standard input.
+fn make_line_editor(
+    name: &str, prompt: &str, is_paging: &Arc<AtomicBool>, paste: &Arc<PasteState>,
+) -> Result<LinefeedInterface<DefaultTerminal>> {
+    let interface = LinefeedInterface::new(name.to_string())?;
+    interface.set_report_signal(Signal::Interrupt, true);
+    interface.set_report_signal(Signal::Quit, true);
+    interface.set_prompt(prompt)?;
+    interface.define_function("sylphie-pager-key", Arc::new(PagerKey(is_paging.clone())));
+    for key in &[" ", "q", "Q"] {
+        interface.bind_sequence(*key, Command::Custom("sylphie-pager-key".into()));
+    }
+    interface.define_function("sylphie-paste-start", Arc::new(PasteKey(paste.clone(), true)));
+    interface.define_function("sylphie-paste-end", Arc::new(PasteKey(paste.clone(), false)));
+    interface.bind_sequence("\x1b[200~", Command::Custom("sylphie-paste-start".into()));
+    interface.bind_sequence("\x1b[201~", Command::Custom("sylphie-paste-end".into()));
+    Ok(interface)
+}
 
 struct TerminalInfo {
     shared: Arc<InterfaceShared>,
-    interface: LinefeedInterface<DefaultTerminal>,
+    /// The line editor, or `None` if standard input is not a terminal.
+    interface: Option<LinefeedInterface<DefaultTerminal>>,
+    prompt: String,
+    continuation_prompt: String,
+    pager: Mutex<VecDeque<String>>,
+    is_paging: Arc<AtomicBool>,
+    paste: Arc<PasteState>,
+    status: Mutex<Option<String>>,
+    show_status: bool,
+    status_bar: Mutex<Option<String>>,
+    current_prompt: Mutex<String>,
+}
+impl Drop for TerminalInfo {
+    fn drop(&mut self) {
+        if self.interface.is_some() {
+            STDIN_CLAIMED.store(false, Ordering::SeqCst);
+        }
+    }
 }
+
+/// The state of input that spans more than one line.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum PendingInput {
+    None,
+    /// The previous line ended with a `\`.
+    Continued,
+    /// The previous line was pasted, and the paste has not yet been run.
+    Pasted,
+}
+
 pub struct Terminal(Arc<TerminalInfo>);
 impl Terminal {
     pub(in super) fn new(shared: Arc<InterfaceShared>) -> Result<Terminal> {
         let internal_name = shared.info.bot_name.to_lowercase().replace(' ', "-");
-        let interface = LinefeedInterface::new(internal_name.clone())?;
-        interface.set_report_signal(Signal::Interrupt, true);
-        interface.set_report_signal(Signal::Quit, true);
-        interface.set_history_size(100);
-        interface.set_prompt(&format!("{}> ", internal_name))?;
-        Ok(Terminal(Arc::new(TerminalInfo { shared, interface })))
+        let prompt = format!("{}> ", internal_name);
+        let continuation_prompt = format!("{}> ", ".".repeat(internal_name.len()));
+        let is_paging = Arc::new(AtomicBool::new(false));
+        let paste = Arc::new(PasteState::default());
+
+        // don't set up the line editor when running under systemd, Docker, etc., when the bot
+        // disabled it, or when another instance in this process is already using it.
+        let is_tty = shared.info.terminal && atty::is(atty::Stream::Stdin);
+        let interface = if is_tty && !STDIN_CLAIMED.swap(true, Ordering::SeqCst) {
+            match make_line_editor(&internal_name, &prompt, &is_paging, &paste) {
+                Ok(interface) => Some(interface),
+                Err(e) => {
+                    // let another instance use standard input instead.
+                    STDIN_CLAIMED.store(false, Ordering::SeqCst);
+                    return Err(e)
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Terminal(Arc::new(TerminalInfo {
+            current_prompt: Mutex::new(prompt.clone()),
+            show_status: interface.is_some() && atty::is(atty::Stream::Stdout) && ansi_supported(),
+            shared, interface, prompt, continuation_prompt,
+            pager: Mutex::new(VecDeque::new()),
+            is_paging,
+            paste,
+            status: Mutex::new(None),
+            status_bar: Mutex::new(None),
+        })))
     }
-    fn shutdown_msg(&self) -> Result<()> {
-        write!(
-            self.0.interface,
-            "Please use the '.shutdown' command to stop {}.\n",
-            self.0.shared.info.bot_name,
-        )?;
+
+    /// Returns whether terminal commands are read from standard input.
+    pub fn is_interactive(&self) -> bool {
+        self.0.interface.is_some()
+    }
+    /// Writes a line of output above the prompt.
+    fn write_line(&self, line: &str) -> Result<()> {
+        match &self.0.interface {
+            Some(interface) => write!(interface, "{}\n", line)?,
+            None => println!("{}", line),
+        }
+        Ok(())
+    }
+
+    /// Queues text to be shown one page at a time once the current command finishes.
+    pub fn page(&self, text: &str) {
+        self.0.pager.lock().extend(text.lines().map(|x| x.to_string()));
+    }
+    fn show_page(&self) -> Result<()> {
+        let (lines, is_done) = {
+            let mut pager = self.0.pager.lock();
+            let count = page_height().min(pager.len());
+            let lines: Vec<String> = pager.drain(..count).collect();
+            (lines, pager.is_empty())
+        };
+        for line in lines {
+            self.write_line(&line)?;
+        }
+        self.0.is_paging.store(!is_done, Ordering::Relaxed);
+        self.set_prompt(if is_done { self.0.prompt.as_str() } else { PAGER_PROMPT })?;
+        Ok(())
+    }
+    /// Sets the prompt, drawing the status bar above it if there is one.
+    fn set_prompt(&self, prompt: &str) -> Result<()> {
+        let interface = match &self.0.interface {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let mut current = self.0.current_prompt.lock();
+        *current = prompt.to_string();
+        match &*self.0.status_bar.lock() {
+            Some(bar) => interface.set_prompt(&format!("{}\n{}", bar, prompt))?,
+            None => interface.set_prompt(prompt)?,
+        }
+        Ok(())
+    }
+    /// Redraws the status bar if its text has changed.
+    fn refresh_status_bar(&self, target: &Handler<impl Events>) -> Result<()> {
+        let text = fit_to_width(&status_bar::status_text(target, &self.0.shared));
+        let is_changed = {
+            let mut bar = self.0.status_bar.lock();
+            let is_changed = bar.as_ref() != Some(&text);
+            *bar = Some(text);
+            is_changed
+        };
+        if is_changed {
+            let prompt = self.0.current_prompt.lock().clone();
+            self.set_prompt(&prompt)?;
+        }
         Ok(())
     }
+    fn shutdown_msg(&self) -> Result<()> {
+        self.write_line(&format!(
+            "Please use the '.shutdown' command to stop {}.", self.0.shared.info.bot_name,
+        ))
+    }
+    fn history_path(&self) -> PathBuf {
+        self.0.shared.info.root_path.join("terminal_history.txt")
+    }
+    fn save_history(&self, interface: &LinefeedInterface<DefaultTerminal>) {
+        if let Err(e) = interface.save_history(self.history_path()) {
+            warn!("Could not save terminal history: {}", e);
+        }
+    }
+    fn run_command(&self, target: &Handler<impl Events>, ev: &SetupTerminalEvent, line: String) {
+        if line.trim().is_empty() {
+            return
+        }
+        let is_secret = is_secret_command(&line, &ev.secret_commands);
+        if let Some(interface) = self.0.interface.as_ref().filter(|_| !is_secret) {
+            if ev.dedup_history {
+                interface.add_history_unique(line.clone());
+            } else {
+                interface.add_history(line.clone());
+            }
+            self.save_history(interface);
+        }
+
+        match source_arg(&line) {
+            Some(path) => self.run_script(target, ev, Path::new(path), 0),
+            None => self.dispatch_command(target, line),
+        }
+        if !self.0.pager.lock().is_empty() {
+            if let Err(e) = self.show_page() {
+                e.report_error();
+            }
+        }
+    }
+    fn dispatch_command(&self, target: &Handler<impl Events>, line: String) {
+        let result = tokio::runtime::Handle::current().block_on(
+            Error::catch_panic_async(async {
+                target.dispatch_async(TerminalCommandEvent(line)).await;
+                Ok(())
+            }),
+        );
+        if let Err(e) = result {
+            e.report_error();
+        }
+        if let Err(e) = self.set_status(None) {
+            e.report_error();
+        }
+    }
+
+    /// Runs each command in a script file, as if it was entered in the terminal.
+    ///
+    /// Scripts may run other scripts with `.source`, with relative paths resolved against the
+    /// directory of the script they are in.
+    fn run_script(
+        &self, target: &Handler<impl Events>, ev: &SetupTerminalEvent, path: &Path, depth: usize,
+    ) {
+        if depth >= MAX_SCRIPT_DEPTH {
+            error!(target: "[term]", "Scripts are nested too deeply to run '{}'.", path.display());
+            return
+        }
+        let script = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(e) => {
+                error!(target: "[term]", "Could not read script '{}': {}", path.display(), e);
+                return
+            }
+        };
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for line in script_commands(&script) {
+            if self.0.shared.is_shutdown.load(Ordering::Relaxed) {
+                break
+            }
+            info!(target: "[term]", "> {}", loggable_command(&line, &ev.secret_commands));
+            match source_arg(&line) {
+                Some(inner) => self.run_script(target, ev, &base.join(inner), depth + 1),
+                None => self.dispatch_command(target, line),
+            }
+        }
+    }
+
+    /// Runs the terminal until the bot is shut down.
+    ///
+    /// The line editor supports the usual readline keybindings, including Ctrl-R to search the
+    /// input history. A line ending with `\` is continued on the next line, and lines pasted
+    /// together are run as a single command, so a block of SQL can be pasted as is. Pastes are
+    /// recognized with bracketed paste mode, so in terminals without it, each pasted line is run
+    /// on its own.
+    ///
+    /// If the bot was started with `--exec-script <file>`, the commands in that file are run
+    /// before any input is read.
+    ///
+    /// If standard input is not a terminal, such as when the bot is run by systemd or in a
+    /// Docker container, no input is read at all. Log messages are still written to standard
+    /// output and the log files, and commands can only be sent through the remote console or
+    /// connectors.
     pub fn start_terminal(&self, target: &Handler<impl Events>) -> Result<()> {
+        let ev = target.dispatch_sync(SetupTerminalEvent {
+            history_size: 100,
+            dedup_history: true,
+            secret_commands: Vec::new(),
+            remote_console: None,
+            status_bar: false,
+        });
+        if let Some(console) = ev.remote_console {
+            let secret_commands = ev.secret_commands.clone();
+            let shared = self.0.shared.clone();
+            if let Err(e) = remote_console::start(target, shared, console, secret_commands) {
+                e.report_error();
+            }
+        }
+
+        if let Some(script) = &target.get_service::<CliArgs>().exec_script {
+            info!("Running startup script '{}'.", script.display());
+            self.run_script(target, &ev, script, 0);
+            if !self.0.pager.lock().is_empty() {
+                self.show_page()?;
+            }
+        }
+
+        let interface = match &self.0.interface {
+            Some(x) => x,
+            None => {
+                if !self.0.shared.info.terminal {
+                    info!("The terminal is disabled. Terminal commands will not be read.");
+                } else if atty::is(atty::Stream::Stdin) {
+                    info!("Another instance is using the terminal. Terminal commands will not \
+                           be read.");
+                } else {
+                    info!("Standard input is not a terminal. Terminal commands will not be read.");
+                }
+                while !self.0.shared.is_shutdown.load(Ordering::Relaxed) {
+                    std::thread::sleep(HEADLESS_POLL);
+                }
+                return Ok(())
+            }
+        };
+        interface.set_history_size(ev.history_size);
+        interface.set_completer(Arc::new(EventCompleter(target.clone())));
+        let history_path = self.history_path();
+        if let Err(e) = secure_history_file(&history_path) {
+            warn!("Could not set the permissions of the terminal history: {}", e);
+        }
+        if history_path.exists() {
+            if let Err(e) = interface.load_history(&history_path) {
+                warn!("Could not load terminal history: {}", e);
+            }
+        }
+
+        let _bracketed_paste = BracketedPasteGuard::enable();
+        let show_status_bar = ev.status_bar && self.0.show_status;
+        let mut last_status_bar: Option<Instant> = None;
         let mut last_failed = false;
+        let mut pending = PendingInput::None;
+        let mut input = String::new();
         'outer: loop {
-            let result = self.0.interface.read_line_step(Some(Duration::from_millis(100)));
+            let result = interface.read_line_step(Some(Duration::from_millis(100)));
             if result.is_ok() {
                 last_failed = false;
             }
             match result {
-                Ok(Some(ReadResult::Input(line))) => if !line.trim().is_empty() {
-                    self.0.interface.add_history_unique(line.clone());
-
-                    // TODO: Error reporting.
-                    tokio::runtime::Handle::current().block_on(async {
-                        target.dispatch_async(TerminalCommandEvent(line)).await;
-                    });
+                Ok(Some(ReadResult::Input(line))) => if self.0.is_paging.load(Ordering::Relaxed) {
+                    if line.trim().eq_ignore_ascii_case("q") {
+                        self.0.pager.lock().clear();
+                    }
+                    self.show_page()?;
+                } else {
+                    if pending != PendingInput::None {
+                        input.push('\n');
+                    }
+                    input.push_str(&line);
+                    if input.ends_with('\\') {
+                        input.pop();
+                        pending = PendingInput::Continued;
+                        self.set_prompt(&self.0.continuation_prompt)?;
+                    } else if input.trim().is_empty() {
+                        pending = PendingInput::None;
+                        input.clear();
+                    } else if self.0.paste.is_pasting.load(Ordering::Relaxed) {
+                        pending = PendingInput::Pasted;
+                    } else {
+                        pending = PendingInput::None;
+                        self.set_prompt(&self.0.prompt)?;
+                        self.run_command(target, &ev, std::mem::take(&mut input));
+                    }
                 }
                 Ok(Some(ReadResult::Eof)) => {
                     self.shutdown_msg()?;
@@ -58,15 +650,33 @@ impl Terminal {
                 Ok(Some(ReadResult::Signal(Signal::Interrupt))) => {
                     eprint!("^C\n");
                     self.shutdown_msg()?;
-                    self.0.interface.set_buffer("")?;
+                    interface.set_buffer("")?;
+                    pending = PendingInput::None;
+                    input.clear();
+                    self.0.pager.lock().clear();
+                    self.0.is_paging.store(false, Ordering::Relaxed);
+                    self.set_prompt(&self.0.prompt)?;
                 }
                 Ok(Some(ReadResult::Signal(Signal::Quit))) => {
-                    write!(self.0.interface, " (killed)\n")?;
+                    write!(interface, " (killed)\n")?;
                     break 'outer;
                 }
                 Ok(Some(ReadResult::Signal(sig))) =>
                     error!("Terminal reader received unexpected signal: {:?}", sig),
-                Ok(None) => { }
+                Ok(None) => {
+                    let is_pasting = self.0.paste.is_pasting.load(Ordering::Relaxed);
+                    if pending == PendingInput::Pasted && !is_pasting {
+                        if self.0.paste.has_partial_line.swap(false, Ordering::Relaxed) {
+                            // the command is finished once the last line is edited and entered.
+                            pending = PendingInput::Continued;
+                            self.set_prompt(&self.0.continuation_prompt)?;
+                        } else {
+                            pending = PendingInput::None;
+                            self.set_prompt(&self.0.prompt)?;
+                            self.run_command(target, &ev, std::mem::take(&mut input));
+                        }
+                    }
+                }
                 Err(err) => {
                     error!("Terminal reader encountered error: {}", err);
                     if last_failed {
@@ -78,13 +688,74 @@ impl Terminal {
                 },
             }
             if self.0.shared.is_shutdown.load(Ordering::Relaxed) {
-                self.0.interface.cancel_read_line()?;
+                interface.cancel_read_line()?;
                 break 'outer;
             }
+            let is_due = last_status_bar.map_or(true, |x| x.elapsed() >= STATUS_BAR_REFRESH);
+            if show_status_bar && is_due {
+                self.refresh_status_bar(target)?;
+                last_status_bar = Some(Instant::now());
+            }
         }
         Ok(())
     }
     pub fn lock_write(&self) -> Result<TerminalLock> {
-        Ok(TerminalLock(self.0.interface.lock_writer_erase()?))
+        let writer = match &self.0.interface {
+            Some(interface) => Some(interface.lock_writer_erase()?),
+            None => None,
+        };
+        let status = self.0.status.lock();
+        if status.is_some() {
+            erase_status();
+        }
+        Ok(TerminalLock { _writer: writer, status })
+    }
+
+    /// Shows a line of text below the output while a command is running, replacing any line
+    /// shown before. The line is removed if `None` is given, or once the command finishes.
+    ///
+    /// Nothing is shown if standard output is not a terminal, or does not support the escape
+    /// codes used to redraw the line.
+    pub fn set_status(&self, status: Option<String>) -> Result<()> {
+        let _writer = match &self.0.interface {
+            Some(interface) => Some(interface.lock_writer_erase()?),
+            None => None,
+        };
+        let mut current = self.0.status.lock();
+        if current.is_some() {
+            erase_status();
+        }
+        *current = status.filter(|_| self.0.show_status);
+        if let Some(status) = &*current {
+            draw_status(status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_scripts() {
+        let script = "# reload everything\n\n.loglevel sylphie_core debug\n  \nselect 1,\\\n  2\n";
+        assert_eq!(script_commands(script), vec![
+            ".loglevel sylphie_core debug".to_string(),
+            "select 1,\n  2".to_string(),
+        ]);
+        assert_eq!(source_arg(".SOURCE  scripts/backup.txt "), Some("scripts/backup.txt"));
+        assert_eq!(source_arg(".source"), None);
+        assert_eq!(source_arg(".sourcefoo bar"), None);
+    }
+
+    #[test]
+    fn secret_commands() {
+        let secret = vec!["rekey".to_string()];
+        assert!(is_secret_command("  REKEY key.txt", &secret));
+        assert!(!is_secret_command("rekeys", &secret));
+        assert!(!is_secret_command("", &secret));
+        assert_eq!(loggable_command("rekey key.txt", &secret), "rekey (arguments hidden)");
+        assert_eq!(loggable_command("help rekey", &secret), "help rekey");
     }
 }
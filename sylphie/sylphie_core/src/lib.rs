@@ -6,11 +6,23 @@
 #[macro_use] extern crate tracing;
 pub mod errors; // this goes before to make sure macros resolve
 
+pub mod assets;
+pub mod cli;
+pub mod config;
 pub mod core;
 mod global_instance;
+pub mod health;
+mod http_server;
 pub mod interface;
+pub mod metrics;
 pub mod module;
+pub mod services;
+pub mod stats;
+pub mod tasks;
+pub mod testing;
 pub mod timer;
+pub mod tokens;
+pub mod watchdog;
 
 pub use crate::core::SylphieCore;
 pub use crate::errors::{Result, Error};
@@ -37,6 +49,138 @@ pub mod __macro_priv {
     }
     impl <T> CheckIsComponent<u32> for T { }
     impl <T: IsComponent> CheckIsComponent<u64> for T { }
+
+    // Support code for isolating panics and filtering scopes in `#[module_impl]` event handlers.
+    use crate::errors::Error;
+    use crate::module::Module;
+    use static_events::prelude_async::*;
+    use std::future::Future;
+    use tracing::Span;
+    use tracing_futures::Instrument;
+
+    pub trait HandlerName {
+        fn handler_name(&self) -> String;
+    }
+    impl <T: ?Sized> HandlerName for T {
+        default fn handler_name(&self) -> String {
+            std::any::type_name::<T>().to_string()
+        }
+    }
+    impl <T: Module> HandlerName for T {
+        fn handler_name(&self) -> String {
+            self.info().name().to_string()
+        }
+    }
+
+    /// The result of an event handler, which decides what happens when it is skipped or panics.
+    ///
+    /// Handlers for failable events return a `Result`, and a panic in them is returned as an
+    /// error, so a failed startup phase stops the bot rather than continuing half-initialized.
+    /// Other handlers report the panic and let the remaining handlers run.
+    pub trait DefaultHandlerResult {
+        fn default_result() -> Self;
+        fn from_panic(name: String, err: Error) -> Self;
+    }
+    impl DefaultHandlerResult for () {
+        fn default_result() -> Self { }
+        fn from_panic(name: String, err: Error) -> Self {
+            report_handler_panic(name, err)
+        }
+    }
+    impl DefaultHandlerResult for EventResult {
+        fn default_result() -> Self {
+            EvOk
+        }
+        fn from_panic(name: String, err: Error) -> Self {
+            report_handler_panic(name, err);
+            EvOk
+        }
+    }
+    impl <E: From<Error>> DefaultHandlerResult for Result<(), E> {
+        fn default_result() -> Self {
+            Ok(())
+        }
+        fn from_panic(name: String, err: Error) -> Self {
+            crate::metrics::record_handler_panic();
+            error!("An event handler in '{}' panicked.", name);
+            Err(err.into())
+        }
+    }
+
+    fn report_handler_panic(name: String, err: Error) {
+        crate::metrics::record_handler_panic();
+        error!("An event handler in '{}' panicked. Continuing with other handlers.", name);
+        err.report_error();
+    }
+
+    // handler spans are at the trace level, as some events are dispatched very often. the name
+    // of the handler is only computed if the span is enabled.
+    fn handler_span(name: &impl Fn() -> String, method: &'static str) -> Span {
+        let span = trace_span!("event_handler", module = tracing::field::Empty, method);
+        if !span.is_disabled() {
+            span.record("module", &name().as_str());
+        }
+        span
+    }
+
+    pub fn isolate_panic<T, N, F>(name: N, method: &'static str, func: F) -> T
+        where T: DefaultHandlerResult, N: Fn() -> String, F: FnOnce() -> T
+    {
+        crate::metrics::record_handler_call();
+        let span = handler_span(&name, method);
+        let _enter = span.enter();
+        match Error::catch_panic(|| Ok(func())) {
+            Ok(v) => v,
+            Err(e) => T::from_panic(name(), e),
+        }
+    }
+    pub async fn isolate_panic_async<T, N, F>(name: N, method: &'static str, fut: F) -> T
+        where T: DefaultHandlerResult, N: Fn() -> String, F: Future<Output = T>
+    {
+        crate::metrics::record_handler_call();
+        let span = handler_span(&name, method);
+        match Error::catch_panic_async(async move { Ok(fut.await) }).instrument(span).await {
+            Ok(v) => v,
+            Err(e) => T::from_panic(name(), e),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn panics_fail_failable_handlers() {
+            let result: Result<(), Error> =
+                isolate_panic(|| "test".to_string(), "test", || panic!("handler failed"));
+            assert!(result.is_err());
+            let result: Result<(), Error> = isolate_panic(|| "test".to_string(), "test", || Ok(()));
+            assert!(result.is_ok());
+        }
+
+        #[test]
+        fn handler_counters_are_per_thread() {
+            use crate::metrics::HandlerCounters;
+            use std::sync::Arc;
+
+            let first = Arc::new(HandlerCounters::default());
+            let second = Arc::new(HandlerCounters::default());
+            let run = |counters: &Arc<HandlerCounters>| {
+                let _guard = counters.enter_thread();
+                let _: Result<(), Error> =
+                    isolate_panic(|| "test".to_string(), "test", || panic!("handler failed"));
+            };
+            run(&first);
+            {
+                let _guard = first.enter_thread();
+                run(&second);
+                isolate_panic(|| "test".to_string(), "test", || ());
+            }
+            isolate_panic(|| "test".to_string(), "test", || ());
+            assert_eq!((first.calls(), first.panics()), (2, 1));
+            assert_eq!((second.calls(), second.panics()), (1, 1));
+        }
+    }
 }
 
 /// A convenience module containing common imports that are useful throughout Sylphie-based code.
@@ -54,7 +198,7 @@ pub mod derives {
     #[doc(inline)] pub use sylphie_derive::{
         CoreModule as Module,
         module_impl_core as module_impl,
-        command, config,
+        command, config, module_hook,
     };
     #[doc(inline)] pub use static_events::handlers::event_handler;
 }
@@ -0,0 +1,282 @@
+//! A registry for metrics reported by modules.
+//!
+//! With the `prometheus` feature, the bot serves its metrics at `/metrics` in the Prometheus
+//! text format, if `metrics.listen` is set in the configuration file to an address such as
+//! `127.0.0.1:9100`.
+
+use crate::errors::*;
+use crate::interface::Interface;
+use crate::module::ModuleInfo;
+use arc_swap::ArcSwap;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "prometheus")] mod prometheus;
+#[cfg(feature = "prometheus")] pub(crate) use prometheus::metrics_server_task;
+
+/// How often [`CollectMetricsEvent`] is dispatched.
+pub const COLLECT_METRICS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The name of the gauge connectors report to show whether they are connected, with `1.0`
+/// meaning connected and `0.0` meaning disconnected.
+///
+/// Modules reporting this metric are listed in the terminal status bar while they are connected.
+/// If the gauge has a `connection` label, the label is listed instead of the module name.
+pub const CONNECTED_METRIC: &str = "connected";
+
+/// Counts calls to `#[module_impl]` event handlers for a single bot instance.
+///
+/// Handlers cannot reach the instance they run in, so calls are counted toward the counters of
+/// the instance the current thread belongs to, as set with [`HandlerCounters::enter_thread`].
+#[derive(Default)]
+pub(crate) struct HandlerCounters {
+    calls: AtomicU64,
+    panics: AtomicU64,
+}
+impl HandlerCounters {
+    /// Returns the number of times an event handler has been called.
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times an event handler has panicked.
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Counts event handlers called on the current thread toward these counters, until the
+    /// returned guard is dropped.
+    pub fn enter_thread(self: &Arc<Self>) -> HandlerCountersGuard {
+        let previous = THREAD_COUNTERS.with(|x| x.borrow_mut().replace(self.clone()));
+        HandlerCountersGuard(previous)
+    }
+}
+
+thread_local! {
+    static THREAD_COUNTERS: RefCell<Option<Arc<HandlerCounters>>> = RefCell::new(None);
+}
+
+/// Restores the counters the current thread used before [`HandlerCounters::enter_thread`].
+pub(crate) struct HandlerCountersGuard(Option<Arc<HandlerCounters>>);
+impl Drop for HandlerCountersGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        let _ = THREAD_COUNTERS.try_with(|x| *x.borrow_mut() = previous);
+    }
+}
+
+fn with_thread_counters(func: impl FnOnce(&HandlerCounters)) {
+    let _ = THREAD_COUNTERS.try_with(|x| if let Some(counters) = &*x.borrow() {
+        func(counters)
+    });
+}
+
+/// Records that an event handler was called on the current thread.
+pub(crate) fn record_handler_call() {
+    with_thread_counters(|x| { x.calls.fetch_add(1, Ordering::Relaxed); });
+}
+
+/// Records that an event handler panicked on the current thread.
+pub(crate) fn record_handler_panic() {
+    with_thread_counters(|x| { x.panics.fetch_add(1, Ordering::Relaxed); });
+}
+
+/// The type of a metric.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MetricKind {
+    /// A value that only ever increases, such as the number of commands executed.
+    Counter,
+    /// A value that can go up and down, such as the number of open connections.
+    Gauge,
+}
+
+/// A single metric value.
+#[derive(Clone, Debug)]
+pub struct Metric {
+    /// The name of the module that reported this metric.
+    pub module: Arc<str>,
+    /// The name of the metric.
+    pub name: Cow<'static, str>,
+    /// Labels distinguishing this metric from others with the same name and module, such as the
+    /// name of a command.
+    pub labels: Vec<(Cow<'static, str>, String)>,
+    /// The type of the metric.
+    pub kind: MetricKind,
+    /// The current value of the metric.
+    pub value: f64,
+}
+
+/// A snapshot of all metrics at a particular time.
+#[derive(Clone, Debug)]
+pub struct MetricsSnapshot {
+    pub collected_at: SystemTime,
+    pub metrics: Vec<Metric>,
+}
+impl MetricsSnapshot {
+    /// Finds a metric by module and name.
+    pub fn get(&self, module: &str, name: &str) -> Option<&Metric> {
+        self.metrics.iter().find(|x| &*x.module == module && x.name == name)
+    }
+}
+
+/// Dispatched periodically to collect metrics from modules.
+pub struct CollectMetricsEvent {
+    metrics: Vec<Metric>,
+}
+self_event!(CollectMetricsEvent);
+impl CollectMetricsEvent {
+    pub(crate) fn new() -> Self {
+        CollectMetricsEvent { metrics: Vec::new() }
+    }
+
+    pub(crate) fn into_metrics(self) -> Vec<Metric> {
+        self.metrics
+    }
+
+    /// Reports the current value of a counter.
+    pub fn counter(
+        &mut self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>, value: u64,
+    ) {
+        self.labeled_counter(module, name, &[], value);
+    }
+
+    /// Reports the current value of a counter with labels, such as `("command", "help")`.
+    pub fn labeled_counter(
+        &mut self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>,
+        labels: &[(&'static str, &str)], value: u64,
+    ) {
+        self.push(module, name, labels, MetricKind::Counter, value as f64);
+    }
+
+    /// Reports the current value of a gauge.
+    pub fn gauge(
+        &mut self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>, value: f64,
+    ) {
+        self.labeled_gauge(module, name, &[], value);
+    }
+
+    /// Reports the current value of a gauge with labels, such as `("connection", "main")`.
+    pub fn labeled_gauge(
+        &mut self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>,
+        labels: &[(&'static str, &str)], value: f64,
+    ) {
+        self.push(module, name, labels, MetricKind::Gauge, value);
+    }
+
+    fn push(
+        &mut self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>,
+        labels: &[(&'static str, &str)], kind: MetricKind, value: f64,
+    ) {
+        self.metrics.push(Metric {
+            module: module.arc_name(),
+            name: name.into(),
+            labels: labels.iter().map(|(k, v)| (Cow::Borrowed(*k), v.to_string())).collect(),
+            kind,
+            value,
+        });
+    }
+}
+
+type MetricKey = (Arc<str>, Cow<'static, str>);
+
+/// Stores the metrics collected from modules.
+///
+/// Besides responding to [`CollectMetricsEvent`], modules can also update counters stored
+/// directly in the registry. This can be retrieved using `get_service`.
+pub struct MetricsRegistry {
+    counters: Mutex<HashMap<MetricKey, u64>>,
+    gauges: Mutex<HashMap<MetricKey, f64>>,
+    snapshot: ArcSwap<MetricsSnapshot>,
+}
+impl Default for MetricsRegistry {
+    fn default() -> Self {
+        MetricsRegistry {
+            counters: Default::default(),
+            gauges: Default::default(),
+            snapshot: ArcSwap::new(Arc::new(MetricsSnapshot {
+                collected_at: SystemTime::now(),
+                metrics: Vec::new(),
+            })),
+        }
+    }
+}
+impl MetricsRegistry {
+    /// Increments a counter stored in the registry.
+    pub fn increment(
+        &self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>, by: u64,
+    ) {
+        *self.counters.lock().entry((module.arc_name(), name.into())).or_insert(0) += by;
+    }
+
+    /// Sets a gauge stored in the registry.
+    pub fn set_gauge(
+        &self, module: &ModuleInfo, name: impl Into<Cow<'static, str>>, value: f64,
+    ) {
+        self.gauges.lock().insert((module.arc_name(), name.into()), value);
+    }
+
+    /// Returns the most recent snapshot of the metrics.
+    pub fn snapshot(&self) -> Arc<MetricsSnapshot> {
+        self.snapshot.load_full()
+    }
+
+    /// Collects metrics from all modules, and updates the current snapshot.
+    pub async fn collect(&self, target: &Handler<impl Events>) -> Arc<MetricsSnapshot> {
+        let mut metrics = target.dispatch_async(CollectMetricsEvent::new()).await.into_metrics();
+        for ((module, name), value) in self.counters.lock().iter() {
+            metrics.push(Metric {
+                module: module.clone(),
+                name: name.clone(),
+                labels: Vec::new(),
+                kind: MetricKind::Counter,
+                value: *value as f64,
+            });
+        }
+        for ((module, name), value) in self.gauges.lock().iter() {
+            metrics.push(Metric {
+                module: module.clone(),
+                name: name.clone(),
+                labels: Vec::new(),
+                kind: MetricKind::Gauge,
+                value: *value,
+            });
+        }
+        // these are counted by `#[module_impl]` event handlers, which cannot reach the registry.
+        let counters = target.get_service::<Interface>().handler_counters();
+        let handler_stats = [
+            ("event_handler_calls", counters.calls()),
+            ("event_handler_panics", counters.panics()),
+        ];
+        for (name, value) in handler_stats.iter() {
+            metrics.push(Metric {
+                module: "sylphie_core".into(),
+                name: Cow::Borrowed(*name),
+                labels: Vec::new(),
+                kind: MetricKind::Counter,
+                value: *value as f64,
+            });
+        }
+        metrics.sort_by(|a, b| {
+            (&a.module, &a.name, &a.labels).cmp(&(&b.module, &b.name, &b.labels))
+        });
+
+        let snapshot = Arc::new(MetricsSnapshot { collected_at: SystemTime::now(), metrics });
+        self.snapshot.store(snapshot.clone());
+        snapshot
+    }
+}
+
+/// Periodically collects metrics until the bot shuts down.
+pub(crate) async fn collect_metrics_task(target: Handler<impl Events>) -> Result<()> {
+    let mut interval = tokio::time::interval(COLLECT_METRICS_INTERVAL);
+    loop {
+        interval.tick().await;
+        target.get_service::<MetricsRegistry>().collect(&target).await;
+    }
+}
@@ -0,0 +1,105 @@
+//! Serves metrics in the Prometheus text exposition format.
+
+use crate::errors::*;
+use crate::http_server::{self, Request, Response};
+use crate::metrics::{MetricKind, MetricsRegistry, MetricsSnapshot};
+use futures::FutureExt;
+use static_events::prelude_async::*;
+use std::fmt::Write;
+
+/// Replaces characters that are not allowed in Prometheus metric names.
+fn metric_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|x| if x.is_ascii_alphanumeric() || x == '_' || x == ':' { x } else { '_' })
+        .collect();
+    format!("sylphie_{}", name)
+}
+
+/// Escapes a label value.
+fn label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Formats a snapshot in the Prometheus text format.
+///
+/// Each metric is named after its name in the registry prefixed with `sylphie_`, and the module
+/// that reported it is added as the `module` label.
+fn format_snapshot(snapshot: &MetricsSnapshot) -> String {
+    // metrics with the same name must be grouped together, under a single `TYPE` line.
+    let mut metrics: Vec<_> = snapshot.metrics.iter().collect();
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut text = String::new();
+    let mut last_name = None;
+    for metric in metrics {
+        let name = metric_name(&metric.name);
+        if last_name.as_ref() != Some(&name) {
+            let kind = match metric.kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+            };
+            writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+        }
+
+        write!(text, "{}{{module=\"{}\"", name, label_value(&metric.module)).unwrap();
+        for (label, value) in &metric.labels {
+            write!(text, ",{}=\"{}\"", label, label_value(value)).unwrap();
+        }
+        writeln!(text, "}} {}", metric.value).unwrap();
+        last_name = Some(name);
+    }
+    text
+}
+
+async fn respond(target: &Handler<impl Events>, request: Request) -> Response {
+    if request.path != "/metrics" {
+        return Response::not_found()
+    }
+    if request.method != "GET" {
+        return Response::method_not_allowed()
+    }
+    let snapshot = target.get_service::<MetricsRegistry>().collect(target).await;
+    Response::new(200, "text/plain; version=0.0.4", format_snapshot(&snapshot))
+}
+
+/// Serves `/metrics` on an address until the bot shuts down.
+///
+/// Metrics are collected from modules each time they are requested.
+pub(crate) async fn metrics_server_task(target: Handler<impl Events>, addr: String) -> Result<()> {
+    http_server::serve(&addr, "metrics", move |request| {
+        let target = target.clone();
+        async move { respond(&target, request).await }.boxed()
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metric;
+    use std::borrow::Cow;
+    use std::time::SystemTime;
+
+    #[test]
+    fn format_metrics() {
+        let metric = |name: &'static str, kind, labels: Vec<(Cow<'static, str>, String)>, value| {
+            Metric { module: "sylphie_commands".into(), name: name.into(), labels, kind, value }
+        };
+        let snapshot = MetricsSnapshot {
+            collected_at: SystemTime::now(),
+            metrics: vec![
+                metric("command_executions", MetricKind::Counter,
+                       vec![("command".into(), "help".to_string())], 3.0),
+                metric("db_idle.connections", MetricKind::Gauge, Vec::new(), 1.5),
+                metric("command_executions", MetricKind::Counter,
+                       vec![("command".into(), "say \"hi\"".to_string())], 1.0),
+            ],
+        };
+        assert_eq!(format_snapshot(&snapshot), "\
+# TYPE sylphie_command_executions counter
+sylphie_command_executions{module=\"sylphie_commands\",command=\"help\"} 3
+sylphie_command_executions{module=\"sylphie_commands\",command=\"say \\\"hi\\\"\"} 1
+# TYPE sylphie_db_idle_connections gauge
+sylphie_db_idle_connections{module=\"sylphie_commands\"} 1.5
+");
+    }
+}
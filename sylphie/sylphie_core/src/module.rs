@@ -1,6 +1,9 @@
+use crate::errors::*;
+use crate::tasks::{TaskHandle, TaskManager};
 use enumset::*;
 use static_events::prelude_async::*;
 use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU32, Ordering};
 
@@ -19,6 +22,12 @@ pub struct ModuleMetadata {
     pub crate_version: &'static str,
     pub git_info: Option<GitInfo>,
     pub flags: EnumSet<ModuleFlag>,
+    /// The priority of this module's initialization tasks, set with
+    /// `#[module(init_priority = N)]`.
+    ///
+    /// Initialization tasks of modules with a higher priority finish before any initialization
+    /// tasks of modules with a lower priority are started. The default priority is `0`.
+    pub init_priority: i32,
 }
 
 /// Metadata relating to an crate containing modules.
@@ -75,6 +84,16 @@ impl ModuleInfo {
     pub fn metadata(&self) -> ModuleMetadata {
         self.0.as_ref().expect("Module not yet initialized!").metadata
     }
+    #[cfg(test)]
+    pub(crate) fn for_test(name: &str) -> ModuleInfo {
+        let metadata = ModuleMetadata {
+            module_path: "sylphie_core::test", crate_version: "0.0.0", git_info: None,
+            flags: EnumSet::new(), init_priority: 0,
+        };
+        ModuleInfo(Some(Arc::new(ModuleInfoInternal {
+            id: ModuleId(0, 0), name: name.into(), metadata,
+        })))
+    }
     fn set(&mut self, data: ModuleInfoInternal) {
         if self.0.is_some() {
             panic!("Module is already initialized!");
@@ -120,6 +139,19 @@ pub trait Module: Events + Sized + Send + Sync + 'static {
     fn info_mut(&mut self) -> &mut ModuleInfo;
 
     fn init_module(parent: &str, walker: &mut ModuleTreeWalker<'_>) -> Self;
+
+    /// Spawns a background task owned by this module.
+    ///
+    /// The task is aborted automatically when the bot shuts down, and is listed under this
+    /// module by the `.tasks` terminal command.
+    fn spawn(
+        &self,
+        target: &Handler<impl Events>,
+        name: &str,
+        task: impl Future<Output = Result<()>> + Send + 'static,
+    ) -> TaskHandle {
+        target.get_service::<TaskManager>().spawn(self.info(), name, task)
+    }
 }
 impl <T: Module> Module for Arc<T> {
     fn metadata(&self) -> ModuleMetadata {
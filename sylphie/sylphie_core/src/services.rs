@@ -0,0 +1,95 @@
+//! A registry allowing modules to share services with each other without depending on each
+//! other's concrete types.
+
+use crate::errors::*;
+use parking_lot::RwLock;
+use std::any::{Any, TypeId, type_name};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+struct ServiceEntry {
+    provider: Arc<str>,
+    name: &'static str,
+    value: Box<dyn Any + Send + Sync>,
+}
+
+/// A registry of services published by modules.
+///
+/// Services are keyed by type, and are usually trait objects such as `dyn MyService`. A module
+/// publishes an implementation with [`Services::publish`], and any other module can then look it
+/// up with [`Services::resolve`] without knowing which module provides it.
+///
+/// This can be retrieved using `get_service`, or with [`SylphieCoreHandlerExt::services`].
+///
+/// [`SylphieCoreHandlerExt::services`]: crate::core::SylphieCoreHandlerExt::services
+#[derive(Default)]
+pub struct Services {
+    services: RwLock<HashMap<TypeId, ServiceEntry>>,
+}
+impl Services {
+    /// Publishes a service on behalf of a given provider.
+    ///
+    /// Returns an error if another provider has already published a service of the same type.
+    pub fn publish<T: ?Sized + Send + Sync + 'static>(
+        &self, provider: &str, service: Arc<T>,
+    ) -> Result<()> {
+        let mut services = self.services.write();
+        if let Some(entry) = services.get(&TypeId::of::<T>()) {
+            bail!(
+                "Service '{}' is already provided by '{}'. (tried to publish from '{}')",
+                entry.name, entry.provider, provider,
+            );
+        }
+        services.insert(TypeId::of::<T>(), ServiceEntry {
+            provider: provider.into(),
+            name: type_name::<T>(),
+            value: Box::new(service),
+        });
+        Ok(())
+    }
+
+    /// Publishes a service, replacing any service of the same type that already exists.
+    pub fn publish_override<T: ?Sized + Send + Sync + 'static>(
+        &self, provider: &str, service: Arc<T>,
+    ) {
+        self.services.write().insert(TypeId::of::<T>(), ServiceEntry {
+            provider: provider.into(),
+            name: type_name::<T>(),
+            value: Box::new(service),
+        });
+    }
+
+    /// Removes a published service, returning it if it existed.
+    pub fn unpublish<T: ?Sized + Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.services.write().remove(&TypeId::of::<T>())
+            .map(|x| x.value.downcast_ref::<Arc<T>>().unwrap().clone())
+    }
+
+    /// Looks up a service by type.
+    pub fn resolve<T: ?Sized + Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.services.read().get(&TypeId::of::<T>())
+            .map(|x| x.value.downcast_ref::<Arc<T>>().unwrap().clone())
+    }
+
+    /// Looks up a service by type, returning an error if no module provides it.
+    pub fn require<T: ?Sized + Send + Sync + 'static>(&self) -> Result<Arc<T>> {
+        match self.resolve::<T>() {
+            Some(v) => Ok(v),
+            None => bail!("No module provides the service '{}'.", type_name::<T>()),
+        }
+    }
+
+    /// Returns the name of the provider of a given service, if it exists.
+    pub fn provider_of<T: ?Sized + Send + Sync + 'static>(&self) -> Option<Arc<str>> {
+        self.services.read().get(&TypeId::of::<T>()).map(|x| x.provider.clone())
+    }
+
+    /// Returns a list of all published services and their providers.
+    pub fn list(&self) -> Vec<(&'static str, Arc<str>)> {
+        let mut list: Vec<_> = self.services.read().values()
+            .map(|x| (x.name, x.provider.clone()))
+            .collect();
+        list.sort();
+        list
+    }
+}
@@ -0,0 +1,126 @@
+//! Statistics about the bot process as a whole.
+
+use chrono::{DateTime, Utc};
+use crate::metrics::HandlerCounters;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Formats how long the bot has been running, such as `01:02:03` or `2d 03h 04m`.
+pub fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {:02}h {:02}m", days, hours, minutes)
+    } else {
+        format!("{:02}:{:02}:{:02}", hours, minutes, secs % 60)
+    }
+}
+
+/// Returns the resident memory of the current process in bytes, if it can be determined.
+#[cfg(target_os = "linux")]
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|x| x.starts_with("VmRSS:"))?;
+    let kib: u64 = line["VmRSS:".len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory() -> Option<u64> {
+    None
+}
+
+/// Tracks statistics about the bot since it was started.
+///
+/// This can be retrieved using `get_service`.
+pub struct CoreStats {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    commands: AtomicU64,
+    crashes: Option<u32>,
+    handler_counters: Arc<HandlerCounters>,
+}
+impl CoreStats {
+    pub(crate) fn new(handler_counters: Arc<HandlerCounters>) -> Self {
+        CoreStats {
+            started: Instant::now(),
+            started_at: Utc::now(),
+            commands: AtomicU64::new(0),
+            crashes: crate::core::supervisor::crash_count(),
+            handler_counters,
+        }
+    }
+
+    /// Returns when the bot was started.
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Returns how long the bot has been running.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Records that a command was processed. This is called by the command manager.
+    pub fn record_command(&self) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of commands processed, whether or not they succeeded.
+    pub fn commands_processed(&self) -> u64 {
+        self.commands.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times an event handler in a module has been called.
+    ///
+    /// Events are counted once for each module handling them, so this measures how much work
+    /// is done in response to events rather than how many events were dispatched.
+    pub fn events_handled(&self) -> u64 {
+        self.handler_counters.calls()
+    }
+
+    /// Returns how many times the bot has crashed and been restarted, or `None` if it was not
+    /// started with `--supervise`.
+    ///
+    /// Crashes are counted for as long as the supervisor runs, across restarts.
+    pub fn crash_count(&self) -> Option<u32> {
+        self.crashes
+    }
+
+    /// Returns the resident memory used by the bot in bytes, or `None` on platforms where this is
+    /// not supported.
+    pub fn memory_usage(&self) -> Option<u64> {
+        resident_memory()
+    }
+
+    /// Returns a human readable summary of the statistics, one line per statistic.
+    pub fn summary(&self) -> Vec<String> {
+        let memory = match self.memory_usage() {
+            Some(bytes) => format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+            None => "unknown".to_string(),
+        };
+        let mut summary = vec![
+            format!("Started: {}", self.started_at.format("%Y-%m-%d %H:%M:%S UTC")),
+            format!("Uptime: {}", format_uptime(self.uptime())),
+            format!("Commands processed: {}", self.commands_processed()),
+            format!("Event handlers called: {}", self.events_handled()),
+            format!("Memory usage: {}", memory),
+        ];
+        if let Some(crashes) = self.crashes {
+            summary.push(format!("Crashes: {}", crashes));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_formatting() {
+        assert_eq!(format_uptime(Duration::from_secs(3723)), "01:02:03");
+        assert_eq!(format_uptime(Duration::from_secs(2 * 86400 + 3 * 3600 + 4 * 60)), "2d 03h 04m");
+    }
+}
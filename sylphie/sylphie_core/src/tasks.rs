@@ -0,0 +1,141 @@
+//! Background tasks that are owned by a particular module.
+
+use crate::errors::*;
+use crate::module::{ModuleId, ModuleInfo};
+use futures::future::{AbortHandle, Abortable};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing_futures::WithSubscriber;
+
+struct TaskEntry {
+    module: ModuleInfo,
+    name: Arc<str>,
+    started: Instant,
+    abort: AbortHandle,
+}
+
+#[derive(Default)]
+struct TaskManagerData {
+    tasks: Mutex<HashMap<u64, TaskEntry>>,
+    next_id: AtomicU64,
+    is_shutdown: AtomicBool,
+}
+
+/// Information about a running background task.
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub module: ModuleInfo,
+    pub name: Arc<str>,
+    pub running_for: Duration,
+}
+
+/// A handle to a background task.
+///
+/// Dropping this handle does not stop the task.
+#[derive(Clone, Debug)]
+pub struct TaskHandle(AbortHandle);
+impl TaskHandle {
+    /// Stops this task at its next suspension point.
+    pub fn abort(&self) {
+        self.0.abort()
+    }
+}
+
+/// Tracks the background tasks spawned by modules.
+///
+/// Tasks are aborted automatically when the bot shuts down, once
+/// [`ShutdownEvent`](`crate::core::ShutdownEvent`) has finished. This can be retrieved using
+/// `get_service`, though [`Module::spawn`](`crate::module::Module::spawn`) is usually more
+/// convenient.
+#[derive(Clone, Default)]
+pub struct TaskManager(Arc<TaskManagerData>);
+impl TaskManager {
+    /// Spawns a background task owned by a module.
+    ///
+    /// Errors and panics returned by the task are reported, and do not affect other tasks.
+    pub fn spawn(
+        &self,
+        module: &ModuleInfo,
+        name: &str,
+        task: impl Future<Output = Result<()>> + Send + 'static,
+    ) -> TaskHandle {
+        let (abort, registration) = AbortHandle::new_pair();
+        if self.0.is_shutdown.load(Ordering::Relaxed) {
+            debug!("Not spawning task {}/{}, as the bot is shutting down.", module.name(), name);
+            abort.abort();
+            return TaskHandle(abort)
+        }
+
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let name: Arc<str> = name.into();
+        self.0.tasks.lock().insert(id, TaskEntry {
+            module: module.clone(),
+            name: name.clone(),
+            started: Instant::now(),
+            abort: abort.clone(),
+        });
+
+        // tasks keep the logger of the thread they were spawned from, as they may run on
+        // threads that were not started by the bot.
+        let dispatch = tracing::dispatcher::get_default(|x| x.clone());
+        let data = self.0.clone();
+        let module_name = module.arc_name();
+        tokio::spawn(async move {
+            match Abortable::new(Error::catch_panic_async(task), registration).await {
+                Ok(Ok(())) => { }
+                Ok(Err(e)) => {
+                    error!("Task {}/{} failed.", module_name, name);
+                    e.report_error();
+                }
+                Err(_) => trace!("Task {}/{} aborted.", module_name, name),
+            }
+            data.tasks.lock().remove(&id);
+        }.with_subscriber(dispatch));
+        TaskHandle(abort)
+    }
+
+    /// Returns a list of all running tasks, sorted by module.
+    pub fn list_tasks(&self) -> Vec<TaskInfo> {
+        let mut list: Vec<_> = self.0.tasks.lock().values().map(|x| TaskInfo {
+            module: x.module.clone(),
+            name: x.name.clone(),
+            running_for: x.started.elapsed(),
+        }).collect();
+        list.sort_by(|a, b| (a.module.name(), &a.name).cmp(&(b.module.name(), &b.name)));
+        list
+    }
+
+    /// Aborts all tasks owned by a given module.
+    pub fn abort_module_tasks(&self, module: ModuleId) {
+        for task in self.0.tasks.lock().values() {
+            if task.module.id() == module {
+                task.abort.abort();
+            }
+        }
+    }
+
+    /// Returns whether the bot is shutting down.
+    ///
+    /// Long running tasks may check this to stop taking on new work, as they are aborted once
+    /// [`ShutdownEvent`](`crate::core::ShutdownEvent`) has finished.
+    pub fn is_shutting_down(&self) -> bool {
+        self.0.is_shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Prevents any new tasks from being started, without stopping the tasks already running.
+    pub(crate) fn stop_spawning(&self) {
+        self.0.is_shutdown.store(true, Ordering::Relaxed);
+    }
+
+    /// Aborts all tasks, and prevents any new tasks from being started.
+    pub(crate) fn shutdown(&self) {
+        self.stop_spawning();
+        for task in self.0.tasks.lock().values() {
+            task.abort.abort();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Utilities for testing modules.
+//!
+//! [`capture_logs`] records the log messages emitted while a test runs, so that tests can check
+//! that warnings and errors are logged when they should be:
+//!
+//! ```rust,ignore
+//! let _logs = capture_logs();
+//! run_migrations_twice();
+//! assert_logged!(Level::WARN, "has been executed more than once");
+//! ```
+
+use crate::interface::MessageVisitor;
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::sync::Arc;
+use tracing::{Event, Level, Subscriber};
+use tracing::subscriber::DefaultGuard;
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::Registry;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+/// A log message recorded by [`capture_logs`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct LogRecord {
+    /// The level the message was logged at.
+    pub level: Level,
+    /// The target of the message, which is usually the module path it was logged from.
+    pub target: String,
+    /// The message, followed by any other fields of the event.
+    pub message: String,
+}
+
+type Records = Arc<Mutex<Vec<LogRecord>>>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Records>> = RefCell::new(None);
+}
+
+struct CaptureLogsLayer(Records);
+impl <S: Subscriber> Layer<S> for CaptureLogsLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.0.lock().push(LogRecord {
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.finish(),
+        });
+    }
+}
+
+/// Records the log messages emitted on the current thread, until it is dropped.
+///
+/// Messages logged on other threads are not recorded, so tests of async code should use a
+/// single threaded runtime, such as the one used by `#[tokio::test]`.
+pub struct CapturedLogs {
+    records: Records,
+    previous: Option<Records>,
+    _guard: DefaultGuard,
+}
+impl CapturedLogs {
+    /// Returns the messages recorded so far, oldest first.
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.records.lock().clone()
+    }
+
+    /// Returns whether a message containing `pattern` was logged at a given level.
+    pub fn contains(&self, level: Level, pattern: &str) -> bool {
+        self.records.lock().iter().any(|x| x.level == level && x.message.contains(pattern))
+    }
+
+    /// Forgets the messages recorded so far.
+    pub fn clear(&self) {
+        self.records.lock().clear();
+    }
+}
+impl Drop for CapturedLogs {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|x| *x.borrow_mut() = previous);
+    }
+}
+
+/// Starts recording the log messages emitted on the current thread, at every level.
+///
+/// This replaces the logger for the current thread until the returned value is dropped, so the
+/// messages are not written to the console or log files. [`assert_logged!`] checks the
+/// messages recorded by the most recent call that has not been dropped yet.
+pub fn capture_logs() -> CapturedLogs {
+    crate::interface::activate_log_compat();
+    let records: Records = Arc::new(Mutex::new(Vec::new()));
+    let previous = CURRENT.with(|x| x.borrow_mut().replace(records.clone()));
+    let subscriber = Registry::default().with(CaptureLogsLayer(records.clone()));
+    CapturedLogs { records, previous, _guard: tracing::subscriber::set_default(subscriber) }
+}
+
+#[doc(hidden)]
+pub fn assert_logged_impl(level: Level, pattern: &str) {
+    let records = match CURRENT.with(|x| x.borrow().clone()) {
+        Some(x) => x,
+        None => panic!("`assert_logged!` was used without capturing logs with `capture_logs`."),
+    };
+    let records = records.lock();
+    if !records.iter().any(|x| x.level == level && x.message.contains(pattern)) {
+        let mut recorded = String::new();
+        for record in records.iter() {
+            recorded.push_str(&format!(
+                "\n    {} {}: {}", record.level, record.target, record.message,
+            ));
+        }
+        if recorded.is_empty() {
+            recorded.push_str(" (none)");
+        }
+        panic!(
+            "No message containing {:?} was logged at level {}. Recorded messages:{}",
+            pattern, level, recorded,
+        );
+    }
+}
+
+/// Asserts that a message containing a pattern was logged at a given level, since logs started
+/// being captured with [`capture_logs`].
+///
+/// This panics and lists every recorded message if no matching message was found.
+#[macro_export]
+macro_rules! assert_logged_18042165cecf4db9bcad8f65745fb00b {
+    ($level:expr, $pattern:expr $(,)?) => {
+        $crate::testing::assert_logged_impl($level, $pattern)
+    };
+}
+
+pub use crate::assert_logged_18042165cecf4db9bcad8f65745fb00b as assert_logged;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_logs() {
+        let logs = capture_logs();
+        warn!("Migration set {} has been executed more than once!", "test");
+        info!(count = 3, "Loaded modules.");
+        assert_logged!(Level::WARN, "executed more than once");
+        assert_logged!(Level::INFO, "Loaded modules. count=3");
+        assert!(!logs.contains(Level::ERROR, "executed more than once"));
+        logs.clear();
+        assert!(logs.records().is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+//! Secret tokens that local tools authenticate with, such as the remote console's.
+
+use crate::errors::*;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// Returns the token stored in a file, creating the file with a new random token if needed.
+///
+/// New token files can only be read by the user running the bot on Unix systems. `name` is
+/// the name of what the token is for in messages, such as `remote console`.
+pub fn load_token(path: &Path, name: &str) -> Result<String> {
+    if path.exists() {
+        let token = std::fs::read_to_string(path)?.trim().to_string();
+        ensure!(!token.is_empty(), "The {} token file is empty.", name);
+        return Ok(token)
+    }
+
+    let token: String = rand::thread_rng().sample_iter(Alphanumeric).take(32).collect();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(format!("{}\n", token).as_bytes())?;
+    info!("Created {} token at '{}'.", name, path.display());
+    Ok(token)
+}
+
+/// Compares two tokens in a time that does not depend on where they differ.
+pub fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(tokens_match("abc", "abc"));
+        assert!(!tokens_match("abc", "abd"));
+        assert!(!tokens_match("abc", "abcd"));
+    }
+}
@@ -0,0 +1,307 @@
+//! Detects when the bot stops making progress.
+//!
+//! Once the bot has started, the watchdog periodically dispatches [`WatchdogPingEvent`] from a
+//! background task, and a separate thread checks that each ping finishes in time. Modules that
+//! own resources that can hang, such as the database connection pool, should use them briefly
+//! when handling the event, and mark the waits with [`WatchdogPingEvent::probe`] so that a stall
+//! can be traced back to them.
+//!
+//! If a ping has not finished once the timeout has passed, or the event loop has not run the
+//! watchdog's task for that long, the running tasks and the unfinished probes are logged, along
+//! with a backtrace of where each probe was started. The watchdog is configured in the
+//! `[watchdog]` table of the configuration file:
+//!
+//! ```toml
+//! [watchdog]
+//! enabled = true  # whether the watchdog runs at all
+//! interval = 10   # how often the bot is pinged, in seconds
+//! timeout = 60    # how long a ping may take before the bot counts as stalled, in seconds
+//! abort = false   # whether to abort the process after logging a stall
+//! ```
+//!
+//! Aborting allows a service manager to restart a bot that has hung, rather than leaving it
+//! running without doing anything.
+
+use backtrace::Backtrace;
+use crate::config::Config;
+use crate::errors::*;
+use crate::interface::Interface;
+use crate::module::ModuleInfo;
+use crate::tasks::TaskManager;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the bot is pinged by default.
+pub const DEFAULT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a ping may take by default before the bot counts as stalled.
+pub const DEFAULT_WATCHDOG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the watchdog thread checks on the pings.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The settings in the `[watchdog]` table of the configuration file.
+#[derive(Copy, Clone)]
+struct WatchdogSettings {
+    interval: Duration,
+    timeout: Duration,
+    abort: bool,
+}
+impl WatchdogSettings {
+    /// Loads the settings, or returns `None` if the watchdog is disabled.
+    fn load(config: &Config) -> Result<Option<WatchdogSettings>> {
+        if !config.get("watchdog.enabled")?.unwrap_or(true) {
+            return Ok(None)
+        }
+        let interval = config.get("watchdog.interval")?.map(Duration::from_secs);
+        let timeout = config.get("watchdog.timeout")?.map(Duration::from_secs);
+        let settings = WatchdogSettings {
+            interval: interval.unwrap_or(DEFAULT_WATCHDOG_INTERVAL),
+            timeout: timeout.unwrap_or(DEFAULT_WATCHDOG_TIMEOUT),
+            abort: config.get("watchdog.abort")?.unwrap_or(false),
+        };
+        let zero = Duration::from_secs(0);
+        if settings.interval == zero || settings.timeout == zero {
+            cmd_error!("watchdog.interval and watchdog.timeout must be at least 1 second.");
+        }
+        Ok(Some(settings))
+    }
+}
+
+struct PendingProbe {
+    id: u64,
+    module: ModuleInfo,
+    name: String,
+    started: Instant,
+    backtrace: Backtrace,
+}
+
+struct WatchdogStatus {
+    last_tick: Instant,
+    ping_started: Option<Instant>,
+    probes: Vec<PendingProbe>,
+    next_probe: u64,
+}
+
+struct WatchdogState {
+    status: Mutex<WatchdogStatus>,
+    is_stopped: AtomicBool,
+}
+impl WatchdogState {
+    fn new() -> Self {
+        WatchdogState {
+            status: Mutex::new(WatchdogStatus {
+                last_tick: Instant::now(),
+                ping_started: None,
+                probes: Vec::new(),
+                next_probe: 0,
+            }),
+            is_stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Describes how the bot has stalled, or returns `None` if it is still making progress.
+    fn check_stall(&self, settings: &WatchdogSettings, tasks: &TaskManager) -> Option<String> {
+        let (mut report, probes) = {
+            let status = self.status.lock();
+            let report = match status.ping_started {
+                Some(started) if started.elapsed() >= settings.timeout => format!(
+                    "The bot has stalled: a watchdog ping has not finished after {} seconds.",
+                    started.elapsed().as_secs(),
+                ),
+                _ if status.last_tick.elapsed() >= settings.interval + settings.timeout => format!(
+                    "The bot has stalled: the event loop has not run the watchdog for {} seconds. \
+                     Every worker thread may be blocked.",
+                    status.last_tick.elapsed().as_secs(),
+                ),
+                _ => return None,
+            };
+            let probes: Vec<_> = status.probes.iter().map(|probe| (
+                format!("{} / {}", probe.module.name(), probe.name),
+                probe.started.elapsed(),
+                probe.backtrace.clone(),
+            )).collect();
+            (report, probes)
+        };
+
+        // resolving backtraces is slow, so it is done without holding the lock, which would
+        // block probes from starting or finishing in the meantime.
+        if !probes.is_empty() {
+            report.push_str("\nUnfinished probes:");
+            for (name, running_for, mut backtrace) in probes {
+                backtrace.resolve();
+                let _ = write!(
+                    report, "\n    {} (for {} seconds)\n{:?}",
+                    name, running_for.as_secs(), backtrace,
+                );
+            }
+        }
+        report.push_str("\nRunning tasks:");
+        for task in tasks.list_tasks() {
+            let _ = write!(
+                report, "\n    {} / {} (for {} seconds)",
+                task.module.name(), task.name, task.running_for.as_secs(),
+            );
+        }
+        Some(report)
+    }
+}
+
+/// Marks the watchdog as stopped when its task is dropped, so that its thread exits.
+struct StopGuard(Arc<WatchdogState>);
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.0.is_stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Dispatched periodically by the watchdog, to check that the bot is still making progress.
+///
+/// Handlers should do a small amount of real work with the resources they own, such as running
+/// a trivial query, so that the ping stalls along with them.
+pub struct WatchdogPingEvent {
+    state: Arc<WatchdogState>,
+}
+simple_event!(WatchdogPingEvent);
+impl WatchdogPingEvent {
+    /// Marks the start of a check that may hang, such as waiting for a database connection.
+    ///
+    /// If the bot stalls while the returned guard is alive, the check is named in the log
+    /// message about the stall, along with a backtrace of where this was called.
+    pub fn probe(&self, module: &ModuleInfo, name: &str) -> WatchdogProbe {
+        let mut status = self.state.status.lock();
+        let id = status.next_probe;
+        status.next_probe += 1;
+        status.probes.push(PendingProbe {
+            id,
+            module: module.clone(),
+            name: name.to_string(),
+            started: Instant::now(),
+            backtrace: Backtrace::new_unresolved(),
+        });
+        WatchdogProbe { state: self.state.clone(), id }
+    }
+}
+
+/// A check started with [`WatchdogPingEvent::probe`], which finishes when this is dropped.
+pub struct WatchdogProbe {
+    state: Arc<WatchdogState>,
+    id: u64,
+}
+impl Drop for WatchdogProbe {
+    fn drop(&mut self) {
+        self.state.status.lock().probes.retain(|x| x.id != self.id);
+    }
+}
+
+async fn watchdog_task(
+    target: Handler<impl Events>, state: Arc<WatchdogState>, interval: Duration, _stop: StopGuard,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        {
+            let mut status = state.status.lock();
+            status.last_tick = Instant::now();
+            status.ping_started = Some(Instant::now());
+        }
+        target.dispatch_async(WatchdogPingEvent { state: state.clone() }).await;
+        state.status.lock().ping_started = None;
+    }
+}
+
+fn watchdog_thread(
+    interface: Interface, state: Arc<WatchdogState>, tasks: TaskManager,
+    settings: WatchdogSettings,
+) {
+    let _thread = interface.enter_thread();
+    let mut is_stalled = false;
+    while !state.is_stopped.load(Ordering::Relaxed) {
+        thread::sleep(CHECK_INTERVAL);
+        match state.check_stall(&settings, &tasks) {
+            Some(report) if !is_stalled => {
+                error!("{}", report);
+                if settings.abort {
+                    error!("Aborting, as watchdog.abort is set.");
+                    std::process::abort();
+                }
+                is_stalled = true;
+            }
+            None if is_stalled => {
+                info!("The bot is making progress again.");
+                is_stalled = false;
+            }
+            _ => { }
+        }
+    }
+}
+
+/// Starts the watchdog, unless it is disabled in the configuration file.
+pub(crate) fn start_watchdog(target: &Handler<impl Events>, module: &ModuleInfo) -> Result<()> {
+    let settings = match WatchdogSettings::load(target.get_service::<Config>())? {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let state = Arc::new(WatchdogState::new());
+
+    let tasks = target.get_service::<TaskManager>().clone();
+    let stop = StopGuard(state.clone());
+    let task = watchdog_task(target.clone(), state.clone(), settings.interval, stop);
+    tasks.spawn(module, "watchdog", task);
+    let interface = target.get_service::<Interface>().clone();
+    thread::Builder::new().name("watchdog thread".to_owned()).spawn(move || {
+        watchdog_thread(interface, state, tasks, settings)
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn load_settings(text: &str) -> Result<Option<WatchdogSettings>> {
+        WatchdogSettings::load(&Config::parse(PathBuf::from("Sylphie.toml"), text)?)
+    }
+
+    #[test]
+    fn settings() {
+        assert!(load_settings("[watchdog]\nenabled = false").unwrap().is_none());
+        let settings = load_settings("[watchdog]\ninterval = 5\nabort = true").unwrap().unwrap();
+        assert_eq!(settings.interval, Duration::from_secs(5));
+        assert_eq!(settings.timeout, DEFAULT_WATCHDOG_TIMEOUT);
+        assert!(settings.abort);
+        assert!(load_settings("[watchdog]\ntimeout = 0").is_err());
+    }
+
+    #[test]
+    fn stalls() {
+        let settings = WatchdogSettings {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            abort: false,
+        };
+        let tasks = TaskManager::default();
+        let now = Instant::now();
+        let long_ago = now.checked_sub(Duration::from_secs(90)).unwrap();
+        let check = |last_tick, ping_started| {
+            let state = WatchdogState::new();
+            {
+                let mut status = state.status.lock();
+                status.last_tick = last_tick;
+                status.ping_started = ping_started;
+            }
+            state.check_stall(&settings, &tasks)
+        };
+
+        assert!(check(now, None).is_none());
+        assert!(check(now, Some(now)).is_none());
+        assert!(check(now, Some(long_ago)).unwrap().contains("ping has not finished"));
+        assert!(check(long_ago, None).unwrap().contains("has not run the watchdog"));
+    }
+}
@@ -0,0 +1,366 @@
+//! Support for backing up the persistent database while the bot is running, and restoring those
+//! backups when it is next started.
+//!
+//! Separate databases opened with [`Database::open_separate`] are backed up to files next to the
+//! backup of the main database, and are restored along with it.
+
+use crate::connection::*;
+use crate::migrations::{MigrationData, RegisterMigrationsEvent};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, TerminalOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::cli::CliArgs;
+use sylphie_core::core::BotInfo;
+use sylphie_core::prelude::*;
+
+/// Configures automatic backups of the persistent database.
+///
+/// This can be set with [`SylphieCoreBackupExt::with_backup_schedule`]. If it is not set, the
+/// database is only backed up when requested with the `backup` command.
+#[derive(Clone, Debug)]
+pub struct BackupSchedule {
+    interval: Duration,
+    retain: usize,
+    directory: Option<PathBuf>,
+}
+impl BackupSchedule {
+    /// Creates a schedule that backs up the database at a given interval.
+    pub fn new(interval: Duration) -> Self {
+        BackupSchedule { interval, retain: 7, directory: None }
+    }
+
+    /// Sets how many backups are kept. Older backups are deleted after each scheduled backup.
+    ///
+    /// This defaults to `7`.
+    pub fn retain(mut self, count: usize) -> Self {
+        self.retain = count.max(1);
+        self
+    }
+
+    /// Sets the directory backups are stored in.
+    ///
+    /// This defaults to the `backups` directory in the bot's data directory.
+    pub fn directory(mut self, path: impl Into<PathBuf>) -> Self {
+        self.directory = Some(path.into());
+        self
+    }
+}
+
+/// Contains extension functions for configuring backups on [`SylphieCore`].
+pub trait SylphieCoreBackupExt {
+    /// Sets the schedule used to automatically back up the database.
+    fn with_backup_schedule(self, schedule: BackupSchedule) -> Self;
+}
+impl <R: Module> SylphieCoreBackupExt for SylphieCore<R> {
+    fn with_backup_schedule(self, schedule: BackupSchedule) -> Self {
+        self.with_service(Arc::new(schedule))
+    }
+}
+
+fn backup_dir(target: &Handler<impl Events>) -> PathBuf {
+    let schedule = target.services().resolve::<BackupSchedule>();
+    match schedule.and_then(|x| x.directory.clone()) {
+        Some(dir) => dir,
+        None => {
+            let mut path = target.get_service::<BotInfo>().root_path().to_owned();
+            path.push("backups");
+            path
+        }
+    }
+}
+fn backup_prefix(target: &Handler<impl Events>) -> String {
+    format!("{}-", target.get_service::<BotInfo>().bot_name())
+}
+
+/// Creates a new empty file for a backup, named after the current time.
+///
+/// A counter is added to the name if a backup was already made in the same millisecond, so an
+/// existing backup is never overwritten.
+fn new_backup_file(dir: &Path, prefix: &str) -> Result<PathBuf> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%3f").to_string();
+    let mut counter = 0;
+    loop {
+        // `_` sorts after the `.` of the extension, so the names stay in chronological order
+        let name = match counter {
+            0 => format!("{}{}.db", prefix, timestamp),
+            _ => format!("{}{}_{}.db", prefix, timestamp, counter),
+        };
+        let path = dir.join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => counter += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Backs up the database to a new timestamped file in the backup directory.
+///
+/// Returns the path of the new backup.
+pub async fn create_backup(target: &Handler<impl Events>) -> Result<PathBuf> {
+    let dir = backup_dir(target);
+    fs::create_dir_all(&dir)?;
+
+    let path = new_backup_file(&dir, &backup_prefix(target))?;
+    if let Err(e) = target.get_service::<Database>().backup_to(&path).await {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Could not remove incomplete backup '{}': {}", path.display(), e);
+        }
+        return Err(e)
+    }
+    Ok(path)
+}
+
+/// Returns the backups of separate databases stored next to a backup, along with their names.
+fn separate_backups(path: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let stem = path.file_stem().and_then(|x| x.to_str())
+        .internal_err(|| format!("'{}' is not a valid backup path.", path.display()))?;
+    let prefix = format!("{}.", stem);
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new())
+    }
+
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let name = file_name.to_str()
+            .and_then(|x| x.strip_prefix(prefix.as_str()))
+            .and_then(|x| x.strip_suffix(".db"));
+        if let Some(name) = name {
+            if is_valid_separate_name(name) && entry.file_type()?.is_file() {
+                backups.push((name.to_string(), entry.path()));
+            }
+        }
+    }
+    Ok(backups)
+}
+
+/// Deletes the oldest backups in the backup directory, keeping only the newest `retain` backups.
+fn prune_backups(dir: &Path, prefix: &str, retain: usize) -> Result<()> {
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if let Some(name) = name.to_str() {
+            // backups of separate databases have a second `.` in their names, and are removed
+            // along with the backup they belong to.
+            let timestamp = name.strip_prefix(prefix).and_then(|x| x.strip_suffix(".db"));
+            if timestamp.map_or(false, |x| !x.contains('.')) && entry.file_type()?.is_file() {
+                backups.push(entry.path());
+            }
+        }
+    }
+    // the timestamps in the file names sort in chronological order
+    backups.sort();
+    if backups.len() > retain {
+        for path in &backups[..backups.len() - retain] {
+            debug!("Removing old backup: {}", path.display());
+            for (_, separate) in separate_backups(path)? {
+                fs::remove_file(separate)?;
+            }
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Starts the scheduled backup task, if a [`BackupSchedule`] has been set.
+pub(crate) fn start_backup_task(target: &Handler<impl Events>, module: &impl Module) {
+    let schedule = match target.services().resolve::<BackupSchedule>() {
+        Some(schedule) => schedule,
+        None => return,
+    };
+    let handler = target.clone();
+    module.spawn(target, "scheduled_backup", async move {
+        let mut interval = tokio::time::interval(schedule.interval);
+        // the first tick completes immediately, and we don't want a backup on every startup.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let result = async {
+                let path = create_backup(&handler).await?;
+                info!("Backed up database to {}", path.display());
+                let dir = backup_dir(&handler);
+                let prefix = backup_prefix(&handler);
+                let retain = schedule.retain;
+                tokio::task::spawn_blocking(move || prune_backups(&dir, &prefix, retain)).await?
+            }.await;
+            if let Err(e) = result {
+                e.report_error();
+            }
+        }
+    });
+}
+
+fn read_backup_versions(path: &Path) -> Result<HashMap<String, u32>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let has_table: bool = conn.query_row(
+        "SELECT count(*) > 0 FROM sqlite_master \
+            WHERE type = 'table' AND name = 'sylphie_db_migrations_tracking';",
+        rusqlite::NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    let mut versions = HashMap::new();
+    if has_table {
+        let mut stat = conn.prepare(
+            "SELECT migration_name, current_version FROM sylphie_db_migrations_tracking;",
+        )?;
+        let mut rows = stat.query(rusqlite::NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            versions.insert(row.get(0)?, row.get(1)?);
+        }
+    }
+    Ok(versions)
+}
+
+/// Checks that the migration sets compiled into the bot can be applied to a backup.
+fn validate_backup(migrations: &[&'static MigrationData], path: &Path) -> Result<()> {
+    let versions = read_backup_versions(path)
+        .internal_err(|| format!("'{}' is not a valid backup.", path.display()))?;
+    for migration in migrations {
+        if migration.is_transient {
+            continue
+        }
+
+        let mut version = versions.get(migration.migration_id).copied().unwrap_or(0);
+        ensure!(
+            version <= migration.target_version,
+            "The backup has migration set {} at version {}, which is newer than the version this \
+             bot uses. ({})",
+            migration.migration_set_name, version, migration.target_version,
+        );
+        for script in migration.scripts {
+            if version == script.from {
+                version = script.to;
+            }
+        }
+        ensure!(
+            version == migration.target_version,
+            "Migration set {} cannot be migrated from the version in the backup.",
+            migration.migration_set_name,
+        );
+    }
+    Ok(())
+}
+
+/// Returns the path of a backup staged by the `restore` command.
+fn pending_restore_path(target: &Handler<impl Events>) -> PathBuf {
+    let info = target.get_service::<BotInfo>();
+    let mut path = info.root_path().to_owned();
+    path.push("db");
+    path.push(format!("{}.restore.db", info.bot_name()));
+    path
+}
+
+/// Restores a backup before the database is used, if one was given with `--restore-backup` or
+/// staged with the `restore` command.
+pub(crate) fn restore_on_startup(target: &Handler<impl Events>) -> Result<()> {
+    let pending = pending_restore_path(target);
+    let (path, is_pending) = match &target.get_service::<CliArgs>().restore_backup {
+        Some(path) => (path.clone(), false),
+        None if pending.is_file() => (pending, true),
+        None => return Ok(()),
+    };
+
+    info!("Restoring database from {}...", path.display());
+    let ev = target.dispatch_sync(RegisterMigrationsEvent::new());
+    validate_backup(ev.migrations(), &path)?;
+    let separate = separate_backups(&path)?;
+    let backend = target.get_service::<Database>().backend();
+    backend.restore_from(&path)?;
+    for (name, separate_path) in &separate {
+        debug!("Restoring separate database '{}'...", name);
+        backend.open_separate(name)?.restore_from(separate_path)?;
+    }
+    if is_pending {
+        for (_, separate_path) in &separate {
+            fs::remove_file(separate_path)?;
+        }
+        fs::remove_file(&path)?;
+    }
+    info!("Database restored.");
+    Ok(())
+}
+
+struct RestoreCommand;
+impl RestoreCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        if ctx.args_count() != 2 {
+            cmd_error!("Usage: restore <path>");
+        }
+        let target = ctx.handler();
+        let path = PathBuf::from(ctx.arg(1).text);
+        let ev = target.dispatch_sync(RegisterMigrationsEvent::new());
+        let migrations = ev.migrations().to_vec();
+        let pending = pending_restore_path(target);
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            validate_backup(&migrations, &path)?;
+            for (_, old) in separate_backups(&pending)? {
+                fs::remove_file(old)?;
+            }
+            for (name, separate) in separate_backups(&path)? {
+                fs::copy(&separate, separate_path(&pending, &name)?)?;
+            }
+            fs::copy(&path, &pending)?;
+            Ok(())
+        }).await??;
+        ctx.respond("The backup will be restored when the bot is next started.").await?;
+        Ok(())
+    }
+}
+impl CommandImpl for RestoreCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+struct BackupCommand;
+impl BackupCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let target = ctx.handler();
+        let path = match ctx.args_count() {
+            1 => create_backup(target).await?,
+            2 => {
+                let path = PathBuf::from(ctx.arg(1).text);
+                target.get_service::<Database>().backup_to(&path).await?;
+                path
+            }
+            _ => cmd_error!("Usage: backup [path]"),
+        };
+        ctx.respond(&format!("Backed up database to {}", path.display())).await?;
+        Ok(())
+    }
+}
+impl CommandImpl for BackupCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `backup` and `restore` commands.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("backup");
+    ev.register_command(Command::new(target, module, info, TerminalOnly(BackupCommand)));
+    let info = CommandInfo::new("restore");
+    ev.register_command(Command::new(target, module, info, TerminalOnly(RestoreCommand)));
+}
@@ -0,0 +1,202 @@
+//! A content-addressed store for large binary data such as user uploads.
+//!
+//! Blobs are stored as files in the `blobs` directory of the bot's root path, named by the hash
+//! of their contents, so storing the same data twice only keeps one copy. The store can be
+//! retrieved as a service with `target.get_service::<BlobStore>()`.
+
+use arc_swap::ArcSwapOption;
+use crate::serializable::*;
+use serde::*;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use sylphie_core::prelude::*;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+/// The default maximum size of a single blob.
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The size of the chunks blobs are read and hashed in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The ID of a blob, derived from a hash of its contents.
+#[derive(Serialize, Deserialize)]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct BlobId([u8; 32]);
+impl BlobId {
+    /// Returns the hash this ID is made from.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+impl fmt::Display for BlobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+impl fmt::Debug for BlobId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlobId({})", self)
+    }
+}
+impl DbSerializable for BlobId {
+    type Format = BincodeFormat;
+    const ID: &'static str = "sylphie_database::blobs::BlobId";
+    const SCHEMA_VERSION: u32 = 0;
+}
+
+/// The store for blobs.
+#[derive(Default)]
+pub struct BlobStore {
+    path: ArcSwapOption<PathBuf>,
+    max_size: AtomicU64,
+    temp_id: AtomicU64,
+}
+impl BlobStore {
+    pub(crate) fn new() -> Self {
+        BlobStore {
+            max_size: AtomicU64::new(DEFAULT_MAX_BLOB_SIZE),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn set_path(&self, path: PathBuf) {
+        self.path.store(Some(Arc::new(path)));
+    }
+
+    fn path(&self) -> Arc<PathBuf> {
+        self.path.load().as_ref().expect("BlobStore is not initialized.").clone()
+    }
+    fn blob_path(&self, id: BlobId) -> PathBuf {
+        let name = id.to_string();
+        let mut path = (*self.path()).clone();
+        path.push(&name[..2]);
+        path.push(name);
+        path
+    }
+
+    /// Returns the maximum size of a single blob, in bytes.
+    pub fn max_size(&self) -> u64 {
+        self.max_size.load(Ordering::Relaxed)
+    }
+
+    /// Sets the maximum size of a single blob, in bytes.
+    ///
+    /// This only affects blobs stored after it is called.
+    pub fn set_max_size(&self, max_size: u64) {
+        self.max_size.store(max_size, Ordering::Relaxed);
+    }
+
+    /// Stores a blob, reading its contents from a stream.
+    ///
+    /// The contents are written to a temporary file while they are hashed, and moved into
+    /// place once the stream ends. If a blob with the same contents already exists, the new copy
+    /// is discarded.
+    pub async fn store(&self, mut data: impl AsyncRead + Unpin) -> Result<BlobId> {
+        let mut temp_path = (*self.path()).clone();
+        temp_path.push("tmp");
+        fs::create_dir_all(&temp_path).await?;
+        temp_path.push(format!(
+            "{}-{}.tmp", std::process::id(), self.temp_id.fetch_add(1, Ordering::Relaxed),
+        ));
+
+        let result = async {
+            let max_size = self.max_size();
+            let mut file = File::create(&temp_path).await?;
+            let mut hasher = blake3::Hasher::new();
+            let mut buf = vec![0; CHUNK_SIZE];
+            let mut size = 0;
+            loop {
+                let len = data.read(&mut buf).await?;
+                if len == 0 {
+                    break
+                }
+                size += len as u64;
+                ensure!(size <= max_size, "Blob is larger than the limit of {} bytes.", max_size);
+                hasher.update(&buf[..len]);
+                file.write_all(&buf[..len]).await?;
+            }
+            file.sync_all().await?;
+            Ok(BlobId(*hasher.finalize().as_bytes()))
+        }.await;
+        let id = match result {
+            Ok(id) => id,
+            Err(e) => {
+                remove_if_exists(&temp_path).await?;
+                return Err(e)
+            }
+        };
+
+        let path = self.blob_path(id);
+        if fs::metadata(&path).await.is_ok() {
+            remove_if_exists(&temp_path).await?;
+        } else {
+            fs::create_dir_all(path.parent().unwrap()).await?;
+            fs::rename(&temp_path, &path).await?;
+        }
+        Ok(id)
+    }
+
+    /// Stores a blob from a buffer in memory.
+    pub async fn store_bytes(&self, data: &[u8]) -> Result<BlobId> {
+        self.store(data).await
+    }
+
+    /// Returns whether a blob exists.
+    pub async fn contains(&self, id: BlobId) -> Result<bool> {
+        Ok(fs::metadata(self.blob_path(id)).await.is_ok())
+    }
+
+    /// Returns the size of a blob, or `None` if it does not exist.
+    pub async fn size(&self, id: BlobId) -> Result<Option<u64>> {
+        match fs::metadata(self.blob_path(id)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Opens a blob for reading as a stream, or returns `None` if it does not exist.
+    pub async fn open(&self, id: BlobId) -> Result<Option<File>> {
+        match File::open(self.blob_path(id)).await {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads the entire contents of a blob into memory, or returns `None` if it does not exist.
+    pub async fn read(&self, id: BlobId) -> Result<Option<Vec<u8>>> {
+        match self.open(id).await? {
+            Some(mut file) => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data).await?;
+                Ok(Some(data))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes a blob.
+    ///
+    /// As blobs are deduplicated, this removes the data for every module that stored the same
+    /// contents. Modules that share blobs should track their own references before removing
+    /// them.
+    pub async fn remove(&self, id: BlobId) -> Result<()> {
+        remove_if_exists(&self.blob_path(id)).await
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,135 @@
+//! The `get_*`, `set_*` and `reset_*` commands generated for each configuration option.
+//!
+//! `set_*` and `reset_*` can only be run by the bot's owners.
+
+use crate::config::*;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, OwnerOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+
+#[derive(Copy, Clone)]
+enum ConfigCommandKind {
+    Get,
+    Set,
+    Reset,
+}
+impl ConfigCommandKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            ConfigCommandKind::Get => "get",
+            ConfigCommandKind::Set => "set",
+            ConfigCommandKind::Reset => "reset",
+        }
+    }
+}
+
+struct ConfigCommand {
+    name: Arc<str>,
+    config: Arc<RegisteredConfig>,
+    kind: ConfigCommandKind,
+}
+impl ConfigCommand {
+    /// Finds the scope a command should change an option in.
+    ///
+    /// If a scope type is given, this is the scope of that type in the current context. If not,
+    /// the most specific scope the option can be set in is used.
+    fn target_scope(&self, scopes: &[Scope], scope_type: Option<&str>) -> Result<Scope> {
+        let order = self.config.resolution_order(scopes);
+        match scope_type {
+            Some(scope_type) => {
+                let scope_type = scope_type.to_ascii_lowercase();
+                let found = order.into_iter().find(|x| {
+                    ConfigFlag::for_scope(x).map_or(false, |flag| flag.name() == scope_type)
+                });
+                found.cmd_error(|| format!(
+                    "'{}' cannot be set in a {} scope here.", self.name, scope_type,
+                ))
+            }
+            None => order.into_iter().next().cmd_error(|| format!(
+                "'{}' cannot be set here.", self.name,
+            )),
+        }
+    }
+
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let target = ctx.handler();
+        match self.kind {
+            ConfigCommandKind::Get => {
+                let (value, scope) = self.config.resolve_display(target, ctx.scopes()).await?;
+                let source = match scope.as_ref().and_then(ConfigFlag::for_scope) {
+                    Some(flag) => format!("set in {} scope", flag.name()),
+                    None => "default".to_string(),
+                };
+                ctx.respond(&format!("{} = {} ({})", self.name, value, source)).await?;
+            }
+            ConfigCommandKind::Set => {
+                let (scope_type, value) = match ctx.args_count() {
+                    2 => (None, ctx.arg(1).text),
+                    3 => (Some(ctx.arg(1).text), ctx.arg(2).text),
+                    _ => cmd_error!(
+                        "Usage: {} [scope] <value>", format!("set_{}", self.name),
+                    ),
+                };
+                let scope = self.target_scope(ctx.scopes(), scope_type)?;
+                self.config.set_parse(target, scope.clone(), value).await?;
+                let value = self.config.get_display(target, scope.clone()).await?;
+                ctx.respond(&format!(
+                    "Set '{}' to {} in {} scope.",
+                    self.name, value, ConfigFlag::for_scope(&scope).map_or("unknown", |x| x.name()),
+                )).await?;
+            }
+            ConfigCommandKind::Reset => {
+                let scope_type = match ctx.args_count() {
+                    1 => None,
+                    2 => Some(ctx.arg(1).text),
+                    _ => cmd_error!("Usage: {} [scope]", format!("reset_{}", self.name)),
+                };
+                let scope = self.target_scope(ctx.scopes(), scope_type)?;
+                self.config.remove(target, scope.clone()).await?;
+                ctx.respond(&format!(
+                    "Reset '{}' in {} scope.",
+                    self.name, ConfigFlag::for_scope(&scope).map_or("unknown", |x| x.name()),
+                )).await?;
+            }
+        }
+        Ok(())
+    }
+}
+impl CommandImpl for ConfigCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the commands for every configuration option.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, manager: &ConfigManager, ev: &mut RegisterCommandsEvent,
+) {
+    if manager.options.load().is_none() {
+        return
+    }
+    for option in manager.option_list().iter() {
+        for entry in option.value.entry_names() {
+            let kinds = [ConfigCommandKind::Get, ConfigCommandKind::Set, ConfigCommandKind::Reset];
+            for kind in &kinds {
+                let info = CommandInfo::new(format!("{}_{}", kind.prefix(), entry.name));
+                let command = ConfigCommand {
+                    name: entry.name.clone(),
+                    config: option.value.clone(),
+                    kind: *kind,
+                };
+                let prefix = entry.prefix.clone();
+                ev.register_command(match kind {
+                    ConfigCommandKind::Get =>
+                        Command::new_dynamic(target, prefix, info, command),
+                    ConfigCommandKind::Set | ConfigCommandKind::Reset =>
+                        Command::new_dynamic(target, prefix, info, OwnerOnly(command)),
+                });
+            }
+        }
+    }
+}
@@ -20,4 +20,18 @@ impl ConfigType for String {
     fn ui_parse(text: &str) -> Result<Self> {
         Ok(text.to_string())
     }
-}
\ No newline at end of file
+}
+
+macro_rules! integer_impls {
+    ($($ty:ty),* $(,)?) => {$(
+        impl ConfigType for $ty {
+            fn ui_fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(self, formatter)
+            }
+            fn ui_parse(text: &str) -> Result<Self> {
+                text.trim().parse().cmd_error(|| format!("'{}' is not a valid number.", text))
+            }
+        }
+    )*};
+}
+integer_impls!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
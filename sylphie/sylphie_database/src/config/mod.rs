@@ -11,16 +11,20 @@ use std::any::{Any, TypeId};
 use std::fmt;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::core::ReloadEvent;
 use sylphie_core::derives::*;
 use sylphie_core::prelude::*;
 use sylphie_utils::cache::LruCache;
 use sylphie_utils::disambiguate::*;
 use sylphie_utils::locks::LockSet;
-use sylphie_utils::scopes::Scope;
+use sylphie_utils::scopes::{CONNECTION_SCOPE_TYPE, Scope, ScopeArgs, ScopedEvent, USER_SCOPE_TYPE};
+use sylphie_utils::strings::StringWrapper;
 
+mod commands;
 mod impls;
 
-static CONFIG_MIGRATIONS: MigrationData = MigrationData {
+pub(crate) static CONFIG_MIGRATIONS: MigrationData = MigrationData {
     migration_id: "config 7c8f3471-5ef7-4a8a-9388-36ea9e512a57",
     migration_set_name: "config",
     is_transient: false,
@@ -30,7 +34,6 @@ static CONFIG_MIGRATIONS: MigrationData = MigrationData {
     ],
 };
 pub(crate) async fn init_config(target: &Handler<impl Events>) -> Result<()> {
-    CONFIG_MIGRATIONS.execute(target).await?;
     target.get_service::<ConfigManager>().reload(target).await?;
     Ok(())
 }
@@ -53,11 +56,66 @@ pub enum ConfigFlag {
     ///
     /// Note that on platforms like IRC, a channel and a server are the same thing.
     Channel,
+    /// This configuration option can be set for a particular user.
+    User,
 
     /// This configuration option can be set in any scope.
     Any,
 }
 
+impl ConfigFlag {
+    /// Returns the flag corresponding to a scope, based on its scope type.
+    ///
+    /// Scope types that do not correspond to any flag return `None`.
+    pub fn for_scope(scope: &Scope) -> Option<ConfigFlag> {
+        match scope.scope_type.as_str() {
+            "global" => Some(ConfigFlag::Global),
+            CONNECTION_SCOPE_TYPE => Some(ConfigFlag::Connection),
+            "server" | "guild" => Some(ConfigFlag::Server),
+            "category" => Some(ConfigFlag::Category),
+            "channel" => Some(ConfigFlag::Channel),
+            USER_SCOPE_TYPE => Some(ConfigFlag::User),
+            _ => None,
+        }
+    }
+
+    /// Returns a lowercase name for this flag, as used in commands.
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigFlag::Global => "global",
+            ConfigFlag::Connection => "connection",
+            ConfigFlag::Server => "server",
+            ConfigFlag::Category => "category",
+            ConfigFlag::Channel => "channel",
+            ConfigFlag::User => "user",
+            ConfigFlag::Any => "any",
+        }
+    }
+}
+
+/// The scope that global configuration options are stored in.
+pub const GLOBAL_SCOPE: Scope = Scope {
+    scope_type: StringWrapper::Static("global"),
+    args: ScopeArgs::None,
+};
+
+/// Returns the scopes a config option is looked up in, from most to least specific.
+pub(crate) fn resolution_order(flags: EnumSet<ConfigFlag>, scopes: &[Scope]) -> Vec<Scope> {
+    let allowed = |flag| flags.contains(ConfigFlag::Any) || flags.contains(flag);
+    let mut order = Vec::new();
+    for scope in scopes {
+        match ConfigFlag::for_scope(scope) {
+            Some(ConfigFlag::Global) | None => { }
+            Some(flag) if allowed(flag) => order.push(scope.clone()),
+            Some(_) => { }
+        }
+    }
+    if allowed(ConfigFlag::Global) {
+        order.push(GLOBAL_SCOPE);
+    }
+    order
+}
+
 pub struct ConfigKey<V: ConfigType>(&'static __macro_priv::ConfigKeyData<V>);
 impl <V: ConfigType> Clone for ConfigKey<V> {
     fn clone(&self) -> Self {
@@ -143,6 +201,9 @@ trait DynConfigType: Send + Sync + 'static {
     async fn get_display<'a>(
         &'a self, target: &'a (dyn Any + Send + Sync + 'static), scope: Scope,
     ) -> Result<String>;
+    async fn resolve_display<'a>(
+        &'a self, target: &'a (dyn Any + Send + Sync + 'static), scopes: &'a [Scope],
+    ) -> Result<(String, Option<Scope>)>;
     async fn set_parse<'a>(
         &'a self, target: &'a (dyn Any + Send + Sync + 'static), scope: Scope, value: &'a str,
     ) -> Result<()>;
@@ -167,6 +228,14 @@ impl <E: Events, T: ConfigType> DynConfigType for DynConfigKey<E, T> {
         let val = manager.get(target, scope, self.0).await?;
         Ok(val.display().to_string())
     }
+    async fn resolve_display<'a>(
+        &'a self, target: &'a (dyn Any + Send + Sync + 'static), scopes: &'a [Scope],
+    ) -> Result<(String, Option<Scope>)> {
+        let target = target.downcast_ref::<Handler<E>>().expect("Wrong Handler type passed.");
+        let manager = target.get_service::<ConfigManager>();
+        let (val, scope) = manager.resolve_0(target, scopes, self.0).await?;
+        Ok((val.display().to_string(), scope))
+    }
     async fn set_parse<'a>(
         &'a self, target: &'a (dyn Any + Send + Sync + 'static), scope: Scope, value: &'a str,
     ) -> Result<()> {
@@ -200,9 +269,34 @@ pub struct RegisteredConfig {
     entry_names: Vec<EntryName>,
     id: TypeId,
     db_id: StringId,
+    flags: EnumSet<ConfigFlag>,
     dyn_config: Box<dyn DynConfigType>,
 }
 impl RegisteredConfig {
+    /// Returns the names this configuration option was registered with.
+    pub fn entry_names(&self) -> &[EntryName] {
+        &self.entry_names
+    }
+
+    /// Returns the scopes this configuration option can be set in.
+    pub fn flags(&self) -> EnumSet<ConfigFlag> {
+        self.flags
+    }
+
+    /// Returns the scopes this configuration option is looked up in, from most to least
+    /// specific, given the scopes of a context.
+    pub fn resolution_order(&self, scopes: &[Scope]) -> Vec<Scope> {
+        resolution_order(self.flags, scopes)
+    }
+
+    /// Resolves the value of this configuration option, returning it alongside the scope it was
+    /// set in, or `None` if the default value is used.
+    pub async fn resolve_display(
+        &self, target: &Handler<impl Events>, scopes: &[Scope],
+    ) -> Result<(String, Option<Scope>)> {
+        self.dyn_config.resolve_display(target, scopes).await
+    }
+
     pub async fn get_display(
         &self, target: &Handler<impl Events>, scope: Scope,
     ) -> Result<String> {
@@ -277,6 +371,7 @@ impl RegisterConfigEvent {
                 entry_names: vec![entry_name],
                 id: key.0.id,
                 db_id,
+                flags: key.0.flags,
                 dyn_config: Box::new(DynConfigKey::new(target, *key)),
             });
         }
@@ -290,6 +385,11 @@ pub struct ConfigurationChangedEvent {
     pub config_key: RegisteredConfig,
 }
 failable_event!(ConfigurationChangedEvent, (), Error);
+impl ScopedEvent for ConfigurationChangedEvent {
+    fn scopes(&self) -> &[Scope] {
+        std::slice::from_ref(&self.scope)
+    }
+}
 
 struct ConfigManagerData {
     disambiguate: DisambiguatedSet<Arc<RegisteredConfig>>,
@@ -321,9 +421,43 @@ pub struct ConfigManager {
 }
 #[module_impl]
 impl ConfigManager {
+    /// Returns the value of a config option in a given scope, or its default value if it has not
+    /// been set in that scope.
     pub async fn get<'a, T: ConfigType>(
         &'a self, target: &'a Handler<impl Events>, scope: Scope, key: ConfigKey<T>,
     ) -> Result<T> {
+        match self.get_opt(target, scope, key).await? {
+            Some(val) => Ok(val),
+            None => Ok((key.0.default_value)()),
+        }
+    }
+
+    /// Resolves the value of a config option for a context, given its scopes ordered from most
+    /// to least specific.
+    ///
+    /// Each scope that the option can be set in is checked in turn, followed by the global
+    /// scope. If the option has not been set in any of them, its default value is returned.
+    pub async fn resolve<'a, T: ConfigType>(
+        &'a self, target: &'a Handler<impl Events>, scopes: &'a [Scope], key: ConfigKey<T>,
+    ) -> Result<T> {
+        Ok(self.resolve_0(target, scopes, key).await?.0)
+    }
+    async fn resolve_0<'a, T: ConfigType>(
+        &'a self, target: &'a Handler<impl Events>, scopes: &'a [Scope], key: ConfigKey<T>,
+    ) -> Result<(T, Option<Scope>)> {
+        for scope in resolution_order(key.0.flags, scopes) {
+            if let Some(val) = self.get_opt(target, scope.clone(), key).await? {
+                return Ok((val, Some(scope)))
+            }
+        }
+        Ok(((key.0.default_value)(), None))
+    }
+
+    /// Returns the value of a config option in a given scope, or `None` if it has not been set
+    /// in that scope.
+    pub async fn get_opt<'a, T: ConfigType>(
+        &'a self, target: &'a Handler<impl Events>, scope: Scope, key: ConfigKey<T>,
+    ) -> Result<Option<T>> {
         let scope = ScopeId::intern(target, scope).await?;
         let val = self.cache.cached_async((scope, key.0.id), async {
             let mut conn = target.connect_db().await?;
@@ -353,11 +487,7 @@ impl ConfigManager {
                 Ok(None)
             }
         }).await?;
-        if val.is_none() {
-            Ok((key.0.default_value)())
-        } else {
-            Ok(val.unwrap().downcast_ref::<T>().unwrap().clone())
-        }
+        Ok(val.map(|x| x.downcast_ref::<T>().unwrap().clone()))
     }
 
     pub async fn set<'a, T: ConfigType>(
@@ -415,6 +545,19 @@ impl ConfigManager {
         Ok(())
     }
 
+    #[event_handler]
+    fn register_commands(&self, target: &Handler<impl Events>, ev: &mut RegisterCommandsEvent) {
+        commands::register_commands(target, self, ev);
+    }
+
+    #[event_handler]
+    async fn reload_on_event(&self, target: &Handler<impl Events>, _: &ReloadEvent) {
+        self.cache.clear();
+        if let Err(e) = self.reload(target).await {
+            e.report_error();
+        }
+    }
+
     /// Reloads the config manager.
     pub async fn reload(&self, target: &Handler<impl Events>) -> Result<()> {
         let new_set = ConfigManagerData::from_event(target.dispatch_async(RegisterConfigEvent {
@@ -453,4 +596,38 @@ impl Default for ConfigManager {
             options: ArcSwapOption::new(None),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The scopes of a message received by a connector belonging to a connection.
+    fn message_scopes() -> Vec<Scope> {
+        vec![
+            Scope::new(USER_SCOPE_TYPE, ScopeArgs::String("discord:1".to_string().into())),
+            Scope::new("channel", ScopeArgs::Long(2)),
+            Scope::new("guild", ScopeArgs::Long(3)),
+            Scope::new(CONNECTION_SCOPE_TYPE, ScopeArgs::Long(0)),
+        ]
+    }
+
+    #[test]
+    fn resolve_connector_scopes() {
+        let scopes = message_scopes();
+        let flags: Vec<_> = scopes.iter().map(ConfigFlag::for_scope).collect();
+        assert_eq!(flags, [
+            Some(ConfigFlag::User), Some(ConfigFlag::Channel),
+            Some(ConfigFlag::Server), Some(ConfigFlag::Connection),
+        ]);
+
+        let order = resolution_order(ConfigFlag::User | ConfigFlag::Connection, &scopes);
+        assert_eq!(order, [scopes[0].clone(), scopes[3].clone()]);
+
+        let order = resolution_order(ConfigFlag::Server | ConfigFlag::Global, &scopes);
+        assert_eq!(order, [scopes[2].clone(), GLOBAL_SCOPE]);
+
+        let order = resolution_order(EnumSet::only(ConfigFlag::Any), &scopes);
+        assert_eq!(order.len(), 5);
+        assert_eq!(order[4], GLOBAL_SCOPE);
+    }
+}
@@ -0,0 +1,538 @@
+use crate::connection::SqlDialect;
+use crate::connection::statement_cache::{STATEMENT_CACHE_CAPACITY, StatementCacheStats};
+use crate::connection::values;
+use crate::serializable::SerializeValue;
+use parking_lot::Mutex;
+use rusqlite::{Connection, OpenFlags, StatementStatus, ToSql};
+use rusqlite::backup::Backup;
+use rusqlite::types::ValueRef;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use sylphie_core::prelude::*;
+
+/// The parameters passed to a SQL statement.
+#[derive(Clone, Debug)]
+pub enum QueryParams {
+    /// Parameters bound to `?` in the order they appear in the statement.
+    Positional(Vec<SerializeValue>),
+    /// Parameters bound by name. Names include their leading `:`, `@` or `$`.
+    Named(Vec<(String, SerializeValue)>),
+}
+impl QueryParams {
+    /// Converts a serializable value into unnamed parameters.
+    pub fn positional(params: impl Serialize) -> Result<Self> {
+        Ok(QueryParams::Positional(values::to_values(params)?))
+    }
+
+    /// Converts a serializable value into named parameters.
+    pub fn named(params: impl Serialize) -> Result<Self> {
+        Ok(QueryParams::Named(values::to_named_values(params)?))
+    }
+}
+
+/// The rows returned by a SQL query.
+#[derive(Clone, Debug, Default)]
+pub struct QueryRows {
+    /// The names of the columns returned by the query.
+    pub columns: Vec<String>,
+    /// The values in each row, in the same order as `columns`.
+    pub rows: Vec<Vec<SerializeValue>>,
+}
+impl QueryRows {
+    /// Deserializes each row into a value.
+    pub fn deserialize<T: DeserializeOwned>(self) -> Result<Vec<T>> {
+        let columns = self.columns;
+        self.rows.into_iter().map(|row| values::from_row(&columns, row)).collect()
+    }
+}
+
+/// A single connection to a [`StorageBackend`].
+///
+/// Connections are only used from blocking threads, and may block freely.
+pub trait BackendConnection: Send + 'static {
+    /// Checks that the connection is still usable.
+    fn check_valid(&mut self) -> Result<()>;
+
+    /// Executes a SQL statement, returning the number of rows changed.
+    fn execute(&mut self, sql: &str, params: QueryParams) -> Result<usize>;
+
+    /// Executes multiple SQL statements with no parameters.
+    fn execute_batch(&mut self, sql: &str) -> Result<()>;
+
+    /// Runs a SQL query, returning at most `max_rows` rows if it is given.
+    fn query(
+        &mut self, sql: &str, params: QueryParams, max_rows: Option<usize>,
+    ) -> Result<QueryRows>;
+
+    /// Returns the number of lookups in this connection's prepared statement cache since this
+    /// was last called.
+    ///
+    /// Connections that do not cache prepared statements always return zero.
+    fn take_statement_cache_stats(&mut self) -> StatementCacheStats {
+        StatementCacheStats::default()
+    }
+}
+
+/// A database engine that Sylphie can store data in.
+///
+/// SQL statements are written in the SQLite dialect, and backends are expected to translate
+/// them as needed. The backend used by the bot can be chosen with
+/// [`SylphieCoreDatabaseExt::with_storage_backend`].
+///
+/// [`SylphieCoreDatabaseExt::with_storage_backend`]:
+///     crate::connection::SylphieCoreDatabaseExt::with_storage_backend
+pub trait StorageBackend: Send + Sync + 'static {
+    /// A short name for this backend, used in log messages.
+    fn name(&self) -> &str;
+
+    /// The SQL dialect used by this backend.
+    fn dialect(&self) -> SqlDialect;
+
+    /// Opens a new connection to the database.
+    fn connect(&self) -> Result<Box<dyn BackendConnection>>;
+
+    /// Opens a new connection to the database that rejects any statement that writes to it.
+    ///
+    /// By default, this opens a normal connection and marks it read-only with
+    /// [`SqlDialect::read_only`].
+    fn connect_read_only(&self) -> Result<Box<dyn BackendConnection>> {
+        let mut conn = self.connect()?;
+        conn.execute_batch(self.dialect().read_only())?;
+        Ok(conn)
+    }
+
+    /// Copies the persistent database to a new SQLite database file, while the bot is running.
+    ///
+    /// Backends that cannot be backed up this way return an error.
+    fn backup_to(&self, _path: &Path) -> Result<()> {
+        bail!("The {} storage backend does not support backups.", self.name())
+    }
+
+    /// Replaces the persistent database with the contents of a SQLite database file created by
+    /// [`backup_to`](`StorageBackend::backup_to`).
+    ///
+    /// This is only called during startup, before any connections are opened.
+    fn restore_from(&self, _path: &Path) -> Result<()> {
+        bail!("The {} storage backend does not support restoring backups.", self.name())
+    }
+
+    /// Copies the transient database to a new SQLite database file.
+    ///
+    /// This is called while the bot shuts down if transient data is kept across restarts.
+    /// Backends that cannot do this return an error.
+    fn snapshot_transient(&self, _path: &Path) -> Result<()> {
+        bail!("The {} storage backend does not support transient snapshots.", self.name())
+    }
+
+    /// Replaces the transient database with a snapshot made by
+    /// [`snapshot_transient`](`StorageBackend::snapshot_transient`), or clears it if no snapshot
+    /// is given.
+    ///
+    /// This is only called during startup, before any connections are opened.
+    fn restore_transient(&self, _path: Option<&Path>) -> Result<()> {
+        bail!("The {} storage backend does not support transient snapshots.", self.name())
+    }
+
+    /// Re-encrypts the database with a new key, returning a backend that uses the new key.
+    ///
+    /// Backends that do not support encryption return an error.
+    fn rekey(&self, _new_key: &str) -> Result<Arc<dyn StorageBackend>> {
+        bail!("The {} storage backend does not support encryption.", self.name())
+    }
+
+    /// Returns a backend for a separate database with the given name, stored alongside this one.
+    ///
+    /// This is used by [`Database::open_separate`](`super::Database::open_separate`). Backends
+    /// that cannot store more than one database return an error.
+    fn open_separate(&self, _name: &str) -> Result<Arc<dyn StorageBackend>> {
+        bail!("The {} storage backend does not support separate databases.", self.name())
+    }
+}
+
+impl ToSql for SerializeValue {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(match self {
+            SerializeValue::Null => ValueRef::Null,
+            SerializeValue::String(s) => ValueRef::Text(s.as_bytes()),
+            SerializeValue::Bytes(b) => ValueRef::Blob(b),
+            SerializeValue::Integer(i) => ValueRef::Integer(*i),
+            SerializeValue::Floating(f) => ValueRef::Real(*f),
+        }.into())
+    }
+}
+
+fn convert_sqlite_value(value: ValueRef<'_>) -> Result<SerializeValue> {
+    Ok(match value {
+        ValueRef::Null => SerializeValue::Null,
+        ValueRef::Integer(i) => SerializeValue::Integer(i),
+        ValueRef::Real(f) => SerializeValue::Floating(f),
+        ValueRef::Text(s) => SerializeValue::String(std::str::from_utf8(s)?.into()),
+        ValueRef::Blob(b) => SerializeValue::Bytes(b.into()),
+    })
+}
+
+struct SqliteConnection {
+    conn: Connection,
+    cache_stats: StatementCacheStats,
+}
+impl SqliteConnection {
+    fn open(
+        db: &str, transient_db: &str, flags: OpenFlags, key: Option<&str>,
+    ) -> Result<SqliteConnection> {
+        let conn = Connection::open_with_flags(db, flags)?;
+        if let Some(key) = key {
+            // this must come before anything else reads the database.
+            conn.pragma_update(None, "key", &key)?;
+        }
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        conn.execute_batch(include_str!("setup_connection.sql"))?;
+        conn.execute(r#"ATTACH DATABASE ? AS transient;"#, &[transient_db])?;
+        conn.execute_batch("PRAGMA transient.auto_vacuum = incremental;")?;
+        Ok(SqliteConnection { conn, cache_stats: Default::default() })
+    }
+
+    /// Returns a statement from rusqlite's statement cache, counting whether it was cached.
+    fn prepare_cached(&mut self, sql: &str) -> Result<rusqlite::CachedStatement<'_>> {
+        let stat = self.conn.prepare_cached(sql)?;
+        // a newly prepared statement has never been run, so one that has came from the cache.
+        if stat.get_status(StatementStatus::Run) > 0 {
+            self.cache_stats.hits += 1;
+        } else {
+            self.cache_stats.misses += 1;
+        }
+        Ok(stat)
+    }
+}
+impl BackendConnection for SqliteConnection {
+    fn check_valid(&mut self) -> Result<()> {
+        self.conn.prepare_cached("SELECT 1")?.query_row(&[0i32; 0], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn execute(&mut self, sql: &str, params: QueryParams) -> Result<usize> {
+        let mut stat = self.prepare_cached(sql)?;
+        Ok(match &params {
+            QueryParams::Positional(params) => stat.execute(params)?,
+            QueryParams::Named(params) => {
+                let params: Vec<_> = params.iter()
+                    .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+                    .collect();
+                stat.execute_named(&params)?
+            }
+        })
+    }
+
+    fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        self.conn.execute_batch(sql)?;
+        Ok(())
+    }
+
+    fn query(
+        &mut self, sql: &str, params: QueryParams, max_rows: Option<usize>,
+    ) -> Result<QueryRows> {
+        let mut stat = self.prepare_cached(sql)?;
+        let columns: Vec<_> = stat.column_names().into_iter().map(|x| x.to_string()).collect();
+        let mut rows = match &params {
+            QueryParams::Positional(params) => stat.query(params)?,
+            QueryParams::Named(params) => {
+                let params: Vec<_> = params.iter()
+                    .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+                    .collect();
+                stat.query_named(&params)?
+            }
+        };
+
+        let mut result = Vec::new();
+        while max_rows.map_or(true, |max| result.len() < max) {
+            match rows.next()? {
+                Some(row) => {
+                    let mut values = Vec::with_capacity(columns.len());
+                    for i in 0..columns.len() {
+                        values.push(convert_sqlite_value(row.get_raw(i))?);
+                    }
+                    result.push(values);
+                }
+                None => break,
+            }
+        }
+        Ok(QueryRows { columns, rows: result })
+    }
+
+    fn take_statement_cache_stats(&mut self) -> StatementCacheStats {
+        std::mem::take(&mut self.cache_stats)
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().internal_err(|| "Could not convert path to str.")
+}
+
+/// Returns the path a separate database with the given name is stored at, next to the database
+/// file at `path`.
+///
+/// For example, a separate database named `logs` next to `sylphie.db` is `sylphie.logs.db`.
+pub(crate) fn separate_path(path: &Path, name: &str) -> Result<PathBuf> {
+    let stem = path.file_stem().internal_err(|| "Database path has no file name.")?;
+    let mut file_name = stem.to_owned();
+    file_name.push(format!(".{}.db", name));
+    Ok(path.with_file_name(file_name))
+}
+
+/// Copies a SQLite database using the online backup API.
+///
+/// The copy is made a few pages at a time, so other connections are not blocked for the entire
+/// length of the backup.
+fn backup_sqlite(db: &str, flags: OpenFlags, path: &Path) -> Result<()> {
+    ensure!(!path.exists(), "Backup file '{}' already exists.", path.display());
+    let source = Connection::open_with_flags(db, flags)?;
+    let mut dest = Connection::open(path)?;
+    let backup = Backup::new(&source, &mut dest)?;
+    backup.run_to_completion(128, Duration::from_millis(10), None)?;
+    Ok(())
+}
+
+/// Copies a backup made by [`backup_sqlite`] over a SQLite database.
+fn restore_sqlite(db: &str, flags: OpenFlags, path: &Path) -> Result<()> {
+    ensure!(path.is_file(), "Backup file '{}' does not exist.", path.display());
+    let source = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut dest = Connection::open_with_flags(db, flags)?;
+    let backup = Backup::new(&source, &mut dest)?;
+    backup.run_to_completion(128, Duration::from_millis(0), None)?;
+    Ok(())
+}
+
+/// A backend that stores data in SQLite database files.
+///
+/// This is the backend used if no other backend is chosen. Transient data is stored in a second
+/// database file that is attached to each connection.
+#[derive(Clone)]
+pub struct SqliteBackend {
+    db_file: Arc<Path>,
+    transient_db_file: Arc<Path>,
+    key: Option<Arc<str>>,
+}
+impl SqliteBackend {
+    /// Creates a new backend using the given database files.
+    pub fn new(db_file: impl AsRef<Path>, transient_db_file: impl AsRef<Path>) -> Self {
+        SqliteBackend {
+            db_file: db_file.as_ref().into(),
+            transient_db_file: transient_db_file.as_ref().into(),
+            key: None,
+        }
+    }
+
+    /// Encrypts the database files with SQLCipher, using the given key.
+    ///
+    /// Databases that already exist must have been created with the same key. An unencrypted
+    /// database cannot be opened this way.
+    #[cfg(feature = "sqlcipher")]
+    pub fn with_key(mut self, key: impl Into<Arc<str>>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    fn remove_transient_files(&self) -> Result<()> {
+        for suffix in &["", "-wal", "-shm"] {
+            let mut file = self.transient_db_file.as_os_str().to_owned();
+            file.push(suffix);
+            if Path::new(&file).exists() {
+                std::fs::remove_file(&file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self, flags: OpenFlags) -> Result<SqliteConnection> {
+        let db_file = path_str(&self.db_file)?;
+        let transient_db_file = path_str(&self.transient_db_file)?;
+        SqliteConnection::open(db_file, transient_db_file, flags, self.key.as_deref())
+    }
+}
+impl StorageBackend for SqliteBackend {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    fn dialect(&self) -> SqlDialect {
+        SqlDialect::Sqlite
+    }
+
+    fn connect(&self) -> Result<Box<dyn BackendConnection>> {
+        Ok(Box::new(self.open(OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE)?))
+    }
+
+    fn backup_to(&self, path: &Path) -> Result<()> {
+        ensure!(self.key.is_none(), "Encrypted databases cannot be backed up.");
+        backup_sqlite(path_str(&self.db_file)?, OpenFlags::SQLITE_OPEN_READ_ONLY, path)
+    }
+
+    fn restore_from(&self, path: &Path) -> Result<()> {
+        ensure!(self.key.is_none(), "Backups cannot be restored to an encrypted database.");
+        restore_sqlite(
+            path_str(&self.db_file)?,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+            path,
+        )?;
+
+        // transient data may refer to interned strings that are not in the backup.
+        self.remove_transient_files()
+    }
+
+    fn snapshot_transient(&self, path: &Path) -> Result<()> {
+        ensure!(self.key.is_none(), "Encrypted transient databases cannot be snapshotted.");
+        backup_sqlite(path_str(&self.transient_db_file)?, OpenFlags::SQLITE_OPEN_READ_ONLY, path)
+    }
+
+    fn restore_transient(&self, path: Option<&Path>) -> Result<()> {
+        self.remove_transient_files()?;
+        if let Some(path) = path {
+            restore_sqlite(
+                path_str(&self.transient_db_file)?,
+                OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+                path,
+            )?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    fn rekey(&self, new_key: &str) -> Result<Arc<dyn StorageBackend>> {
+        ensure!(self.key.is_some(), "The database is not encrypted.");
+        let conn = self.open(OpenFlags::SQLITE_OPEN_READ_WRITE)?.conn;
+        conn.execute_batch("PRAGMA main.wal_checkpoint(TRUNCATE);")?;
+        conn.pragma_update(None, "rekey", &new_key)?;
+        conn.pragma_update(Some(rusqlite::DatabaseName::Attached("transient")), "rekey", &new_key)?;
+
+        let mut backend = self.clone();
+        backend.key = Some(new_key.into());
+        Ok(Arc::new(backend))
+    }
+
+    fn open_separate(&self, name: &str) -> Result<Arc<dyn StorageBackend>> {
+        Ok(Arc::new(SqliteBackend {
+            db_file: separate_path(&self.db_file, name)?.into(),
+            transient_db_file: separate_path(&self.transient_db_file, name)?.into(),
+            key: self.key.clone(),
+        }))
+    }
+}
+
+static MEMORY_DB_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A backend that stores all data in memory, and discards it when dropped.
+///
+/// This is mainly useful for tests. It is backed by SQLite, so it behaves exactly like
+/// [`SqliteBackend`] aside from not persisting anything.
+pub struct MemoryBackend {
+    db_uri: String,
+    transient_db_uri: String,
+    // an in-memory database is deleted when its last connection closes, so we keep one open for
+    // as long as the backend exists.
+    keep_alive: Mutex<Option<SqliteConnection>>,
+}
+impl MemoryBackend {
+    /// Creates a new empty in-memory database.
+    pub fn new() -> Self {
+        let id = MEMORY_DB_ID.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        MemoryBackend {
+            db_uri: format!("file:sylphie_mem_{}_{}?mode=memory&cache=shared", pid, id),
+            transient_db_uri: format!(
+                "file:sylphie_mem_{}_{}_transient?mode=memory&cache=shared", pid, id,
+            ),
+            keep_alive: Mutex::new(None),
+        }
+    }
+
+    fn open(&self) -> Result<SqliteConnection> {
+        SqliteConnection::open(
+            &self.db_uri,
+            &self.transient_db_uri,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE |
+                OpenFlags::SQLITE_OPEN_URI,
+            None,
+        )
+    }
+
+    fn keep_alive(&self) -> Result<()> {
+        let mut keep_alive = self.keep_alive.lock();
+        if keep_alive.is_none() {
+            *keep_alive = Some(self.open()?);
+        }
+        Ok(())
+    }
+}
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend::new()
+    }
+}
+impl StorageBackend for MemoryBackend {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn dialect(&self) -> SqlDialect {
+        SqlDialect::Sqlite
+    }
+
+    fn connect(&self) -> Result<Box<dyn BackendConnection>> {
+        self.keep_alive()?;
+        Ok(Box::new(self.open()?))
+    }
+
+    fn backup_to(&self, path: &Path) -> Result<()> {
+        backup_sqlite(
+            &self.db_uri,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+            path,
+        )
+    }
+
+    fn restore_from(&self, path: &Path) -> Result<()> {
+        self.keep_alive()?;
+        restore_sqlite(
+            &self.db_uri,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE |
+                OpenFlags::SQLITE_OPEN_URI,
+            path,
+        )
+    }
+
+    fn open_separate(&self, _name: &str) -> Result<Arc<dyn StorageBackend>> {
+        Ok(Arc::new(MemoryBackend::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_backend() {
+        let no_params = || QueryParams::Positional(Vec::new());
+        let backend = MemoryBackend::new();
+        let mut conn = backend.connect().unwrap();
+        conn.execute_batch("CREATE TABLE t (k INTEGER, v TEXT);").unwrap();
+        let params = vec![SerializeValue::Integer(1), "a".to_string().into()];
+        let params = QueryParams::Positional(params);
+        assert_eq!(conn.execute("INSERT INTO t VALUES (?, ?)", params).unwrap(), 1);
+
+        // every connection to the backend shares the same database.
+        let mut other = backend.connect_read_only().unwrap();
+        let rows = other.query("SELECT k, v FROM t", no_params(), None).unwrap();
+        assert_eq!(rows.columns, ["k", "v"]);
+        assert_eq!(rows.rows.len(), 1);
+        assert!(other.execute("INSERT INTO t VALUES (2, 'b')", no_params()).is_err());
+    }
+
+    #[test]
+    fn separate_paths() {
+        let path = separate_path(Path::new("db/sylphie.db"), "logs").unwrap();
+        assert_eq!(path, Path::new("db/sylphie.logs.db"));
+    }
+}
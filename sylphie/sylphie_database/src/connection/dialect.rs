@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use sylphie_core::prelude::*;
+
+/// The SQL dialect used by the underlying database.
+///
+/// Queries are written in the SQLite dialect, and are translated as needed for other databases.
+/// This is only needed for the few statements that cannot be translated automatically.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SqlDialect {
+    Sqlite,
+    Postgres,
+}
+impl SqlDialect {
+    /// Returns a statement that inserts a row, replacing any existing row with the same key.
+    pub fn upsert(&self, table: &str, key_columns: &[&str], columns: &[&str]) -> String {
+        let params = vec!["?"; columns.len()].join(", ");
+        match self {
+            SqlDialect::Sqlite => format!(
+                "REPLACE INTO {} ({}) VALUES ({});", table, columns.join(", "), params,
+            ),
+            SqlDialect::Postgres => {
+                let updates: Vec<_> = columns.iter()
+                    .filter(|x| !key_columns.contains(x))
+                    .map(|x| format!("{} = EXCLUDED.{}", x, x))
+                    .collect();
+                let conflict = if updates.is_empty() {
+                    "DO NOTHING".to_string()
+                } else {
+                    format!("DO UPDATE SET {}", updates.join(", "))
+                };
+                format!(
+                    "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) {};",
+                    table, columns.join(", "), params, key_columns.join(", "), conflict,
+                )
+            }
+        }
+    }
+
+    /// Returns a statement that sets how long statements wait on locks held by other connections.
+    pub fn busy_timeout(&self, timeout: Duration) -> String {
+        match self {
+            SqlDialect::Sqlite => format!("PRAGMA busy_timeout = {};", timeout.as_millis()),
+            SqlDialect::Postgres => format!("SET lock_timeout = {};", timeout.as_millis()),
+        }
+    }
+
+    /// Returns a statement that makes a connection reject any statement that writes to the
+    /// database.
+    pub fn read_only(&self) -> &'static str {
+        match self {
+            SqlDialect::Sqlite => "PRAGMA query_only = ON;",
+            SqlDialect::Postgres => "SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY;",
+        }
+    }
+
+    /// Returns a statement that creates an index on a table if it does not already exist.
+    ///
+    /// The table may be qualified with a schema such as `transient`, in which case the index is
+    /// created in the same schema.
+    pub fn create_index(&self, name: &str, table: &str, columns: &[&str]) -> String {
+        let (schema, unqualified) = match table.find('.') {
+            Some(i) => (&table[..i + 1], &table[i + 1..]),
+            None => ("", table),
+        };
+        match self {
+            SqlDialect::Sqlite => format!(
+                "CREATE INDEX IF NOT EXISTS {}{} ON {} ({});",
+                schema, name, unqualified, columns.join(", "),
+            ),
+            SqlDialect::Postgres => format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({});", name, table, columns.join(", "),
+            ),
+        }
+    }
+}
+
+/// The parameter order of a translated statement.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) enum ParamOrder {
+    /// Parameters are passed in the order they were given.
+    Positional(usize),
+    /// Each parameter is the named parameter with the given name.
+    Named(Vec<String>),
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Translates a statement written for SQLite into one usable in Postgres.
+///
+/// This replaces `?` and named parameters with numbered parameters, and the SQLite type names
+/// and table options used in Sylphie's schemas with their Postgres equivalents.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) fn translate_for_postgres(sql: &str, is_named: bool) -> Result<(String, ParamOrder)> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut out = String::with_capacity(sql.len());
+    let mut positional = 0;
+    let mut names: Vec<String> = Vec::new();
+    let mut name_ids = HashMap::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' | '"' => {
+                // copy quoted strings and identifiers verbatim
+                out.push(c);
+                i += 1;
+                while i < chars.len() {
+                    out.push(chars[i]);
+                    i += 1;
+                    if chars[i - 1] == c {
+                        if i < chars.len() && chars[i] == c {
+                            out.push(chars[i]);
+                            i += 1;
+                        } else {
+                            break
+                        }
+                    }
+                }
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                if i < chars.len() {
+                    out.push_str("*/");
+                    i += 2;
+                }
+            }
+            ':' if chars.get(i + 1) == Some(&':') => {
+                // Postgres type casts
+                out.push_str("::");
+                i += 2;
+            }
+            '?' => {
+                ensure!(!is_named, "Cannot use positional parameters in a named query.");
+                positional += 1;
+                out.push_str(&format!("${}", positional));
+                i += 1;
+            }
+            ':' | '@' | '$' if chars.get(i + 1).map_or(false, |x| is_ident_start(*x)) => {
+                ensure!(is_named, "Cannot use named parameters in a positional query.");
+                let start = i + 1;
+                i = start;
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                let name: String = chars[start..i].iter().collect();
+                let id = match name_ids.get(&name) {
+                    Some(id) => *id,
+                    None => {
+                        names.push(name.clone());
+                        name_ids.insert(name, names.len());
+                        names.len()
+                    }
+                };
+                out.push_str(&format!("${}", id));
+            }
+            c if is_ident_start(c) => {
+                let start = i;
+                while i < chars.len() && is_ident_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let upper = word.to_ascii_uppercase();
+                match upper.as_str() {
+                    "BLOB" => out.push_str("BYTEA"),
+                    "INTEGER" => out.push_str("BIGINT"),
+                    "WITHOUT" => {
+                        let mut j = i;
+                        while j < chars.len() && chars[j].is_whitespace() {
+                            j += 1;
+                        }
+                        let mut k = j;
+                        while k < chars.len() && is_ident_char(chars[k]) {
+                            k += 1;
+                        }
+                        let next: String = chars[j..k].iter().collect();
+                        if next.eq_ignore_ascii_case("ROWID") {
+                            i = k;
+                        } else {
+                            out.push_str(&word);
+                        }
+                    }
+                    _ => out.push_str(&word),
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    let order = if is_named {
+        ParamOrder::Named(names)
+    } else {
+        ParamOrder::Positional(positional)
+    };
+    Ok((out, order))
+}
+
+/// Returns the statement used to begin a transaction for a given dialect.
+pub(crate) fn begin_transaction_sql(
+    dialect: SqlDialect, t: super::TransactionType,
+) -> &'static str {
+    use super::TransactionType;
+    match (dialect, t) {
+        (SqlDialect::Sqlite, TransactionType::Exclusive) => "BEGIN EXCLUSIVE TRANSACTION;",
+        (SqlDialect::Sqlite, TransactionType::Immediate) => "BEGIN IMMEDIATE TRANSACTION;",
+        (SqlDialect::Sqlite, TransactionType::Deferred) => "BEGIN DEFERRED TRANSACTION;",
+        (SqlDialect::Postgres, TransactionType::Exclusive) =>
+            "BEGIN ISOLATION LEVEL SERIALIZABLE;",
+        (SqlDialect::Postgres, _) => "BEGIN;",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_positional() {
+        let (sql, order) = translate_for_postgres(
+            "SELECT name FROM t WHERE a = ? AND b = '?' AND c = ?;", false,
+        ).unwrap();
+        assert_eq!(sql, "SELECT name FROM t WHERE a = $1 AND b = '?' AND c = $2;");
+        assert_eq!(order, ParamOrder::Positional(2));
+    }
+
+    #[test]
+    fn translate_named() {
+        let (sql, order) = translate_for_postgres(
+            "UPDATE t SET a = :a, b = :b WHERE a = :a", true,
+        ).unwrap();
+        assert_eq!(sql, "UPDATE t SET a = $1, b = $2 WHERE a = $1");
+        assert_eq!(order, ParamOrder::Named(vec!["a".to_string(), "b".to_string()]));
+    }
+
+    #[test]
+    fn translate_schema() {
+        let (sql, _) = translate_for_postgres(
+            "CREATE TABLE t (key BLOB PRIMARY KEY, v INTEGER NOT NULL) WITHOUT ROWID; -- BLOB",
+            false,
+        ).unwrap();
+        assert_eq!(sql, "CREATE TABLE t (key BYTEA PRIMARY KEY, v BIGINT NOT NULL) ; -- BLOB");
+    }
+
+    #[test]
+    fn upsert() {
+        assert_eq!(
+            SqlDialect::Postgres.upsert("t", &["k"], &["k", "v"]),
+            "INSERT INTO t (k, v) VALUES (?, ?) ON CONFLICT (k) DO UPDATE SET v = EXCLUDED.v;",
+        );
+    }
+
+    #[test]
+    fn create_index() {
+        assert_eq!(
+            SqlDialect::Sqlite.create_index("t_i", "transient.t", &["a", "b"]),
+            "CREATE INDEX IF NOT EXISTS transient.t_i ON t (a, b);",
+        );
+        assert_eq!(
+            SqlDialect::Postgres.create_index("t_i", "transient.t", &["a"]),
+            "CREATE INDEX IF NOT EXISTS t_i ON transient.t (a);",
+        );
+    }
+}
@@ -1,18 +1,41 @@
 use arc_swap::*;
 use async_trait::*;
-use rusqlite::{Connection, OpenFlags};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::future::Future;
 use std::ops::{Deref, DerefMut};
-use std::path::{PathBuf, Path};
-use std::time;
+use std::path::Path;
+use std::time::{self, Instant};
 use std::sync::Arc;
 use sylphie_core::prelude::*;
 use sylphie_utils::strings::StringWrapper;
 use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing_futures::Instrument;
 
+mod backend;
+mod dialect;
 mod pool;
+#[cfg(feature = "postgres")] mod postgres;
+mod schema;
+mod statement_cache;
+mod stats;
+mod values;
+
+pub use backend::{
+    BackendConnection, MemoryBackend, QueryParams, QueryRows, SqliteBackend, StorageBackend,
+};
+pub(crate) use backend::separate_path;
+pub use dialect::SqlDialect;
+pub use schema::{ColumnSchema, DatabaseSchema, IndexSchema, TableSchema};
+pub use statement_cache::StatementCacheStats;
+#[cfg(feature = "postgres")] pub use postgres::PostgresBackend;
+pub use stats::{DatabaseStats, SlowQueryEvent};
 use pool::{Pool, ManageConnection, PooledConnection};
+use stats::StatsCounters;
 
 struct BlockingWrapper<T: Send + 'static> {
     inner: Option<Box<T>>,
@@ -48,36 +71,35 @@ impl <T: Send + 'static> BlockingWrapper<T> {
     }
 }
 
-struct ConnectionPaths {
-    db_file: Arc<Path>,
-    transient_db_file: Arc<Path>,
-}
+type ActiveBackend = Arc<ArcSwapOption<Arc<dyn StorageBackend>>>;
+
+type RawConnection = Box<dyn BackendConnection>;
 
 struct ConnectionManager {
-    paths: Arc<ArcSwapOption<ConnectionPaths>>,
+    backend: ActiveBackend,
     handle: Arc<Handle>,
+    busy_timeout: time::Duration,
+    read_only: bool,
 }
 #[async_trait]
 impl ManageConnection for ConnectionManager {
-    type Connection = BlockingWrapper<Connection>;
+    type Connection = BlockingWrapper<RawConnection>;
     type Error = ErrorWrapper;
 
-    async fn connect(&self) -> StdResult<BlockingWrapper<Connection>, ErrorWrapper> {
-        let paths = self.paths.load();
-        let paths = paths.as_ref().expect("Paths not set in database?");
-        let db_file = paths.db_file.clone();
-        let transient_db_file = paths.transient_db_file.clone();
+    async fn connect(&self) -> StdResult<BlockingWrapper<RawConnection>, ErrorWrapper> {
+        let backend = self.backend.load();
+        let backend: Arc<dyn StorageBackend> =
+            (**backend.as_ref().expect("Backend not set in database?")).clone();
         let handle = self.handle.clone();
+        let busy_timeout = self.busy_timeout;
+        let read_only = self.read_only;
         Ok(self.handle.spawn_blocking(move || -> Result<_> {
-            let conn = Connection::open_with_flags(&db_file,
-                OpenFlags::SQLITE_OPEN_READ_WRITE |
-                OpenFlags::SQLITE_OPEN_CREATE)?;
-            conn.set_prepared_statement_cache_capacity(64);
-            conn.execute_batch(include_str!("setup_connection.sql"))?;
-            conn.execute(
-                r#"ATTACH DATABASE ? AS transient;"#,
-                &[transient_db_file.to_str().expect("Could not convert path to str.")],
-            )?;
+            let mut conn = if read_only {
+                backend.connect_read_only()?
+            } else {
+                backend.connect()?
+            };
+            conn.execute_batch(&backend.dialect().busy_timeout(busy_timeout))?;
             Ok(BlockingWrapper {
                 inner: Some(Box::new(conn)),
                 handle,
@@ -85,22 +107,22 @@ impl ManageConnection for ConnectionManager {
         }).await.map_err(ErrorWrapper::new)??)
     }
     async fn is_valid(
-        &self, conn: &mut BlockingWrapper<Connection>,
+        &self, conn: &mut BlockingWrapper<RawConnection>,
     ) -> StdResult<(), ErrorWrapper> {
-        Ok(conn.run_blocking(|c| {
-            c.prepare_cached("SELECT 1")?.query_row(&[0i32; 0], |_| Ok(()))?;
-            Ok(())
-        }).await.map_err(ErrorWrapper::new)?)
+        Ok(conn.run_blocking(|c| c.check_valid()).await.map_err(ErrorWrapper::new)?)
     }
 
-    fn has_broken(&self, conn: &mut BlockingWrapper<Connection>) -> bool {
+    fn has_broken(&self, conn: &mut BlockingWrapper<RawConnection>) -> bool {
         conn.inner.is_some()
     }
 }
 
 /// The type of transaction to perform.
 ///
-/// See the Sqlite documentation for more information.
+/// See the Sqlite documentation for more information. On Postgres, exclusive transactions use
+/// the serializable isolation level, and other transactions use the default isolation level.
+/// Serializable transactions can fail if they conflict with another transaction, and should be
+/// run with [`Database::retry_serializable`].
 #[derive(Copy, Clone, Debug)]
 pub enum TransactionType {
     Deferred,
@@ -108,9 +130,30 @@ pub enum TransactionType {
     Exclusive,
 }
 
+/// The number of times a transaction is retried after conflicting with another transaction.
+const SERIALIZATION_RETRIES: u32 = 5;
+
+/// Returns whether an error is a serializable transaction failing because it conflicted with
+/// another transaction. Retrying the transaction may succeed.
+///
+/// This only happens on Postgres.
+pub fn is_serialization_failure(err: &Error) -> bool {
+    #[cfg(feature = "postgres")]
+    {
+        postgres::is_serialization_failure(err)
+    }
+    #[cfg(not(feature = "postgres"))]
+    {
+        let _ = err;
+        false
+    }
+}
+
 struct DbOpsData {
     conn_handle: Option<PooledConnection<ConnectionManager>>,
-    conn: BlockingWrapper<Connection>,
+    conn: BlockingWrapper<RawConnection>,
+    dialect: SqlDialect,
+    stats: Arc<StatsCounters>,
     is_begin_transaction: bool,
     is_begin_commit: bool,
     is_in_transaction: bool,
@@ -120,11 +163,7 @@ impl DbOpsData {
     fn begin_transaction(&mut self, t: TransactionType) -> Result<()> {
         assert!(!self.is_in_transaction);
 
-        let sql = match t {
-            TransactionType::Exclusive => "BEGIN EXCLUSIVE TRANSACTION;",
-            TransactionType::Immediate => "BEGIN IMMEDIATE TRANSACTION;",
-            TransactionType::Deferred => "BEGIN DEFERRED TRANSACTION;",
-        };
+        let sql = dialect::begin_transaction_sql(self.dialect, t);
 
         self.is_begin_transaction = true;
         self.execute_batch(sql.into())?;
@@ -159,11 +198,11 @@ impl DbOpsData {
         //
         // this poisons this DbOps and makes it unusable for further operations.
         let mut conn_handle = self.conn_handle.take().unwrap();
-        let conn = self.conn.take();
+        let mut conn = self.conn.take();
         self.conn.handle.clone().spawn_blocking(move || {
-            match conn.inner.as_ref().unwrap().execute_batch("ROLLBACK;") {
+            match conn.inner.as_mut().unwrap().execute_batch("ROLLBACK;") {
                 Ok(_) => *conn_handle = conn,
-                Err(e) => Error::from(e).report_error(),
+                Err(e) => e.report_error(),
             }
             ::std::mem::drop(conn_handle);
         });
@@ -174,61 +213,60 @@ impl DbOpsData {
         }
     }
 
+    fn timed<R>(
+        &mut self, sql: &str, func: impl FnOnce(&mut RawConnection) -> Result<R>,
+    ) -> Result<R> {
+        let conn = self.conn.get()?;
+        let start = Instant::now();
+        let result = func(&mut *conn);
+        self.stats.record_query(sql, start.elapsed());
+        self.stats.record_statement_cache(conn.take_statement_cache_stats());
+        result
+    }
+
     fn execute(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
-        let data = serde_rusqlite::to_params(params)?;
-        Ok(self.conn.get()?.execute(&sql, &data.to_slice())?)
+        self.timed(&sql, |c| c.execute(&sql, QueryParams::positional(params)?))
     }
     fn execute_named(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
-        let data = serde_rusqlite::to_params_named(params)?;
-        Ok(self.conn.get()?.execute_named(&sql, &data.to_slice())?)
+        self.timed(&sql, |c| c.execute(&sql, QueryParams::named(params)?))
     }
     fn execute_batch(&mut self, sql: StringWrapper) -> Result<()> {
-        self.conn.get()?.execute_batch(&sql)?;
-        Ok(())
+        self.timed(&sql, |c| c.execute_batch(&sql))
     }
 
     fn query_row<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
-        let data = serde_rusqlite::to_params(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let mut rows = stat.query_and_then(&data.to_slice(), serde_rusqlite::from_row)?;
-        match rows.next() {
-            Some(r) => Ok(Some(r?)),
-            None => Ok(None),
-        }
+        let rows = self.timed(&sql, |c| c.query(&sql, QueryParams::positional(params)?, Some(1)))?;
+        Ok(rows.deserialize()?.pop())
     }
     fn query_row_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
-        let data = serde_rusqlite::to_params_named(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let mut rows = stat.query_and_then_named(&data.to_slice(), serde_rusqlite::from_row)?;
-        match rows.next() {
-            Some(r) => Ok(Some(r?)),
-            None => Ok(None),
-        }
+        let rows = self.timed(&sql, |c| c.query(&sql, QueryParams::named(params)?, Some(1)))?;
+        Ok(rows.deserialize()?.pop())
     }
 
     fn query_vec<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
-        let data = serde_rusqlite::to_params(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let rows = stat.query_and_then(&data.to_slice(), serde_rusqlite::from_row)?;
-        Ok(rows.collect::<StdResult<Vec<T>, _>>()?)
+        self.timed(&sql, |c| c.query(&sql, QueryParams::positional(params)?, None))?.deserialize()
     }
     fn query_vec_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
-        let data = serde_rusqlite::to_params_named(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let rows = stat.query_and_then_named(&data.to_slice(), serde_rusqlite::from_row)?;
-        Ok(rows.collect::<StdResult<Vec<T>, _>>()?)
+        self.timed(&sql, |c| c.query(&sql, QueryParams::named(params)?, None))?.deserialize()
+    }
+
+    fn checkpoint(&mut self) -> Result<()> {
+        match self.dialect {
+            SqlDialect::Sqlite => self.execute_batch("PRAGMA wal_checkpoint(RESTART);".into()),
+            SqlDialect::Postgres => Ok(()),
+        }
     }
 }
 impl Drop for DbOpsData {
@@ -251,73 +289,72 @@ impl Drop for DbOpsData {
 /// [`DbConnection`] and [`DbTransaction`].
 pub struct DbOps(BlockingWrapper<DbOpsData>);
 impl DbOps {
+    async fn run_query<R: Send + 'static>(
+        &mut self, sql: StringWrapper,
+        func: impl FnOnce(&mut DbOpsData, StringWrapper) -> Result<R> + Send + 'static,
+    ) -> Result<R> {
+        // the span is entered here, as spans entered on the blocking thread would have no parent.
+        let span = debug_span!("db_query", sql = &*sql);
+        self.0.run_blocking(move |c| func(c, sql)).instrument(span).await
+    }
+
     /// Executes a SQL query with unnamed parameters.
     pub async fn execute(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.execute(sql, params)).await
+        self.run_query(sql.into(), move |c, sql| c.execute(sql, params)).await
     }
     /// Executes a SQL query with no parameters.
     pub async fn execute_nullary(&mut self, sql: impl Into<StringWrapper>) -> Result<usize> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.execute_named(sql, &[] as &[()])).await
+        self.run_query(sql.into(), move |c, sql| c.execute_named(sql, &[] as &[()])).await
     }
     /// Executes a SQL query with named parameters.
     pub async fn execute_named(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.execute_named(sql, params)).await
+        self.run_query(sql.into(), move |c, sql| c.execute_named(sql, params)).await
     }
     /// Executes multiple SQL statements.
     pub async fn execute_batch(&mut self, sql: impl Into<StringWrapper>) -> Result<()> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.execute_batch(sql)).await
+        self.run_query(sql.into(), move |c, sql| c.execute_batch(sql)).await
     }
 
     /// Queries a row of the SQL statements with unnamed parameters.
     pub async fn query_row<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.query_row(sql, params)).await
+        self.run_query(sql.into(), move |c, sql| c.query_row(sql, params)).await
     }
     /// Queries a row of the SQL statements with no parameters.
     pub async fn query_row_nullary<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>,
     ) -> Result<Option<T>> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.query_row(sql, &[] as &[()])).await
+        self.run_query(sql.into(), move |c, sql| c.query_row(sql, &[] as &[()])).await
     }
     /// Queries a row of the SQL statements with named parameters.
     pub async fn query_row_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.query_row_named(sql, params)).await
+        self.run_query(sql.into(), move |c, sql| c.query_row_named(sql, params)).await
     }
 
     /// Queries the results of SQL statements with unnamed parameters.
     pub async fn query_vec<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.query_vec(sql, params)).await
+        self.run_query(sql.into(), move |c, sql| c.query_vec(sql, params)).await
     }
     /// Queries the results of SQL statements with no parameters.
     pub async fn query_vec_nullary<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>
     ) -> Result<Vec<T>> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.query_vec(sql, &[] as &[()])).await
+        self.run_query(sql.into(), move |c, sql| c.query_vec(sql, &[] as &[()])).await
     }
     /// Queries the results of SQL statements with named parameters.
     pub async fn query_vec_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
-        let sql = sql.into();
-        self.0.run_blocking(move |c| c.query_vec_named(sql, params)).await
+        self.run_query(sql.into(), move |c, sql| c.query_vec_named(sql, params)).await
     }
 }
 
@@ -338,8 +375,10 @@ impl DerefMut for DbConnection {
 }
 impl DbConnection {
     /// Checkpoints the database, dumping the write-ahead log to disk.
+    ///
+    /// This does nothing on databases other than SQLite.
     pub async fn checkpoint(&mut self) -> Result<()> {
-        self.ops.execute_batch("PRAGMA wal_checkpoint(RESTART);").await
+        self.ops.0.run_blocking(|c| c.checkpoint()).await
     }
 
     /// Starts a new deferred transaction.
@@ -464,6 +503,24 @@ impl DbSyncOps {
     ) -> Result<Vec<T>> {
         self.get_ops()?.query_vec_named(sql.into(), params)
     }
+
+    /// Runs asynchronous database operations on this connection, blocking until they complete.
+    ///
+    /// This must be called from a blocking thread, as it blocks on the tokio runtime.
+    pub(crate) fn block_on_async<R>(
+        &mut self, func: impl for<'a> FnOnce(&'a mut DbOps) -> BoxFuture<'a, Result<R>>,
+    ) -> Result<R> {
+        let data = self.0.take()
+            .internal_err(|| "DbSyncOps has been poisoned by a dropped transaction.")?;
+        let handle = data.conn.handle.clone();
+        let mut ops = DbOps(BlockingWrapper { inner: Some(Box::new(data)), handle });
+        let result = Handle::current().block_on(func(&mut ops));
+        match ops.0.inner.take() {
+            Some(data) => self.0 = Some(*data),
+            None => bail!("DbOps was poisoned while running asynchronous operations."),
+        }
+        result
+    }
 }
 
 /// A connection to the database.
@@ -483,8 +540,10 @@ impl DerefMut for DbSyncConnection {
 }
 impl DbSyncConnection {
     /// Checkpoints the database, dumping the write-ahead log to disk.
+    ///
+    /// This does nothing on databases other than SQLite.
     pub fn checkpoint(&mut self) -> Result<()> {
-        self.ops.execute_batch("PRAGMA wal_checkpoint(RESTART);")
+        self.ops.get_ops()?.checkpoint()
     }
 
     /// Starts a new deferred transaction.
@@ -543,45 +602,206 @@ impl <'a> Drop for DbSyncTransaction<'a> {
     }
 }
 
+/// Configures the pool of connections to the database.
+///
+/// This can be set with [`SylphieCoreDatabaseExt::with_pool_config`].
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    max_size: u32,
+    acquire_timeout: time::Duration,
+    busy_timeout: time::Duration,
+    slow_query_threshold: time::Duration,
+}
+impl PoolConfig {
+    /// Creates a new pool configuration with the default settings.
+    pub fn new() -> Self {
+        PoolConfig {
+            max_size: 15,
+            acquire_timeout: time::Duration::from_secs(30),
+            busy_timeout: time::Duration::from_secs(5),
+            slow_query_threshold: time::Duration::from_millis(250),
+        }
+    }
+
+    /// Sets the maximum number of connections open at once. This defaults to `15`.
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        assert!(max_size > 0, "max_size must be greater than zero.");
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets how long to wait for a connection from the pool before failing. This defaults to
+    /// 30 seconds.
+    pub fn acquire_timeout(mut self, timeout: time::Duration) -> Self {
+        assert!(timeout > time::Duration::from_secs(0), "acquire_timeout must be non-zero.");
+        self.acquire_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a statement waits for a locked database before failing. This defaults to
+    /// 5 seconds.
+    pub fn busy_timeout(mut self, timeout: time::Duration) -> Self {
+        self.busy_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a statement can take before it is counted as a slow query in
+    /// [`DatabaseStats`] and reported with [`SlowQueryEvent`]. A threshold of zero disables this.
+    /// This defaults to 250 milliseconds.
+    pub fn slow_query_threshold(mut self, threshold: time::Duration) -> Self {
+        self.slow_query_threshold = threshold;
+        self
+    }
+}
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig::new()
+    }
+}
+
 /// Manages connections to the database.
 #[derive(Clone)]
 pub struct Database {
-    paths: Arc<ArcSwapOption<ConnectionPaths>>,
-    pool: Arc<Pool<ConnectionManager>>,
+    backend: ActiveBackend,
+    pool: Arc<ArcSwapOption<Pool<ConnectionManager>>>,
+    read_only_pool: Arc<ArcSwapOption<Pool<ConnectionManager>>>,
+    config: Arc<ArcSwap<PoolConfig>>,
+    stats: Arc<StatsCounters>,
+    handle: Arc<Handle>,
+    separate: Arc<Mutex<HashMap<String, Database>>>,
 }
 impl Database {
     pub fn new() -> Self {
-        let paths = Arc::new(ArcSwapOption::new(None));
-        let manager = ConnectionManager {
-            paths: paths.clone(),
+        Database {
+            backend: Arc::new(ArcSwapOption::new(None)),
+            pool: Arc::new(ArcSwapOption::new(None)),
+            read_only_pool: Arc::new(ArcSwapOption::new(None)),
+            config: Arc::new(ArcSwap::new(Arc::new(PoolConfig::new()))),
+            stats: Default::default(),
             handle: Arc::new(Handle::current()),
+            separate: Default::default(),
+        }
+    }
+
+    /// Creates a database that stores everything in memory, using [`MemoryBackend`].
+    ///
+    /// Each database created this way is separate from every other, so tests using this can be
+    /// run in parallel. No migrations are run on the database, so tests must create any tables
+    /// they use themselves.
+    pub fn in_memory() -> Self {
+        let database = Database::new();
+        database.set_backend(Arc::new(MemoryBackend::new()), PoolConfig::new());
+        database
+    }
+
+    pub(crate) fn set_backend(&self, backend: Arc<dyn StorageBackend>, config: PoolConfig) {
+        debug!("Using storage backend: {}", backend.name());
+        debug!("Database pool configuration: {:?}", config);
+        self.backend.store(Some(Arc::new(backend)));
+
+        self.pool.store(Some(Arc::new(self.make_pool(&config, false))));
+        self.read_only_pool.store(Some(Arc::new(self.make_pool(&config, true))));
+        self.stats.set_slow_query_threshold(config.slow_query_threshold);
+        self.config.store(Arc::new(config));
+    }
+
+    fn make_pool(&self, config: &PoolConfig, read_only: bool) -> Pool<ConnectionManager> {
+        let manager = ConnectionManager {
+            backend: self.backend.clone(),
+            handle: self.handle.clone(),
+            busy_timeout: config.busy_timeout,
+            read_only,
         };
-        let pool = Arc::new(Handle::current().block_on(
-            Pool::builder()
-                .max_size(15)
-                .idle_timeout(Some(time::Duration::from_secs(60 * 5)))
-                .build(manager)
-        ).expect("Failed to initialize database pool."));
-        Database {
-            paths,
-            pool: pool.clone(),
+        Pool::builder()
+            .max_size(config.max_size)
+            .connection_timeout(config.acquire_timeout)
+            .idle_timeout(Some(time::Duration::from_secs(60 * 5)))
+            .build_unchecked(manager)
+    }
+
+    /// Returns a separate database with its own connection pool, for modules that write heavily
+    /// enough to slow down everything else sharing the main database.
+    ///
+    /// On SQLite, this is stored in its own pair of files next to the main database, so writes
+    /// to it never wait on locks held for the main database. The same database is returned every
+    /// time this is called with the same name. Migrations and KVS stores only use the main
+    /// database, so modules must create the tables they use in a separate database themselves.
+    ///
+    /// Separate databases opened this way are included in backups made with
+    /// [`backup_to`](Self::backup_to), and are re-encrypted along with the main database by
+    /// [`rekey`](Self::rekey). They contain no KVS stores, so the `export` command does not
+    /// apply to them.
+    pub fn open_separate(&self, name: &str) -> Result<Database> {
+        ensure!(is_valid_separate_name(name), "Invalid separate database name '{}'.", name);
+        let mut separate = self.separate.lock();
+        if let Some(database) = separate.get(name) {
+            return Ok(database.clone())
+        }
+        let database = Database::new();
+        database.set_backend(self.backend().open_separate(name)?, self.pool_config());
+        separate.insert(name.to_string(), database.clone());
+        Ok(database)
+    }
+
+    /// Returns the configuration of the connection pool.
+    pub fn pool_config(&self) -> PoolConfig {
+        (**self.config.load()).clone()
+    }
+
+    /// Returns the channel slow queries are sent to, if it has not already been taken.
+    pub(crate) fn take_slow_queries(&self) -> Option<UnboundedReceiver<SlowQueryEvent>> {
+        self.stats.take_slow_queries()
+    }
+
+    /// Returns statistics about the connection pools and the queries run on them.
+    pub fn stats(&self) -> DatabaseStats {
+        let max_size = self.config.load().max_size;
+        let (mut connections, mut idle_connections, mut max_connections) = (0, 0, 0);
+        for pool in &[self.pool.load(), self.read_only_pool.load()] {
+            if let Some(pool) = pool.as_ref() {
+                let state = pool.state();
+                connections += state.connections;
+                idle_connections += state.idle_connections;
+                max_connections += max_size;
+            }
         }
+        self.stats.snapshot(connections, idle_connections, max_connections)
     }
 
-    pub(crate) fn set_paths(&self, db_file: PathBuf, transient_db_file: PathBuf) {
-        self.paths.store(Some(Arc::new(ConnectionPaths {
-            db_file: db_file.into(),
-            transient_db_file: transient_db_file.into(),
-        })));
+    /// Returns the storage backend used by this database.
+    ///
+    /// # Panics
+    ///
+    /// This panics if called before the database module has started initializing.
+    pub fn backend(&self) -> Arc<dyn StorageBackend> {
+        let backend = self.backend.load();
+        (**backend.as_ref().expect("Backend not set in database?")).clone()
     }
 
-    async fn make_ops(&self) -> Result<(DbOpsData, Arc<Handle>)> {
-        let mut conn_handle = self.pool.get().await?;
+    /// Returns the SQL dialect used by this database.
+    ///
+    /// This is only meaningful once the database module has started initializing.
+    pub fn dialect(&self) -> SqlDialect {
+        match self.backend.load().as_ref() {
+            Some(backend) => backend.dialect(),
+            None => SqlDialect::Sqlite,
+        }
+    }
+
+    async fn make_ops(&self, read_only: bool) -> Result<(DbOpsData, Arc<Handle>)> {
+        let dialect = self.dialect();
+        let pool = if read_only { &self.read_only_pool } else { &self.pool };
+        let pool = pool.load_full().internal_err(|| "Database backend has not been set.")?;
+        let start = Instant::now();
+        let mut conn_handle = pool.get().await?;
+        self.stats.record_acquire(start.elapsed());
         let conn = conn_handle.take();
         let handle = conn.handle.clone();
         Ok((DbOpsData {
             conn_handle: Some(conn_handle),
             conn,
+            dialect,
+            stats: self.stats.clone(),
             is_begin_transaction: false,
             is_begin_commit: false,
             is_in_transaction: false,
@@ -589,8 +809,8 @@ impl Database {
         }, handle))
     }
 
-    pub async fn connect(&self) -> Result<DbConnection> {
-        let (inner, handle) = self.make_ops().await?;
+    async fn connect_inner(&self, read_only: bool) -> Result<DbConnection> {
+        let (inner, handle) = self.make_ops(read_only).await?;
         Ok(DbConnection {
             ops: DbOps(BlockingWrapper {
                 inner: Some(Box::new(inner)),
@@ -598,11 +818,160 @@ impl Database {
             }),
         })
     }
+
+    pub async fn connect(&self) -> Result<DbConnection> {
+        self.connect_inner(false).await
+    }
     pub fn connect_sync(&self) -> Result<DbSyncConnection> {
         let handle = Handle::current();
-        let (inner, _) = handle.block_on(self.make_ops())?;
+        let (inner, _) = handle.block_on(self.make_ops(false))?;
         Ok(DbSyncConnection { ops: DbSyncOps(Some(inner)) })
     }
+
+    /// Runs a function that makes a transaction, retrying it if the transaction fails because
+    /// it conflicted with another one.
+    ///
+    /// Each attempt is given a new connection, as a connection cannot be used again once a
+    /// transaction on it was dropped without being committed.
+    pub async fn retry_serializable<T, F: Future<Output = Result<T>>>(
+        &self, mut func: impl FnMut(DbConnection) -> F,
+    ) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            match func(self.connect().await?).await {
+                Err(e) if retries < SERIALIZATION_RETRIES && is_serialization_failure(&e) => {
+                    retries += 1;
+                    debug!("Transaction conflicted with another transaction, retrying: {}", e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs a function that makes a transaction synchronously, retrying it if the transaction
+    /// fails because it conflicted with another one.
+    ///
+    /// See [`retry_serializable`](Self::retry_serializable) for details.
+    pub fn retry_serializable_sync<T>(
+        &self, mut func: impl FnMut(DbSyncConnection) -> Result<T>,
+    ) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            match func(self.connect_sync()?) {
+                Err(e) if retries < SERIALIZATION_RETRIES && is_serialization_failure(&e) => {
+                    retries += 1;
+                    debug!("Transaction conflicted with another transaction, retrying: {}", e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Connects to the database with a connection that rejects any statement that writes to it.
+    ///
+    /// This is useful for commands that report on the contents of the database, and should be
+    /// guaranteed to not change anything. On Postgres, these connections are made to the read
+    /// replica if one was set with `PostgresBackend::with_read_replica`, so their results may
+    /// lag slightly behind writes made with [`connect`](`Database::connect`).
+    pub async fn connect_read_only(&self) -> Result<DbConnection> {
+        self.connect_inner(true).await
+    }
+
+    /// Returns the tables, columns and indexes in both the persistent and transient databases.
+    ///
+    /// This includes the tables created by migrations and KVS stores, which have generated
+    /// names, as well as any tables created by modules directly.
+    pub async fn schema(&self) -> Result<DatabaseSchema> {
+        let mut conn = self.connect_read_only().await?;
+        schema::load_schema(&mut conn, self.dialect()).await
+    }
+
+    /// Re-encrypts the database with a new key.
+    ///
+    /// Connections opened after this use the new key. This should only be done while the bot is
+    /// idle, as statements running on other connections at the same time may fail. Separate
+    /// databases that have been opened are re-encrypted first, so the main database keeps its
+    /// old key if any of them fail.
+    pub async fn rekey(&self, new_key: &str) -> Result<()> {
+        for (name, database) in self.separate_databases() {
+            let rekey = database.rekey_0(new_key).await;
+            rekey.internal_err(|| format!("Could not re-encrypt separate database '{}'.", name))?;
+        }
+        self.rekey_0(new_key).await
+    }
+    async fn rekey_0(&self, new_key: &str) -> Result<()> {
+        let backend = self.backend();
+        let new_key = new_key.to_string();
+        let backend = Handle::current().spawn_blocking(move || backend.rekey(&new_key)).await??;
+        self.set_backend(backend, self.pool_config());
+        Ok(())
+    }
+
+    /// Copies the persistent database to a new SQLite database file at the given path.
+    ///
+    /// This can be done while the bot is running, and does not include transient data. Each
+    /// separate database that has been opened is copied to a file next to the backup, named as
+    /// described in [`open_separate`](Self::open_separate).
+    pub async fn backup_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref().to_owned();
+        let mut backends = vec![(self.backend(), path.clone())];
+        for (name, database) in self.separate_databases() {
+            backends.push((database.backend(), separate_path(&path, &name)?));
+        }
+        Handle::current().spawn_blocking(move || {
+            for (backend, path) in backends {
+                backend.backup_to(&path)?;
+            }
+            Ok(())
+        }).await?
+    }
+
+    fn separate_databases(&self) -> Vec<(String, Database)> {
+        let separate = self.separate.lock();
+        separate.iter().map(|(name, database)| (name.clone(), database.clone())).collect()
+    }
+}
+
+/// Names that separate databases cannot use, as their files would have the same names as files
+/// used for the main database.
+const RESERVED_SEPARATE_NAMES: &[&str] = &["transient", "restore"];
+
+/// Returns whether a name can be used for a separate database.
+pub(crate) fn is_valid_separate_name(name: &str) -> bool {
+    !name.is_empty() &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+        !RESERVED_SEPARATE_NAMES.iter().any(|x| x.eq_ignore_ascii_case(name))
+}
+
+/// Contains extension functions defined on [`SylphieCore`].
+pub trait SylphieCoreDatabaseExt {
+    /// Sets the storage backend used by the bot.
+    ///
+    /// If this is not called, data is stored in SQLite databases in the bot's data directory.
+    fn with_storage_backend(self, backend: impl StorageBackend) -> Self;
+
+    /// Sets the configuration used for the pool of database connections.
+    fn with_pool_config(self, config: PoolConfig) -> Self;
+
+    /// Stores all data in memory, and discards it when the bot exits.
+    ///
+    /// This is meant for tests, which then do not need a data directory to store databases in,
+    /// and do not share any data with other tests running at the same time.
+    fn with_in_memory_database(self) -> Self;
+}
+impl <R: Module> SylphieCoreDatabaseExt for SylphieCore<R> {
+    fn with_storage_backend(self, backend: impl StorageBackend) -> Self {
+        let backend: Arc<dyn StorageBackend> = Arc::new(backend);
+        self.with_service(backend)
+    }
+
+    fn with_pool_config(self, config: PoolConfig) -> Self {
+        self.with_service(Arc::new(config))
+    }
+
+    fn with_in_memory_database(self) -> Self {
+        self.with_storage_backend(MemoryBackend::new())
+    }
 }
 
 /// Contains extension functions defined directly on `Handler<impl Events>`.
@@ -613,6 +982,14 @@ pub trait SylphieDatabaseHandlerExt {
 
     /// Connects to the database synchronously.
     fn connect_db_sync(&self) -> Result<DbSyncConnection>;
+
+    /// Connects to the database with a read-only connection.
+    async fn connect_db_read_only(&self) -> Result<DbConnection>;
+
+    /// Returns a separate database with the given name.
+    ///
+    /// See [`Database::open_separate`] for details.
+    fn separate_db(&self, name: &str) -> Result<Database>;
 }
 #[async_trait]
 impl <E: Events> SylphieDatabaseHandlerExt for Handler<E> {
@@ -623,5 +1000,13 @@ impl <E: Events> SylphieDatabaseHandlerExt for Handler<E> {
     fn connect_db_sync(&self) -> Result<DbSyncConnection> {
         self.get_service::<Database>().connect_sync()
     }
+
+    async fn connect_db_read_only(&self) -> Result<DbConnection> {
+        self.get_service::<Database>().connect_read_only().await
+    }
+
+    fn separate_db(&self, name: &str) -> Result<Database> {
+        self.get_service::<Database>().open_separate(name)
+    }
 }
 
@@ -0,0 +1,352 @@
+//! Support for storing data on a Postgres server.
+//!
+//! Statements are written for SQLite and translated by [`translate_for_postgres`]. As SQLite
+//! allows any value to be stored in a `BLOB` column, `BYTEA` columns store a one byte type tag
+//! followed by the value itself, so values round trip through them with their original types.
+
+use crate::connection::{BackendConnection, QueryParams, QueryRows, SqlDialect, StorageBackend};
+use crate::connection::dialect::{translate_for_postgres, ParamOrder};
+use crate::connection::statement_cache::{StatementCache, StatementCacheStats};
+use crate::connection::statement_cache::STATEMENT_CACHE_CAPACITY;
+use crate::serializable::SerializeValue;
+use parking_lot::Mutex;
+use postgres::{Client, NoTls, Row, Statement};
+use postgres::error::SqlState;
+use postgres::types::{ToSql, Type};
+use std::path::Path;
+use std::sync::Arc;
+use sylphie_core::prelude::*;
+
+const TAG_NULL: u8 = 0;
+const TAG_BYTES: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_FLOATING: u8 = 4;
+
+fn encode_tagged(value: &SerializeValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match value {
+        SerializeValue::Null => buf.push(TAG_NULL),
+        SerializeValue::Bytes(b) => {
+            buf.push(TAG_BYTES);
+            buf.extend_from_slice(b);
+        }
+        SerializeValue::String(s) => {
+            buf.push(TAG_STRING);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        SerializeValue::Integer(i) => {
+            buf.push(TAG_INTEGER);
+            buf.extend_from_slice(&i.to_be_bytes());
+        }
+        SerializeValue::Floating(f) => {
+            buf.push(TAG_FLOATING);
+            buf.extend_from_slice(&f.to_bits().to_be_bytes());
+        }
+    }
+    buf
+}
+fn decode_tagged(data: &[u8]) -> Result<SerializeValue> {
+    let (tag, rest) = match data.split_first() {
+        Some(x) => x,
+        None => bail!("Encoded value is empty."),
+    };
+    let as_u64 = |rest: &[u8]| -> Result<u64> {
+        ensure!(rest.len() == 8, "Encoded value has wrong length.");
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(rest);
+        Ok(u64::from_be_bytes(buf))
+    };
+    Ok(match *tag {
+        TAG_NULL => SerializeValue::Null,
+        TAG_BYTES => SerializeValue::Bytes(rest.into()),
+        TAG_STRING => SerializeValue::String(std::str::from_utf8(rest)?.into()),
+        TAG_INTEGER => SerializeValue::Integer(as_u64(rest)? as i64),
+        TAG_FLOATING => SerializeValue::Floating(f64::from_bits(as_u64(rest)?)),
+        _ => bail!("Unknown value tag {}.", tag),
+    })
+}
+
+/// Returns whether an error is Postgres aborting a serializable transaction because it conflicted
+/// with another transaction.
+pub(crate) fn is_serialization_failure(err: &Error) -> bool {
+    match err.error_kind() {
+        ErrorKind::GenericError(e) => match e.downcast_ref::<postgres::Error>() {
+            Some(e) => e.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+type BoxedParam = Box<dyn ToSql + Sync + Send>;
+
+fn convert_param(ty: &Type, value: &SerializeValue) -> Result<BoxedParam> {
+    let is_null = matches!(value, SerializeValue::Null);
+    let as_int = || -> Result<Option<i64>> {
+        match value {
+            SerializeValue::Null => Ok(None),
+            SerializeValue::Integer(i) => Ok(Some(*i)),
+            _ => bail!("Expected an integer for a parameter of type {}.", ty),
+        }
+    };
+
+    let param: BoxedParam = if *ty == Type::BYTEA {
+        Box::new(if is_null { None } else { Some(encode_tagged(value)) })
+    } else if *ty == Type::INT8 {
+        Box::new(as_int()?)
+    } else if *ty == Type::INT4 {
+        Box::new(as_int()?.map(|x| x as i32))
+    } else if *ty == Type::INT2 {
+        Box::new(as_int()?.map(|x| x as i16))
+    } else if *ty == Type::BOOL {
+        Box::new(as_int()?.map(|x| x != 0))
+    } else if *ty == Type::FLOAT8 || *ty == Type::FLOAT4 {
+        let value = match value {
+            SerializeValue::Null => None,
+            SerializeValue::Integer(i) => Some(*i as f64),
+            SerializeValue::Floating(f) => Some(*f),
+            _ => bail!("Expected a number for a parameter of type {}.", ty),
+        };
+        if *ty == Type::FLOAT4 {
+            Box::new(value.map(|x| x as f32))
+        } else {
+            Box::new(value)
+        }
+    } else if *ty == Type::TEXT || *ty == Type::VARCHAR {
+        Box::new(match value {
+            SerializeValue::Null => None,
+            SerializeValue::String(s) => Some(s.to_string()),
+            SerializeValue::Integer(i) => Some(i.to_string()),
+            SerializeValue::Floating(f) => Some(f.to_string()),
+            SerializeValue::Bytes(_) => bail!("Cannot store bytes in a text parameter."),
+        })
+    } else {
+        bail!("Unsupported Postgres parameter type: {}", ty)
+    };
+    Ok(param)
+}
+
+fn convert_column(row: &Row, i: usize) -> Result<SerializeValue> {
+    let ty = row.columns()[i].type_();
+    let value = if *ty == Type::BYTEA {
+        match row.try_get::<_, Option<Vec<u8>>>(i)? {
+            Some(data) => Some(decode_tagged(&data)?),
+            None => None,
+        }
+    } else if *ty == Type::INT8 {
+        row.try_get::<_, Option<i64>>(i)?.map(SerializeValue::Integer)
+    } else if *ty == Type::INT4 {
+        row.try_get::<_, Option<i32>>(i)?.map(|x| SerializeValue::Integer(x as i64))
+    } else if *ty == Type::INT2 {
+        row.try_get::<_, Option<i16>>(i)?.map(|x| SerializeValue::Integer(x as i64))
+    } else if *ty == Type::BOOL {
+        row.try_get::<_, Option<bool>>(i)?.map(|x| SerializeValue::Integer(x as i64))
+    } else if *ty == Type::FLOAT8 {
+        row.try_get::<_, Option<f64>>(i)?.map(SerializeValue::Floating)
+    } else if *ty == Type::FLOAT4 {
+        row.try_get::<_, Option<f32>>(i)?.map(|x| SerializeValue::Floating(x as f64))
+    } else if *ty == Type::TEXT || *ty == Type::VARCHAR || *ty == Type::NAME {
+        row.try_get::<_, Option<String>>(i)?.map(SerializeValue::from)
+    } else {
+        bail!("Unsupported Postgres column type: {}", ty)
+    };
+    Ok(value.unwrap_or(SerializeValue::Null))
+}
+
+fn row_values(row: &Row) -> Result<Vec<SerializeValue>> {
+    let mut values = Vec::with_capacity(row.len());
+    for i in 0..row.len() {
+        values.push(convert_column(row, i)?);
+    }
+    Ok(values)
+}
+
+fn prepare(
+    client: &mut Client, cache: &mut StatementCache<Statement>, sql: &str, params: QueryParams,
+) -> Result<(Statement, Vec<BoxedParam>)> {
+    let is_named = matches!(params, QueryParams::Named(_));
+    let (sql, order) = translate_for_postgres(sql, is_named)?;
+    let values = match (params, order) {
+        (QueryParams::Positional(values), ParamOrder::Positional(count)) => {
+            ensure!(
+                values.len() == count,
+                "Statement expects {} parameters, but {} were given.", count, values.len(),
+            );
+            values
+        }
+        (QueryParams::Named(mut values), ParamOrder::Named(names)) => {
+            let mut ordered = Vec::new();
+            for name in names {
+                let position = values.iter().position(|x| {
+                    x.0.trim_start_matches(|c: char| c == ':' || c == '@' || c == '$') == name
+                });
+                match position {
+                    Some(i) => ordered.push(values.swap_remove(i).1),
+                    None => bail!("Named parameter '{}' was not given.", name),
+                }
+            }
+            ensure!(values.is_empty(), "Statement was given unused named parameters.");
+            ordered
+        }
+        _ => unreachable!(),
+    };
+
+    // the translated statement is what is prepared by the server, so it is used as the key.
+    let statement = cache.get_or_prepare(&sql, || Ok(client.prepare(&sql)?))?;
+    let mut params = Vec::new();
+    for (ty, value) in statement.params().iter().zip(values.iter()) {
+        params.push(convert_param(ty, value)?);
+    }
+    Ok((statement, params))
+}
+fn param_refs(params: &[BoxedParam]) -> Vec<&(dyn ToSql + Sync)> {
+    params.iter().map(|x| &**x as &(dyn ToSql + Sync)).collect()
+}
+
+struct PostgresConnection {
+    client: Client,
+    cache: StatementCache<Statement>,
+}
+impl PostgresConnection {
+    fn new(client: Client) -> Self {
+        PostgresConnection { client, cache: StatementCache::new(STATEMENT_CACHE_CAPACITY) }
+    }
+}
+impl BackendConnection for PostgresConnection {
+    fn check_valid(&mut self) -> Result<()> {
+        self.client.simple_query("SELECT 1")?;
+        Ok(())
+    }
+
+    fn execute(&mut self, sql: &str, params: QueryParams) -> Result<usize> {
+        let (statement, params) = prepare(&mut self.client, &mut self.cache, sql, params)?;
+        Ok(self.client.execute(&statement, &param_refs(&params))? as usize)
+    }
+
+    fn execute_batch(&mut self, sql: &str) -> Result<()> {
+        let (sql, _) = translate_for_postgres(sql, false)?;
+        self.client.batch_execute(&sql)?;
+        Ok(())
+    }
+
+    fn query(
+        &mut self, sql: &str, params: QueryParams, max_rows: Option<usize>,
+    ) -> Result<QueryRows> {
+        let (statement, params) = prepare(&mut self.client, &mut self.cache, sql, params)?;
+        let columns = statement.columns().iter().map(|x| x.name().to_string()).collect();
+        let rows = self.client.query(&statement, &param_refs(&params))?;
+        let count = max_rows.unwrap_or(rows.len()).min(rows.len());
+        let mut result = Vec::with_capacity(count);
+        for row in &rows[..count] {
+            result.push(row_values(row)?);
+        }
+        Ok(QueryRows { columns, rows: result })
+    }
+
+    fn take_statement_cache_stats(&mut self) -> StatementCacheStats {
+        self.cache.take_stats()
+    }
+}
+
+/// Drops every transient table, and recreates the empty schema they are stored in.
+const CLEAR_TRANSIENT: &str = "DROP SCHEMA IF EXISTS transient CASCADE; CREATE SCHEMA transient;";
+
+/// A backend that stores data on a Postgres server.
+///
+/// Transient tables are stored in the `transient` schema, which is cleared when the bot starts.
+#[derive(Clone)]
+pub struct PostgresBackend {
+    url: String,
+    replica_url: Option<String>,
+    is_transient_cleared: Arc<Mutex<bool>>,
+}
+impl PostgresBackend {
+    /// Creates a new backend connecting to the given URL.
+    pub fn new(url: impl Into<String>) -> Self {
+        PostgresBackend {
+            url: url.into(),
+            replica_url: None,
+            is_transient_cleared: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Sets a read replica used for read-only connections.
+    ///
+    /// The replica should be kept up to date with the primary server by Postgres replication, as
+    /// migrations and writes are only ever performed on the primary server.
+    pub fn with_read_replica(mut self, url: impl Into<String>) -> Self {
+        self.replica_url = Some(url.into());
+        self
+    }
+}
+impl StorageBackend for PostgresBackend {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+
+    fn dialect(&self) -> SqlDialect {
+        SqlDialect::Postgres
+    }
+
+    fn connect(&self) -> Result<Box<dyn BackendConnection>> {
+        let mut client = Client::connect(&self.url, NoTls)?;
+        let mut is_transient_cleared = self.is_transient_cleared.lock();
+        if *is_transient_cleared {
+            client.batch_execute("CREATE SCHEMA IF NOT EXISTS transient;")?;
+        } else {
+            // the server keeps transient tables across restarts, so the first connection made
+            // after the bot starts drops them.
+            client.batch_execute(CLEAR_TRANSIENT)?;
+            *is_transient_cleared = true;
+        }
+        Ok(Box::new(PostgresConnection::new(client)))
+    }
+
+    fn connect_read_only(&self) -> Result<Box<dyn BackendConnection>> {
+        // the transient schema is created by the read-write connections, and cannot be created
+        // on a replica.
+        let url = self.replica_url.as_ref().unwrap_or(&self.url);
+        let mut client = Client::connect(url, NoTls)?;
+        client.batch_execute(SqlDialect::Postgres.read_only())?;
+        Ok(Box::new(PostgresConnection::new(client)))
+    }
+
+    fn restore_transient(&self, path: Option<&Path>) -> Result<()> {
+        ensure!(path.is_none(), "The postgres storage backend does not support transient \
+                                 snapshots.");
+        let mut client = Client::connect(&self.url, NoTls)?;
+        client.batch_execute(CLEAR_TRANSIENT)?;
+        *self.is_transient_cleared.lock() = true;
+        Ok(())
+    }
+
+    fn open_separate(&self, _name: &str) -> Result<Arc<dyn StorageBackend>> {
+        // Postgres doesn't lock whole databases for writes, so a separate connection pool to the
+        // same server is enough.
+        Ok(Arc::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tagged_values() {
+        let values = [
+            SerializeValue::Null,
+            SerializeValue::Bytes(vec![0u8, 1, 2].into()),
+            SerializeValue::String("text".into()),
+            SerializeValue::Integer(-42),
+            SerializeValue::Floating(1.5),
+        ];
+        for value in &values {
+            let decoded = decode_tagged(&encode_tagged(value)).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", value));
+        }
+        assert!(decode_tagged(&[]).is_err());
+        assert!(decode_tagged(&[TAG_INTEGER, 1, 2]).is_err());
+        assert!(decode_tagged(&[255]).is_err());
+    }
+}
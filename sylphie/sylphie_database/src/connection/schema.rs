@@ -0,0 +1,158 @@
+use crate::connection::{DbOps, SqlDialect};
+use sylphie_core::prelude::*;
+
+/// A column of a table, returned by [`Database::schema`](`super::Database::schema`).
+#[derive(Clone, Debug)]
+pub struct ColumnSchema {
+    /// The name of the column.
+    pub name: String,
+    /// The type of the column, as reported by the database.
+    pub sql_type: String,
+    /// Whether the column is declared `NOT NULL`.
+    pub not_null: bool,
+    /// Whether the column is part of the table's primary key.
+    pub primary_key: bool,
+}
+
+/// An index on a table, returned by [`Database::schema`](`super::Database::schema`).
+#[derive(Clone, Debug)]
+pub struct IndexSchema {
+    /// The name of the index.
+    pub name: String,
+    /// Whether the index enforces that its values are unique.
+    pub unique: bool,
+    /// The columns covered by the index, in order. Indexes on expressions have no columns.
+    pub columns: Vec<String>,
+}
+
+/// A table, returned by [`Database::schema`](`super::Database::schema`).
+#[derive(Clone, Debug)]
+pub struct TableSchema {
+    /// The name of the table.
+    pub name: String,
+    /// Whether the table is in the transient database.
+    pub is_transient: bool,
+    /// The columns of the table, in the order they are declared.
+    pub columns: Vec<ColumnSchema>,
+    /// The indexes on the table.
+    pub indexes: Vec<IndexSchema>,
+}
+
+/// The tables in the persistent and transient databases.
+#[derive(Clone, Debug, Default)]
+pub struct DatabaseSchema {
+    /// Every table in the database, with persistent tables first.
+    pub tables: Vec<TableSchema>,
+}
+impl DatabaseSchema {
+    /// Finds a table by its name.
+    pub fn table(&self, name: &str, is_transient: bool) -> Option<&TableSchema> {
+        self.tables.iter().find(|x| x.name == name && x.is_transient == is_transient)
+    }
+}
+
+struct SchemaQueries {
+    tables: String,
+    columns: String,
+    indexes: String,
+}
+impl SchemaQueries {
+    fn new(dialect: SqlDialect, is_transient: bool) -> Self {
+        match dialect {
+            SqlDialect::Sqlite => {
+                let schema = if is_transient { "transient" } else { "main" };
+                SchemaQueries {
+                    tables: format!(
+                        "SELECT name FROM {}.sqlite_master \
+                         WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+                        schema,
+                    ),
+                    columns: format!(
+                        "SELECT name, type, \"notnull\", pk > 0 \
+                         FROM pragma_table_info(?, '{}') ORDER BY cid;",
+                        schema,
+                    ),
+                    indexes: format!(
+                        "SELECT il.name, il.\"unique\", ii.name \
+                         FROM pragma_index_list(?, '{0}') AS il, \
+                              pragma_index_info(il.name, '{0}') AS ii \
+                         ORDER BY il.name, ii.seqno;",
+                        schema,
+                    ),
+                }
+            }
+            SqlDialect::Postgres => {
+                let schema = if is_transient { "'transient'" } else { "current_schema()" };
+                SchemaQueries {
+                    tables: format!(
+                        "SELECT t.relname::text FROM pg_class t \
+                         JOIN pg_namespace n ON n.oid = t.relnamespace \
+                         WHERE n.nspname = {} AND t.relkind IN ('r', 'p') ORDER BY t.relname;",
+                        schema,
+                    ),
+                    columns: format!(
+                        "SELECT a.attname::text, format_type(a.atttypid, a.atttypmod), \
+                                a.attnotnull::int, \
+                                COALESCE(a.attnum = ANY(pk.indkey), FALSE)::int \
+                         FROM pg_attribute a \
+                         JOIN pg_class t ON t.oid = a.attrelid \
+                         JOIN pg_namespace n ON n.oid = t.relnamespace \
+                         LEFT JOIN pg_index pk ON pk.indrelid = t.oid AND pk.indisprimary \
+                         WHERE n.nspname = {} AND t.relname::text = ? \
+                           AND a.attnum > 0 AND NOT a.attisdropped \
+                         ORDER BY a.attnum;",
+                        schema,
+                    ),
+                    indexes: format!(
+                        "SELECT i.relname::text, ix.indisunique::int, a.attname::text \
+                         FROM pg_index ix \
+                         JOIN pg_class t ON t.oid = ix.indrelid \
+                         JOIN pg_class i ON i.oid = ix.indexrelid \
+                         JOIN pg_namespace n ON n.oid = t.relnamespace \
+                         JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = ANY(ix.indkey) \
+                         WHERE n.nspname = {} AND t.relname::text = ? \
+                         ORDER BY i.relname, array_position(ix.indkey::int2[], a.attnum);",
+                        schema,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Reads the schema of every table in the database.
+pub(crate) async fn load_schema(conn: &mut DbOps, dialect: SqlDialect) -> Result<DatabaseSchema> {
+    let mut schema = DatabaseSchema::default();
+    for &is_transient in &[false, true] {
+        let queries = SchemaQueries::new(dialect, is_transient);
+        let tables: Vec<String> = conn.query_vec_nullary(queries.tables.clone()).await?;
+        for name in tables {
+            let columns: Vec<(String, String, i64, i64)> =
+                conn.query_vec(queries.columns.clone(), name.clone()).await?;
+            let index_rows: Vec<(String, i64, Option<String>)> =
+                conn.query_vec(queries.indexes.clone(), name.clone()).await?;
+
+            let mut indexes: Vec<IndexSchema> = Vec::new();
+            for (index_name, unique, column) in index_rows {
+                if indexes.last().map_or(true, |x| x.name != index_name) {
+                    indexes.push(IndexSchema {
+                        name: index_name, unique: unique != 0, columns: Vec::new(),
+                    });
+                }
+                indexes.last_mut().unwrap().columns.extend(column);
+            }
+
+            schema.tables.push(TableSchema {
+                name,
+                is_transient,
+                columns: columns.into_iter().map(|(name, sql_type, not_null, primary_key)| {
+                    ColumnSchema {
+                        name, sql_type, not_null: not_null != 0, primary_key: primary_key != 0,
+                    }
+                }).collect(),
+                indexes,
+            });
+        }
+    }
+    Ok(schema)
+}
@@ -1,3 +1,3 @@
+PRAGMA auto_vacuum = incremental;
 PRAGMA journal_mode = wal;
 PRAGMA foreign_keys = true;
-PRAGMA busy_timeout = 5000;
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use sylphie_core::prelude::*;
+
+/// The number of prepared statements kept by each connection.
+pub(crate) const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// The number of lookups in a connection's prepared statement cache.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatementCacheStats {
+    /// The number of statements that were found in the cache.
+    pub hits: u64,
+    /// The number of statements that had to be prepared.
+    pub misses: u64,
+}
+
+/// A least recently used cache of prepared statements, keyed by their SQL text.
+///
+/// This is used by backends whose clients do not cache prepared statements themselves.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub(crate) struct StatementCache<T> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (T, u64)>,
+    stats: StatementCacheStats,
+}
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+impl <T: Clone> StatementCache<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        StatementCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            stats: Default::default(),
+        }
+    }
+
+    /// Returns the cached statement for some SQL, preparing and caching it if needed.
+    pub(crate) fn get_or_prepare(
+        &mut self, sql: &str, prepare: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(sql) {
+            entry.1 = self.tick;
+            self.stats.hits += 1;
+            return Ok(entry.0.clone())
+        }
+
+        self.stats.misses += 1;
+        let statement = prepare()?;
+        if self.entries.len() >= self.capacity {
+            let oldest = self.entries.iter().min_by_key(|x| (x.1).1).map(|x| x.0.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(sql.to_string(), (statement.clone(), self.tick));
+        Ok(statement)
+    }
+
+    /// Returns the lookups made since this was last called.
+    pub(crate) fn take_stats(&mut self) -> StatementCacheStats {
+        std::mem::take(&mut self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = StatementCache::new(2);
+        let mut prepared = 0;
+        let mut get = |cache: &mut StatementCache<u32>, sql: &str| {
+            cache.get_or_prepare(sql, || { prepared += 1; Ok(prepared) }).unwrap()
+        };
+        assert_eq!(get(&mut cache, "a"), 1);
+        assert_eq!(get(&mut cache, "b"), 2);
+        assert_eq!(get(&mut cache, "a"), 1);
+        assert_eq!(get(&mut cache, "c"), 3);
+        assert_eq!(get(&mut cache, "a"), 1);
+        assert_eq!(get(&mut cache, "b"), 4);
+        assert_eq!(cache.take_stats(), StatementCacheStats { hits: 2, misses: 4 });
+        assert_eq!(cache.take_stats(), StatementCacheStats::default());
+    }
+}
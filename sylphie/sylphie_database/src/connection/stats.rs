@@ -0,0 +1,156 @@
+use crate::connection::StatementCacheStats;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use sylphie_core::prelude::*;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Dispatched when a statement takes longer than the slow query threshold set with
+/// [`PoolConfig::slow_query_threshold`](`super::PoolConfig::slow_query_threshold`).
+///
+/// This is dispatched from a background task shortly after the statement finishes, rather than
+/// from the connection that ran it.
+#[derive(Clone, Debug)]
+pub struct SlowQueryEvent {
+    /// The statement that was run.
+    pub sql: Arc<str>,
+    /// How long the statement took to run.
+    pub duration: Duration,
+}
+self_event!(SlowQueryEvent);
+
+/// Counters updated as connections are acquired and queries are run.
+pub(crate) struct StatsCounters {
+    acquire_count: AtomicU64,
+    acquire_wait_us: AtomicU64,
+    max_acquire_wait_us: AtomicU64,
+    query_count: AtomicU64,
+    slow_query_count: AtomicU64,
+    slow_query_threshold_us: AtomicU64,
+    statement_cache_hits: AtomicU64,
+    statement_cache_misses: AtomicU64,
+    slow_query_send: UnboundedSender<SlowQueryEvent>,
+    slow_query_recv: Mutex<Option<UnboundedReceiver<SlowQueryEvent>>>,
+}
+impl Default for StatsCounters {
+    fn default() -> Self {
+        let (send, recv) = mpsc::unbounded_channel();
+        StatsCounters {
+            acquire_count: AtomicU64::new(0),
+            acquire_wait_us: AtomicU64::new(0),
+            max_acquire_wait_us: AtomicU64::new(0),
+            query_count: AtomicU64::new(0),
+            slow_query_count: AtomicU64::new(0),
+            slow_query_threshold_us: AtomicU64::new(0),
+            statement_cache_hits: AtomicU64::new(0),
+            statement_cache_misses: AtomicU64::new(0),
+            slow_query_send: send,
+            slow_query_recv: Mutex::new(Some(recv)),
+        }
+    }
+}
+impl StatsCounters {
+    pub(crate) fn set_slow_query_threshold(&self, threshold: Duration) {
+        self.slow_query_threshold_us.store(threshold.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_acquire(&self, wait: Duration) {
+        let wait = wait.as_micros() as u64;
+        self.acquire_count.fetch_add(1, Ordering::Relaxed);
+        self.acquire_wait_us.fetch_add(wait, Ordering::Relaxed);
+        self.max_acquire_wait_us.fetch_max(wait, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_query(&self, sql: &str, time: Duration) {
+        let millis = time.as_secs_f64() * 1000.0;
+        trace!(target: "sylphie_database::query", "({:.3} ms) {}", millis, sql);
+        self.query_count.fetch_add(1, Ordering::Relaxed);
+        let threshold = self.slow_query_threshold_us.load(Ordering::Relaxed);
+        if threshold != 0 && time.as_micros() as u64 >= threshold {
+            self.slow_query_count.fetch_add(1, Ordering::Relaxed);
+            // this only fails if the receiver was dropped, and nobody is listening anyway.
+            let _ = self.slow_query_send.send(SlowQueryEvent { sql: sql.into(), duration: time });
+        }
+    }
+
+    pub(crate) fn record_statement_cache(&self, stats: StatementCacheStats) {
+        self.statement_cache_hits.fetch_add(stats.hits, Ordering::Relaxed);
+        self.statement_cache_misses.fetch_add(stats.misses, Ordering::Relaxed);
+    }
+
+    pub(crate) fn take_slow_queries(&self) -> Option<UnboundedReceiver<SlowQueryEvent>> {
+        self.slow_query_recv.lock().take()
+    }
+
+    pub(crate) fn snapshot(
+        &self, connections: u32, idle_connections: u32, max_connections: u32,
+    ) -> DatabaseStats {
+        DatabaseStats {
+            connections,
+            idle_connections,
+            max_connections,
+            acquire_count: self.acquire_count.load(Ordering::Relaxed),
+            total_acquire_wait: Duration::from_micros(
+                self.acquire_wait_us.load(Ordering::Relaxed),
+            ),
+            max_acquire_wait: Duration::from_micros(
+                self.max_acquire_wait_us.load(Ordering::Relaxed),
+            ),
+            query_count: self.query_count.load(Ordering::Relaxed),
+            slow_query_count: self.slow_query_count.load(Ordering::Relaxed),
+            slow_query_threshold: Duration::from_micros(
+                self.slow_query_threshold_us.load(Ordering::Relaxed),
+            ),
+            statement_cache: StatementCacheStats {
+                hits: self.statement_cache_hits.load(Ordering::Relaxed),
+                misses: self.statement_cache_misses.load(Ordering::Relaxed),
+            },
+        }
+    }
+}
+
+/// Statistics about the connection pools and the queries run on them.
+///
+/// Connection counts include both read-write and read-only connections.
+///
+/// This can be retrieved with [`Database::stats`](`super::Database::stats`).
+#[derive(Clone, Debug)]
+pub struct DatabaseStats {
+    /// The number of connections currently open.
+    pub connections: u32,
+    /// The number of open connections that are not in use.
+    pub idle_connections: u32,
+    /// The maximum number of connections the pool will open.
+    pub max_connections: u32,
+    /// The number of connections acquired from the pool since startup.
+    pub acquire_count: u64,
+    /// The total time spent waiting for connections from the pool.
+    pub total_acquire_wait: Duration,
+    /// The longest time spent waiting for a single connection from the pool.
+    pub max_acquire_wait: Duration,
+    /// The number of statements run since startup.
+    pub query_count: u64,
+    /// The number of statements that took longer than the slow query threshold.
+    pub slow_query_count: u64,
+    /// The threshold used to decide which statements count as slow queries.
+    pub slow_query_threshold: Duration,
+    /// The total lookups in the prepared statement caches of all connections.
+    pub statement_cache: StatementCacheStats,
+}
+impl DatabaseStats {
+    /// Returns the number of connections currently in use.
+    pub fn active_connections(&self) -> u32 {
+        self.connections.saturating_sub(self.idle_connections)
+    }
+
+    /// Returns the average time spent waiting for a connection from the pool.
+    pub fn average_acquire_wait(&self) -> Duration {
+        if self.acquire_count == 0 {
+            Duration::from_secs(0)
+        } else {
+            let average = self.total_acquire_wait.as_micros() / self.acquire_count as u128;
+            Duration::from_micros(average as u64)
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Conversions between query parameters, rows and [`SerializeValue`]s.
+
+use crate::serializable::SerializeValue;
+use rusqlite::ToSql;
+use rusqlite::types::{ToSqlOutput, Value};
+use serde::Serialize;
+use serde::de::{DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+use serde::de::value::{Error as ValueError, MapDeserializer, SeqDeserializer};
+use sylphie_core::prelude::*;
+
+fn to_value(param: &dyn ToSql) -> Result<SerializeValue> {
+    #[allow(unreachable_patterns)]
+    let value = match param.to_sql()? {
+        ToSqlOutput::Borrowed(v) => Value::from(v),
+        ToSqlOutput::Owned(v) => v,
+        _ => bail!("Unsupported query parameter type."),
+    };
+    Ok(match value {
+        Value::Null => SerializeValue::Null,
+        Value::Integer(i) => SerializeValue::Integer(i),
+        Value::Real(f) => SerializeValue::Floating(f),
+        Value::Text(s) => SerializeValue::String(s.into()),
+        Value::Blob(b) => SerializeValue::Bytes(b.into()),
+    })
+}
+
+/// Converts unnamed query parameters into a list of values.
+pub(crate) fn to_values(params: impl Serialize) -> Result<Vec<SerializeValue>> {
+    let data = serde_rusqlite::to_params(params)?;
+    data.to_slice().into_iter().map(to_value).collect()
+}
+
+/// Converts named query parameters into a list of names and values.
+pub(crate) fn to_named_values(params: impl Serialize) -> Result<Vec<(String, SerializeValue)>> {
+    let data = serde_rusqlite::to_params_named(params)?;
+    data.to_slice().into_iter()
+        .map(|(name, value)| Ok((name.to_string(), to_value(value)?)))
+        .collect()
+}
+
+pub struct ValueDeserializer(SerializeValue);
+impl <'de> IntoDeserializer<'de, ValueError> for SerializeValue {
+    type Deserializer = ValueDeserializer;
+    fn into_deserializer(self) -> Self::Deserializer {
+        ValueDeserializer(self)
+    }
+}
+impl <'de> Deserializer<'de> for ValueDeserializer {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> StdResult<V::Value, ValueError> {
+        match self.0 {
+            SerializeValue::Null => visitor.visit_unit(),
+            SerializeValue::String(s) => visitor.visit_str(&s),
+            SerializeValue::Bytes(b) => visitor.visit_bytes(&b),
+            SerializeValue::Integer(i) => visitor.visit_i64(i),
+            SerializeValue::Floating(f) => visitor.visit_f64(f),
+        }
+    }
+    fn deserialize_option<V: Visitor<'de>>(
+        self, visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        match self.0 {
+            SerializeValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> StdResult<V::Value, ValueError> {
+        match self.0 {
+            SerializeValue::Integer(i) => visitor.visit_bool(i != 0),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self, _: &'static str, visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct RowDeserializer<'a> {
+    columns: &'a [String],
+    values: Vec<SerializeValue>,
+}
+impl <'a> RowDeserializer<'a> {
+    fn single_value(mut self) -> StdResult<ValueDeserializer, ValueError> {
+        if self.values.len() != 1 {
+            return Err(serde::de::Error::custom(format!(
+                "Expected 1 column, found {} columns.", self.values.len(),
+            )))
+        }
+        Ok(ValueDeserializer(self.values.pop().unwrap()))
+    }
+}
+impl <'de, 'a> Deserializer<'de> for RowDeserializer<'a> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> StdResult<V::Value, ValueError> {
+        if self.values.len() == 1 {
+            self.single_value()?.deserialize_any(visitor)
+        } else {
+            self.deserialize_seq(visitor)
+        }
+    }
+    fn deserialize_option<V: Visitor<'de>>(
+        self, visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        self.single_value()?.deserialize_option(visitor)
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self, _: &'static str, visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> StdResult<V::Value, ValueError> {
+        visitor.visit_seq(SeqDeserializer::new(self.values.into_iter()))
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self, _: usize, visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        self.deserialize_seq(visitor)
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self, _: &'static str, _: usize, visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        self.deserialize_seq(visitor)
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> StdResult<V::Value, ValueError> {
+        let columns = self.columns.iter().map(|x| x.as_str());
+        visitor.visit_map(MapDeserializer::new(columns.zip(self.values.into_iter())))
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self, _: &'static str, _: &'static [&'static str], visitor: V,
+    ) -> StdResult<V::Value, ValueError> {
+        self.deserialize_map(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct enum identifier ignored_any
+    }
+}
+
+/// Deserializes a row, given its column names and values.
+pub(crate) fn from_row<T: DeserializeOwned>(
+    columns: &[String], values: Vec<SerializeValue>,
+) -> Result<T> {
+    Ok(T::deserialize(RowDeserializer { columns, values })?)
+}
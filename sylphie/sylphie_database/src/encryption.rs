@@ -0,0 +1,99 @@
+//! Support for encrypting the database with SQLCipher.
+//!
+//! The key is set with [`SylphieCoreEncryptionExt::with_database_key`], or with the
+//! `SYLPHIE_DB_KEY` environment variable.
+//!
+//! The `rekey` terminal command re-encrypts the database with a new key, read from a file or
+//! from the `SYLPHIE_NEW_DB_KEY` environment variable so it is not typed into the terminal:
+//!
+//! ```text
+//! rekey [key file]
+//! ```
+
+use crate::connection::*;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, TerminalOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::interface::SetupTerminalEvent;
+use sylphie_core::prelude::*;
+
+/// The environment variable the new key is read from by `rekey`, if no file is given.
+const NEW_KEY_VAR: &str = "SYLPHIE_NEW_DB_KEY";
+
+struct DatabaseKey(Arc<str>);
+
+/// Contains extension functions for configuring encryption on [`SylphieCore`].
+pub trait SylphieCoreEncryptionExt {
+    /// Sets the key used to encrypt the database.
+    ///
+    /// This takes priority over the `SYLPHIE_DB_KEY` environment variable.
+    fn with_database_key(self, key: impl Into<Arc<str>>) -> Self;
+}
+impl <R: Module> SylphieCoreEncryptionExt for SylphieCore<R> {
+    fn with_database_key(self, key: impl Into<Arc<str>>) -> Self {
+        self.with_service(Arc::new(DatabaseKey(key.into())))
+    }
+}
+
+/// Returns the key used to encrypt the database, if one was given.
+pub(crate) fn database_key(target: &Handler<impl Events>) -> Option<Arc<str>> {
+    match target.services().resolve::<DatabaseKey>() {
+        Some(key) => Some(key.0.clone()),
+        None => std::env::var("SYLPHIE_DB_KEY").ok().map(Into::into),
+    }
+}
+
+struct RekeyCommand;
+impl RekeyCommand {
+    /// Reads the new key from the file given to the command, or from [`NEW_KEY_VAR`].
+    async fn new_key(&self, ctx: &CommandCtx<impl Events>) -> Result<String> {
+        let key = match ctx.args_count() {
+            1 => std::env::var(NEW_KEY_VAR).ok().cmd_error(|| format!(
+                "No key file was given, and {} is not set.", NEW_KEY_VAR,
+            ))?,
+            2 => {
+                let path = ctx.arg(1).text;
+                tokio::fs::read_to_string(path).await
+                    .cmd_error(|| format!("Could not read the key file '{}'.", path))?
+            }
+            _ => cmd_error!("Usage: rekey [key file]"),
+        };
+        let key = key.trim().to_string();
+        if key.is_empty() {
+            cmd_error!("The new key is empty.");
+        }
+        Ok(key)
+    }
+
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let key = self.new_key(ctx).await?;
+        ctx.handler().get_service::<Database>().rekey(&key).await?;
+        ctx.respond(
+            "Re-encrypted the database. The new key must be used when the bot is next started.",
+        ).await?;
+        Ok(())
+    }
+}
+impl CommandImpl for RekeyCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `rekey` command.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("rekey");
+    ev.register_command(Command::new(target, module, info, TerminalOnly(RekeyCommand)));
+}
+
+/// Keeps `rekey` out of the terminal history, in case a key is given to it by mistake.
+pub(crate) fn setup_terminal(ev: &mut SetupTerminalEvent) {
+    ev.add_secret_command("rekey");
+}
@@ -0,0 +1,221 @@
+//! An append-only log of entries, such as moderation actions.
+
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, TimeZone, Utc};
+use crate::connection::*;
+use crate::interner::*;
+use crate::serializable::*;
+use futures::{stream, Stream};
+use static_events::prelude_async::*;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use sylphie_core::derives::*;
+use sylphie_core::prelude::*;
+
+/// The number of entries loaded at once when reading a range of the log.
+const LOG_PAGE_SIZE: usize = 64;
+
+/// An entry in an [`EventLogStore`].
+#[derive(Clone, Debug)]
+pub struct LogEntry<T> {
+    /// The sequence number of the entry. Later entries always have higher sequence numbers.
+    pub seq: u64,
+    /// The time the entry was appended.
+    pub time: DateTime<Utc>,
+    /// The entry itself.
+    pub value: T,
+}
+
+struct EventLogInfo {
+    db: Database,
+    interner: InternerLock,
+    value_id: StringId,
+    append_query: Arc<str>,
+    /// Returns the sequence number of the entry just appended, on databases where
+    /// `append_query` does not return it itself.
+    inserted_seq_query: Option<Arc<str>>,
+    range_query: Arc<str>,
+    last_query: Arc<str>,
+    prune_query: Arc<str>,
+}
+impl EventLogInfo {
+    fn create_query(dialect: SqlDialect, table_name: &str) -> String {
+        let seq_column = match dialect {
+            SqlDialect::Sqlite => "seq INTEGER PRIMARY KEY AUTOINCREMENT",
+            SqlDialect::Postgres => "seq BIGSERIAL PRIMARY KEY",
+        };
+        format!(
+            "CREATE TABLE IF NOT EXISTS {0} (\
+                {1}, \
+                time BIGINT NOT NULL, \
+                value BLOB NOT NULL, \
+                value_schema_id BIGINT NOT NULL, \
+                value_schema_ver INTEGER NOT NULL\
+            ); \
+            CREATE INDEX IF NOT EXISTS {0}_time_idx ON {0} (time);",
+            table_name, seq_column,
+        )
+    }
+
+    async fn decode<T: DbSerializable>(
+        &self, conn: &mut DbOps, row: (u64, i64, SerializeValue, StringId, u32),
+    ) -> Result<LogEntry<T>> {
+        let (seq, time, value, schema_id, schema_ver) = row;
+        let value = if schema_id == self.value_id && schema_ver == T::SCHEMA_VERSION {
+            T::Format::deserialize(value)?
+        } else {
+            let schema_name = self.interner.get_str_id_rev(conn, schema_id).await?;
+            ensure!(
+                T::can_migrate_from(&schema_name, schema_ver),
+                "Cannot migrate log entry from {}:{} to {}:{}.",
+                schema_name, schema_ver, T::ID, T::SCHEMA_VERSION,
+            );
+            T::do_migration(&schema_name, schema_ver, value)?
+        };
+        Ok(LogEntry { seq, time: Utc.timestamp_millis(time), value })
+    }
+}
+
+/// An append-only log of entries.
+///
+/// Each entry is given a sequence number higher than any earlier entry, and entries can only be
+/// removed by pruning everything older than a given age. This is useful for moderation logs and
+/// undo history.
+///
+/// This is a module, and should be used by attaching it to the your module as a submodule.
+#[derive(Module)]
+#[module(component)]
+pub struct EventLogStore<T: DbSerializable> {
+    #[module_info] info: ModuleInfo,
+    data: ArcSwapOption<EventLogInfo>,
+    phantom: PhantomData<fn(T) -> T>,
+}
+#[module_impl]
+impl <T: DbSerializable> EventLogStore<T> {
+    #[event_handler]
+    async fn init_event_log(
+        &self, target: &Handler<impl Events>, _: &crate::InitDbEvent,
+    ) -> Result<()> {
+        let db = target.get_service::<Database>().clone();
+        let hash = blake3::hash(self.info.name().as_bytes()).to_hex();
+        let table_name = format!("sylphie_db_log_{}", &hash.as_str()[0..16]);
+        let mut conn = db.connect().await?;
+        conn.execute_batch(EventLogInfo::create_query(db.dialect(), &table_name)).await?;
+        std::mem::drop(conn);
+
+        self.data.store(Some(Arc::new(EventLogInfo {
+            interner: target.get_service::<Interner>().lock(),
+            value_id: StringId::intern(target, T::ID).await?,
+            append_query: format!(
+                "INSERT INTO {} (time, value, value_schema_id, value_schema_ver) \
+                 VALUES (?, ?, ?, ?){};",
+                table_name,
+                match db.dialect() {
+                    SqlDialect::Sqlite => "",
+                    SqlDialect::Postgres => " RETURNING seq",
+                },
+            ).into(),
+            inserted_seq_query: match db.dialect() {
+                SqlDialect::Sqlite => Some("SELECT last_insert_rowid();".into()),
+                SqlDialect::Postgres => None,
+            },
+            range_query: format!(
+                "SELECT seq, time, value, value_schema_id, value_schema_ver FROM {} \
+                 WHERE seq >= ? AND seq < ? ORDER BY seq LIMIT {};",
+                table_name, LOG_PAGE_SIZE,
+            ).into(),
+            last_query: format!(
+                "SELECT seq, time, value, value_schema_id, value_schema_ver FROM {} \
+                 ORDER BY seq DESC LIMIT ?;",
+                table_name,
+            ).into(),
+            prune_query: format!("DELETE FROM {} WHERE time < ?;", table_name).into(),
+            db,
+        })));
+        Ok(())
+    }
+
+    fn load_data(&self) -> Arc<EventLogInfo> {
+        self.data.load().as_ref().expect("EventLogStore not yet initialized.").clone()
+    }
+
+    /// Appends an entry to the log, returning its sequence number.
+    pub async fn append(&self, value: &T) -> Result<u64> {
+        let data = self.load_data();
+        let mut conn = data.db.connect().await?;
+        let params = (
+            Utc::now().timestamp_millis(),
+            T::Format::serialize(value)?,
+            data.value_id,
+            T::SCHEMA_VERSION,
+        );
+        let seq: Option<u64> = match &data.inserted_seq_query {
+            Some(query) => {
+                conn.execute(data.append_query.clone(), params).await?;
+                conn.query_row_nullary(query.clone()).await?
+            }
+            None => conn.query_row(data.append_query.clone(), params).await?,
+        };
+        seq.internal_err(|| "Appended log entry has no sequence number.")
+    }
+
+    /// Returns the most recent entries in the log, newest first.
+    pub async fn last(&self, count: usize) -> Result<Vec<LogEntry<T>>> {
+        let data = self.load_data();
+        let mut conn = data.db.connect().await?;
+        let rows: Vec<(u64, i64, SerializeValue, StringId, u32)> =
+            conn.query_vec(data.last_query.clone(), count as i64).await?;
+        let mut entries = Vec::with_capacity(rows.len());
+        for row in rows {
+            entries.push(data.decode(&mut conn, row).await?);
+        }
+        Ok(entries)
+    }
+
+    /// Returns a stream of the entries with sequence numbers from `from` up to but not including
+    /// `to`, oldest first.
+    ///
+    /// Entries are loaded from the database a few at a time as the stream is consumed.
+    pub fn range(&self, from: u64, to: u64) -> impl Stream<Item = Result<LogEntry<T>>> {
+        let data = self.load_data();
+        let state = (data, from, VecDeque::new(), false);
+        stream::unfold(state, move |(data, mut next, mut buffer, mut is_done)| async move {
+            if buffer.is_empty() && !is_done {
+                let result: Result<()> = async {
+                    let mut conn = data.db.connect().await?;
+                    let rows: Vec<(u64, i64, SerializeValue, StringId, u32)> = conn.query_vec(
+                        data.range_query.clone(), (next as i64, to.min(i64::MAX as u64) as i64),
+                    ).await?;
+                    is_done = rows.len() < LOG_PAGE_SIZE;
+                    for row in rows {
+                        next = row.0 + 1;
+                        buffer.push_back(data.decode(&mut conn, row).await?);
+                    }
+                    Ok(())
+                }.await;
+                if let Err(e) = result {
+                    return Some((Err(e), (data, next, buffer, true)))
+                }
+            }
+            match buffer.pop_front() {
+                Some(entry) => Some((Ok(entry), (data, next, buffer, is_done))),
+                None => None,
+            }
+        })
+    }
+
+    /// Returns a stream of every entry in the log, oldest first.
+    pub fn iter(&self) -> impl Stream<Item = Result<LogEntry<T>>> {
+        self.range(0, u64::MAX)
+    }
+
+    /// Deletes every entry older than a given age, returning the number of entries deleted.
+    pub async fn prune_older_than(&self, age: Duration) -> Result<usize> {
+        let data = self.load_data();
+        let age = age.as_millis().min(i64::MAX as u128) as i64;
+        let before = Utc::now().timestamp_millis().saturating_sub(age);
+        data.db.connect().await?.execute(data.prune_query.clone(), before).await
+    }
+}
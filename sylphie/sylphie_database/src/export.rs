@@ -0,0 +1,82 @@
+//! Implements the `export` and `import` commands for KVS stores.
+
+use crate::kvs::{KvsExportEvent, KvsImportEvent};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, TerminalOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::prelude::*;
+
+struct ExportCommand;
+impl ExportCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        if ctx.args_count() != 3 {
+            cmd_error!("Usage: export <store> <path>");
+        }
+        let store_path = ctx.arg(1).text.to_string();
+        let path = PathBuf::from(ctx.arg(2).text);
+        let ev = ctx.handler().dispatch_async(KvsExportEvent {
+            store_path: store_path.clone(),
+            result: None,
+        }).await;
+        let (data, count) = match ev.result {
+            Some(result) => result?,
+            None => cmd_error!("No KVS store named '{}' exists.", store_path),
+        };
+        tokio::fs::write(&path, data).await?;
+        ctx.respond(&format!("Exported {} entries to {}.", count, path.display())).await?;
+        Ok(())
+    }
+}
+
+struct ImportCommand;
+impl ImportCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        if ctx.args_count() != 3 {
+            cmd_error!("Usage: import <store> <path>");
+        }
+        let store_path = ctx.arg(1).text.to_string();
+        let path = PathBuf::from(ctx.arg(2).text);
+        let data = tokio::fs::read(&path).await
+            .cmd_error(|| format!("Could not read '{}'.", path.display()))?;
+        let ev = ctx.handler().dispatch_async(KvsImportEvent {
+            store_path: store_path.clone(),
+            data: data.into(),
+            result: None,
+        }).await;
+        let count = match ev.result {
+            Some(result) => result?,
+            None => cmd_error!("No KVS store named '{}' exists.", store_path),
+        };
+        ctx.respond(&format!("Imported {} entries into '{}'.", count, store_path)).await?;
+        Ok(())
+    }
+}
+
+impl CommandImpl for ExportCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+impl CommandImpl for ImportCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `export` and `import` commands.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("export");
+    ev.register_command(Command::new(target, module, info, TerminalOnly(ExportCommand)));
+    let info = CommandInfo::new("import");
+    ev.register_command(Command::new(target, module, info, TerminalOnly(ImportCommand)));
+}
@@ -0,0 +1,204 @@
+//! Feature flags, which turn experimental behavior on or off without changing code.
+//!
+//! A flag is named by a string, and is off unless it has been enabled. Flags can be enabled or
+//! disabled for the whole bot, or for a single scope such as a server, with the most specific
+//! setting taking precedence in the same way as for configuration options. They are stored in
+//! the database, so they last across restarts and apply to every process sharing it.
+//!
+//! Flags are changed with the `flags` command, which can only be used by the bot's owners:
+//!
+//! ```text
+//! flags list
+//! flags enable <name> [scope]
+//! flags disable <name> [scope]
+//! flags reset <name> [scope]
+//! ```
+//!
+//! The scope is a scope type such as `server` or `channel`, and defaults to `global`. Scopes
+//! other than `global` are those of the channel the command is sent in, so they can only be
+//! changed by running the command through a connector, rather than from the terminal. Modules
+//! check flags with [`FeatureFlags::is_enabled`], and can react to changes by handling
+//! [`FlagChangedEvent`].
+
+use crate::config::{ConfigFlag, GLOBAL_SCOPE};
+use crate::kvs::*;
+use crate::serializable::*;
+use enumset::EnumSet;
+use futures::{FutureExt, StreamExt};
+use futures::future::BoxFuture;
+use serde::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, OwnerOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::derives::*;
+use sylphie_core::prelude::*;
+use sylphie_utils::scopes::{Scope, ScopedEvent};
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+struct FlagValue {
+    enabled: bool,
+}
+impl DbSerializable for FlagValue {
+    type Format = BincodeFormat;
+    const ID: &'static str = "sylphie_database::flags::FlagValue";
+    const SCHEMA_VERSION: u32 = 0;
+}
+
+/// Returns a name for a scope, as used in command responses.
+fn scope_name(scope: &Scope) -> String {
+    match ConfigFlag::for_scope(scope) {
+        Some(ConfigFlag::Global) => "global".to_string(),
+        Some(flag) => format!("{} {:?}", flag.name(), scope.args),
+        None => format!("{:?}", scope),
+    }
+}
+
+/// Dispatched after a feature flag is enabled, disabled or reset in a scope.
+#[derive(Clone, Debug)]
+pub struct FlagChangedEvent {
+    /// The name of the flag.
+    pub name: Arc<str>,
+    /// The scope the flag was changed in.
+    pub scope: Scope,
+    /// Whether the flag is now enabled or disabled in the scope, or `None` if it was reset.
+    pub enabled: Option<bool>,
+}
+simple_event!(FlagChangedEvent);
+impl ScopedEvent for FlagChangedEvent {
+    fn scopes(&self) -> &[Scope] {
+        std::slice::from_ref(&self.scope)
+    }
+}
+
+/// The store for feature flags.
+///
+/// This can be retrieved using `get_service`.
+#[derive(Module)]
+pub struct FeatureFlags {
+    #[module_info] info: ModuleInfo,
+    #[submodule] store: KvsStore<NamespacedKey<Scope>, FlagValue>,
+}
+impl FeatureFlags {
+    /// Returns whether a flag is enabled for the whole bot.
+    pub async fn is_enabled(&self, name: &str) -> Result<bool> {
+        self.is_enabled_in(name, &[]).await
+    }
+
+    /// Returns whether a flag is enabled in a context, such as the scopes of a command.
+    ///
+    /// The most specific scope the flag was set in is used, falling back to the global scope.
+    pub async fn is_enabled_in(&self, name: &str, scopes: &[Scope]) -> Result<bool> {
+        let order = crate::config::resolution_order(EnumSet::only(ConfigFlag::Any), scopes);
+        let values = self.store.namespace(name).get_many(&order).await?;
+        Ok(values.into_iter().flatten().next().map_or(false, |x| x.enabled))
+    }
+
+    /// Enables or disables a flag in a scope, or resets it if `enabled` is `None`, and
+    /// dispatches [`FlagChangedEvent`].
+    pub async fn set(
+        &self, target: &Handler<impl Events>, name: &str, scope: Scope, enabled: Option<bool>,
+    ) -> Result<()> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            cmd_error!("Flag names cannot be empty or contain spaces.");
+        }
+        let namespace = self.store.namespace(name);
+        match enabled {
+            Some(enabled) => namespace.set(scope.clone(), FlagValue { enabled }).await?,
+            None => namespace.remove(scope.clone()).await?,
+        }
+        target.dispatch_async(FlagChangedEvent { name: name.into(), scope, enabled }).await;
+        Ok(())
+    }
+
+    /// Returns every flag that is set in some scope, along with the scopes it is set in.
+    pub async fn list(&self) -> Result<BTreeMap<Arc<str>, Vec<(Scope, bool)>>> {
+        let mut flags = BTreeMap::<_, Vec<_>>::new();
+        let mut entries = Box::pin(self.store.iter());
+        while let Some(entry) = entries.next().await {
+            let (key, value) = entry?;
+            flags.entry(key.namespace).or_default().push((key.key, value.enabled));
+        }
+        Ok(flags)
+    }
+}
+
+struct FlagsCommand;
+impl FlagsCommand {
+    /// Finds the scope of a type in the current context, or the global scope by default.
+    fn target_scope(ctx: &CommandCtx<impl Events>, scope_type: Option<&str>) -> Result<Scope> {
+        let scope_type = match scope_type {
+            Some(x) => x.to_ascii_lowercase(),
+            None => return Ok(GLOBAL_SCOPE),
+        };
+        if scope_type == "global" {
+            return Ok(GLOBAL_SCOPE)
+        }
+        let found = ctx.scopes().iter().find(|x| {
+            ConfigFlag::for_scope(x).map_or(false, |flag| flag.name() == scope_type)
+        });
+        found.cloned().cmd_error(|| format!("There is no {} scope here.", scope_type))
+    }
+
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let target = ctx.handler();
+        let flags = target.get_service::<FeatureFlags>();
+        let action = match ctx.args_count() {
+            1 => "list".to_string(),
+            _ => ctx.arg(1).text.to_ascii_lowercase(),
+        };
+        match action.as_str() {
+            "list" => {
+                let list = flags.list().await?;
+                if list.is_empty() {
+                    ctx.respond("No feature flags are set.").await?;
+                }
+                for (name, scopes) in list {
+                    let scopes: Vec<_> = scopes.iter().map(|(scope, enabled)| {
+                        let state = if *enabled { "enabled" } else { "disabled" };
+                        format!("{} in {}", state, scope_name(scope))
+                    }).collect();
+                    ctx.respond(&format!("* {}: {}", name, scopes.join(", "))).await?;
+                }
+            }
+            "enable" | "disable" | "reset" => {
+                let (name, scope_type) = match ctx.args_count() {
+                    3 => (ctx.arg(2).text, None),
+                    4 => (ctx.arg(2).text, Some(ctx.arg(3).text)),
+                    _ => cmd_error!("Usage: flags {} <name> [scope]", action),
+                };
+                let scope = Self::target_scope(ctx, scope_type)?;
+                let enabled = match action.as_str() {
+                    "enable" => Some(true),
+                    "disable" => Some(false),
+                    _ => None,
+                };
+                flags.set(target, name, scope.clone(), enabled).await?;
+                let verb = match enabled {
+                    Some(true) => "Enabled",
+                    Some(false) => "Disabled",
+                    None => "Reset",
+                };
+                ctx.respond(&format!("{} '{}' in {}.", verb, name, scope_name(&scope))).await?;
+            }
+            _ => cmd_error!("Usage: flags [list | enable <name> | disable <name> | reset <name>]"),
+        }
+        Ok(())
+    }
+}
+impl CommandImpl for FlagsCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `flags` command.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("flags");
+    ev.register_command(Command::new(target, module, info, OwnerOnly(FlagsCommand)));
+}
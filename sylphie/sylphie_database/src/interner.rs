@@ -13,7 +13,7 @@ use sylphie_utils::locks::LockSet;
 use sylphie_utils::scopes::Scope;
 use sylphie_utils::strings::InternString;
 
-static INTERNER_MIGRATIONS: MigrationData = MigrationData {
+pub(crate) static INTERNER_MIGRATIONS: MigrationData = MigrationData {
     migration_id: "interner b7a62621-ae52-4247-bda6-49d297de20d9",
     migration_set_name: "interner",
     is_transient: false,
@@ -52,7 +52,7 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
         })
     }
 
-    async fn intern_query(&self, conn: &mut DbConnection, value: T) -> Result<u64> {
+    async fn intern_query(&self, conn: &mut DbOps, value: T) -> Result<u64> {
         self.cache.cached_async(value.clone(), async {
             let result: Option<u64> = conn.query_row(
                 "SELECT int_id FROM sylphie_db_interner WHERE hive = ? AND name = ?;",
@@ -61,7 +61,7 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
             Ok(result.unwrap_or(0))
         }).await
     }
-    async fn intern(&self, conn: &mut DbConnection, value: T) -> Result<u64> {
+    async fn intern(&self, conn: &mut DbOps, value: T) -> Result<u64> {
         let id = self.intern_query(conn, value.clone()).await?;
         if id == 0 {
             let _guard = self.new_value_lock.lock(value.clone()).await;
@@ -82,7 +82,7 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
         }
     }
     async fn rev_intern(
-        &self, conn: &mut DbConnection, value: u64, intern: impl FnOnce(T) -> T,
+        &self, conn: &mut DbOps, value: u64, intern: impl FnOnce(T) -> T,
     ) -> Result<T> {
         self.rev_cache.cached_async(value.clone(), async {
             let result: SerializeValue = conn.query_row(
@@ -103,17 +103,17 @@ pub struct InternerLock {
     data: Arc<InternerData>,
 }
 impl InternerLock {
-    pub async fn get_scope_id(&self, conn: &mut DbConnection, name: Scope) -> Result<ScopeId> {
+    pub async fn get_scope_id(&self, conn: &mut DbOps, name: Scope) -> Result<ScopeId> {
         Ok(ScopeId(self.data.hive_scopes.intern(conn, name.intern()).await?))
     }
-    pub async fn get_scope_id_rev(&self, conn: &mut DbConnection, id: ScopeId) -> Result<Scope> {
+    pub async fn get_scope_id_rev(&self, conn: &mut DbOps, id: ScopeId) -> Result<Scope> {
         self.data.hive_scopes.rev_intern(conn, id.0, |x| x.intern()).await
     }
 
-    pub async fn get_str_id(&self, conn: &mut DbConnection, str: &str) -> Result<StringId> {
+    pub async fn get_str_id(&self, conn: &mut DbOps, str: &str) -> Result<StringId> {
         Ok(StringId(self.data.hive_other.intern(conn, str.intern()).await?))
     }
-    pub async fn get_str_id_rev(&self, conn: &mut DbConnection, id: StringId) -> Result<Arc<str>> {
+    pub async fn get_str_id_rev(&self, conn: &mut DbOps, id: StringId) -> Result<Arc<str>> {
         self.data.hive_other.rev_intern(conn, id.0, |x| x.intern()).await
     }
 }
@@ -130,6 +130,34 @@ impl Interner {
     }
 }
 
+/// The [`SerializationFormat`] used for interned IDs, which stores them as plain integers.
+pub enum InternedIdFormat { }
+macro_rules! interned_id_format {
+    ($($ty:ident => $id:literal),* $(,)?) => {$(
+        impl SerializationFormat<$ty> for InternedIdFormat {
+            fn serialize(val: &$ty) -> Result<SerializeValue> {
+                Ok(SerializeValue::Integer(val.0 as i64))
+            }
+            fn deserialize(val: SerializeValue) -> Result<$ty> {
+                Ok($ty(val.into_u64()?))
+            }
+        }
+        impl DbSerializable for $ty {
+            type Format = InternedIdFormat;
+            const ID: &'static str = $id;
+            const SCHEMA_VERSION: u32 = 0;
+        }
+    )*};
+}
+interned_id_format! {
+    StringId => "sylphie_database::interner::StringId",
+    ScopeId => "sylphie_database::interner::ScopeId",
+}
+
+/// A compact integer ID standing in for a string, such as a user ID.
+///
+/// The same string is always given the same ID, so these can be used as keys in KVS stores in
+/// place of long identifiers, taking a fraction of the space in large tables.
 #[derive(Serialize, Deserialize)]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug)]
 #[serde(transparent)]
@@ -151,6 +179,9 @@ impl StringId {
     }
 }
 
+/// A compact integer ID standing in for a [`Scope`].
+///
+/// Like [`StringId`], this can be used as a KVS key in place of the full scope path.
 #[derive(Serialize, Deserialize)]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug)]
 #[serde(transparent)]
@@ -173,8 +204,6 @@ impl ScopeId {
 }
 
 pub(crate) async fn init_interner(target: &Handler<impl Events>) -> Result<()> {
-    INTERNER_MIGRATIONS.execute(target).await?;
-
     let mut conn = target.connect_db().await?;
 
     let hive_scopes = InternerHive::from_db(HiveId::Scopes, &mut conn).await?;
@@ -1,23 +1,38 @@
 use arc_swap::*;
+use bincode::Options;
 use crate::connection::*;
 use crate::migrations::*;
 use crate::interner::*;
 use crate::serializable::*;
+use futures::{stream, Stream, StreamExt};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use static_events::prelude_async::*;
-use std::collections::{HashMap, HashSet};
+use std::any::Any;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::Hash;
+use std::io::{BufRead, Write};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use sylphie_core::core::ShutdownEvent;
 use sylphie_core::derives::*;
 use sylphie_core::prelude::*;
 use sylphie_utils::cache::LruCache;
 use sylphie_utils::locks::{LockSet, LockSetGuard};
+use tokio::sync::mpsc::{self, UnboundedSender};
 
 mod private {
     pub trait Sealed: 'static {
         const IS_TRANSIENT: bool;
     }
+    pub trait SealedIntegral: 'static {
+        /// Adds `delta` to a value as it is stored in the database, returning `None` if the
+        /// result does not fit in the type.
+        fn checked_incr(stored: i64, delta: i64) -> Option<i64>;
+    }
 }
 
 /// A marker trait for a type of KVS store.
@@ -37,6 +52,138 @@ impl private::Sealed for TransientKvsType {
 }
 impl KvsType for TransientKvsType { }
 
+/// A marker trait for integer values that can be incremented with [`BaseKvsStore::incr`].
+pub trait KvsIntegral: DbSerializable + private::SealedIntegral { }
+macro_rules! kvs_integral {
+    ($($ty:ident)*) => {$(
+        impl private::SealedIntegral for $ty {
+            fn checked_incr(stored: i64, delta: i64) -> Option<i64> {
+                let value = (stored as $ty as i128).checked_add(delta as i128)?;
+                let value = <$ty as std::convert::TryFrom<i128>>::try_from(value).ok()?;
+                Some(value as i64)
+            }
+        }
+        impl KvsIntegral for $ty { }
+    )*};
+}
+kvs_integral!(u8 u16 u32 u64 usize i8 i16 i32 i64 isize);
+
+/// A field of a value that is indexed when it is stored in a KVS store.
+///
+/// Indexes are declared with [`DbSerializable::kvs_indexes`]. Each index is stored in an
+/// auxiliary table, which allows [`BaseKvsStore::find_by_index`] to find entries without
+/// loading the entire store.
+pub struct KvsIndex<V> {
+    name: &'static str,
+    extract: Box<dyn Fn(&V) -> Result<SerializeValue> + Send + Sync>,
+}
+impl <V: DbSerializable> KvsIndex<V> {
+    /// Creates a new index, given its name and a function that returns the indexed field.
+    ///
+    /// The name may only contain ASCII letters, digits and underscores.
+    pub fn new<F: DbSerializable>(
+        name: &'static str, extract: impl Fn(&V) -> F + Send + Sync + 'static,
+    ) -> Self {
+        KvsIndex { name, extract: Box::new(move |v| F::Format::serialize(&extract(v))) }
+    }
+
+    /// Converts this into an index on a wrapper type containing the indexed type.
+    pub(crate) fn wrap<W: 'static>(self, unwrap: fn(&W) -> &V) -> KvsIndex<W> {
+        let extract = self.extract;
+        KvsIndex { name: self.name, extract: Box::new(move |w| extract(unwrap(w))) }
+    }
+}
+
+/// Dispatched after a value in a KVS store is written or removed.
+///
+/// This is dispatched from a background task shortly after the write, and is not dispatched for
+/// writes made directly to the store's table with SQL.
+#[derive(Clone, Debug)]
+pub struct KvsChangedEvent {
+    /// The module path of the store that was changed.
+    pub store_path: Arc<str>,
+    /// The serialized key that was changed.
+    pub key: SerializeValue,
+}
+self_event!(KvsChangedEvent);
+impl KvsChangedEvent {
+    /// Deserializes the key that was changed.
+    pub fn key<K: DbSerializable>(&self) -> Result<K> {
+        K::Format::deserialize(self.key.clone())
+    }
+}
+
+/// Dispatched by the `export` command to export a KVS store by its module path.
+pub(crate) struct KvsExportEvent {
+    pub store_path: String,
+    pub result: Option<Result<(Vec<u8>, usize)>>,
+}
+simple_event!(KvsExportEvent);
+
+/// Dispatched by the `import` command to import into a KVS store by its module path.
+pub(crate) struct KvsImportEvent {
+    pub store_path: String,
+    pub data: Arc<[u8]>,
+    pub result: Option<Result<usize>>,
+}
+simple_event!(KvsImportEvent);
+
+#[derive(Serialize)]
+struct ExportedEntry<'a, K, V> {
+    key: &'a K,
+    value: &'a V,
+}
+
+#[derive(Deserialize)]
+struct ImportedEntry<K, V> {
+    key: K,
+    value: V,
+}
+
+/// Dispatched to discard values cached in memory by KVS stores.
+///
+/// Every KVS store keeps recently used values in memory, and updates them as it writes to the
+/// database. Changes made to its table by anything else, such as another bot process sharing the
+/// database, are not seen until this event is dispatched.
+pub struct InvalidateKvsCacheEvent {
+    module: Option<String>,
+    keys: Option<Vec<SerializeValue>>,
+}
+simple_event!(InvalidateKvsCacheEvent);
+impl InvalidateKvsCacheEvent {
+    /// Invalidates every value cached by every KVS store.
+    pub fn all() -> Self {
+        InvalidateKvsCacheEvent { module: None, keys: None }
+    }
+
+    /// Invalidates every value cached by the KVS store with the given module name.
+    pub fn store(module: impl Into<String>) -> Self {
+        InvalidateKvsCacheEvent { module: Some(module.into()), keys: None }
+    }
+
+    /// Invalidates the given keys in the KVS store with the given module name.
+    pub fn keys<K: DbSerializable>(module: impl Into<String>, keys: &[K]) -> Result<Self> {
+        let keys = keys.iter().map(K::Format::serialize).collect::<Result<_>>()?;
+        Ok(InvalidateKvsCacheEvent { module: Some(module.into()), keys: Some(keys) })
+    }
+}
+
+/// The current version of the schema used for the tables of KVS stores.
+const KVS_SCHEMA_VERSION: u32 = 1;
+
+/// The maximum number of keys loaded by a single query in [`BaseKvsStore::get_many`].
+const KVS_BATCH_SIZE: usize = 256;
+
+/// How often expired entries are deleted from KVS stores.
+const KVS_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often writes to transient KVS stores are flushed to the database.
+const KVS_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
 #[derive(Eq, PartialEq, Hash)]
 struct KvsTarget {
     module_path: String,
@@ -54,6 +201,7 @@ struct InitKvsEvent {
     used_table_names: HashSet<String>,
 
     module_metadata: HashMap<KvsTarget, KvsMetadata>,
+    db: Database,
     conn: DbConnection,
 }
 failable_self_event!(InitKvsEvent, Error);
@@ -146,30 +294,33 @@ impl InitKvsEvent {
         debug!("Creating table for KVS store '{}'...", table_name);
 
         let str_id = interner.get_str_id(&mut self.conn, key_id).await?;
-        let mut transaction = self.conn.transaction_with_type(TransactionType::Exclusive).await?;
         let target_transient = if is_transient { "transient." } else { "" };
-        transaction.execute_batch(format!(
-            "CREATE TABLE {}{} (\
-                key BLOB PRIMARY KEY, \
-                value BLOB NOT NULL, \
-                value_schema_id INTEGER NOT NULL, \
-                value_schema_ver INTEGER NOT NULL \
-            )",
-            target_transient, table_name,
-        )).await?;
-        transaction.execute(
-            format!(
-                "INSERT INTO {}sylphie_db_kvs_info \
-                     (module_path, table_name, kvs_schema_version, key_id, key_version)\
-                 VALUES (?, ?, ?, ?, ?)",
-                target_transient,
-            ),
-            (
-                module_path.clone(), table_name.clone(), 0u32,
-                str_id, key_version,
-            ),
-        ).await?;
-        transaction.commit().await?;
+        self.db.retry_serializable(|mut conn| {
+            let (module_path, table_name) = (module_path.clone(), table_name.clone());
+            async move {
+                let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+                transaction.execute_batch(format!(
+                    "CREATE TABLE {}{} (\
+                        key BLOB PRIMARY KEY, \
+                        value BLOB NOT NULL, \
+                        value_schema_id INTEGER NOT NULL, \
+                        value_schema_ver INTEGER NOT NULL, \
+                        expires_at INTEGER \
+                    )",
+                    target_transient, table_name,
+                )).await?;
+                transaction.execute(
+                    format!(
+                        "INSERT INTO {}sylphie_db_kvs_info \
+                             (module_path, table_name, kvs_schema_version, key_id, key_version)\
+                         VALUES (?, ?, ?, ?, ?)",
+                        target_transient,
+                    ),
+                    (module_path, table_name, KVS_SCHEMA_VERSION, str_id, key_version),
+                ).await?;
+                transaction.commit().await
+            }
+        }).await?;
 
         self.used_table_names.insert(table_name.to_string());
         self.module_metadata.insert(
@@ -193,10 +344,13 @@ impl InitKvsEvent {
             ),
         ).await?;
         for (module_path, table_name, schema_version, key_id, key_version) in values {
-            assert_eq!(
-                schema_version, 0u32,
+            assert!(
+                schema_version <= KVS_SCHEMA_VERSION,
                 "This database was created with a future version of Sylphie.",
             );
+            if schema_version == 0 {
+                self.upgrade_kvs_table_0_to_1(&table_name, is_transient).await?;
+            }
             self.used_table_names.insert(table_name.clone());
             self.module_metadata.insert(
                 KvsTarget { module_path, is_transient },
@@ -205,6 +359,33 @@ impl InitKvsEvent {
         }
         Ok(())
     }
+
+    async fn upgrade_kvs_table_0_to_1(
+        &mut self, table_name: &str, is_transient: bool,
+    ) -> Result<()> {
+        debug!("Upgrading table for KVS store '{}'...", table_name);
+
+        let target_transient = if is_transient { "transient." } else { "" };
+        self.db.retry_serializable(|mut conn| {
+            let table_name = table_name.to_string();
+            async move {
+                let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+                transaction.execute_batch(format!(
+                    "ALTER TABLE {}{} ADD COLUMN expires_at INTEGER;",
+                    target_transient, table_name,
+                )).await?;
+                transaction.execute(
+                    format!(
+                        "UPDATE {}sylphie_db_kvs_info SET kvs_schema_version = ? \
+                         WHERE table_name = ?",
+                        target_transient,
+                    ),
+                    (KVS_SCHEMA_VERSION, table_name),
+                ).await?;
+                transaction.commit().await
+            }
+        }).await
+    }
 }
 
 struct InitKvsLate {
@@ -212,7 +393,7 @@ struct InitKvsLate {
 }
 failable_event!(InitKvsLate, (), Error);
 
-static PERSISTENT_KVS_MIGRATIONS: MigrationData = MigrationData {
+pub(crate) static PERSISTENT_KVS_MIGRATIONS: MigrationData = MigrationData {
     migration_id: "kvs persistent ebc80f22-f8e8-4c0f-b09c-6fd12e3c853b",
     migration_set_name: "kvs_persistent",
     is_transient: false,
@@ -221,7 +402,7 @@ static PERSISTENT_KVS_MIGRATIONS: MigrationData = MigrationData {
         migration_script!(0, 1, "sql/kvs_persistent_0_to_1.sql"),
     ],
 };
-static TRANSIENT_KVS_MIGRATIONS: MigrationData = MigrationData {
+pub(crate) static TRANSIENT_KVS_MIGRATIONS: MigrationData = MigrationData {
     migration_id: "kvs transient e9031b35-e448-444d-b161-e75245b30bd8",
     migration_set_name: "kvs_transient",
     is_transient: true,
@@ -231,14 +412,12 @@ static TRANSIENT_KVS_MIGRATIONS: MigrationData = MigrationData {
     ],
 };
 pub(crate) async fn init_kvs(target: &Handler<impl Events>) -> Result<()> {
-    PERSISTENT_KVS_MIGRATIONS.execute(target).await?;
-    TRANSIENT_KVS_MIGRATIONS.execute(target).await?;
-
     // initialize the state for init KVS
     let mut event = InitKvsEvent {
         found_modules: Default::default(),
         used_table_names: Default::default(),
         module_metadata: HashMap::new(),
+        db: target.get_service::<Database>().clone(),
         conn: target.connect_db().await?,
     };
 
@@ -275,6 +454,8 @@ pub(crate) async fn init_kvs(target: &Handler<impl Events>) -> Result<()> {
 
 struct BaseKvsStoreInfo {
     db: Database,
+    name: Arc<str>,
+    changes: UnboundedSender<KvsChangedEvent>,
     interner: InternerLock,
     value_id: StringId,
     queries: KvsStoreQueries,
@@ -283,6 +464,7 @@ impl BaseKvsStoreInfo {
     async fn new<'a>(
         target: &'a Handler<impl Events>,
         module: &'a str, is_transient: bool, late: &'a InitKvsLate, value_id: &'static str,
+        indexes: &'a [&'static str], changes: UnboundedSender<KvsChangedEvent>,
     ) -> Result<Self> {
         let metadata = late.module_metadata.get(&KvsTarget {
             module_path: module.to_string(),
@@ -290,89 +472,526 @@ impl BaseKvsStoreInfo {
         }).unwrap();
         let interner = target.get_service::<Interner>().lock();
         let value_id = StringId::intern(target, value_id).await?;
+        let db = target.get_service::<Database>().clone();
         Ok(BaseKvsStoreInfo {
-            db: target.get_service::<Database>().clone(),
-            interner,
-            value_id,
-            queries: KvsStoreQueries::new(&format!(
+            queries: KvsStoreQueries::new(db.dialect(), &format!(
                 "{}{}",
                 if is_transient { "transient." } else { "" },
                 metadata.table_name,
-            )),
+            ), indexes),
+            db,
+            name: module.into(),
+            changes,
+            interner,
+            value_id,
         })
     }
 }
 
 struct KvsStoreQueries {
+    table_name: Arc<str>,
     store_query: Arc<str>,
     delete_query: Arc<str>,
     load_query: Arc<str>,
+    sweep_query: Arc<str>,
+    incr_query: Arc<str>,
+    load_raw_query: Arc<str>,
+    count_query: Arc<str>,
+    load_all_query: Arc<str>,
+    indexes: Vec<KvsIndexQueries>,
 }
 impl KvsStoreQueries {
-    fn new(table_name: &str) -> Self {
+    fn new(dialect: SqlDialect, table_name: &str, indexes: &[&'static str]) -> Self {
         KvsStoreQueries {
-            store_query: format!(
-                "REPLACE INTO {} (key, value, value_schema_id, value_schema_ver) \
-                 VALUES (?, ?, ?, ?)",
-                table_name,
+            table_name: table_name.into(),
+            store_query: dialect.upsert(
+                table_name, &["key"],
+                &["key", "value", "value_schema_id", "value_schema_ver", "expires_at"],
             ).into(),
             delete_query: format!("DELETE FROM {} WHERE key = ?;", table_name).into(),
             load_query: format!(
-                "SELECT value, value_schema_id, value_schema_ver FROM {} WHERE key = ?;",
+                "SELECT value, value_schema_id, value_schema_ver, expires_at FROM {} \
+                 WHERE key = ? AND (expires_at IS NULL OR expires_at > ?);",
+                table_name,
+            ).into(),
+            sweep_query: format!(
+                "DELETE FROM {} WHERE expires_at IS NOT NULL AND expires_at <= ?;", table_name,
+            ).into(),
+            incr_query: format!(
+                "UPDATE {} SET value = value + ? \
+                 WHERE key = ? AND value_schema_id = ? AND value_schema_ver = ? \
+                 AND (expires_at IS NULL OR expires_at > ?);",
                 table_name,
             ).into(),
+            load_raw_query: format!(
+                "SELECT value, expires_at FROM {} WHERE key = ?;", table_name,
+            ).into(),
+            count_query: format!("SELECT COUNT(*) FROM {};", table_name).into(),
+            load_all_query: format!(
+                "SELECT key, value, value_schema_id, value_schema_ver FROM {};", table_name,
+            ).into(),
+            indexes: indexes.iter()
+                .map(|x| KvsIndexQueries::new(dialect, table_name, *x))
+                .collect(),
+        }
+    }
+
+    fn find_index(&self, name: &str) -> Result<&KvsIndexQueries> {
+        match self.indexes.iter().find(|x| x.name == name) {
+            Some(index) => Ok(index),
+            None => bail!("KVS store has no index named '{}'.", name),
+        }
+    }
+
+    /// Creates the tables for each index, returning the indexes that must be rebuilt.
+    async fn create_indexes(&self, conn: &mut DbOps) -> Result<Vec<&KvsIndexQueries>> {
+        let mut needs_rebuild = Vec::new();
+        if self.indexes.is_empty() {
+            return Ok(needs_rebuild)
+        }
+
+        let count: usize = conn.query_row_nullary(self.count_query.clone()).await?.unwrap_or(0);
+        for index in &self.indexes {
+            conn.execute_batch(index.create_query.clone()).await?;
+            let index_count: usize =
+                conn.query_row_nullary(index.count_query.clone()).await?.unwrap_or(0);
+            // every entry has exactly one row in each index, so if the counts differ, the index
+            // is new or has become out of date.
+            if index_count != count {
+                needs_rebuild.push(index);
+            }
         }
+        Ok(needs_rebuild)
     }
 
     async fn store_value<K: DbSerializable, V: DbSerializable>(
-        &self, conn: &mut DbConnection, key: &K, value: &V, value_schema_id: StringId,
+        &self, conn: &mut DbOps, key: &K, value: &V, value_schema_id: StringId,
+        expires_at: Option<i64>, index_values: &[SerializeValue],
     ) -> Result<()> {
+        let key = K::Format::serialize(key)?;
         conn.execute(
             self.store_query.clone(),
             (
-                K::Format::serialize(key)?,
+                key.clone(),
                 V::Format::serialize(value)?,
                 value_schema_id, V::SCHEMA_VERSION,
+                expires_at,
             ),
         ).await?;
+        for (index, value) in self.indexes.iter().zip(index_values) {
+            conn.execute(index.store_query.clone(), (key.clone(), value.clone())).await?;
+        }
         Ok(())
     }
+    async fn incr_value<K: DbSerializable, V: KvsIntegral>(
+        &self, conn: &mut DbOps, key: &K, delta: i64, value_schema_id: StringId,
+    ) -> Result<Option<(V, Option<i64>)>> {
+        let changed = conn.execute(
+            self.incr_query.clone(),
+            (delta, K::Format::serialize(key)?, value_schema_id, V::SCHEMA_VERSION, now_millis()),
+        ).await?;
+        if changed == 0 {
+            return Ok(None)
+        }
+        let (value, expires_at): (SerializeValue, Option<i64>) = conn.query_row(
+            self.load_raw_query.clone(),
+            K::Format::serialize(key)?,
+        ).await?.internal_err(|| "Incremented value is missing.")?;
+        // SQLite turns integers that overflow into floats, and narrower types can overflow
+        // without overflowing an `i64`. in either case, the caller drops the transaction
+        // without committing it, so the value is left as it was.
+        let is_valid = match &value {
+            &SerializeValue::Integer(new) =>
+                V::checked_incr(new.wrapping_sub(delta), delta) == Some(new),
+            _ => false,
+        };
+        if !is_valid {
+            cmd_error!("The value is too large or too small to add {} to.", delta);
+        }
+        Ok(Some((V::Format::deserialize(value)?, expires_at)))
+    }
+    async fn sweep_expired(&self, conn: &mut DbConnection) -> Result<usize> {
+        let now = now_millis();
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        for index in &self.indexes {
+            transaction.execute(index.sweep_query.clone(), now).await?;
+        }
+        let count = transaction.execute(self.sweep_query.clone(), now).await?;
+        transaction.commit().await?;
+        Ok(count)
+    }
     async fn delete_value<K: DbSerializable>(
-        &self, conn: &mut DbConnection, key: &K,
+        &self, conn: &mut DbOps, key: &K,
     ) -> Result<()> {
-        conn.execute(
-            self.delete_query.clone(),
-            K::Format::serialize(key)?,
-        ).await?;
+        let key = K::Format::serialize(key)?;
+        for index in &self.indexes {
+            conn.execute(index.delete_query.clone(), key.clone()).await?;
+        }
+        conn.execute(self.delete_query.clone(), key).await?;
         Ok(())
     }
     async fn load_value<'a, K: DbSerializable, V: DbSerializable>(
-        &'a self, conn: &'a mut DbConnection, key: &K, store_info: &'a BaseKvsStoreInfo,
+        &'a self, conn: &'a mut DbOps, key: &K, store_info: &'a BaseKvsStoreInfo,
         value_schema_id: StringId, is_migration_mandatory: bool,
-    ) -> Result<Option<V>> {
-        let result: Option<(SerializeValue, StringId, u32)> = conn.query_row(
+    ) -> Result<CachedValue<V>> {
+        let result: Option<(SerializeValue, StringId, u32, Option<i64>)> = conn.query_row(
             self.load_query.clone(),
-            K::Format::serialize(key)?,
+            (K::Format::serialize(key)?, now_millis()),
         ).await?;
-        if let Some((value, schema_id, schema_ver)) = result {
-            if schema_id == value_schema_id && V::SCHEMA_VERSION == schema_ver {
-                Ok(Some(V::Format::deserialize(value)?))
+        if let Some((value, schema_id, schema_ver, expires_at)) = result {
+            let row = (value, schema_id, schema_ver);
+            let value = Self::decode_value(
+                conn, store_info, value_schema_id, row, is_migration_mandatory,
+            ).await?;
+            Ok(CachedValue { value, expires_at })
+        } else {
+            Ok(CachedValue { value: None, expires_at: None })
+        }
+    }
+    async fn load_many<'a, K: DbSerializable + Hash + Eq, V: DbSerializable>(
+        &'a self, conn: &'a mut DbOps, keys: &'a [K], store_info: &'a BaseKvsStoreInfo,
+        value_schema_id: StringId, is_migration_mandatory: bool,
+    ) -> Result<HashMap<K, CachedValue<V>>> {
+        let mut params = Vec::with_capacity(keys.len() + 1);
+        for key in keys {
+            params.push(K::Format::serialize(key)?);
+        }
+        params.push(SerializeValue::Integer(now_millis()));
+
+        let sql = format!(
+            "SELECT key, value, value_schema_id, value_schema_ver, expires_at FROM {} \
+             WHERE key IN ({}) AND (expires_at IS NULL OR expires_at > ?);",
+            self.table_name, vec!["?"; keys.len()].join(", "),
+        );
+        let rows: Vec<(SerializeValue, SerializeValue, StringId, u32, Option<i64>)> =
+            conn.query_vec(sql, params).await?;
+
+        let mut result = HashMap::new();
+        for (key, value, schema_id, schema_ver, expires_at) in rows {
+            let row = (value, schema_id, schema_ver);
+            let value = Self::decode_value(
+                conn, store_info, value_schema_id, row, is_migration_mandatory,
+            ).await?;
+            result.insert(K::Format::deserialize(key)?, CachedValue { value, expires_at });
+        }
+        Ok(result)
+    }
+    async fn decode_value<'a, V: DbSerializable>(
+        conn: &'a mut DbOps, store_info: &'a BaseKvsStoreInfo, value_schema_id: StringId,
+        (value, schema_id, schema_ver): (SerializeValue, StringId, u32),
+        is_migration_mandatory: bool,
+    ) -> Result<Option<V>> {
+        if schema_id == value_schema_id && V::SCHEMA_VERSION == schema_ver {
+            Ok(Some(V::Format::deserialize(value)?))
+        } else {
+            let schema_name = store_info.interner.get_str_id_rev(conn, schema_id).await?;
+            if V::can_migrate_from(&schema_name, schema_ver) {
+                Ok(Some(V::do_migration(&schema_name, schema_ver, value)?))
+            } else if !is_migration_mandatory {
+                Ok(None)
             } else {
-                let schema_name = store_info.interner.get_str_id_rev(conn, schema_id).await?;
-                if V::can_migrate_from(&schema_name, schema_ver) {
-                    Ok(Some(V::do_migration(&schema_name, schema_ver, value)?))
-                } else if !is_migration_mandatory {
-                    Ok(None)
-                } else {
-                    bail!(
-                        "Could not migrate value to current schema version! ({}:{} -> {}:{})",
-                        schema_name, schema_ver, V::ID, V::SCHEMA_VERSION,
-                    );
-                }
+                bail!(
+                    "Could not migrate value to current schema version! ({}:{} -> {}:{})",
+                    schema_name, schema_ver, V::ID, V::SCHEMA_VERSION,
+                );
             }
+        }
+    }
+
+    fn scan_query(&self, with_values: bool, bound: ScanBound) -> String {
+        format!(
+            "SELECT key{} FROM {} WHERE {}(expires_at IS NULL OR expires_at > ?) \
+             ORDER BY key LIMIT {};",
+            if with_values { ", value, value_schema_id, value_schema_ver" } else { "" },
+            self.table_name,
+            match bound {
+                ScanBound::None => "",
+                ScanBound::From => "key >= ? AND ",
+                ScanBound::After => "key > ? AND ",
+            },
+            SCAN_PAGE_SIZE,
+        )
+    }
+}
+
+/// The queries used to maintain and search a single index on a KVS store.
+struct KvsIndexQueries {
+    name: &'static str,
+    create_query: Arc<str>,
+    count_query: Arc<str>,
+    store_query: Arc<str>,
+    delete_query: Arc<str>,
+    clear_query: Arc<str>,
+    sweep_query: Arc<str>,
+    find_query: Arc<str>,
+}
+impl KvsIndexQueries {
+    fn new(dialect: SqlDialect, table_name: &str, name: &'static str) -> Self {
+        let index_table = format!("{}_idx_{}", table_name, name);
+        let unqualified = match index_table.find('.') {
+            Some(i) => &index_table[i + 1..],
+            None => &index_table,
+        };
+        KvsIndexQueries {
+            name,
+            create_query: format!(
+                "CREATE TABLE IF NOT EXISTS {} (\
+                    key BLOB PRIMARY KEY, \
+                    idx_value BLOB NOT NULL \
+                ) WITHOUT ROWID; {}",
+                index_table,
+                dialect.create_index(
+                    &format!("{}_value", unqualified), &index_table, &["idx_value"],
+                ),
+            ).into(),
+            count_query: format!("SELECT COUNT(*) FROM {};", index_table).into(),
+            store_query: dialect.upsert(&index_table, &["key"], &["key", "idx_value"]).into(),
+            delete_query: format!("DELETE FROM {} WHERE key = ?;", index_table).into(),
+            clear_query: format!("DELETE FROM {};", index_table).into(),
+            sweep_query: format!(
+                "DELETE FROM {} WHERE key IN (\
+                    SELECT key FROM {} WHERE expires_at IS NOT NULL AND expires_at <= ?\
+                );",
+                index_table, table_name,
+            ).into(),
+            find_query: format!(
+                "SELECT t.key, t.value, t.value_schema_id, t.value_schema_ver \
+                 FROM {} i JOIN {} t ON i.key = t.key \
+                 WHERE i.idx_value = ? AND (t.expires_at IS NULL OR t.expires_at > ?) \
+                 ORDER BY t.key;",
+                index_table, table_name,
+            ).into(),
+        }
+    }
+}
+
+fn is_valid_index_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A value loaded from a KVS store, along with the time it expires at.
+#[derive(Clone)]
+struct CachedValue<V> {
+    value: Option<V>,
+    expires_at: Option<i64>,
+}
+impl <V: Clone> CachedValue<V> {
+    fn get(&self) -> Option<V> {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now_millis() => None,
+            _ => self.value.clone(),
+        }
+    }
+}
+
+/// A write to a transient KVS store that has not yet been flushed to the database.
+#[derive(Clone)]
+struct PendingWrite<V> {
+    seq: u64,
+    value: CachedValue<V>,
+    index_values: Vec<SerializeValue>,
+}
+
+/// Writes to a transient KVS store waiting to be flushed to the database.
+///
+/// Only the latest write to each key is kept, so a key written many times between flushes is
+/// only written to the database once.
+struct PendingWrites<K: Hash + Eq, V> {
+    entries: Mutex<HashMap<K, PendingWrite<V>>>,
+    next_seq: AtomicU64,
+    flush_lock: tokio::sync::Mutex<()>,
+}
+impl <K: DbSerializable + Hash + Eq, V: DbSerializable> PendingWrites<K, V> {
+    fn new() -> Self {
+        PendingWrites {
+            entries: Mutex::new(HashMap::new()),
+            next_seq: AtomicU64::new(0),
+            flush_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn push(&self, k: K, value: CachedValue<V>, index_values: Vec<SerializeValue>) {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.entries.lock().insert(k, PendingWrite { seq, value, index_values });
+    }
+
+    fn get(&self, k: &K) -> Option<CachedValue<V>> {
+        self.entries.lock().get(k).map(|x| x.value.clone())
+    }
+
+    /// Writes every pending value to the database in a single transaction.
+    ///
+    /// Entries are only removed once the transaction commits, so a read that misses both the
+    /// cache and this map always finds the latest value in the table.
+    async fn flush(&self, data: &BaseKvsStoreInfo) -> Result<()> {
+        let _guard = self.flush_lock.lock().await;
+        if self.entries.lock().is_empty() {
+            return Ok(())
+        }
+
+        let mut conn = data.db.connect().await?;
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        let entries = self.write_all(&mut transaction, data).await?;
+        transaction.commit().await?;
+        self.remove_written(entries);
+        Ok(())
+    }
+
+    /// Writes every pending value using an existing connection, returning the writes made.
+    ///
+    /// The caller must hold `flush_lock` until the writes are committed, and then pass them to
+    /// [`remove_written`](Self::remove_written).
+    async fn write_all(
+        &self, conn: &mut DbOps, data: &BaseKvsStoreInfo,
+    ) -> Result<Vec<(K, PendingWrite<V>)>> {
+        let entries: Vec<(K, PendingWrite<V>)> =
+            self.entries.lock().iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        for (k, write) in &entries {
+            match &write.value.value {
+                Some(v) => data.queries.store_value(
+                    conn, k, v, data.value_id, write.value.expires_at, &write.index_values,
+                ).await?,
+                None => data.queries.delete_value(conn, k).await?,
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Removes committed writes, unless the key was written again in the meantime.
+    fn remove_written(&self, entries: Vec<(K, PendingWrite<V>)>) {
+        let mut pending = self.entries.lock();
+        for (k, write) in entries {
+            if pending.get(&k).map_or(false, |x| x.seq == write.seq) {
+                pending.remove(&k);
+            }
+        }
+    }
+}
+
+/// The number of rows loaded at once when iterating over a KVS store.
+const SCAN_PAGE_SIZE: usize = 64;
+
+#[derive(Copy, Clone)]
+enum ScanBound {
+    None,
+    From,
+    After,
+}
+
+fn has_prefix(value: &SerializeValue, prefix: &SerializeValue) -> bool {
+    match (value, prefix) {
+        (SerializeValue::String(v), SerializeValue::String(p)) => v.starts_with(&**p),
+        (SerializeValue::Bytes(v), SerializeValue::Bytes(p)) => v.starts_with(p),
+        (SerializeValue::Integer(v), SerializeValue::Integer(p)) => v == p,
+        (SerializeValue::Floating(v), SerializeValue::Floating(p)) => v == p,
+        (SerializeValue::Null, SerializeValue::Null) => true,
+        _ => false,
+    }
+}
+
+/// A change made to a value during an atomic update.
+enum KvsUpdate<V> {
+    Keep,
+    Set(V),
+    Remove,
+}
+
+/// The state of a scan over the rows of a KVS store.
+struct KvsScan<K: Hash + Eq, V> {
+    data: Arc<BaseKvsStoreInfo>,
+    pending: Option<Arc<PendingWrites<K, V>>>,
+    with_values: bool,
+    is_migration_mandatory: bool,
+    prefix: Option<SerializeValue>,
+    last_key: Option<SerializeValue>,
+    buffer: VecDeque<(K, Option<V>)>,
+    error: Option<Error>,
+    is_done: bool,
+}
+impl <K: DbSerializable + Hash + Eq, V: DbSerializable> KvsScan<K, V> {
+    async fn fetch_page(&mut self) -> Result<()> {
+        if let Some(pending) = self.pending.take() {
+            pending.flush(&self.data).await?;
+        }
+        let queries = &self.data.queries;
+        let mut conn = self.data.db.connect().await?;
+        let keys: Vec<SerializeValue>;
+        let mut values = Vec::new();
+        let (bound, param) = match (&self.last_key, &self.prefix) {
+            (Some(last), _) => (ScanBound::After, Some(last.clone())),
+            (None, Some(prefix)) => (ScanBound::From, Some(prefix.clone())),
+            (None, None) => (ScanBound::None, None),
+        };
+        let sql = queries.scan_query(self.with_values, bound);
+        if self.with_values {
+            let rows: Vec<(SerializeValue, SerializeValue, StringId, u32)> = match param {
+                Some(param) => conn.query_vec(sql, (param, now_millis())).await?,
+                None => conn.query_vec(sql, now_millis()).await?,
+            };
+            let mut row_keys = Vec::new();
+            for (key, value, schema_id, schema_ver) in rows {
+                row_keys.push(key);
+                values.push((value, schema_id, schema_ver));
+            }
+            keys = row_keys;
         } else {
-            Ok(None)
+            keys = match param {
+                Some(param) => conn.query_vec(sql, (param, now_millis())).await?,
+                None => conn.query_vec(sql, now_millis()).await?,
+            };
+        }
+
+        if keys.len() < SCAN_PAGE_SIZE {
+            self.is_done = true;
         }
+        let mut values = values.into_iter();
+        for key in keys {
+            if let Some(prefix) = &self.prefix {
+                if !has_prefix(&key, prefix) {
+                    self.is_done = true;
+                    break
+                }
+            }
+            self.last_key = Some(key.clone());
+
+            let key = K::Format::deserialize(key)?;
+            let value = match values.next() {
+                Some(row) => KvsStoreQueries::decode_value(
+                    &mut conn, &self.data, self.data.value_id, row, self.is_migration_mandatory,
+                ).await?,
+                None => None,
+            };
+            self.buffer.push_back((key, value));
+        }
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Option<Result<(K, Option<V>)>> {
+        loop {
+            if let Some(entry) = self.buffer.pop_front() {
+                return Some(Ok(entry))
+            }
+            if let Some(err) = self.error.take() {
+                self.is_done = true;
+                return Some(Err(err))
+            }
+            if self.is_done {
+                return None
+            }
+            if let Err(err) = self.fetch_page().await {
+                self.is_done = true;
+                return Some(Err(err))
+            }
+        }
+    }
+
+    fn into_stream(self) -> impl Stream<Item = Result<(K, Option<V>)>> {
+        stream::unfold(self, |mut scan| async move {
+            match scan.next().await {
+                Some(item) => Some((item, scan)),
+                None => None,
+            }
+        })
     }
 }
 
@@ -388,8 +1007,10 @@ pub struct BaseKvsStore<K: DbSerializable + Hash + Eq, V: DbSerializable, T: Kvs
     #[module_info] info: ModuleInfo,
     data: ArcSwapOption<BaseKvsStoreInfo>,
     // TODO: Figure out a better way to do the LruCache capacity.
-    #[init_with { LruCache::new(1024) }] cache: LruCache<K, Option<V>>,
+    #[init_with { LruCache::new(1024) }] cache: LruCache<K, CachedValue<V>>,
+    #[init_with { V::kvs_indexes() }] indexes: Vec<KvsIndex<V>>,
     lock_set: LockSet<K>,
+    #[init_with { Arc::new(PendingWrites::new()) }] pending: Arc<PendingWrites<K, V>>,
     phantom: PhantomData<fn(& &mut T)>,
 }
 #[module_impl]
@@ -404,12 +1025,217 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
 
     #[event_handler]
     async fn init_kvs_late(&self, target: &Handler<impl Events>, ev: &InitKvsLate) -> Result<()> {
-        self.data.store(Some(Arc::new(BaseKvsStoreInfo::new(
-            target, self.info.name(), T::IS_TRANSIENT, ev, V::ID,
-        ).await?)));
+        let mut index_names = Vec::new();
+        for index in &self.indexes {
+            ensure!(
+                is_valid_index_name(index.name),
+                "Invalid KVS index name '{}' in '{}'.", index.name, self.info.name(),
+            );
+            ensure!(
+                !index_names.contains(&index.name),
+                "Duplicate KVS index name '{}' in '{}'.", index.name, self.info.name(),
+            );
+            index_names.push(index.name);
+        }
+
+        let (changes, mut changes_recv) = mpsc::unbounded_channel();
+        let data = Arc::new(BaseKvsStoreInfo::new(
+            target, self.info.name(), T::IS_TRANSIENT, ev, V::ID, &index_names, changes,
+        ).await?);
+        self.init_indexes(&data).await?;
+        self.data.store(Some(data.clone()));
+
+        self.spawn(target, "sweep_expired", async move {
+            let mut interval = tokio::time::interval(KVS_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let result = async {
+                    data.queries.sweep_expired(&mut data.db.connect().await?).await
+                }.await;
+                match result {
+                    Ok(0) => { }
+                    Ok(count) => trace!("Removed {} expired entries.", count),
+                    Err(e) => e.report_error(),
+                }
+            }
+        });
+        let handler = target.clone();
+        self.spawn(target, "change_events", async move {
+            while let Some(ev) = changes_recv.recv().await {
+                handler.dispatch_async(ev).await;
+            }
+            Ok(())
+        });
+        if T::IS_TRANSIENT {
+            let pending = self.pending.clone();
+            let data = self.load_data();
+            self.spawn(target, "flush_writes", async move {
+                let mut interval = tokio::time::interval(KVS_FLUSH_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = pending.flush(&data).await {
+                        e.report_error();
+                    }
+                }
+            });
+        }
         Ok(())
     }
 
+    #[event_handler]
+    async fn flush_on_shutdown(&self, _: &ShutdownEvent) {
+        if T::IS_TRANSIENT {
+            if let Some(data) = self.data.load_full() {
+                if let Err(e) = self.pending.flush(&data).await {
+                    e.report_error();
+                }
+            }
+        }
+    }
+
+    #[event_handler]
+    fn invalidate_cache(&self, ev: &InvalidateKvsCacheEvent) {
+        if ev.module.as_ref().map_or(false, |x| x != self.info.name()) {
+            return
+        }
+        match &ev.keys {
+            Some(keys) => for key in keys {
+                match K::Format::deserialize(key.clone()) {
+                    Ok(key) => self.cache.invalidate(&key),
+                    // if the key can't be decoded, be conservative and drop everything.
+                    Err(e) => {
+                        e.report_error();
+                        self.cache.clear();
+                        return
+                    }
+                }
+            },
+            None => self.cache.clear(),
+        }
+    }
+
+    #[event_handler]
+    async fn export_event(&self, ev: &mut KvsExportEvent) {
+        if ev.store_path == self.info.name() {
+            let mut buf = Vec::new();
+            let result = self.export_json(&mut buf).await;
+            ev.result = Some(result.map(|count| (buf, count)));
+        }
+    }
+
+    #[event_handler]
+    async fn import_event(&self, ev: &mut KvsImportEvent) {
+        if ev.store_path == self.info.name() {
+            ev.result = Some(self.import_json(&*ev.data).await);
+        }
+    }
+
+    /// Writes every entry in the store to a writer as JSON, returning the number of entries.
+    ///
+    /// Each entry is written on its own line as an object with a `key` and a `value`, so the
+    /// output can be inspected or edited, and loaded again with
+    /// [`import_json`](Self::import_json). Expiry times are not exported.
+    pub async fn export_json(&self, mut writer: impl Write + Send) -> Result<usize> {
+        let mut count = 0;
+        let mut entries = Box::pin(self.iter());
+        while let Some(entry) = entries.next().await {
+            let (key, value) = entry?;
+            serde_json::to_writer(&mut writer, &ExportedEntry { key: &key, value: &value })?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Stores every entry written by [`export_json`](Self::export_json) from a reader,
+    /// returning the number of entries.
+    ///
+    /// Existing entries with the same keys are replaced, and other entries are kept.
+    pub async fn import_json(&self, reader: impl BufRead + Send) -> Result<usize> {
+        let mut count = 0;
+        let mut batch = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue
+            }
+            let entry: ImportedEntry<K, V> = serde_json::from_str(&line)
+                .cmd_error(|| format!("Invalid entry on line {}.", i + 1))?;
+            batch.push((entry.key, entry.value));
+            count += 1;
+            if batch.len() >= KVS_BATCH_SIZE {
+                self.set_many(&batch).await?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            self.set_many(&batch).await?;
+        }
+        Ok(count)
+    }
+
+    /// Discards the cached value for a key, forcing the next access to load it from the
+    /// database.
+    pub fn invalidate(&self, k: &K) {
+        self.cache.invalidate(k);
+    }
+
+    /// Discards every cached value in this store.
+    pub fn invalidate_all(&self) {
+        self.cache.clear();
+    }
+
+    async fn init_indexes(&self, data: &BaseKvsStoreInfo) -> Result<()> {
+        data.db.retry_serializable(move |conn| self.init_indexes_with(data, conn)).await
+    }
+    async fn init_indexes_with(
+        &self, data: &BaseKvsStoreInfo, mut conn: DbConnection,
+    ) -> Result<()> {
+        let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+        let needs_rebuild = data.queries.create_indexes(&mut transaction).await?;
+        if !needs_rebuild.is_empty() {
+            debug!("Rebuilding indexes for KVS store '{}'...", self.info.name());
+
+            let rows: Vec<(SerializeValue, SerializeValue, StringId, u32)> =
+                transaction.query_vec_nullary(data.queries.load_all_query.clone()).await?;
+            for index in &needs_rebuild {
+                transaction.execute_nullary(index.clear_query.clone()).await?;
+            }
+            for (key, value, schema_id, schema_ver) in rows {
+                let value: Option<V> = KvsStoreQueries::decode_value(
+                    &mut transaction, data, data.value_id, (value, schema_id, schema_ver),
+                    !T::IS_TRANSIENT,
+                ).await?;
+                let value = match value {
+                    Some(value) => value,
+                    None => continue,
+                };
+                for (index, queries) in self.indexes.iter().zip(&data.queries.indexes) {
+                    if needs_rebuild.iter().any(|x| x.name == queries.name) {
+                        transaction.execute(
+                            queries.store_query.clone(), (key.clone(), (index.extract)(&value)?),
+                        ).await?;
+                    }
+                }
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+    /// Updates the cache after a write, and notifies other modules of the change.
+    fn store_cached(&self, data: &BaseKvsStoreInfo, k: K, v: CachedValue<V>) {
+        if let Ok(key) = K::Format::serialize(&k) {
+            // this only fails once the bot is shutting down.
+            let _ = data.changes.send(KvsChangedEvent { store_path: data.name.clone(), key });
+        }
+        self.cache.insert(k, v);
+    }
+
+    fn index_values(&self, v: &V) -> Result<Vec<SerializeValue>> {
+        self.indexes.iter().map(|x| (x.extract)(v)).collect()
+    }
+
     fn load_data(&self) -> Arc<BaseKvsStoreInfo> {
         self.data.load().as_ref().expect("BaseKvsStore not yet initialized.").clone()
     }
@@ -417,44 +1243,148 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
         data.db.connect().await
     }
 
-    async fn get_db(&self, data: &BaseKvsStoreInfo, k: K) -> Result<Option<V>> {
+    /// Flushes pending writes before an operation that reads or writes the table directly.
+    async fn flush_pending(&self, data: &BaseKvsStoreInfo) -> Result<()> {
+        if T::IS_TRANSIENT {
+            self.pending.flush(data).await
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn get_db(&self, data: &BaseKvsStoreInfo, k: K) -> Result<CachedValue<V>> {
+        if T::IS_TRANSIENT {
+            if let Some(value) = self.pending.get(&k) {
+                return Ok(value)
+            }
+        }
         data.queries.load_value(
             &mut self.connect_db(&data).await?, &k, &data, data.value_id, !T::IS_TRANSIENT,
         ).await
     }
-    async fn get_0(&self, data: &BaseKvsStoreInfo, k: K) -> Result<Option<V>> {
+    async fn get_0(&self, data: &BaseKvsStoreInfo, k: K) -> Result<CachedValue<V>> {
         self.cache.cached_async(k.clone(), self.get_db(data, k)).await
     }
-    async fn set_0(&self, data: &BaseKvsStoreInfo, k: K, v: V) -> Result<()> {
-        data.queries.store_value(&mut self.connect_db(&data).await?, &k, &v, data.value_id).await?;
-        self.cache.insert(k, Some(v));
+    async fn set_0(
+        &self, data: &BaseKvsStoreInfo, k: K, v: V, expires_at: Option<i64>,
+    ) -> Result<()> {
+        let index_values = self.index_values(&v)?;
+        if T::IS_TRANSIENT {
+            let cached = CachedValue { value: Some(v), expires_at };
+            self.pending.push(k.clone(), cached.clone(), index_values);
+            self.store_cached(&data, k, cached);
+            return Ok(())
+        }
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction().await?;
+        data.queries.store_value(
+            &mut transaction, &k, &v, data.value_id, expires_at, &index_values,
+        ).await?;
+        transaction.commit().await?;
+        self.store_cached(&data, k, CachedValue { value: Some(v), expires_at });
         Ok(())
     }
     async fn remove_0(&self, data: &BaseKvsStoreInfo, k: K) -> Result<()> {
-        data.queries.delete_value(&mut self.connect_db(&data).await?, &k).await?;
-        self.cache.insert(k, None);
+        if T::IS_TRANSIENT {
+            let cached = CachedValue { value: None, expires_at: None };
+            self.pending.push(k.clone(), cached.clone(), Vec::new());
+            self.store_cached(&data, k, cached);
+            return Ok(())
+        }
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction().await?;
+        data.queries.delete_value(&mut transaction, &k).await?;
+        transaction.commit().await?;
+        self.store_cached(&data, k, CachedValue { value: None, expires_at: None });
         Ok(())
     }
+    async fn update_0<R>(
+        &self, k: K, f: impl FnOnce(Option<V>) -> Result<(KvsUpdate<V>, R)>,
+    ) -> Result<R> {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let data = self.load_data();
+        if T::IS_TRANSIENT {
+            // writes to transient stores are only queued, so the value can be read and replaced
+            // without a transaction while we hold the lock for the key.
+            let current = self.get_0(&data, k.clone()).await?;
+            let value = current.get();
+            let expires_at = if value.is_some() { current.expires_at } else { None };
+            let (update, result) = f(value)?;
+            match update {
+                KvsUpdate::Keep => { }
+                KvsUpdate::Set(v) => self.set_0(&data, k, v, expires_at).await?,
+                KvsUpdate::Remove => self.remove_0(&data, k).await?,
+            }
+            return Ok(result)
+        }
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+
+        let current: CachedValue<V> = data.queries.load_value(
+            &mut transaction, &k, &data, data.value_id, !T::IS_TRANSIENT,
+        ).await?;
+        let expires_at = current.expires_at;
+        let (update, result) = f(current.value)?;
+        let cached = match update {
+            KvsUpdate::Keep => return Ok(result),
+            KvsUpdate::Set(v) => {
+                let index_values = self.index_values(&v)?;
+                data.queries.store_value(
+                    &mut transaction, &k, &v, data.value_id, expires_at, &index_values,
+                ).await?;
+                CachedValue { value: Some(v), expires_at }
+            }
+            KvsUpdate::Remove => {
+                data.queries.delete_value(&mut transaction, &k).await?;
+                CachedValue { value: None, expires_at: None }
+            }
+        };
+        transaction.commit().await?;
+
+        self.store_cached(&data, k, cached);
+        Ok(result)
+    }
+    async fn lock_many<'a>(&'a self, keys: &[K]) -> Vec<LockSetGuard<'a, K>> {
+        let keys: HashSet<K> = keys.iter().cloned().collect();
+        'retry: loop {
+            // we never wait while holding a lock, so batches locking overlapping keys in
+            // different orders cannot deadlock.
+            let mut guards = Vec::with_capacity(keys.len());
+            for key in &keys {
+                match self.lock_set.try_lock(key.clone()) {
+                    Some(guard) => guards.push(guard),
+                    None => {
+                        std::mem::drop(guards);
+                        std::mem::drop(self.lock_set.lock(key.clone()).await);
+                        continue 'retry
+                    }
+                }
+            }
+            return guards
+        }
+    }
     async fn get_mut_0<'a>(
         &'a self, guard: LockSetGuard<'a, K>, k: K, make_default: impl FnOnce() -> Result<V>,
     ) -> Result<KvsMutGuard<'a, K, V, T>> {
         let data = self.load_data();
-        let value = self.get_0(&data, k.clone()).await?;
+        let cached = self.get_0(&data, k.clone()).await?;
+        let (value, expires_at) = match cached.get() {
+            Some(v) => (v, cached.expires_at),
+            None => (make_default()?, None),
+        };
         Ok(KvsMutGuard {
             kvs_parent: self,
             _guard: guard,
             ul_key: k,
-            ul_value: match value {
-                Some(v) => v,
-                None => make_default()?,
-            },
+            ul_value: value,
+            ul_expires_at: expires_at,
             ul_data: data,
         })
     }
 
     /// Retrieves a value from a KVS store in the database.
     pub async fn get(&self, k: K) -> Result<Option<V>> {
-        self.get_0(&self.load_data(), k).await
+        Ok(self.get_0(&self.load_data(), k).await?.get())
     }
 
     /// Stores a value from the KVS store in the database.
@@ -462,7 +1392,199 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
     /// If another task is already writing to this database, this function will temporarily block.
     pub async fn set(&self, k: K, v: V) -> Result<()> {
         let _guard = self.lock_set.lock(k.clone()).await;
-        self.set_0(&self.load_data(), k, v).await
+        self.set_0(&self.load_data(), k, v, None).await
+    }
+
+    /// Replaces a value in the KVS store if it currently equals an expected value.
+    ///
+    /// If `expected` is `None`, the value is only stored if the key does not currently exist.
+    /// The comparison and write happen in a single transaction. Returns whether the value was
+    /// replaced.
+    pub async fn compare_and_set(&self, k: K, expected: Option<V>, new: V) -> Result<bool>
+        where V: PartialEq,
+    {
+        self.update_0(k, |current| {
+            if current == expected {
+                Ok((KvsUpdate::Set(new), true))
+            } else {
+                Ok((KvsUpdate::Keep, false))
+            }
+        }).await
+    }
+
+    /// Atomically updates a value in the KVS store.
+    ///
+    /// The closure is called with the current value, and returns the new value, or `None` to
+    /// remove the key. The value is read and written in a single transaction, and the new value
+    /// is returned. An expiry time set with [`set_with_ttl`](Self::set_with_ttl) is kept.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    pub async fn update(
+        &self, k: K, f: impl FnOnce(Option<V>) -> Result<Option<V>>,
+    ) -> Result<Option<V>> {
+        self.update_0(k, |current| match f(current)? {
+            Some(v) => Ok((KvsUpdate::Set(v.clone()), Some(v))),
+            None => Ok((KvsUpdate::Remove, None)),
+        }).await
+    }
+
+    fn add_checked(value: &V, delta: i64) -> Result<V> where V: KvsIntegral {
+        let stored = V::Format::serialize(value)?.into_i64()?;
+        match V::checked_incr(stored, delta) {
+            Some(new) => V::Format::deserialize(SerializeValue::Integer(new)),
+            None => cmd_error!("The value is too large or too small to add {} to.", delta),
+        }
+    }
+
+    /// Atomically adds to an integer value in the KVS store, returning the new value.
+    ///
+    /// On SQLite, the addition is done by the database itself rather than by loading and
+    /// rewriting the value. If the key does not exist or has expired, it is set to `delta`. If the
+    /// result does not fit in the value type, an error is returned and the value is unchanged.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    pub async fn incr(&self, k: K, delta: i64) -> Result<V> where V: KvsIntegral {
+        let data = self.load_data();
+        if T::IS_TRANSIENT || data.db.dialect() != SqlDialect::Sqlite || !self.indexes.is_empty() {
+            // values are stored as tagged binary data on other databases, so we can't add to
+            // them in a query. indexes need the new value to be updated, too, and writes to
+            // transient stores are queued rather than written immediately.
+            let value = self.update(k, |current| match current {
+                Some(v) => Ok(Some(Self::add_checked(&v, delta)?)),
+                None => Ok(Some(V::Format::deserialize(SerializeValue::Integer(delta))?)),
+            }).await?;
+            return value.internal_err(|| "Incremented value is missing.")
+        }
+
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        let incremented = data.queries.incr_value::<K, V>(
+            &mut transaction, &k, delta, data.value_id,
+        ).await?;
+        let (value, expires_at) = match incremented {
+            Some(x) => x,
+            None => {
+                // the key is missing, or its value is stored with an older schema. the value is
+                // loaded normally so an old value is migrated rather than replaced.
+                let current: CachedValue<V> = data.queries.load_value(
+                    &mut transaction, &k, &data, data.value_id, true,
+                ).await?;
+                let value = match &current.value {
+                    Some(v) => Self::add_checked(v, delta)?,
+                    None => V::Format::deserialize(SerializeValue::Integer(delta))?,
+                };
+                let expires_at = if current.value.is_some() { current.expires_at } else { None };
+                data.queries.store_value(
+                    &mut transaction, &k, &value, data.value_id, expires_at, &[],
+                ).await?;
+                (value, expires_at)
+            }
+        };
+        transaction.commit().await?;
+
+        self.store_cached(&data, k, CachedValue { value: Some(value.clone()), expires_at });
+        Ok(value)
+    }
+
+    /// Retrieves multiple values from the KVS store at once.
+    ///
+    /// Values that are not already cached are loaded using a single query for each batch of
+    /// keys, rather than one query per key. The values are returned in the same order as the
+    /// keys they were loaded for.
+    pub async fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        let data = self.load_data();
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        for key in keys {
+            match self.cache.get(key) {
+                Some(cached) => {
+                    found.insert(key.clone(), cached);
+                }
+                None => match self.pending.get(key) {
+                    Some(pending) => {
+                        found.insert(key.clone(), pending);
+                    }
+                    None => missing.push(key.clone()),
+                },
+            }
+        }
+
+        if !missing.is_empty() {
+            let mut conn = self.connect_db(&data).await?;
+            for chunk in missing.chunks(KVS_BATCH_SIZE) {
+                let mut loaded = data.queries.load_many(
+                    &mut conn, chunk, &data, data.value_id, !T::IS_TRANSIENT,
+                ).await?;
+                for key in chunk {
+                    let value = loaded.remove(key)
+                        .unwrap_or(CachedValue { value: None, expires_at: None });
+                    let value = self.cache.cached(key.clone(), || Ok(value))?;
+                    found.insert(key.clone(), value);
+                }
+            }
+        }
+
+        Ok(keys.iter().map(|key| found.get(key).and_then(|x| x.get())).collect())
+    }
+
+    /// Stores multiple values in the KVS store at once.
+    ///
+    /// All values are written in a single transaction, so either every value is stored or none
+    /// of them are.
+    ///
+    /// If another task is already writing to any of these keys, this function will temporarily
+    /// block.
+    pub async fn set_many(&self, entries: &[(K, V)]) -> Result<()> {
+        let keys: Vec<K> = entries.iter().map(|x| x.0.clone()).collect();
+        let _guards = self.lock_many(&keys).await;
+
+        let data = self.load_data();
+        if T::IS_TRANSIENT {
+            // the writes are taken from the pending map together by the next flush, so they are
+            // still stored in a single transaction.
+            let mut writes = Vec::with_capacity(entries.len());
+            for (k, v) in entries {
+                writes.push((k, v, self.index_values(v)?));
+            }
+            for (k, v, index_values) in writes {
+                let cached = CachedValue { value: Some(v.clone()), expires_at: None };
+                self.pending.push(k.clone(), cached.clone(), index_values);
+                self.store_cached(&data, k.clone(), cached);
+            }
+            return Ok(())
+        }
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        for (k, v) in entries {
+            let index_values = self.index_values(v)?;
+            data.queries.store_value(
+                &mut transaction, k, v, data.value_id, None, &index_values,
+            ).await?;
+        }
+        transaction.commit().await?;
+
+        for (k, v) in entries {
+            let cached = CachedValue { value: Some(v.clone()), expires_at: None };
+            self.store_cached(&data, k.clone(), cached);
+        }
+        Ok(())
+    }
+
+    /// Stores a value in the KVS store that expires after a given amount of time.
+    ///
+    /// Once the value expires, it is treated as if it was removed, and it is eventually deleted
+    /// from the database by a background task. Mutating the value with [`get_mut`] keeps its
+    /// expiry time, while [`set`] stores it permanently.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    ///
+    /// [`get_mut`]: Self::get_mut
+    /// [`set`]: Self::set
+    pub async fn set_with_ttl(&self, k: K, v: V, ttl: Duration) -> Result<()> {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let expires_at = now_millis().saturating_add(ttl.as_millis().min(i64::MAX as u128) as i64);
+        self.set_0(&self.load_data(), k, v, Some(expires_at)).await
     }
 
     /// Removes a value from the KVS store in the database.
@@ -533,6 +1655,167 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
     }
 }
 
+impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore<K, V, T> {
+    fn scan(
+        &self, prefix: Result<Option<SerializeValue>>, with_values: bool,
+    ) -> impl Stream<Item = Result<(K, Option<V>)>> {
+        let (prefix, error) = match prefix {
+            Ok(prefix) => (prefix, None),
+            Err(e) => (None, Some(e)),
+        };
+        KvsScan {
+            data: self.load_data(),
+            pending: if T::IS_TRANSIENT { Some(self.pending.clone()) } else { None },
+            with_values,
+            is_migration_mandatory: !T::IS_TRANSIENT,
+            prefix,
+            last_key: None,
+            buffer: VecDeque::new(),
+            is_done: error.is_some(),
+            error,
+        }.into_stream()
+    }
+
+    /// Finds all entries whose indexed field is equal to a given value.
+    ///
+    /// The index must be declared by the value type's [`DbSerializable::kvs_indexes`]. Entries
+    /// are ordered by their serialized keys.
+    pub async fn find_by_index<F: DbSerializable>(
+        &self, index: &str, value: &F,
+    ) -> Result<Vec<(K, V)>> {
+        let data = self.load_data();
+        let index = data.queries.find_index(index)?;
+        self.flush_pending(&data).await?;
+        let mut conn = self.connect_db(&data).await?;
+        let rows: Vec<(SerializeValue, SerializeValue, StringId, u32)> = conn.query_vec(
+            index.find_query.clone(), (F::Format::serialize(value)?, now_millis()),
+        ).await?;
+
+        let mut result = Vec::new();
+        for (key, value, schema_id, schema_ver) in rows {
+            let value = KvsStoreQueries::decode_value(
+                &mut conn, &data, data.value_id, (value, schema_id, schema_ver),
+                !T::IS_TRANSIENT,
+            ).await?;
+            if let Some(value) = value {
+                result.push((K::Format::deserialize(key)?, value));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns a stream of all entries in the KVS store.
+    ///
+    /// Entries are ordered by their serialized keys, and are loaded from the database a few at a
+    /// time as the stream is consumed. Values that cannot be migrated to the current schema are
+    /// skipped in transient stores.
+    pub fn iter(&self) -> impl Stream<Item = Result<(K, V)>> {
+        self.scan(Ok(None), true).filter_map(|x| async move {
+            match x {
+                Ok((k, Some(v))) => Some(Ok((k, v))),
+                Ok((_, None)) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+
+    /// Returns a stream of all keys in the KVS store.
+    ///
+    /// Keys are ordered by their serialized form.
+    pub fn keys(&self) -> impl Stream<Item = Result<K>> {
+        self.scan(Ok(None), false).map(|x| x.map(|(k, _)| k))
+    }
+
+    /// Locks a key for a transaction, which must be done for every key it uses before it
+    /// begins. The lock is held until the transaction is committed or dropped.
+    ///
+    /// Keys are locked before the transaction takes the database's write lock, in the same
+    /// order as other writes to KVS stores, so waiting for a key cannot deadlock with them.
+    /// Transactions that share keys should lock them in the same order as each other.
+    pub async fn lock_in<'a>(&'a self, tx: &mut KvsTransactionBuilder<'a>, k: K) {
+        let id = self as *const Self as usize;
+        if T::IS_TRANSIENT && !tx.locked.contains_key(&id) {
+            // the background flush is held off until the transaction ends, so it cannot
+            // overwrite the values written in the transaction with older ones.
+            tx.guards.push(Box::new(self.pending.flush_lock.lock().await));
+        }
+        let locked = tx.locked.entry(id).or_insert_with(|| Box::new(HashSet::<K>::new()));
+        let locked = locked.downcast_mut::<HashSet<K>>().expect("KVS store has wrong key type?");
+        if locked.insert(k.clone()) {
+            tx.guards.push(Box::new(self.lock_set.lock(k).await));
+        }
+    }
+
+    /// Checks that a key was locked for a transaction, returning the store's state.
+    async fn check_locked_in<'a>(
+        &'a self, tx: &mut KvsTransaction<'a>, k: &K,
+    ) -> Result<Arc<BaseKvsStoreInfo>> {
+        let data = self.load_data();
+        let id = self as *const Self as usize;
+        let is_locked = tx.locked.get(&id)
+            .and_then(|x| x.downcast_ref::<HashSet<K>>())
+            .map_or(false, |x| x.contains(k));
+        ensure!(is_locked, "KVS keys must be locked with `lock_in` before a transaction begins.");
+        if T::IS_TRANSIENT && tx.flushed.insert(id) {
+            // queued writes go into the transaction first, so it sees the latest values.
+            let written = self.pending.write_all(&mut tx.transaction, &data).await?;
+            let pending = self.pending.clone();
+            tx.on_commit.push(Box::new(move || pending.remove_written(written)));
+        }
+        Ok(data)
+    }
+
+    /// Retrieves a value from the KVS store as part of a transaction.
+    pub async fn get_in<'a>(&'a self, tx: &mut KvsTransaction<'a>, k: K) -> Result<Option<V>> {
+        let data = self.check_locked_in(tx, &k).await?;
+        let value: CachedValue<V> = data.queries.load_value(
+            &mut tx.transaction, &k, &data, data.value_id, !T::IS_TRANSIENT,
+        ).await?;
+        Ok(value.get())
+    }
+
+    /// Stores a value in the KVS store as part of a transaction.
+    pub async fn set_in<'a>(&'a self, tx: &mut KvsTransaction<'a>, k: K, v: V) -> Result<()> {
+        let data = self.check_locked_in(tx, &k).await?;
+        let index_values = self.index_values(&v)?;
+        data.queries.store_value(
+            &mut tx.transaction, &k, &v, data.value_id, None, &index_values,
+        ).await?;
+        tx.on_commit.push(Box::new(move || {
+            self.store_cached(&data, k, CachedValue { value: Some(v), expires_at: None });
+        }));
+        Ok(())
+    }
+
+    /// Removes a value from the KVS store as part of a transaction.
+    pub async fn remove_in<'a>(&'a self, tx: &mut KvsTransaction<'a>, k: K) -> Result<()> {
+        let data = self.check_locked_in(tx, &k).await?;
+        data.queries.delete_value(&mut tx.transaction, &k).await?;
+        tx.on_commit.push(Box::new(move || {
+            self.store_cached(&data, k, CachedValue { value: None, expires_at: None });
+        }));
+        Ok(())
+    }
+
+    /// Returns a stream of all entries whose serialized key starts with the serialized form of
+    /// the given prefix.
+    ///
+    /// For string and byte buffer keys, this finds all keys starting with the prefix. For keys
+    /// serialized with [`BincodeFormat`], such as tuples, the prefix may be a value of the key's
+    /// leading fields. For other keys, this only finds an exact match.
+    pub fn scan_prefix<P: DbSerializable>(
+        &self, prefix: &P,
+    ) -> impl Stream<Item = Result<(K, V)>> {
+        self.scan(P::Format::serialize(prefix).map(Some), true).filter_map(|x| async move {
+            match x {
+                Ok((k, Some(v))) => Some(Ok((k, v))),
+                Ok((_, None)) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+}
+
 /// The base type for KVS stores backed by the database.
 ///
 /// This is a module, and should be used by attaching it to the your module as a submodule.
@@ -540,21 +1823,107 @@ pub type KvsStore<K, V> = BaseKvsStore<K, V, PersistentKvsType>;
 
 /// The base type for KVS stores backed by the transient database.
 ///
+/// Writes to transient stores are kept in memory and flushed to the database in batches every
+/// second, so frequently updated keys are only written once per flush. Remaining writes are
+/// flushed when the bot shuts down cleanly, but are lost if it crashes.
+///
 /// This is a module, and should be used by attaching it to the your module as a submodule.
 pub type TransientKvsStore<K, V> = BaseKvsStore<K, V, TransientKvsType>;
 
+/// The keys locked for a [`KvsTransaction`], before the transaction itself begins.
+///
+/// This is created with [`KvsTransaction::builder`]. Every key the transaction uses is locked
+/// with [`BaseKvsStore::lock_in`], and then the transaction is started with
+/// [`begin`](Self::begin).
+pub struct KvsTransactionBuilder<'a> {
+    conn: &'a mut DbConnection,
+    locked: HashMap<usize, Box<dyn Any + Send>>,
+    guards: Vec<Box<dyn Send + 'a>>,
+}
+impl <'a> KvsTransactionBuilder<'a> {
+    /// Begins the transaction, once every key it uses has been locked.
+    pub async fn begin(self) -> Result<KvsTransaction<'a>> {
+        Ok(KvsTransaction {
+            transaction: self.conn.transaction_with_type(TransactionType::Immediate).await?,
+            locked: self.locked,
+            flushed: HashSet::new(),
+            guards: self.guards,
+            on_commit: Vec::new(),
+        })
+    }
+}
+
+/// A database transaction spanning operations on any number of KVS stores.
+///
+/// Values are read and written in the transaction with methods such as
+/// [`BaseKvsStore::get_in`] and [`BaseKvsStore::set_in`], and raw SQL can be run on the
+/// transaction directly. Either every change is committed, or none of them are.
+///
+/// The keys used must be locked before the transaction begins:
+///
+/// ```rust,ignore
+/// let mut conn = target.connect_db().await?;
+/// let mut tx = KvsTransaction::builder(&mut conn);
+/// balances.lock_in(&mut tx, from).await;
+/// balances.lock_in(&mut tx, to).await;
+/// let mut tx = tx.begin().await?;
+/// ```
+///
+/// Keys stay locked until the transaction is committed or dropped, so other tasks writing to
+/// them will block. Dropping the transaction rolls it back.
+pub struct KvsTransaction<'a> {
+    transaction: DbTransaction<'a>,
+    locked: HashMap<usize, Box<dyn Any + Send>>,
+    flushed: HashSet<usize>,
+    guards: Vec<Box<dyn Send + 'a>>,
+    on_commit: Vec<Box<dyn FnOnce() + Send + 'a>>,
+}
+impl <'a> KvsTransaction<'a> {
+    /// Prepares a new transaction on a connection, which begins once the keys it uses are
+    /// locked.
+    pub fn builder(conn: &'a mut DbConnection) -> KvsTransactionBuilder<'a> {
+        KvsTransactionBuilder { conn, locked: HashMap::new(), guards: Vec::new() }
+    }
+
+    /// Commits the transaction.
+    pub async fn commit(self) -> Result<()> {
+        self.transaction.commit().await?;
+        for func in self.on_commit {
+            func();
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction.
+    pub async fn rollback(self) -> Result<()> {
+        self.transaction.rollback().await
+    }
+}
+impl <'a> Deref for KvsTransaction<'a> {
+    type Target = DbOps;
+    fn deref(&self) -> &Self::Target {
+        &self.transaction
+    }
+}
+impl <'a> DerefMut for KvsTransaction<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.transaction
+    }
+}
+
 /// A guard for mutating values in the KVS as a mutable object.
 pub struct KvsMutGuard<'a, K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> {
     kvs_parent: &'a BaseKvsStore<K, V, T>,
     _guard: LockSetGuard<'a, K>,
     ul_key: K,
     ul_value: V,
+    ul_expires_at: Option<i64>,
     ul_data: Arc<BaseKvsStoreInfo>,
 }
 impl <'a, K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> KvsMutGuard<'a, K, V, T> {
     /// Commit the changed KVS value to the database.
     pub async fn commit(self) -> Result<()> {
-        self.kvs_parent.set_0(&self.ul_data, self.ul_key, self.ul_value).await
+        self.kvs_parent.set_0(&self.ul_data, self.ul_key, self.ul_value, self.ul_expires_at).await
     }
 
     /// Deletes the KVS value from the database.
@@ -576,4 +1945,124 @@ impl <'a, K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType>
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.ul_value
     }
-}
\ No newline at end of file
+}
+/// A key in a KVS store that holds several logical maps, as used by [`KvsNamespace`].
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespacedKey<K> {
+    /// The namespace the key belongs to.
+    pub namespace: Arc<str>,
+    /// The key within its namespace.
+    pub key: K,
+}
+impl <K: DbSerializable> DbSerializable for NamespacedKey<K> {
+    type Format = BincodeFormat;
+    const ID: &'static str = "sylphie_database::kvs::NamespacedKey";
+    const SCHEMA_VERSION: u32 = K::SCHEMA_VERSION;
+}
+
+impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType>
+    BaseKvsStore<NamespacedKey<K>, V, T>
+{
+    /// Returns a view of the entries in a single namespace of this store.
+    ///
+    /// Keys used through the view are scoped to the namespace, so several views of the same
+    /// store with different names never see each other's entries.
+    pub fn namespace(&self, name: &str) -> KvsNamespace<'_, K, V, T> {
+        KvsNamespace { store: self, namespace: name.into() }
+    }
+}
+
+/// A view of a single namespace of a KVS store keyed by [`NamespacedKey`].
+///
+/// This is created with [`BaseKvsStore::namespace`].
+pub struct KvsNamespace<'a, K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> {
+    store: &'a BaseKvsStore<NamespacedKey<K>, V, T>,
+    namespace: Arc<str>,
+}
+impl <'a, K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> KvsNamespace<'a, K, V, T> {
+    fn key(&self, key: K) -> NamespacedKey<K> {
+        NamespacedKey { namespace: self.namespace.clone(), key }
+    }
+
+    /// Returns the name of this namespace.
+    pub fn name(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Retrieves a value from this namespace.
+    pub async fn get(&self, k: K) -> Result<Option<V>> {
+        self.store.get(self.key(k)).await
+    }
+
+    /// Retrieves multiple values from this namespace at once.
+    pub async fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>> {
+        let keys: Vec<_> = keys.iter().map(|k| self.key(k.clone())).collect();
+        self.store.get_many(&keys).await
+    }
+
+    /// Stores a value in this namespace.
+    pub async fn set(&self, k: K, v: V) -> Result<()> {
+        self.store.set(self.key(k), v).await
+    }
+
+    /// Stores a value in this namespace that expires after a given amount of time.
+    pub async fn set_with_ttl(&self, k: K, v: V, ttl: Duration) -> Result<()> {
+        self.store.set_with_ttl(self.key(k), v, ttl).await
+    }
+
+    /// Atomically updates a value in this namespace.
+    ///
+    /// See [`BaseKvsStore::update`] for details.
+    pub async fn update(
+        &self, k: K, f: impl FnOnce(Option<V>) -> Result<Option<V>>,
+    ) -> Result<Option<V>> {
+        self.store.update(self.key(k), f).await
+    }
+
+    /// Removes a value from this namespace.
+    pub async fn remove(&self, k: K) -> Result<()> {
+        self.store.remove(self.key(k)).await
+    }
+
+    /// Returns a mutable handle to a value in this namespace.
+    ///
+    /// See [`BaseKvsStore::get_mut`] for details.
+    pub async fn get_mut(
+        &self, k: K, default: impl FnOnce() -> Result<V>,
+    ) -> Result<KvsMutGuard<'a, NamespacedKey<K>, V, T>> {
+        self.store.get_mut(self.key(k), default).await
+    }
+
+    /// Returns a mutable handle to a value in this namespace, initializing it with
+    /// [`Default::default`] if it does not already exist.
+    pub async fn get_mut_default(&self, k: K) -> Result<KvsMutGuard<'a, NamespacedKey<K>, V, T>>
+        where V: Default,
+    {
+        self.store.get_mut_default(self.key(k)).await
+    }
+
+    /// Returns a stream of all entries in this namespace.
+    ///
+    /// Entries are ordered by their serialized keys.
+    pub fn iter(&self) -> impl Stream<Item = Result<(K, V)>> + 'a {
+        self.store.scan(self.prefix(), true).filter_map(|x| async move {
+            match x {
+                Ok((k, Some(v))) => Some(Ok((k.key, v))),
+                Ok((_, None)) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+
+    /// Returns a stream of all keys in this namespace.
+    pub fn keys(&self) -> impl Stream<Item = Result<K>> + 'a {
+        self.store.scan(self.prefix(), false).map(|x| x.map(|(k, _)| k.key))
+    }
+
+    /// The serialized form of the namespace, which every key in it starts with.
+    fn prefix(&self) -> Result<Option<SerializeValue>> {
+        let prefix = bincode::DefaultOptions::new().with_varint_encoding()
+            .serialize(&self.namespace)?;
+        Ok(Some(prefix.into()))
+    }
+}
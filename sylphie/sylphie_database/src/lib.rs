@@ -4,12 +4,24 @@
 
 pub mod migrations; // this goes early because there are macros we use in here
 
+pub mod backup;
+pub mod blobs;
 pub mod config;
+#[cfg(feature = "sqlcipher")] pub mod encryption;
 mod interner;
+mod maintenance;
+#[cfg(feature = "otlp")] mod otlp;
 pub mod connection;
+pub mod event_log;
+mod export;
+pub mod flags;
 pub mod kvs;
+pub mod search;
 pub mod serializable;
 pub mod singleton;
+pub mod timeseries;
+pub mod transient;
+mod stats;
 
 /// Contains misc types that involve the database.
 ///
@@ -18,12 +30,19 @@ pub mod utils {
     pub use crate::interner::{ScopeId, StringId};
 }
 
+use crate::config::RegisterConfigEvent;
+use crate::migrations::{MigrationManager, RegisterMigrationsEvent};
 use std::fs;
-use sylphie_core::core::{EarlyInitEvent, BotInfo};
+use std::sync::Arc;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::config::Config;
+use sylphie_core::core::{EarlyInitEvent, BotInfo, InitEvent, RegisterInitTasksEvent, ShutdownEvent};
 use sylphie_core::derives::*;
-use sylphie_core::interface::SetupLoggerEvent;
+use sylphie_core::interface::{SetupLoggerEvent, SetupTerminalEvent};
+use sylphie_core::metrics::CollectMetricsEvent;
 use sylphie_core::prelude::*;
-use tokio::runtime::Handle;
+use sylphie_core::watchdog::WatchdogPingEvent;
+use tracing::level_filters::LevelFilter;
 
 /// The event called to initialize the database.
 pub struct InitDbEvent(());
@@ -33,6 +52,7 @@ failable_event!(InitDbEvent, (), Error);
 struct InnerHandler {
     #[service] #[subhandler] config: config::ConfigManager,
     #[service] interner: interner::Interner,
+    #[service] blobs: blobs::BlobStore,
     #[service] database: connection::Database,
     #[service] migrations: migrations::MigrationManager,
 }
@@ -42,6 +62,7 @@ impl InnerHandler {
         InnerHandler {
             config: Default::default(),
             interner: Default::default(),
+            blobs: blobs::BlobStore::new(),
             database: database.clone(),
             migrations: migrations::MigrationManager::new(database),
         }
@@ -53,58 +74,229 @@ impl InnerHandler {
 /// This should be a part of the module tree for database connections and migrations to work
 /// correctly.
 #[derive(Module)]
+#[module(init_priority = 1000)]
 pub struct DatabaseModule {
     #[module_info] info: ModuleInfo,
     #[subhandler] #[init_with { InnerHandler::new() }] inner: InnerHandler,
     #[submodule] #[service] store: singleton::SingletonDataStore,
+    #[submodule] #[service] flags: flags::FeatureFlags,
 }
 #[module_impl]
 impl DatabaseModule {
     #[event_handler(EvInit)]
-    fn init_database(&self, target: &Handler<impl Events>, _: &EarlyInitEvent) {
-        let handle = Handle::current();
-        if let Err(e) = handle.block_on(self.early_init_db(target)) {
-            e.report_error();
-            panic!("Error occurred during early database initialization.");
+    fn init_database(&self, target: &Handler<impl Events>, _: &EarlyInitEvent) -> Result<()> {
+        self.init_backend(target)
+            .internal_err(|| "Error occurred during early database initialization.")?;
+        crate::transient::restore_on_startup(target)
+            .internal_err(|| "Could not restore the transient database.")?;
+        crate::backup::restore_on_startup(target)
+            .internal_err(|| "Could not restore the database from a backup.")?;
+        if migrations::is_check_mode(target) {
+            // nothing else should run, as init tasks would apply the migrations we are checking.
+            let code = match self.check_migrations(target) {
+                Ok(()) => 0,
+                Err(e) => {
+                    e.report_error();
+                    1
+                }
+            };
+            std::process::exit(code);
         }
-        if let Err(e) = handle.block_on(target.dispatch_async(InitDbEvent(()))) {
-            e.report_error();
-            panic!("Error occurred during database initialization.");
+        Ok(())
+    }
+
+    #[event_handler]
+    fn register_migrations(ev: &mut RegisterMigrationsEvent) {
+        ev.add_migration(&crate::interner::INTERNER_MIGRATIONS);
+        ev.add_migration(&crate::kvs::PERSISTENT_KVS_MIGRATIONS);
+        ev.add_migration(&crate::kvs::TRANSIENT_KVS_MIGRATIONS);
+        ev.add_migration(&crate::config::CONFIG_MIGRATIONS);
+    }
+
+    #[event_handler]
+    fn register_init_tasks(
+        &self, target: &Handler<impl Events>, ev: &mut RegisterInitTasksEvent,
+    ) {
+        let info = self.info();
+
+        let handler = target.clone();
+        ev.add_task(info, "auto_vacuum", &[], async move {
+            crate::maintenance::enable_auto_vacuum(&handler).await
+        });
+        let handler = target.clone();
+        ev.add_task(info, "migrations", &["auto_vacuum"], async move {
+            crate::migrations::execute_registered(&handler).await
+        });
+        let handler = target.clone();
+        ev.add_task(info, "interner", &["migrations"], async move {
+            crate::interner::init_interner(&handler).await
+        });
+        let handler = target.clone();
+        ev.add_task(info, "kvs", &["interner"], async move {
+            crate::kvs::init_kvs(&handler).await
+        });
+        let handler = target.clone();
+        ev.add_task(info, "config", &["interner"], async move {
+            crate::config::init_config(&handler).await
+        });
+        let handler = target.clone();
+        ev.add_task(info, "init_db", &["kvs", "config"], async move {
+            handler.dispatch_async(InitDbEvent(())).await
+        });
+    }
+
+    fn check_migrations(&self, target: &Handler<impl Events>) -> Result<()> {
+        let manager = target.get_service::<MigrationManager>();
+        let ev = target.dispatch_sync(RegisterMigrationsEvent::new());
+        let mut all_valid = true;
+        for migration in ev.sorted_migrations()? {
+            let plan = manager.plan_sync(migration)?;
+            if plan.is_up_to_date() {
+                info!(
+                    "Migration set {} is up to date at version {}.",
+                    migration.migration_set_name, plan.current_version,
+                );
+                continue
+            }
+            info!(
+                "Migration set {} would be migrated from version {} to version {}:",
+                migration.migration_set_name, plan.current_version, plan.final_version(),
+            );
+            for script in &plan.scripts {
+                info!("    {} ({} -> {})", script.script_name, script.from, script.to);
+            }
+            if !plan.reaches_target() {
+                error!(
+                    "Migration set {} cannot be migrated to its target version {}.",
+                    migration.migration_set_name, migration.target_version,
+                );
+                all_valid = false;
+            }
         }
+        ensure!(all_valid, "Some migration sets cannot be applied.");
+        Ok(())
     }
 
-    async fn early_init_db(&self, target: &Handler<impl Events>) -> Result<()> {
-        self.init_db_paths(target)?;
-        self.init_serializers(target).await?;
+    fn init_backend(&self, target: &Handler<impl Events>) -> Result<()> {
+        let backend = self.create_backend(target)?;
+        let config = target.services().resolve::<connection::PoolConfig>()
+            .map_or_else(Default::default, |x| (*x).clone());
+        self.inner.database.set_backend(backend, config);
+
+        let mut blob_path = target.get_service::<BotInfo>().root_path().to_owned();
+        blob_path.push("blobs");
+        self.inner.blobs.set_path(blob_path);
         Ok(())
     }
 
-    fn init_db_paths(&self, target: &Handler<impl Events>) -> Result<()> {
+    fn create_backend(
+        &self, target: &Handler<impl Events>,
+    ) -> Result<Arc<dyn connection::StorageBackend>> {
+        if let Some(backend) = target.services().resolve::<dyn connection::StorageBackend>() {
+            return Ok(backend)
+        }
+
+        #[cfg(feature = "postgres")]
+        if let Ok(url) = std::env::var("SYLPHIE_POSTGRES_URL") {
+            let mut backend = connection::PostgresBackend::new(url);
+            if let Ok(replica_url) = std::env::var("SYLPHIE_POSTGRES_REPLICA_URL") {
+                backend = backend.with_read_replica(replica_url);
+            }
+            return Ok(Arc::new(backend))
+        }
+
         let info = target.get_service::<BotInfo>();
+        let core_config = target.get_service::<Config>().core();
+        let config = &core_config.database;
 
         let mut db_path = info.root_path().to_owned();
         db_path.push("db");
-        fs::create_dir_all(&db_path)?;
+        let persistent_path = match &config.path {
+            Some(path) => info.root_path().join(path),
+            None => db_path.join(format!("{}.db", info.bot_name())),
+        };
+        let transient_path = match &config.transient_path {
+            Some(path) => info.root_path().join(path),
+            None => db_path.join(format!("{}.transient.db", info.bot_name())),
+        };
+        for path in &[&persistent_path, &transient_path] {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
 
-        let mut persistent_path = db_path.to_owned();
-        persistent_path.push(format!("{}.db", info.bot_name()));
+        let backend = connection::SqliteBackend::new(persistent_path, transient_path);
+        #[cfg(feature = "sqlcipher")]
+        let backend = match crate::encryption::database_key(target) {
+            Some(key) => backend.with_key(key),
+            None => backend,
+        };
+        Ok(Arc::new(backend))
+    }
 
-        let mut transient_path = db_path.to_owned();
-        transient_path.push(format!("{}.transient.db", info.bot_name()));
+    #[event_handler]
+    fn register_commands(&self, target: &Handler<impl Events>, ev: &mut RegisterCommandsEvent) {
+        crate::migrations::register_commands(target, self, ev);
+        crate::backup::register_commands(target, self, ev);
+        crate::export::register_commands(target, self, ev);
+        crate::flags::register_commands(target, self, ev);
+        crate::stats::register_commands(target, self, ev);
+        #[cfg(feature = "sqlcipher")]
+        crate::encryption::register_commands(target, self, ev);
+    }
 
-        self.inner.database.set_paths(persistent_path, transient_path);
-        Ok(())
+    #[event_handler]
+    fn collect_metrics(&self, target: &Handler<impl Events>, ev: &mut CollectMetricsEvent) {
+        crate::stats::collect_metrics(target, self.info(), ev);
+    }
+
+    #[event_handler]
+    fn start_tasks(&self, target: &Handler<impl Events>, _: &InitEvent) {
+        crate::backup::start_backup_task(target, self);
+        crate::maintenance::start_maintenance_task(target, self);
+        #[cfg(feature = "otlp")]
+        crate::otlp::start_otlp_task(target, self);
+        crate::stats::start_slow_query_task(target, self);
     }
 
-    async fn init_serializers(&self, target: &Handler<impl Events>) -> Result<()> {
-        crate::interner::init_interner(target).await?;
-        crate::kvs::init_kvs(target).await?;
-        crate::config::init_config(target).await?;
+    #[event_handler]
+    async fn watchdog_ping(&self, target: &Handler<impl Events>, ev: &WatchdogPingEvent) {
+        let _probe = ev.probe(self.info(), "connection pool");
+        let database = target.get_service::<connection::Database>();
+        let result = async {
+            database.connect().await?.query_row_nullary::<usize>("SELECT 1").await
+        }.await;
+        if let Err(e) = result {
+            warn!("Could not query the database for the watchdog: {}", e);
+        }
+    }
+
+    #[event_handler(EvAfterEvent)]
+    async fn snapshot_transient(&self, target: &Handler<impl Events>, _: &ShutdownEvent) {
+        // this runs after the normal handlers, so transient KVS stores have flushed their writes.
+        if let Err(e) = crate::transient::snapshot_on_shutdown(target).await {
+            e.report_error();
+        }
+    }
+
+    #[event_handler]
+    async fn register_config(
+        &self, target: &Handler<impl Events>, ev: &mut RegisterConfigEvent,
+    ) -> Result<()> {
+        crate::maintenance::register_config(target, self.info(), ev).await?;
+        #[cfg(feature = "otlp")]
+        crate::otlp::register_config(target, self.info(), ev).await?;
         Ok(())
     }
 
     #[event_handler]
     fn setup_logger(ev: &mut SetupLoggerEvent) {
-        ev.add_console_directive("sylphie_database=debug");
+        ev.set_crate_level("sylphie_database", LevelFilter::DEBUG);
+    }
+
+    #[event_handler]
+    fn setup_terminal(_ev: &mut SetupTerminalEvent) {
+        #[cfg(feature = "sqlcipher")]
+        crate::encryption::setup_terminal(_ev);
     }
 }
\ No newline at end of file
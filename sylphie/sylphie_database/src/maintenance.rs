@@ -0,0 +1,134 @@
+//! Periodic maintenance of the persistent and transient databases.
+//!
+//! The interval of each operation is set in minutes with a global configuration option, and an
+//! interval of `0` disables that operation.
+
+use crate::config::*;
+use crate::connection::*;
+use std::time::{Duration, Instant};
+use sylphie_core::prelude::*;
+
+const CFG_VACUUM_INTERVAL: ConfigKey<u32> =
+    config_option!(Global, "sylphie_database.maintenance.vacuum_interval", || 60 * 24);
+const CFG_ANALYZE_INTERVAL: ConfigKey<u32> =
+    config_option!(Global, "sylphie_database.maintenance.analyze_interval", || 60 * 24);
+const CFG_CHECKPOINT_INTERVAL: ConfigKey<u32> =
+    config_option!(Global, "sylphie_database.maintenance.checkpoint_interval", || 15);
+
+const MAINTENANCE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Copy, Clone, Debug)]
+enum MaintenanceOp {
+    Vacuum,
+    Analyze,
+    Checkpoint,
+}
+impl MaintenanceOp {
+    fn name(self) -> &'static str {
+        match self {
+            MaintenanceOp::Vacuum => "vacuum",
+            MaintenanceOp::Analyze => "analyze",
+            MaintenanceOp::Checkpoint => "checkpoint",
+        }
+    }
+
+    fn config_key(self) -> ConfigKey<u32> {
+        match self {
+            MaintenanceOp::Vacuum => CFG_VACUUM_INTERVAL,
+            MaintenanceOp::Analyze => CFG_ANALYZE_INTERVAL,
+            MaintenanceOp::Checkpoint => CFG_CHECKPOINT_INTERVAL,
+        }
+    }
+
+    fn sql(self, dialect: SqlDialect) -> Option<&'static str> {
+        match (dialect, self) {
+            (SqlDialect::Sqlite, MaintenanceOp::Vacuum) =>
+                Some("PRAGMA main.incremental_vacuum; PRAGMA transient.incremental_vacuum;"),
+            (SqlDialect::Sqlite, MaintenanceOp::Analyze) =>
+                Some("ANALYZE main; ANALYZE transient;"),
+            (SqlDialect::Sqlite, MaintenanceOp::Checkpoint) => Some(
+                "PRAGMA main.wal_checkpoint(TRUNCATE); PRAGMA transient.wal_checkpoint(TRUNCATE);",
+            ),
+            (SqlDialect::Postgres, MaintenanceOp::Vacuum) => Some("VACUUM;"),
+            (SqlDialect::Postgres, MaintenanceOp::Analyze) => Some("ANALYZE;"),
+            // Postgres checkpoints on its own, and requires superuser access to force one.
+            (SqlDialect::Postgres, MaintenanceOp::Checkpoint) => None,
+        }
+    }
+}
+const ALL_OPS: [MaintenanceOp; 3] =
+    [MaintenanceOp::Vacuum, MaintenanceOp::Analyze, MaintenanceOp::Checkpoint];
+
+/// Registers the configuration options for database maintenance.
+pub(crate) async fn register_config(
+    target: &Handler<impl Events>, module: &ModuleInfo, ev: &mut RegisterConfigEvent,
+) -> Result<()> {
+    ev.register_config(target, module, "db_vacuum_interval", &CFG_VACUUM_INTERVAL).await?;
+    ev.register_config(target, module, "db_analyze_interval", &CFG_ANALYZE_INTERVAL).await?;
+    ev.register_config(target, module, "db_checkpoint_interval", &CFG_CHECKPOINT_INTERVAL).await?;
+    Ok(())
+}
+
+/// Rebuilds databases that were created before incremental vacuuming was enabled.
+///
+/// SQLite only changes the `auto_vacuum` mode of a database that already contains tables when
+/// it is rebuilt with `VACUUM`, so this runs once for each older database.
+pub(crate) async fn enable_auto_vacuum(target: &Handler<impl Events>) -> Result<()> {
+    let database = target.get_service::<Database>();
+    if database.dialect() != SqlDialect::Sqlite {
+        return Ok(())
+    }
+    let mut conn = database.connect().await?;
+    let schemas = [
+        ("main", "PRAGMA main.auto_vacuum;", "VACUUM main;"),
+        ("transient", "PRAGMA transient.auto_vacuum;", "VACUUM transient;"),
+    ];
+    for (name, mode_sql, vacuum_sql) in schemas.iter() {
+        // `2` is the value of the `incremental` mode.
+        let mode: u32 = conn.query_row_nullary(*mode_sql).await?.unwrap_or(2);
+        if mode != 2 {
+            info!("Rebuilding the {} database to enable incremental vacuuming.", name);
+            conn.execute_batch(*vacuum_sql).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_op(target: &Handler<impl Events>, op: MaintenanceOp) -> Result<()> {
+    let database = target.get_service::<Database>();
+    if let Some(sql) = op.sql(database.dialect()) {
+        let start = Instant::now();
+        database.connect().await?.execute_batch(sql).await?;
+        debug!("Database {} finished in {} ms.", op.name(), start.elapsed().as_millis());
+    }
+    Ok(())
+}
+
+async fn run_if_due(
+    target: &Handler<impl Events>, op: MaintenanceOp, last_run: &mut Instant,
+) -> Result<()> {
+    let config = target.get_service::<ConfigManager>();
+    let minutes = config.get(target, GLOBAL_SCOPE, op.config_key()).await?;
+    if minutes != 0 && last_run.elapsed() >= Duration::from_secs(minutes as u64 * 60) {
+        *last_run = Instant::now();
+        run_op(target, op).await?;
+    }
+    Ok(())
+}
+
+/// Starts the task that periodically runs maintenance operations on the database.
+pub(crate) fn start_maintenance_task(target: &Handler<impl Events>, module: &impl Module) {
+    let handler = target.clone();
+    module.spawn(target, "maintenance", async move {
+        let mut last_run = [Instant::now(); ALL_OPS.len()];
+        let mut interval = tokio::time::interval(MAINTENANCE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            for (op, last_run) in ALL_OPS.iter().zip(last_run.iter_mut()) {
+                if let Err(e) = run_if_due(&handler, *op, last_run).await {
+                    e.report_error();
+                }
+            }
+        }
+    });
+}
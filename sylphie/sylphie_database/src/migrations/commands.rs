@@ -0,0 +1,52 @@
+//! The terminal command used to roll back migration sets.
+
+use crate::migrations::*;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, TerminalOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::prelude::*;
+
+struct RollbackMigrationCommand;
+impl RollbackMigrationCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        if ctx.args_count() != 3 {
+            cmd_error!("Usage: rollback_migration <migration set> <version>");
+        }
+        let target = ctx.handler();
+        let name = ctx.arg(1).text;
+        let version: u32 = match ctx.arg(2).text.parse() {
+            Ok(version) => version,
+            Err(_) => cmd_error!("'{}' is not a valid schema version.", ctx.arg(2).text),
+        };
+
+        let manager = target.get_service::<MigrationManager>();
+        let migration = manager.find_migration(name)
+            .cmd_error(|| format!("No migration set named '{}' has been run.", name))?;
+        migration.execute_rollback(target, version).await?;
+
+        ctx.respond(&format!(
+            "Rolled back migration set {} to version {}. The bot should be restarted with code \
+             that expects this schema version, as the migrations will otherwise be run again \
+             on the next start.",
+            migration.migration_set_name, version,
+        )).await?;
+        Ok(())
+    }
+}
+impl CommandImpl for RollbackMigrationCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `rollback_migration` command.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("rollback_migration");
+    ev.register_command(Command::new(target, module, info, TerminalOnly(RollbackMigrationCommand)));
+}
@@ -0,0 +1,624 @@
+use crate::connection::*;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use sylphie_core::cli::CliArgs;
+use sylphie_core::errors::*;
+use tokio::runtime::Handle;
+
+mod commands;
+pub(crate) use commands::register_commands;
+
+/// Stores the data for a given migration.
+#[derive(Copy, Clone, Debug)]
+pub struct MigrationScriptData {
+    /// The schema version this script migrates from.
+    ///
+    /// Note that version `0` represents a newly initialized database with no tables at all.
+    pub from: u32,
+    /// The schema version this script migrates to.
+    ///
+    /// Note that version `0` represents a newly initialized database with no tables at all.
+    pub to: u32,
+    /// The name of the migration script.
+    pub script_name: &'static str,
+    /// The migration script to run.
+    pub script_data: &'static str,
+    /// A script that reverts this migration, migrating from `to` back to `from`.
+    ///
+    /// Migrations without a down script cannot be rolled back.
+    pub down_script_data: Option<&'static str>,
+    /// Rust code to run after the migration script, in the same transaction.
+    ///
+    /// This allows migrations to transform data in ways that cannot be done in SQL, such as
+    /// changing how values are serialized.
+    ///
+    /// Compiled code cannot be checksummed, so migrations with code are not checked for changes
+    /// after they are applied.
+    pub code: Option<MigrationCode>,
+}
+
+/// A migration step implemented in Rust.
+///
+/// This is created from a function taking the [`DbOps`] of the migration's transaction. The
+/// functions are usually written as:
+///
+/// ```ignore
+/// fn migrate_values(conn: &mut DbOps) -> BoxFuture<'_, Result<()>> {
+///     async move {
+///         // ...
+///         Ok(())
+///     }.boxed()
+/// }
+/// ```
+#[derive(Copy, Clone)]
+pub struct MigrationCode(pub for<'a> fn(&'a mut DbOps) -> BoxFuture<'a, Result<()>>);
+impl fmt::Debug for MigrationCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MigrationCode(..)")
+    }
+}
+
+/// Stores the data for a given set of migrations.
+#[derive(Copy, Clone, Debug)]
+pub struct MigrationData {
+    /// The name of the migration set that is stored internally in the database.
+    ///
+    /// As this should not change, and should not conflict with any other migration set, this
+    /// should contain something unique such as an UUID.
+    pub migration_id: &'static str,
+    /// The name of the migration set that is disabled to the user.
+    pub migration_set_name: &'static str,
+    /// Whether this migration set is for the transient database.
+    pub is_transient: bool,
+    /// The current schema version.
+    pub target_version: u32,
+    /// A list of migrations for this migration set.
+    ///
+    /// Each migration is checked in order, and if the current version matches the current version,
+    /// it will be applied. Therefore, scripts should be sorted in the order you want them to be
+    /// applied in.
+    pub scripts: &'static [MigrationScriptData],
+}
+impl MigrationData {
+    /// Applies this migration set immediately.
+    ///
+    /// Sets registered with [`RegisterMigrationsEvent`] are applied automatically during startup,
+    /// so this is only needed for migration sets that are applied at some other time.
+    pub async fn execute(&'static self, target: &Handler<impl Events>) -> Result<()> {
+        target.get_service::<MigrationManager>().execute_migration(self).await
+    }
+    pub fn execute_sync(&'static self, target: &Handler<impl Events>) -> Result<()> {
+        target.get_service::<MigrationManager>().execute_migration_sync(self)
+    }
+
+    /// Determines which scripts would be run to migrate this set, without applying them.
+    pub async fn plan(&'static self, target: &Handler<impl Events>) -> Result<MigrationPlan> {
+        target.get_service::<MigrationManager>().plan(self).await
+    }
+
+    /// Rolls back this migration set to an earlier schema version using its down scripts.
+    ///
+    /// Code using the migration set will generally expect the current schema version, so the
+    /// bot should be restarted after this. Unless the migration set itself is changed, it will be
+    /// migrated forward again on the next start.
+    pub async fn execute_rollback(
+        &'static self, target: &Handler<impl Events>, target_version: u32,
+    ) -> Result<()> {
+        target.get_service::<MigrationManager>().execute_rollback(self, target_version).await
+    }
+}
+
+/// Defines a migration script.
+///
+/// This may be used as `migration_script!(from, to, "script.sql")`, optionally followed by
+/// `down: "down_script.sql"` to allow the migration to be rolled back. Migration steps written in
+/// Rust are defined with `migration_script!(from, to, code: function_name)`, and unlike scripts,
+/// are not checked for changes once they have been applied.
+#[macro_export]
+macro_rules! migration_script_ff344e40783a4f25b33f98135991d80f {
+    ($from:expr, $to:expr, code: $code:path $(,)?) => {
+        $crate::migrations::MigrationScriptData {
+            from: $from,
+            to: $to,
+            script_name: stringify!($code),
+            script_data: "",
+            down_script_data: None,
+            code: Some($crate::migrations::MigrationCode($code)),
+        }
+    };
+    ($from:expr, $to:expr, $source:expr $(,)?) => {
+        $crate::migrations::MigrationScriptData {
+            from: $from,
+            to: $to,
+            script_name: $source,
+            script_data: include_str!($source),
+            down_script_data: None,
+            code: None,
+        }
+    };
+    ($from:expr, $to:expr, $source:expr, down: $down_source:expr $(,)?) => {
+        $crate::migrations::MigrationScriptData {
+            from: $from,
+            to: $to,
+            script_name: $source,
+            script_data: include_str!($source),
+            down_script_data: Some(include_str!($down_source)),
+            code: None,
+        }
+    };
+}
+
+#[doc(inline)]
+pub use crate::{migration_script_ff344e40783a4f25b33f98135991d80f as migration_script};
+
+/// The scripts that would be run to bring a migration set up to date.
+#[derive(Clone, Debug)]
+pub struct MigrationPlan {
+    /// The migration set this plan is for.
+    pub migration: &'static MigrationData,
+    /// The schema version currently stored in the database.
+    pub current_version: u32,
+    /// The scripts that would be run, in order.
+    pub scripts: Vec<&'static MigrationScriptData>,
+}
+impl MigrationPlan {
+    /// Returns the schema version the database would be at after running the scripts.
+    pub fn final_version(&self) -> u32 {
+        self.scripts.last().map_or(self.current_version, |x| x.to)
+    }
+
+    /// Returns whether the migration set is already at its target version.
+    pub fn is_up_to_date(&self) -> bool {
+        self.scripts.is_empty() && self.current_version == self.migration.target_version
+    }
+
+    /// Returns whether running the scripts would bring the set to its target version.
+    pub fn reaches_target(&self) -> bool {
+        self.final_version() == self.migration.target_version
+    }
+}
+
+/// Dispatched to collect the migration sets used by the bot.
+///
+/// Every migration set registered by this event is applied automatically during startup, before
+/// [`InitDbEvent`](`crate::InitDbEvent`) is dispatched, so modules only need to handle this event
+/// rather than executing their migrations manually. It is also used to check which migrations
+/// would be run when the bot is started with `--check-migrations`.
+///
+/// This event is dispatched synchronously.
+pub struct RegisterMigrationsEvent {
+    migrations: Vec<&'static MigrationData>,
+    deps: HashMap<&'static str, Vec<&'static str>>,
+}
+self_event!(RegisterMigrationsEvent);
+impl RegisterMigrationsEvent {
+    pub(crate) fn new() -> Self {
+        RegisterMigrationsEvent { migrations: Vec::new(), deps: HashMap::new() }
+    }
+
+    /// Registers a migration set.
+    pub fn add_migration(&mut self, migration: &'static MigrationData) {
+        self.migrations.push(migration);
+    }
+
+    /// Registers a migration set that must be applied after a list of other migration sets.
+    ///
+    /// This is needed when a migration creates foreign keys to tables in another set, or when
+    /// migration code reads data another set creates.
+    pub fn add_migration_after(
+        &mut self, migration: &'static MigrationData, deps: &[&'static MigrationData],
+    ) {
+        self.migrations.push(migration);
+        self.deps.entry(migration.migration_id).or_default()
+            .extend(deps.iter().map(|x| x.migration_id));
+    }
+
+    pub(crate) fn migrations(&self) -> &[&'static MigrationData] {
+        &self.migrations
+    }
+
+    /// Returns the registered migration sets in the order they should be applied.
+    ///
+    /// Sets are kept in registration order unless a dependency requires otherwise.
+    pub(crate) fn sorted_migrations(&self) -> Result<Vec<&'static MigrationData>> {
+        let mut by_id = HashMap::new();
+        for migration in &self.migrations {
+            by_id.insert(migration.migration_id, *migration);
+        }
+
+        fn visit(
+            ev: &RegisterMigrationsEvent,
+            by_id: &HashMap<&'static str, &'static MigrationData>,
+            state: &mut HashMap<&'static str, bool>,
+            sorted: &mut Vec<&'static MigrationData>,
+            migration: &'static MigrationData,
+        ) -> Result<()> {
+            match state.get(migration.migration_id) {
+                Some(true) => return Ok(()),
+                Some(false) => bail!(
+                    "Migration set {} depends on itself.", migration.migration_set_name,
+                ),
+                None => {}
+            }
+            state.insert(migration.migration_id, false);
+            for dep in ev.deps.get(migration.migration_id).into_iter().flatten() {
+                let dep_migration = by_id.get(dep).internal_err(|| format!(
+                    "Migration set {} depends on {}, which is not registered.",
+                    migration.migration_set_name, dep,
+                ))?;
+                visit(ev, by_id, state, sorted, dep_migration)?;
+            }
+            state.insert(migration.migration_id, true);
+            sorted.push(migration);
+            Ok(())
+        }
+
+        let mut state = HashMap::new();
+        let mut sorted = Vec::new();
+        for migration in &self.migrations {
+            visit(self, &by_id, &mut state, &mut sorted, migration)?;
+        }
+        Ok(sorted)
+    }
+}
+
+/// Applies every registered migration set, in dependency order.
+pub(crate) async fn execute_registered(target: &Handler<impl Events>) -> Result<()> {
+    let ev = target.dispatch_sync(RegisterMigrationsEvent::new());
+    let manager = target.get_service::<MigrationManager>();
+    for migration in ev.sorted_migrations()? {
+        manager.execute_migration(migration).await?;
+    }
+    Ok(())
+}
+
+/// Returns whether the bot was started with `--check-migrations` or `--check`.
+pub(crate) fn is_check_mode(target: &Handler<impl Events>) -> bool {
+    let args = target.get_service::<CliArgs>();
+    args.check_migrations || args.check
+}
+
+pub struct MigrationManager {
+    pool: Database,
+    data: Arc<Mutex<MigrationManagerState>>,
+}
+impl MigrationManager {
+    pub(in super) fn new(pool: Database) -> Self {
+        MigrationManager {
+            pool,
+            data: Arc::new(Mutex::new(MigrationManagerState {
+                tables_created: false,
+                repeat_transaction_watch: HashMap::new(),
+            })),
+        }
+    }
+
+    pub async fn execute_migration(&self, migration: &'static MigrationData) -> Result<()> {
+        let pool = self.pool.clone();
+        let data = self.data.clone();
+        Handle::current().spawn_blocking(move || -> Result<()> {
+            pool.retry_serializable_sync(|mut connection| {
+                data.lock().execute_migration(&mut connection, pool.dialect(), migration)
+            })
+        }).await?
+    }
+
+    pub fn execute_migration_sync(&self, migration: &'static MigrationData) -> Result<()> {
+        self.pool.retry_serializable_sync(|mut connection| {
+            self.data.lock().execute_migration(&mut connection, self.pool.dialect(), migration)
+        })
+    }
+
+    pub async fn execute_rollback(
+        &self, migration: &'static MigrationData, target_version: u32,
+    ) -> Result<()> {
+        let pool = self.pool.clone();
+        let data = self.data.clone();
+        Handle::current().spawn_blocking(move || -> Result<()> {
+            pool.retry_serializable_sync(|mut connection| {
+                let dialect = pool.dialect();
+                data.lock().execute_rollback(&mut connection, dialect, migration, target_version)
+            })
+        }).await?
+    }
+
+    pub async fn plan(&self, migration: &'static MigrationData) -> Result<MigrationPlan> {
+        let pool = self.pool.clone();
+        Handle::current().spawn_blocking(move || -> Result<MigrationPlan> {
+            let mut connection = pool.connect_sync()?;
+            MigrationManagerState::plan(&mut connection, migration)
+        }).await?
+    }
+
+    pub fn plan_sync(&self, migration: &'static MigrationData) -> Result<MigrationPlan> {
+        let mut connection = self.pool.connect_sync()?;
+        MigrationManagerState::plan(&mut connection, migration)
+    }
+
+    /// Finds a migration set that has been executed, by its name or ID.
+    pub fn find_migration(&self, name: &str) -> Option<&'static MigrationData> {
+        let data = self.data.lock();
+        data.repeat_transaction_watch.values()
+            .find(|x| x.migration_set_name == name || x.migration_id == name)
+            .copied()
+    }
+}
+
+struct MigrationManagerState {
+    tables_created: bool,
+    repeat_transaction_watch: HashMap<&'static str, &'static MigrationData>,
+}
+impl MigrationManagerState {
+    fn create_migrations_table(&mut self, conn: &mut DbSyncConnection) -> Result<()> {
+        if !self.tables_created {
+            conn.execute_batch(create_migrations_table_sql(false))?;
+            conn.execute_batch(create_migrations_table_sql(true))?;
+            self.tables_created = true;
+        }
+        Ok(())
+    }
+
+    fn execute_migration(
+        &mut self,
+        conn: &mut DbSyncConnection,
+        dialect: SqlDialect,
+        migration: &'static MigrationData,
+    ) -> Result<()> {
+        self.create_migrations_table(conn)?;
+        if let Some(data) = self.repeat_transaction_watch.get(&migration.migration_id) {
+            let data_off = data as *const _ as usize;
+            let migration_off = migration as *const _ as usize;
+            if data_off == migration_off {
+                warn!(
+                    "Migration set {} has been executed more than once!",
+                    migration.migration_id,
+                );
+            } else {
+                warn!(
+                    "Migration set id {} conflicts! ({} at 0x{:x}, {} at 0x{:x})",
+                    migration.migration_id, migration.migration_set_name, migration_off,
+                    data.migration_set_name, data_off,
+                )
+            }
+        }
+
+        trace!("Running migration set {}", migration.migration_set_name);
+
+        let mut transaction = conn.transaction_with_type(TransactionType::Exclusive)?;
+        verify_checksums(&mut transaction, migration)?;
+        let start_version: u32 = transaction.query_row(
+            query_migrations_table_sql(migration.is_transient),
+            migration.migration_id,
+        )?.unwrap_or(0);
+        let mut current_version = start_version;
+        for script in migration.scripts {
+            if current_version == script.from {
+                debug!(
+                    "Running migration {}/{}",
+                    migration.migration_set_name,
+                    script.script_name.rsplit('/').next().unwrap(),
+                );
+                transaction.execute_batch(script.script_data)?;
+                if let Some(code) = script.code {
+                    transaction.block_on_async(code.0)?;
+                }
+                transaction.execute(
+                    replace_migrations_table_sql(dialect, migration.is_transient),
+                    (migration.migration_id, script.to),
+                )?;
+                if let Some(checksum) = script_checksum(script) {
+                    transaction.execute(
+                        replace_checksum_sql(dialect, migration.is_transient),
+                        (migration.migration_id, script.from, script.to, checksum),
+                    )?;
+                }
+                current_version = script.to;
+            }
+        }
+        if migration.target_version != current_version {
+            error!(
+                "Could not apply migration {} to version {}. (got from {} to {})",
+                migration.migration_set_name, migration.target_version,
+                start_version, current_version,
+            );
+            bail!("Could not successfully apply migration.");
+        }
+        transaction.commit()?;
+
+        self.repeat_transaction_watch.insert(migration.migration_id, migration);
+
+        Ok(())
+    }
+
+    fn plan(
+        conn: &mut DbSyncConnection, migration: &'static MigrationData,
+    ) -> Result<MigrationPlan> {
+        // this transaction is never committed, so creating the tracking table here does not
+        // make any changes to the database.
+        let mut transaction = conn.transaction()?;
+        transaction.execute_batch(create_migrations_table_sql(migration.is_transient))?;
+        verify_checksums(&mut transaction, migration)?;
+        let current_version: u32 = transaction.query_row(
+            query_migrations_table_sql(migration.is_transient),
+            migration.migration_id,
+        )?.unwrap_or(0);
+
+        let mut version = current_version;
+        let mut scripts = Vec::new();
+        for script in migration.scripts {
+            if version == script.from {
+                scripts.push(script);
+                version = script.to;
+            }
+        }
+        Ok(MigrationPlan { migration, current_version, scripts })
+    }
+
+    fn execute_rollback(
+        &mut self,
+        conn: &mut DbSyncConnection,
+        dialect: SqlDialect,
+        migration: &'static MigrationData,
+        target_version: u32,
+    ) -> Result<()> {
+        self.create_migrations_table(conn)?;
+
+        let mut transaction = conn.transaction_with_type(TransactionType::Exclusive)?;
+        let start_version: u32 = transaction.query_row(
+            query_migrations_table_sql(migration.is_transient),
+            migration.migration_id,
+        )?.unwrap_or(0);
+        ensure!(
+            target_version <= start_version,
+            "Migration set {} is at version {}, and cannot be rolled back to version {}.",
+            migration.migration_set_name, start_version, target_version,
+        );
+
+        let mut current_version = start_version;
+        while current_version != target_version {
+            let script = migration.scripts.iter().rev().find(|x| {
+                x.to == current_version && x.from < current_version && x.from >= target_version &&
+                    x.down_script_data.is_some()
+            });
+            let script = match script {
+                Some(script) => script,
+                None => {
+                    error!(
+                        "No down script found to roll back migration {} from version {}.",
+                        migration.migration_set_name, current_version,
+                    );
+                    bail!("Could not successfully roll back migration.");
+                }
+            };
+            debug!(
+                "Rolling back migration {}/{}",
+                migration.migration_set_name,
+                script.script_name.rsplit('/').next().unwrap(),
+            );
+            transaction.execute_batch(script.down_script_data.unwrap())?;
+            transaction.execute(
+                delete_checksum_sql(migration.is_transient),
+                (migration.migration_id, script.from, script.to),
+            )?;
+            current_version = script.from;
+        }
+        transaction.execute(
+            replace_migrations_table_sql(dialect, migration.is_transient),
+            (migration.migration_id, current_version),
+        )?;
+        transaction.commit()?;
+
+        info!(
+            "Rolled back migration set {} from version {} to version {}.",
+            migration.migration_set_name, start_version, current_version,
+        );
+        Ok(())
+    }
+}
+fn create_migrations_table_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            CREATE TABLE IF NOT EXISTS {0}sylphie_db_migrations_tracking ( \
+                migration_name TEXT NOT NULL PRIMARY KEY, \
+                current_version INTEGER NOT NULL \
+            ) WITHOUT ROWID; \
+            CREATE TABLE IF NOT EXISTS {0}sylphie_db_migrations_checksums ( \
+                migration_name TEXT NOT NULL, \
+                from_version INTEGER NOT NULL, \
+                to_version INTEGER NOT NULL, \
+                checksum TEXT NOT NULL, \
+                PRIMARY KEY (migration_name, from_version, to_version) \
+            ) WITHOUT ROWID; \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
+fn query_migrations_table_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            SELECT current_version FROM {}sylphie_db_migrations_tracking \
+                WHERE migration_name = ?; \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
+fn replace_migrations_table_sql(dialect: SqlDialect, is_transient: bool) -> String {
+    dialect.upsert(
+        &format!(
+            "{}sylphie_db_migrations_tracking",
+            if is_transient { "transient." } else { "" },
+        ),
+        &["migration_name"],
+        &["migration_name", "current_version"],
+    )
+}
+
+/// Returns the checksum of a migration script, or `None` for migrations with code, which cannot
+/// be checksummed.
+fn script_checksum(script: &MigrationScriptData) -> Option<String> {
+    match script.code {
+        Some(_) => None,
+        None => Some(blake3::hash(script.script_data.as_bytes()).to_hex().to_string()),
+    }
+}
+
+/// Checks that the scripts that were already applied for a migration set have not changed since.
+///
+/// Databases created before checksums were recorded have no checksums for their old scripts,
+/// and those scripts are not checked. Neither are migrations with code, even if a checksum was
+/// recorded for them.
+fn verify_checksums(
+    conn: &mut DbSyncTransaction<'_>, migration: &'static MigrationData,
+) -> Result<()> {
+    let applied: Vec<(u32, u32, String)> = conn.query_vec(
+        query_checksums_sql(migration.is_transient), migration.migration_id,
+    )?;
+    let mut mismatched = false;
+    for (from, to, checksum) in applied {
+        let script = migration.scripts.iter().find(|x| x.from == from && x.to == to);
+        if let Some(script) = script {
+            if script_checksum(script).map_or(false, |x| x != checksum) {
+                error!(
+                    "Migration script {} of {} ({} -> {}) has been changed since it was applied \
+                     to the database!",
+                    script.script_name, migration.migration_set_name, from, to,
+                );
+                mismatched = true;
+            }
+        }
+    }
+    ensure!(!mismatched, "Applied migration scripts do not match the scripts in the bot.");
+    Ok(())
+}
+fn query_checksums_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            SELECT from_version, to_version, checksum FROM {}sylphie_db_migrations_checksums \
+                WHERE migration_name = ?; \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
+fn replace_checksum_sql(dialect: SqlDialect, is_transient: bool) -> String {
+    dialect.upsert(
+        &format!(
+            "{}sylphie_db_migrations_checksums",
+            if is_transient { "transient." } else { "" },
+        ),
+        &["migration_name", "from_version", "to_version"],
+        &["migration_name", "from_version", "to_version", "checksum"],
+    )
+}
+fn delete_checksum_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            DELETE FROM {}sylphie_db_migrations_checksums \
+                WHERE migration_name = ? AND from_version = ? AND to_version = ?; \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
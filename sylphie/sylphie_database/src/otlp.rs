@@ -0,0 +1,52 @@
+//! Configures the OTLP exporter from a global configuration option.
+//!
+//! The `otlp_endpoint` option is checked once a minute, and the logger is reloaded whenever it
+//! changes. Setting it to an empty string stops spans from being exported. Only spans are
+//! exported, and metrics are still served in the Prometheus format.
+
+use crate::config::*;
+use std::time::Duration;
+use sylphie_core::interface::Interface;
+use sylphie_core::prelude::*;
+
+const CFG_OTLP_ENDPOINT: ConfigKey<String> =
+    config_option!(Global, "sylphie_database.otlp.endpoint", || String::new());
+
+const OTLP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Registers the configuration option for the OTLP exporter.
+pub(crate) async fn register_config(
+    target: &Handler<impl Events>, module: &ModuleInfo, ev: &mut RegisterConfigEvent,
+) -> Result<()> {
+    ev.register_config(target, module, "otlp_endpoint", &CFG_OTLP_ENDPOINT).await
+}
+
+async fn apply_endpoint(target: &Handler<impl Events>) -> Result<()> {
+    let config = target.get_service::<ConfigManager>();
+    let endpoint = config.get(target, GLOBAL_SCOPE, CFG_OTLP_ENDPOINT).await?;
+    let endpoint = Some(endpoint.trim()).filter(|x| !x.is_empty());
+
+    let interface = target.get_service::<Interface>();
+    if interface.otlp_endpoint().as_deref() != endpoint {
+        interface.set_otlp_endpoint(target, endpoint)?;
+        match endpoint {
+            Some(endpoint) => info!("Exporting spans to OTLP collector at {}.", endpoint),
+            None => info!("Stopped exporting spans to OTLP collector."),
+        }
+    }
+    Ok(())
+}
+
+/// Starts the task that applies changes to the OTLP exporter configuration.
+pub(crate) fn start_otlp_task(target: &Handler<impl Events>, module: &impl Module) {
+    let handler = target.clone();
+    module.spawn(target, "otlp", async move {
+        let mut interval = tokio::time::interval(OTLP_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = apply_endpoint(&handler).await {
+                e.report_error();
+            }
+        }
+    });
+}
@@ -0,0 +1,163 @@
+//! A full-text search index backed by SQLite's FTS5 extension.
+
+use arc_swap::ArcSwapOption;
+use crate::connection::*;
+use crate::serializable::*;
+use static_events::prelude_async::*;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use sylphie_core::derives::*;
+use sylphie_core::prelude::*;
+
+/// A document found by [`SearchStore::search`].
+#[derive(Clone, Debug)]
+pub struct SearchResult<K> {
+    /// The key the document was indexed with.
+    pub key: K,
+    /// How well the document matches the query. Lower scores are better matches.
+    pub score: f64,
+    /// A fragment of the document around the matched terms, with the terms surrounded by `[`
+    /// and `]`.
+    pub snippet: String,
+}
+
+struct SearchQueries {
+    db: Database,
+    add_key_query: Arc<str>,
+    find_key_query: Arc<str>,
+    index_query: Arc<str>,
+    delete_query: Arc<str>,
+    delete_key_query: Arc<str>,
+    search_query: Arc<str>,
+}
+impl SearchQueries {
+    fn new(db: Database, module_name: &str) -> (Self, String) {
+        let hash = blake3::hash(module_name.as_bytes()).to_hex();
+        let table_name = format!("sylphie_db_fts_{}", &hash.as_str()[0..16]);
+        let keys_table = format!("sylphie_db_fts_keys_{}", &hash.as_str()[0..16]);
+        // documents are stored in the index under the rowid their key is mapped to, so they can
+        // be found without a full scan of the index.
+        let create_query = format!(
+            "CREATE TABLE IF NOT EXISTS {1} (doc_id INTEGER PRIMARY KEY, key NOT NULL UNIQUE); \
+             CREATE VIRTUAL TABLE IF NOT EXISTS {0} USING fts5(body);",
+            table_name, keys_table,
+        );
+        (SearchQueries {
+            db,
+            add_key_query: format!("INSERT OR IGNORE INTO {} (key) VALUES (?);", keys_table)
+                .into(),
+            find_key_query: format!("SELECT doc_id FROM {} WHERE key = ?;", keys_table).into(),
+            index_query: format!("INSERT INTO {} (rowid, body) VALUES (?, ?);", table_name).into(),
+            delete_query: format!(
+                "DELETE FROM {} WHERE rowid = (SELECT doc_id FROM {} WHERE key = ?);",
+                table_name, keys_table,
+            ).into(),
+            delete_key_query: format!("DELETE FROM {} WHERE key = ?;", keys_table).into(),
+            search_query: format!(
+                "SELECT {1}.key, bm25({0}), snippet({0}, 0, '[', ']', '...', 16) \
+                 FROM {0} JOIN {1} ON {1}.doc_id = {0}.rowid \
+                 WHERE {0} MATCH ? ORDER BY rank LIMIT ?;",
+                table_name, keys_table,
+            ).into(),
+        }, create_query)
+    }
+}
+
+/// Returns whether an error is SQLite rejecting the syntax of a FTS5 query.
+fn is_query_syntax_error(err: &Error) -> bool {
+    let message = match err.error_kind() {
+        ErrorKind::GenericError(e) => match e.downcast_ref::<rusqlite::Error>() {
+            Some(rusqlite::Error::SqliteFailure(_, Some(message))) => message,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    // column filters such as `title: word` name a column that does not exist.
+    message.starts_with("fts5:") || message.starts_with("no such column") ||
+        message.starts_with("unknown special query")
+}
+
+/// A full-text search index over text documents such as quotes or notes.
+///
+/// Each document is identified by a key, and is replaced when it is indexed again under the same
+/// key. Queries use the FTS5 query syntax, and results are ranked by relevance.
+///
+/// This is a module, and should be used by attaching it to the your module as a submodule. It is
+/// only supported on SQLite databases.
+#[derive(Module)]
+#[module(component)]
+pub struct SearchStore<K: DbSerializable> {
+    #[module_info] info: ModuleInfo,
+    queries: ArcSwapOption<SearchQueries>,
+    phantom: PhantomData<fn(K)>,
+}
+#[module_impl]
+impl <K: DbSerializable> SearchStore<K> {
+    #[event_handler]
+    async fn init_search(
+        &self, target: &Handler<impl Events>, _: &crate::InitDbEvent,
+    ) -> Result<()> {
+        let db = target.get_service::<Database>().clone();
+        ensure!(
+            db.dialect() == SqlDialect::Sqlite,
+            "Full-text search in '{}' is only supported on SQLite databases.", self.info.name(),
+        );
+        let (queries, create_query) = SearchQueries::new(db, self.info.name());
+        queries.db.connect().await?.execute_batch(create_query).await?;
+        self.queries.store(Some(Arc::new(queries)));
+        Ok(())
+    }
+
+    fn load_queries(&self) -> Arc<SearchQueries> {
+        self.queries.load().as_ref().expect("SearchStore not yet initialized.").clone()
+    }
+
+    /// Adds a document to the index, replacing any document with the same key.
+    pub async fn index(&self, k: &K, text: &str) -> Result<()> {
+        let queries = self.load_queries();
+        let key = K::Format::serialize(k)?;
+        let mut conn = queries.db.connect().await?;
+        let mut transaction = conn.transaction().await?;
+        transaction.execute(queries.delete_query.clone(), key.clone()).await?;
+        transaction.execute(queries.add_key_query.clone(), key.clone()).await?;
+        let doc_id: i64 = transaction.query_row(queries.find_key_query.clone(), key).await?
+            .internal_err(|| "Search key was not added?")?;
+        transaction.execute(queries.index_query.clone(), (doc_id, text.to_string())).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Removes a document from the index.
+    pub async fn remove(&self, k: &K) -> Result<()> {
+        let queries = self.load_queries();
+        let key = K::Format::serialize(k)?;
+        let mut conn = queries.db.connect().await?;
+        let mut transaction = conn.transaction().await?;
+        transaction.execute(queries.delete_query.clone(), key.clone()).await?;
+        transaction.execute(queries.delete_key_query.clone(), key).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Searches the index, returning at most `limit` documents, best matches first.
+    ///
+    /// The query uses the FTS5 syntax, in which words are matched individually and phrases can
+    /// be quoted. A malformed query is reported as an error to the user.
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult<K>>> {
+        let queries = self.load_queries();
+        let mut conn = queries.db.connect().await?;
+        let rows: Vec<(SerializeValue, f64, String)> = match conn.query_vec(
+            queries.search_query.clone(), (query.to_string(), limit as i64),
+        ).await {
+            Ok(rows) => rows,
+            Err(e) if is_query_syntax_error(&e) => cmd_error!("Invalid search query."),
+            Err(e) => return Err(e),
+        };
+
+        let mut results = Vec::with_capacity(rows.len());
+        for (key, score, snippet) in rows {
+            results.push(SearchResult { key: K::Format::deserialize(key)?, score, snippet });
+        }
+        Ok(results)
+    }
+}
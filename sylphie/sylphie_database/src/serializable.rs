@@ -1,4 +1,5 @@
 use bincode::Options;
+use crate::kvs::KvsIndex;
 use serde::*;
 use serde::de::{DeserializeOwned, Visitor, Error as DeError, SeqAccess};
 use serde::de::value::SeqAccessDeserializer;
@@ -157,6 +158,17 @@ impl <T: DbSerializable> SerializationFormat<T> for BincodeFormat {
     }
 }
 
+/// A [`SerializationFormat`] that serializes a value as a JSON string.
+pub enum JsonFormat { }
+impl <T: DbSerializable> SerializationFormat<T> for JsonFormat {
+    fn serialize(val: &T) -> Result<SerializeValue> {
+        Ok(serde_json::to_string(val)?.into())
+    }
+    fn deserialize(val: SerializeValue) -> Result<T> {
+        Ok(serde_json::from_str(&val.into_str()?)?)
+    }
+}
+
 /// A [`SerializationFormat`] that serializes a value as CBOR.
 pub enum CborFormat { }
 impl <T: DbSerializable> SerializationFormat<T> for CborFormat {
@@ -179,19 +191,53 @@ pub trait DbSerializable: Clone + Sized + Serialize + DeserializeOwned + Send +
 
     /// The schema version of this particular type.
     ///
-    /// This is used to allow for manual migrations.
+    /// The version is stored alongside every value of this type, and should be incremented
+    /// whenever the type changes in a way that makes values stored by older versions unreadable.
+    /// Those values can then be converted with [`upgrade`](`DbSerializable::upgrade`).
     const SCHEMA_VERSION: u32;
 
-    /// Returns whether a given id/version combination can be migrated to the current one.
-    fn can_migrate_from(_from_id: &str, _from_version: u32) -> bool {
+    /// Returns whether values stored with an older schema version of this type can be converted
+    /// by [`upgrade`](`DbSerializable::upgrade`).
+    fn can_upgrade_from(_old_version: u32) -> bool {
         false
     }
 
+    /// Converts a value stored with an older schema version of this type.
+    ///
+    /// The data is exactly what was stored by the old version, so it should usually be
+    /// deserialized into a copy of the old definition of this type with the same format, and
+    /// then converted.
+    fn upgrade(_old_version: u32, _data: SerializeValue) -> Result<Self> {
+        bail!("Upgrading from older schema versions is not supported.")
+    }
+
+    /// Returns whether a given id/version combination can be migrated to the current one.
+    ///
+    /// By default, this only allows older versions of this type that
+    /// [`can_upgrade_from`](`DbSerializable::can_upgrade_from`) accepts.
+    fn can_migrate_from(from_id: &str, from_version: u32) -> bool {
+        from_id == Self::ID && from_version < Self::SCHEMA_VERSION &&
+            Self::can_upgrade_from(from_version)
+    }
+
     /// Loads a value from a outdated KVS store
+    ///
+    /// By default, this converts older versions of this type with
+    /// [`upgrade`](`DbSerializable::upgrade`).
     fn do_migration(
-        _from_id: &str, _from_version: u32, _data: SerializeValue,
+        from_id: &str, from_version: u32, data: SerializeValue,
     ) -> Result<Self> {
-        bail!("Migration not supported.")
+        ensure!(from_id == Self::ID, "Migration not supported.");
+        Self::upgrade(from_version, data)
+    }
+
+    /// Returns the fields of this type that are indexed when it is stored in a KVS store.
+    ///
+    /// Indexed fields can be searched with [`BaseKvsStore::find_by_index`].
+    ///
+    /// [`BaseKvsStore::find_by_index`]: crate::kvs::BaseKvsStore::find_by_index
+    fn kvs_indexes() -> Vec<KvsIndex<Self>> {
+        Vec::new()
     }
 
     /// Downcasts this to a concrete type. This is used for some more fancy formatters.
@@ -381,34 +427,178 @@ impl <T: DbSerializable> DbSerializable for Option<T> {
     }
 }
 
-/// A simple wrapper that implements [`DbSerializable`] over any compatible type.
+macro_rules! serde_wrapper {
+    ($(#[$meta:meta])* $name:ident, $format:ty, $id:literal) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+        #[derive(Serialize)]
+        #[serde(transparent)]
+        pub struct $name<T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>(pub T);
+        impl <T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
+            From<T> for $name<T>
+        {
+            fn from(t: T) -> Self {
+                $name(t)
+            }
+        }
+        impl <T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
+            DbSerializable for $name<T>
+        {
+            type Format = $format;
+
+            const ID: &'static str = $id;
+            const SCHEMA_VERSION: u32 = 0;
+        }
+        impl <'de, T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
+            Deserialize<'de> for $name<T>
+        {
+            fn deserialize<D>(deser: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
+                T::deserialize(deser).map($name)
+            }
+        }
+    };
+}
+
+serde_wrapper!(
+    /// A simple wrapper that implements [`DbSerializable`] over any compatible type.
+    ///
+    /// This does not support migrations and serializes using a non self-describing format.
+    ///
+    /// The schema ID will `"simple_serialize"` with a schema version of 0.
+    SimpleSerialize, BincodeFormat, "simple_serialize"
+);
+serde_wrapper!(
+    /// A wrapper that implements [`DbSerializable`] over any compatible type, storing it as JSON.
+    ///
+    /// This is larger than [`SimpleSerialize`], but the stored values can be read directly from
+    /// the database, and fields can be added with `#[serde(default)]` without a migration.
+    ///
+    /// The schema ID will be `"json_serialize"` with a schema version of 0.
+    JsonSerialize, JsonFormat, "json_serialize"
+);
+serde_wrapper!(
+    /// A wrapper that implements [`DbSerializable`] over any compatible type, storing it as CBOR.
+    ///
+    /// Like [`JsonSerialize`], this uses a self-describing format, but stores values in a more
+    /// compact binary form.
+    ///
+    /// The schema ID will be `"cbor_serialize"` with a schema version of 0.
+    CborSerialize, CborFormat, "cbor_serialize"
+);
+
+mod private_compressed {
+    use super::*;
+    use std::marker::PhantomData;
+
+    const TAG_BYTES: u8 = 0;
+    const TAG_COMPRESSED_BYTES: u8 = 1;
+    const TAG_COMPRESSED_STRING: u8 = 2;
+
+    fn compress(tag: u8, data: &[u8]) -> Result<SerializeValue> {
+        let mut buf = vec![tag];
+        buf.extend(zstd::encode_all(data, 0)?);
+        Ok(buf.into())
+    }
+
+    enum Void { }
+    pub struct CompressedFormat<T>(PhantomData<T>, Void);
+    impl <T: DbSerializable> CompressedFormat<T> {
+        pub fn decompress(val: SerializeValue) -> Result<SerializeValue> {
+            Ok(match val {
+                SerializeValue::Bytes(b) => match b.split_first() {
+                    Some((&TAG_BYTES, rest)) => rest.to_vec().into(),
+                    Some((&TAG_COMPRESSED_BYTES, rest)) => zstd::decode_all(rest)?.into(),
+                    Some((&TAG_COMPRESSED_STRING, rest)) =>
+                        String::from_utf8(zstd::decode_all(rest)?)?.into(),
+                    _ => bail!("Compressed value has an unknown tag."),
+                },
+                val => val,
+            })
+        }
+    }
+    impl <T: DbSerializable> SerializationFormat<Compressed<T>> for CompressedFormat<T> {
+        fn serialize(val: &Compressed<T>) -> Result<SerializeValue> {
+            match T::Format::serialize(&val.0)? {
+                SerializeValue::Bytes(b) if b.len() >= COMPRESSION_THRESHOLD =>
+                    compress(TAG_COMPRESSED_BYTES, &b),
+                SerializeValue::Bytes(b) => {
+                    let mut buf = vec![TAG_BYTES];
+                    buf.extend_from_slice(&b);
+                    Ok(buf.into())
+                }
+                SerializeValue::String(s) if s.len() >= COMPRESSION_THRESHOLD =>
+                    compress(TAG_COMPRESSED_STRING, s.as_bytes()),
+                val => Ok(val),
+            }
+        }
+        fn deserialize(val: SerializeValue) -> Result<Compressed<T>> {
+            Ok(Compressed(T::Format::deserialize(Self::decompress(val)?)?))
+        }
+    }
+}
+
+/// The size in bytes above which [`Compressed`] compresses values.
+pub const COMPRESSION_THRESHOLD: usize = 512;
+
+/// A wrapper that compresses the serialized form of a [`DbSerializable`] type with zstd.
+///
+/// Only values that serialize to at least [`COMPRESSION_THRESHOLD`] bytes are compressed, as
+/// compressing smaller values rarely saves space.
 ///
-/// This does not support migrations and serializes using a non self-describing format.
+/// Values stored as the wrapped type can still be loaded with this wrapper, so existing stores
+/// can switch to it without a migration. Upgrades between schema versions are passed through to
+/// the wrapped type, with the data already decompressed.
 ///
-/// The schema ID will `"simple_serialize"` with a schema version of 0.
+/// The schema ID will be `"compressed"` with the schema version of the wrapped type.
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
 #[derive(Serialize)]
 #[serde(transparent)]
-pub struct SimpleSerialize<T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>(pub T);
-impl <T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
-    From<T> for SimpleSerialize<T>
-{
+pub struct Compressed<T: DbSerializable>(pub T);
+impl <T: DbSerializable> From<T> for Compressed<T> {
     fn from(t: T) -> Self {
-        SimpleSerialize(t)
+        Compressed(t)
     }
 }
-impl <T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
-    DbSerializable for SimpleSerialize<T>
-{
-    type Format = BincodeFormat;
+impl <T: DbSerializable> DbSerializable for Compressed<T> {
+    type Format = private_compressed::CompressedFormat<T>;
 
-    const ID: &'static str = "simple_serialize";
-    const SCHEMA_VERSION: u32 = 0;
+    const ID: &'static str = "compressed";
+    const SCHEMA_VERSION: u32 = T::SCHEMA_VERSION;
+
+    fn can_upgrade_from(old_version: u32) -> bool {
+        T::can_upgrade_from(old_version)
+    }
+
+    fn upgrade(old_version: u32, data: SerializeValue) -> Result<Self> {
+        let data = private_compressed::CompressedFormat::<T>::decompress(data)?;
+        Ok(Compressed(T::upgrade(old_version, data)?))
+    }
+
+    fn can_migrate_from(from_id: &str, from_version: u32) -> bool {
+        if from_id == Self::ID {
+            from_version < Self::SCHEMA_VERSION && T::can_upgrade_from(from_version)
+        } else {
+            (from_id == T::ID && from_version == T::SCHEMA_VERSION) ||
+                T::can_migrate_from(from_id, from_version)
+        }
+    }
+
+    fn do_migration(from_id: &str, from_version: u32, data: SerializeValue) -> Result<Self> {
+        if from_id == Self::ID {
+            Self::upgrade(from_version, data)
+        } else if from_id == T::ID && from_version == T::SCHEMA_VERSION {
+            Ok(Compressed(T::Format::deserialize(data)?))
+        } else {
+            Ok(Compressed(T::do_migration(from_id, from_version, data)?))
+        }
+    }
+
+    fn kvs_indexes() -> Vec<KvsIndex<Self>> {
+        T::kvs_indexes().into_iter().map(|x| x.wrap(|x: &Compressed<T>| &x.0)).collect()
+    }
 }
-impl <'de, T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
-    Deserialize<'de> for SimpleSerialize<T>
-{
+impl <'de, T: DbSerializable> Deserialize<'de> for Compressed<T> {
     fn deserialize<D>(deser: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
-        T::deserialize(deser).map(SimpleSerialize)
+        T::deserialize(deser).map(Compressed)
     }
-}
\ No newline at end of file
+}
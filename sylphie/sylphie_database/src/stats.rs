@@ -0,0 +1,128 @@
+//! Reports statistics about the connection pool through the `db stats` command and the metrics
+//! registry, and dispatches [`SlowQueryEvent`] for slow statements. The `db schema` command is
+//! also implemented here.
+
+use crate::connection::*;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use std::time::Duration;
+use sylphie_commands::commands::{Command, CommandImpl, CommandInfo, TerminalOnly};
+use sylphie_commands::ctx::CommandCtx;
+use sylphie_commands::manager::RegisterCommandsEvent;
+use sylphie_core::metrics::CollectMetricsEvent;
+use sylphie_core::prelude::*;
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// Reports the statistics of the connection pool as metrics.
+pub(crate) fn collect_metrics(
+    target: &Handler<impl Events>, module: &ModuleInfo, ev: &mut CollectMetricsEvent,
+) {
+    let stats = target.get_service::<Database>().stats();
+    ev.gauge(module, "db_connections", stats.connections as f64);
+    ev.gauge(module, "db_active_connections", stats.active_connections() as f64);
+    ev.gauge(module, "db_idle_connections", stats.idle_connections as f64);
+    ev.gauge(module, "db_max_connections", stats.max_connections as f64);
+    ev.gauge(module, "db_max_acquire_wait_ms", millis(stats.max_acquire_wait));
+    ev.counter(module, "db_acquires", stats.acquire_count);
+    ev.counter(module, "db_acquire_wait_ms", stats.total_acquire_wait.as_millis() as u64);
+    ev.counter(module, "db_queries", stats.query_count);
+    ev.counter(module, "db_slow_queries", stats.slow_query_count);
+    ev.counter(module, "db_statement_cache_hits", stats.statement_cache.hits);
+    ev.counter(module, "db_statement_cache_misses", stats.statement_cache.misses);
+}
+
+/// Starts the task that dispatches [`SlowQueryEvent`] for statements reported by connections.
+pub(crate) fn start_slow_query_task(target: &Handler<impl Events>, module: &impl Module) {
+    let mut queries = match target.get_service::<Database>().take_slow_queries() {
+        Some(queries) => queries,
+        None => return,
+    };
+    let handler = target.clone();
+    module.spawn(target, "slow_queries", async move {
+        while let Some(ev) = queries.recv().await {
+            handler.dispatch_async(ev).await;
+        }
+        Ok(())
+    });
+}
+
+struct DbCommand;
+impl DbCommand {
+    async fn run(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        match (ctx.args_count(), ctx.arg_opt(1).map(|x| x.text)) {
+            (2, Some("stats")) => self.stats(ctx).await,
+            (2, Some("schema")) | (3, Some("schema")) => self.schema(ctx).await,
+            _ => cmd_error!("Usage: db stats, db schema [table]"),
+        }
+    }
+
+    async fn stats(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let stats = ctx.handler().get_service::<Database>().stats();
+        ctx.respond(&format!(
+            "Connections: {} active, {} idle, {} maximum\n\
+             Acquired {} connections, waiting {:.2} ms on average and {:.2} ms at most\n\
+             Ran {} statements, {} of which were slower than {} ms\n\
+             Statement cache: {} hits, {} misses",
+            stats.active_connections(), stats.idle_connections, stats.max_connections,
+            stats.acquire_count, millis(stats.average_acquire_wait()),
+            millis(stats.max_acquire_wait),
+            stats.query_count, stats.slow_query_count,
+            stats.slow_query_threshold.as_millis(),
+            stats.statement_cache.hits, stats.statement_cache.misses,
+        )).await?;
+        Ok(())
+    }
+
+    async fn schema(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let filter = if ctx.args_count() == 3 { Some(ctx.arg(2).text) } else { None };
+        let schema = ctx.handler().get_service::<Database>().schema().await?;
+
+        let mut out = String::new();
+        for table in &schema.tables {
+            if filter.map_or(false, |x| x != table.name) {
+                continue
+            }
+            let prefix = if table.is_transient { "transient." } else { "" };
+            out.push_str(&format!("{}{}\n", prefix, table.name));
+            for column in &table.columns {
+                out.push_str(&format!("    {} {}", column.name, column.sql_type));
+                if column.primary_key {
+                    out.push_str(" PRIMARY KEY");
+                }
+                if column.not_null {
+                    out.push_str(" NOT NULL");
+                }
+                out.push('\n');
+            }
+            for index in &table.indexes {
+                let unique = if index.unique { "unique " } else { "" };
+                out.push_str(&format!(
+                    "    {}index {} ({})\n", unique, index.name, index.columns.join(", "),
+                ));
+            }
+        }
+        match filter {
+            Some(name) if out.is_empty() => cmd_error!("No table named '{}' exists.", name),
+            _ => ctx.respond(out.trim_end()).await?,
+        }
+        Ok(())
+    }
+}
+impl CommandImpl for DbCommand {
+    fn execute<'a>(
+        &'a self, _: Command, ctx: &'a CommandCtx<impl Events>,
+    ) -> BoxFuture<'a, Result<()>> {
+        self.run(ctx).boxed()
+    }
+}
+
+/// Registers the `db` command.
+pub(crate) fn register_commands(
+    target: &Handler<impl Events>, module: &impl Module, ev: &mut RegisterCommandsEvent,
+) {
+    let info = CommandInfo::new("db").completions(&[&["stats", "schema"]]);
+    ev.register_command(Command::new(target, module, info, TerminalOnly(DbCommand)));
+}
@@ -0,0 +1,233 @@
+//! A store for numeric values recorded over time, such as message counts.
+
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, TimeZone, Utc};
+use crate::connection::*;
+use parking_lot::Mutex;
+use static_events::prelude_async::*;
+use std::sync::Arc;
+use std::time::Duration;
+use sylphie_core::derives::*;
+use sylphie_core::prelude::*;
+
+/// How often old points are downsampled and deleted.
+const TIME_SERIES_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+/// How long points are kept for by default.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 90);
+
+fn duration_millis(duration: Duration) -> i64 {
+    duration.as_millis().min(i64::MAX as u128) as i64
+}
+
+/// How a time-series store keeps its old points.
+#[derive(Copy, Clone, Debug)]
+struct RetentionConfig {
+    retention: Option<Duration>,
+    downsampling: Option<(Duration, Duration)>,
+}
+
+struct TimeSeriesQueries {
+    db: Database,
+    table_name: String,
+    record_query: Arc<str>,
+    query_query: Arc<str>,
+    series_query: Arc<str>,
+    prune_query: Arc<str>,
+}
+impl TimeSeriesQueries {
+    fn new(db: Database, module_name: &str) -> Self {
+        let hash = blake3::hash(module_name.as_bytes()).to_hex();
+        let table_name = format!("sylphie_db_ts_{}", &hash.as_str()[0..16]);
+        TimeSeriesQueries {
+            db,
+            record_query: format!(
+                "INSERT INTO {} (series, timestamp, value, bucket_ms) VALUES (?, ?, ?, 0);",
+                table_name,
+            ).into(),
+            query_query: format!(
+                "SELECT timestamp, value FROM {} \
+                 WHERE series = ? AND timestamp >= ? AND timestamp < ? ORDER BY timestamp;",
+                table_name,
+            ).into(),
+            series_query: format!(
+                "SELECT DISTINCT series FROM {} ORDER BY series;", table_name,
+            ).into(),
+            prune_query: format!("DELETE FROM {} WHERE timestamp < ?;", table_name).into(),
+            table_name,
+        }
+    }
+
+    fn create_queries(&self) -> String {
+        format!(
+            "CREATE TABLE IF NOT EXISTS {0} (\
+                series TEXT NOT NULL, \
+                timestamp BIGINT NOT NULL, \
+                value DOUBLE PRECISION NOT NULL, \
+                bucket_ms BIGINT NOT NULL\
+            ); \
+            CREATE INDEX IF NOT EXISTS {0}_idx ON {0} (series, timestamp);",
+            self.table_name,
+        )
+    }
+
+    /// Replaces the raw points before a cutoff with the average of each bucket.
+    async fn downsample(&self, conn: &mut DbConnection, before: i64, bucket: i64) -> Result<()> {
+        // only whole buckets are downsampled, so a bucket is never averaged twice.
+        let before = before - before.rem_euclid(bucket);
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        transaction.execute(format!(
+            "INSERT INTO {0} (series, timestamp, value, bucket_ms) \
+             SELECT series, (timestamp / {1}) * {1}, AVG(value), {1} FROM {0} \
+             WHERE bucket_ms = 0 AND timestamp < ? GROUP BY series, timestamp / {1};",
+            self.table_name, bucket,
+        ), before).await?;
+        transaction.execute(format!(
+            "DELETE FROM {} WHERE bucket_ms = 0 AND timestamp < ?;", self.table_name,
+        ), before).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+/// A store for numeric values recorded over time.
+///
+/// Each point belongs to a named series, such as `messages.general`. Points older than the
+/// retention period are deleted, and points can be averaged into coarser buckets once they are
+/// old enough to keep the table small. By default, points are kept for 90 days without
+/// downsampling.
+///
+/// This is a module, and should be used by attaching it to the your module as a submodule.
+#[derive(Module)]
+#[module(component)]
+pub struct TimeSeriesStore {
+    #[module_info] info: ModuleInfo,
+    queries: ArcSwapOption<TimeSeriesQueries>,
+    #[init_with {
+        Arc::new(Mutex::new(RetentionConfig {
+            retention: Some(DEFAULT_RETENTION),
+            downsampling: None,
+        }))
+    }]
+    config: Arc<Mutex<RetentionConfig>>,
+}
+#[module_impl]
+impl TimeSeriesStore {
+    #[event_handler]
+    async fn init_time_series(
+        &self, target: &Handler<impl Events>, _: &crate::InitDbEvent,
+    ) -> Result<()> {
+        let db = target.get_service::<Database>().clone();
+        let queries = Arc::new(TimeSeriesQueries::new(db, self.info.name()));
+        queries.db.connect().await?.execute_batch(queries.create_queries()).await?;
+        self.queries.store(Some(queries.clone()));
+
+        let config = self.config.clone();
+        self.spawn(target, "time_series_maintenance", async move {
+            let mut interval = tokio::time::interval(TIME_SERIES_MAINTENANCE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let current = *config.lock();
+                let result = async {
+                    let now = Utc::now().timestamp_millis();
+                    let mut conn = queries.db.connect().await?;
+                    if let Some((after, bucket)) = current.downsampling {
+                        let before = now - duration_millis(after);
+                        queries.downsample(&mut conn, before, duration_millis(bucket)).await?;
+                    }
+                    if let Some(retention) = current.retention {
+                        let before = now - duration_millis(retention);
+                        conn.execute(queries.prune_query.clone(), before).await?;
+                    }
+                    Ok(())
+                }.await;
+                if let Err(e) = result {
+                    e.report_error();
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn load_queries(&self) -> Arc<TimeSeriesQueries> {
+        self.queries.load().as_ref().expect("TimeSeriesStore not yet initialized.").clone()
+    }
+
+    /// Sets how long points are kept for, or keeps them forever if `None` is given.
+    pub fn set_retention(&self, retention: Option<Duration>) {
+        self.config.lock().retention = retention;
+    }
+
+    /// Averages points older than `after` into buckets `bucket` long.
+    ///
+    /// Downsampled points are returned by queries like any other point, at the start of their
+    /// bucket.
+    pub fn set_downsampling(&self, after: Duration, bucket: Duration) {
+        assert!(duration_millis(bucket) > 0, "Downsampling buckets must be at least 1 ms long.");
+        self.config.lock().downsampling = Some((after, bucket));
+    }
+
+    /// Records a point in a series at the current time.
+    pub async fn record(&self, series: &str, value: f64) -> Result<()> {
+        self.record_at(series, Utc::now(), value).await
+    }
+
+    /// Records a point in a series at a given time.
+    pub async fn record_at(&self, series: &str, time: DateTime<Utc>, value: f64) -> Result<()> {
+        let queries = self.load_queries();
+        let mut conn = queries.db.connect().await?;
+        conn.execute(
+            queries.record_query.clone(), (series.to_string(), time.timestamp_millis(), value),
+        ).await?;
+        Ok(())
+    }
+
+    /// Returns the points in a series between two times, oldest first.
+    ///
+    /// The range includes `from` but not `to`.
+    pub async fn query(
+        &self, series: &str, from: DateTime<Utc>, to: DateTime<Utc>,
+    ) -> Result<Vec<(DateTime<Utc>, f64)>> {
+        let queries = self.load_queries();
+        let mut conn = queries.db.connect().await?;
+        let rows: Vec<(i64, f64)> = conn.query_vec(
+            queries.query_query.clone(),
+            (series.to_string(), from.timestamp_millis(), to.timestamp_millis()),
+        ).await?;
+        Ok(rows.into_iter().map(|(time, value)| (Utc.timestamp_millis(time), value)).collect())
+    }
+
+    /// Returns the average of the points in a series in each bucket between two times.
+    ///
+    /// Buckets are aligned to multiples of `bucket` since the Unix epoch, and buckets with no
+    /// points are left out.
+    pub async fn query_buckets(
+        &self, series: &str, from: DateTime<Utc>, to: DateTime<Utc>, bucket: Duration,
+    ) -> Result<Vec<(DateTime<Utc>, f64)>> {
+        let bucket = duration_millis(bucket);
+        ensure!(bucket > 0, "Buckets must be at least 1 ms long.");
+
+        let mut buckets: Vec<(i64, f64, u32)> = Vec::new();
+        for (time, value) in self.query(series, from, to).await? {
+            let start = time.timestamp_millis();
+            let start = start - start.rem_euclid(bucket);
+            match buckets.last_mut() {
+                Some(last) if last.0 == start => {
+                    last.1 += value;
+                    last.2 += 1;
+                }
+                _ => buckets.push((start, value, 1)),
+            }
+        }
+        Ok(buckets.into_iter()
+            .map(|(start, sum, count)| (Utc.timestamp_millis(start), sum / count as f64))
+            .collect())
+    }
+
+    /// Returns the names of every series with points in this store.
+    pub async fn series(&self) -> Result<Vec<String>> {
+        let queries = self.load_queries();
+        let mut conn = queries.db.connect().await?;
+        conn.query_vec_nullary(queries.series_query.clone()).await
+    }
+}
@@ -0,0 +1,78 @@
+//! Support for keeping the transient database across clean restarts of the bot.
+
+use crate::connection::*;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use sylphie_core::core::BotInfo;
+use sylphie_core::prelude::*;
+
+/// The marker service used to enable transient snapshots.
+struct TransientSnapshots;
+
+/// Contains extension functions for configuring the transient database on [`SylphieCore`].
+pub trait SylphieCoreTransientExt {
+    /// Keeps transient data, such as cooldowns, across planned restarts.
+    ///
+    /// The transient database is copied to a snapshot when the bot shuts down cleanly, and the
+    /// snapshot is restored when it is next started. If the bot stops without shutting down,
+    /// no snapshot is made and transient data is cleared on the next start instead.
+    fn with_transient_snapshots(self) -> Self;
+}
+impl <R: Module> SylphieCoreTransientExt for SylphieCore<R> {
+    fn with_transient_snapshots(self) -> Self {
+        self.with_service(Arc::new(TransientSnapshots))
+    }
+}
+
+fn is_enabled(target: &Handler<impl Events>) -> bool {
+    target.services().resolve::<TransientSnapshots>().is_some()
+}
+
+fn snapshot_path(target: &Handler<impl Events>) -> PathBuf {
+    let info = target.get_service::<BotInfo>();
+    let mut path = info.root_path().to_owned();
+    path.push("db");
+    path.push(format!("{}.transient.snapshot.db", info.bot_name()));
+    path
+}
+
+/// Restores the snapshot taken at the last clean shutdown, or clears the transient database if
+/// there is none.
+pub(crate) fn restore_on_startup(target: &Handler<impl Events>) -> Result<()> {
+    if !is_enabled(target) {
+        return Ok(())
+    }
+
+    let path = snapshot_path(target);
+    let backend = target.get_service::<Database>().backend();
+    if path.is_file() {
+        info!("Restoring transient data from the last shutdown...");
+        backend.restore_transient(Some(&path))?;
+        // the snapshot is only good for one start, so a crash after this clears the data.
+        fs::remove_file(&path)?;
+    } else {
+        debug!("No transient snapshot was found, clearing transient data.");
+        backend.restore_transient(None)?;
+    }
+    Ok(())
+}
+
+/// Takes a snapshot of the transient database while the bot is shutting down.
+pub(crate) async fn snapshot_on_shutdown(target: &Handler<impl Events>) -> Result<()> {
+    if !is_enabled(target) {
+        return Ok(())
+    }
+
+    let path = snapshot_path(target);
+    let backend = target.get_service::<Database>().backend();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        backend.snapshot_transient(&path)?;
+        Ok(())
+    }).await??;
+    info!("Saved transient data for the next start.");
+    Ok(())
+}
@@ -67,6 +67,8 @@ struct ModuleAttrs {
     anonymous: bool,
     #[darling(default)]
     component: bool,
+    #[darling(default)]
+    init_priority: i32,
 }
 
 fn git_metadata(paths: &CratePaths) -> std::result::Result<SynTokenStream, GitError> {
@@ -107,6 +109,7 @@ fn module_metadata(paths: &CratePaths, attrs: &ModuleAttrs) -> SynTokenStream {
     if attrs.anonymous || attrs.component {
         flags.extend(quote! { | #core::module::ModuleFlag::Anonymous });
     }
+    let init_priority = attrs.init_priority;
     let git_info = match git_metadata(paths) {
         Ok(v) => quote! { #core::__macro_export::Some(#v) },
         _ => quote! { #core::__macro_export::None },
@@ -118,6 +121,7 @@ fn module_metadata(paths: &CratePaths, attrs: &ModuleAttrs) -> SynTokenStream {
             crate_version: ::std::option_env!("CARGO_PKG_VERSION").unwrap_or("<unknown>"),
             git_info: #git_info,
             flags: #core::__macro_export::EnumSet::new() #flags,
+            init_priority: #init_priority,
         }
     }
 }
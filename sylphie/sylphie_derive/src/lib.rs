@@ -14,12 +14,14 @@ pub(crate) struct CratePaths {
     core: SynTokenStream,
     commands: SynTokenStream,
     database: SynTokenStream,
+    utils: SynTokenStream,
 }
 fn crate_paths_for_sylphie() -> CratePaths {
     CratePaths {
         core: quote! { ::sylphie::__macro_export::sylphie_core },
         commands: quote! { ::sylphie::__macro_export::sylphie_commands },
         database: quote! { ::sylphie::__macro_export::sylphie_database },
+        utils: quote! { ::sylphie::__macro_export::sylphie_utils },
     }
 }
 fn crate_paths_for_core() -> CratePaths {
@@ -27,6 +29,7 @@ fn crate_paths_for_core() -> CratePaths {
         core: quote! { ::sylphie_core },
         commands: quote! { ::sylphie_commands },
         database: quote! { ::sylphie_database },
+        utils: quote! { ::sylphie_utils },
     }
 }
 fn crate_paths_for_core_internal() -> CratePaths {
@@ -34,6 +37,7 @@ fn crate_paths_for_core_internal() -> CratePaths {
         core: quote! { crate },
         commands: quote! { __CANNOT_USE_COMMANDS_IN_CORE_INTERNAL__ },
         database: quote! { __CANNOT_USE_COMMANDS_IN_CORE_INTERNAL__ },
+        utils: quote! { __CANNOT_USE_UTILS_IN_CORE_INTERNAL__ },
     }
 }
 
@@ -68,3 +72,4 @@ pub fn module_impl_core(_: TokenStream, item: TokenStream) -> TokenStream {
 
 derived_attr!(command, module_impl);
 derived_attr!(config, module_impl);
+derived_attr!(module_hook, module_impl);
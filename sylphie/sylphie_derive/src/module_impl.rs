@@ -1,6 +1,7 @@
 use crate::CratePaths;
 use darling::*;
 use proc_macro::TokenStream;
+use proc_macro2::{TokenStream as SynTokenStream};
 use static_events_internals::{*, Error, Result};
 use static_events_internals::utils::*;
 use syn::*;
@@ -19,6 +20,12 @@ struct ConfigAttrs {
     name: Option<String>,
 }
 
+#[derive(FromMeta, Debug, Default)]
+struct HookAttrs {
+    #[darling(default)]
+    health: bool,
+}
+
 fn parse_meta<T: FromMeta + Default>(attr: &Attribute) -> Result<T> {
     if attr.tokens.is_empty() {
         Ok(T::default())
@@ -31,12 +38,14 @@ fn parse_meta<T: FromMeta + Default>(attr: &Attribute) -> Result<T> {
 enum HandlerType {
     Command(CommandAttrs),
     Config(ConfigAttrs),
+    Hook(HookAttrs),
 }
 impl HandlerType {
     fn is_attr(attr: &Attribute) -> bool {
         match last_path_segment(&attr.path).as_str() {
             "command" => true,
             "config" => true,
+            "module_hook" => true,
             _ => false,
         }
     }
@@ -44,6 +53,7 @@ impl HandlerType {
         match last_path_segment(&attr.path).as_str() {
             "command" => Ok(Some(HandlerType::Command(parse_meta(attr)?))),
             "config" => Ok(Some(HandlerType::Config(parse_meta(attr)?))),
+            "module_hook" => Ok(Some(HandlerType::Hook(parse_meta(attr)?))),
             _ => Ok(None),
         }
     }
@@ -71,6 +81,7 @@ impl HandlerType {
         match self {
             HandlerType::Command(_) => "#[command]",
             HandlerType::Config(_) => "#[config]",
+            HandlerType::Hook(_) => "#[module_hook]",
         }
     }
 }
@@ -220,6 +231,61 @@ fn create_option_handler(
     Ok(())
 }
 
+fn create_hook_handler(
+    paths: &CratePaths, events: &mut EventsImplAttr, attrs: &HookAttrs, method: &ImplItemMethod,
+) -> Result<()> {
+    let core = &paths.core;
+    let static_events = quote! { #core::__macro_export::static_events::prelude_async };
+
+    if !attrs.health {
+        error(method.span(), "#[module_hook] requires a hook type, e.g. #[module_hook(health)].")?;
+    }
+    if !method.sig.generics.params.iter().all(|x| match x {
+        GenericParam::Lifetime(_) => true,
+        _ => false,
+    }) {
+        error(method.sig.generics.span(), "#[module_hook] methods may not be generic.")?;
+    }
+    match method.sig.inputs.first() {
+        Some(FnArg::Receiver(_)) => { }
+        _ => error(method.sig.span(), "#[module_hook] methods must take a self parameter.")?,
+    }
+
+    if method.sig.inputs.len() > 2 {
+        error(
+            method.sig.inputs.span(),
+            "#[module_hook(health)] methods may only take a handler as a parameter.",
+        )?;
+    }
+
+    let hook_call = &method.sig.ident;
+    let hook_call = if method.sig.inputs.len() == 2 {
+        quote! { self.#hook_call(target) }
+    } else {
+        quote! { self.#hook_call() }
+    };
+    let hook_call = if method.sig.asyncness.is_some() {
+        quote! { #hook_call.await }
+    } else {
+        hook_call
+    };
+
+    let health_hook = ident!("__module_impl__hook_health_{}", method.sig.ident);
+    events.process_synthetic_method(quote! {
+        #[#static_events::event_handler]
+        async fn #health_hook(
+            &self,
+            target: &#static_events::Handler<impl #static_events::Events>,
+            ev: &mut #core::health::CheckHealthEvent,
+        ) {
+            let _ = target;
+            let report = #core::health::HealthReport::from(#hook_call);
+            ev.report(#core::module::Module::info(self), report);
+        }
+    })?;
+    Ok(())
+}
+
 fn process_items(
     paths: &CratePaths, events: &mut EventsImplAttr, input: &mut ItemImpl,
 ) -> Result<()> {
@@ -246,6 +312,12 @@ fn process_items(
                                 ) { errors = errors.combine(e); }
                                 processed = true;
                             }
+                            HandlerType::Hook(hook) => {
+                                if let Err(e) = create_hook_handler(
+                                    paths, events, &hook, method,
+                                ) { errors = errors.combine(e); }
+                                processed = true;
+                            }
                             _ => { }
                         }
                         ImplItem::Const(con) => match ty {
@@ -287,8 +359,144 @@ fn process_items(
     }
 }
 
+/// Removes a `scope = "..."` argument from an `#[event_handler]` attribute, as static-events
+/// does not understand it itself.
+fn take_scope_filter(attr: &mut Attribute) -> Result<Option<LitStr>> {
+    if attr.tokens.is_empty() {
+        return Ok(None)
+    }
+    let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        _ => return Ok(None),
+    };
+
+    let mut scope = None;
+    let mut rest = Vec::new();
+    for nested in list.nested {
+        match nested {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("scope") => {
+                if scope.is_some() {
+                    error(nv.span(), "Only one scope filter may be used.")?;
+                }
+                match nv.lit {
+                    Lit::Str(lit) => scope = Some(lit),
+                    _ => error(nv.lit.span(), "Scope filters must be strings.")?,
+                }
+            }
+            nested => rest.push(nested),
+        }
+    }
+    if scope.is_some() {
+        attr.tokens = if rest.is_empty() {
+            SynTokenStream::new()
+        } else {
+            quote! { (#(#rest),*) }
+        };
+    }
+    Ok(scope)
+}
+
+/// Finds the event parameter of an event handler, renaming it if it is not a simple identifier.
+fn event_param(method: &mut ImplItemMethod) -> Result<Ident> {
+    fn is_handler(ty: &Type) -> bool {
+        match ty {
+            Type::Reference(ty) => match &*ty.elem {
+                Type::Path(path) => last_path_segment(&path.path) == "Handler",
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    let span = method.sig.span();
+    for arg in &mut method.sig.inputs {
+        if let FnArg::Typed(arg) = arg {
+            if is_handler(&arg.ty) {
+                continue
+            }
+            return match &*arg.pat {
+                Pat::Ident(pat) => Ok(pat.ident.clone()),
+                _ => {
+                    let ident = ident!("__module_impl__scope_filter_ev");
+                    arg.pat = Box::new(parse2(quote! { #ident })?);
+                    Ok(ident)
+                }
+            }
+        }
+    }
+    error(span, "Scope filters can only be used on handlers with an event parameter.")
+}
+
+/// Preprocesses `#[event_handler]` methods before they are passed to static-events.
+///
+/// This strips and implements scope filters, and wraps each handler's body so a panic in one
+/// handler is reported rather than aborting the whole dispatch, and so it runs in a span naming
+/// the handler.
+fn preprocess_event_handlers(paths: &CratePaths, input: &mut ItemImpl) -> Result<()> {
+    let core = &paths.core;
+    let utils = &paths.utils;
+    for item in &mut input.items {
+        if let ImplItem::Method(method) = item {
+            let mut is_handler = false;
+            let mut scope_filter = None;
+            for attr in &mut method.attrs {
+                if last_path_segment(&attr.path) == "event_handler" {
+                    is_handler = true;
+                    if let Some(scope) = take_scope_filter(attr)? {
+                        scope_filter = Some(scope);
+                    }
+                }
+            }
+            if !is_handler {
+                continue
+            }
+
+            let filter = match scope_filter {
+                Some(scope) => {
+                    let ev = event_param(method)?;
+                    quote! {
+                        if !#utils::scopes::ScopedEvent::has_scope_type(&*#ev, #scope) {
+                            return #core::__macro_priv::DefaultHandlerResult::default_result();
+                        }
+                    }
+                }
+                None => SynTokenStream::new(),
+            };
+
+            let name_fn = match method.sig.receiver() {
+                Some(_) => quote! { || #core::__macro_priv::HandlerName::handler_name(self) },
+                None => quote! { || ::std::any::type_name::<Self>().to_string() },
+            };
+            let ret_ty = match &method.sig.output {
+                ReturnType::Default => quote! { () },
+                ReturnType::Type(_, ty) => quote! { #ty },
+            };
+            let method_name = method.sig.ident.to_string();
+            let block = &method.block;
+            let new_block = if method.sig.asyncness.is_some() {
+                quote! {{
+                    #filter
+                    #core::__macro_priv::isolate_panic_async::<#ret_ty, _, _>(
+                        #name_fn, #method_name, async move #block,
+                    ).await
+                }}
+            } else {
+                quote! {{
+                    #filter
+                    #core::__macro_priv::isolate_panic(
+                        #name_fn, #method_name, || -> #ret_ty #block,
+                    )
+                }}
+            };
+            method.block = parse2(new_block)?;
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn derive_impl(paths: &CratePaths, input: TokenStream) -> Result<TokenStream> {
     let mut input: ItemImpl = parse(input)?;
+    preprocess_event_handlers(paths, &mut input)?;
 
     let core = &paths.core;
     let mut events = EventsImplAttr::new(
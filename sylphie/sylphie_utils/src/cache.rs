@@ -178,6 +178,11 @@ impl <
         }
     }
 
+    /// Returns the cached value for a given key, if one exists.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.check_cached(key)
+    }
+
     /// Inserts a value into the cache.
     pub fn insert(&self, key: K, value: V) {
         self.insert_cache(key, value, true);
@@ -188,6 +193,12 @@ impl <
         self.invalidate_cache(key);
     }
 
+    /// Invalidates every key in the cache.
+    pub fn clear(&self) {
+        let lines = self.data.load().cache_data.len();
+        self.data.store(Arc::new(LruData::new(lines)));
+    }
+
     /// Caches a given future.
     ///
     /// The future is not run if a cached value is already available.
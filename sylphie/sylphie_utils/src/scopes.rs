@@ -27,6 +27,13 @@ pub enum ScopeArgs {
     Int3(u32, u32, u32),
 }
 
+/// The scope type of the scopes connections made with `sylphie_connections` are in.
+pub const CONNECTION_SCOPE_TYPE: &str = "sylphie_connections:connection";
+
+/// The scope type of the scopes of the users who sent a message, in which the argument is the ID
+/// of the user, such as `discord:123456789`.
+pub const USER_SCOPE_TYPE: &str = "user";
+
 /// A tagged scope used as an identifier.
 #[derive(Serialize, Deserialize, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 pub struct Scope {
@@ -41,4 +48,19 @@ impl Scope {
             args,
         }
     }
-}
\ No newline at end of file
+}
+
+/// An event that occurs within a particular set of scopes.
+///
+/// Events implementing this trait can be filtered with `#[event_handler(scope = "...")]` in a
+/// `#[module_impl]` block, in which case the handler is only called if the event has a scope of
+/// the given type.
+pub trait ScopedEvent {
+    /// Returns the scopes this event occurred in, from the most to least specific.
+    fn scopes(&self) -> &[Scope];
+
+    /// Returns whether this event occurred in a scope of a given type.
+    fn has_scope_type(&self, scope_type: &str) -> bool {
+        self.scopes().iter().any(|x| x.scope_type.as_str() == scope_type)
+    }
+}
@@ -0,0 +1,254 @@
+use async_trait::*;
+use crate::http::{DiscordHttp, MESSAGE_LIMIT};
+use crate::model::*;
+use enumset::EnumSet;
+use parking_lot::{Mutex, RwLock};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use sylphie::commands::connector::{Connector, ConnectorCapability, split_message};
+use sylphie::config::Config;
+use sylphie::connections::*;
+use sylphie::prelude::*;
+use sylphie::tasks::{TaskHandle, TaskManager};
+
+/// Reads the bot token from the configuration file.
+fn load_token(target: &Handler<impl Events>) -> Result<String> {
+    match target.get_service::<Config>().get::<String>("discord.token")? {
+        Some(token) if !token.trim().is_empty() => Ok(token.trim().to_string()),
+        _ => cmd_error!("No Discord token is set. Set `token` in the `[discord]` table of the \
+                         configuration file."),
+    }
+}
+
+/// Reads whether users other than the owners can run commands from the configuration file.
+fn load_public(target: &Handler<impl Events>) -> Result<bool> {
+    Ok(target.get_service::<Config>().get("discord.public")?.unwrap_or(false))
+}
+
+/// Parses a channel or message ID passed to the connector.
+fn parse_id(id: &str) -> Result<Snowflake> {
+    id.parse().map(Snowflake).cmd_error(|| format!("'{}' is not a Discord ID.", id))
+}
+
+struct DiscordConnectionData {
+    module: ModuleInfo,
+    scope: Scope,
+    token: String,
+    http: DiscordHttp,
+    cache: DiscordCache,
+    bot_user: RwLock<Option<Arc<User>>>,
+    status: Mutex<ConnectionStatus>,
+    is_public: AtomicBool,
+    gateway: Mutex<Option<TaskHandle>>,
+    start_gateway: Box<dyn Fn(DiscordConnection) -> TaskHandle + Send + Sync>,
+}
+
+/// A connection to Discord.
+///
+/// This is available to modules through the context of commands received from Discord, and
+/// provides the information the connection has about guilds, channels and users.
+#[derive(Clone)]
+pub struct DiscordConnection(Arc<DiscordConnectionData>);
+impl DiscordConnection {
+    /// Returns the scope of this connection.
+    pub fn scope(&self) -> &Scope {
+        &self.0.scope
+    }
+
+    /// Returns the guilds, channels and users this connection has seen.
+    pub fn cache(&self) -> &DiscordCache {
+        &self.0.cache
+    }
+
+    /// Returns the bot's own user, or `None` if it has not yet connected.
+    pub fn bot_user(&self) -> Option<Arc<User>> {
+        self.0.bot_user.read().clone()
+    }
+
+    /// Returns whether the connection is currently connected to Discord.
+    pub fn status(&self) -> ConnectionStatus {
+        *self.0.status.lock()
+    }
+
+    /// Returns the scopes of a channel, from most to least specific.
+    ///
+    /// These are the channel, its parents, the guild if it is in one, and the connection.
+    pub fn scopes(&self, channel: Snowflake, guild: Option<Snowflake>) -> Vec<Scope> {
+        let mut scopes = Vec::new();
+        let mut current = Some(channel);
+        while let Some(id) = current {
+            let info = self.0.cache.channel(id);
+            let scope_type = match info.as_ref().map(|x| x.kind) {
+                Some(ChannelKind::Category) => "category",
+                _ => "channel",
+            };
+            scopes.push(Scope::new(scope_type, ScopeArgs::Long(id.0)));
+            current = info.and_then(|x| x.parent_id);
+            // a thread is in a channel, which is in a category, and nothing is nested deeper.
+            if scopes.len() == 3 {
+                break
+            }
+        }
+        if let Some(guild) = guild {
+            scopes.push(Scope::new("guild", ScopeArgs::Long(guild.0)));
+        }
+        scopes.push(self.0.scope.clone());
+        scopes
+    }
+
+    /// Sends a message to a channel, splitting it into several messages if it is too long.
+    ///
+    /// Returns the last message sent, or `None` if the message was empty.
+    pub async fn send_message(&self, channel: Snowflake, text: &str) -> Result<Option<Message>> {
+        let mut last = None;
+        for part in split_message(text, MESSAGE_LIMIT) {
+            last = Some(self.0.http.send_message(channel, part).await?);
+        }
+        Ok(last)
+    }
+
+    /// Replaces the text of a message the bot sent, which must fit in a single message.
+    pub async fn edit_message(
+        &self, channel: Snowflake, message: Snowflake, text: &str,
+    ) -> Result<Message> {
+        ensure!(text.chars().count() <= MESSAGE_LIMIT, "Edited message is too long.");
+        self.0.http.edit_message(channel, message, text).await
+    }
+
+    pub(crate) fn module(&self) -> &ModuleInfo {
+        &self.0.module
+    }
+
+    pub(crate) fn token(&self) -> &str {
+        &self.0.token
+    }
+
+    pub(crate) fn set_bot_user(&self, user: User) {
+        *self.0.bot_user.write() = Some(Arc::new(user));
+    }
+
+    pub(crate) fn set_status(&self, status: ConnectionStatus) {
+        *self.0.status.lock() = status;
+    }
+}
+#[async_trait]
+impl Connector for DiscordConnection {
+    fn platform(&self) -> &str {
+        "discord"
+    }
+
+    fn capabilities(&self) -> EnumSet<ConnectorCapability> {
+        ConnectorCapability::EditMessages | ConnectorCapability::Replies
+    }
+
+    fn message_limit(&self) -> Option<usize> {
+        Some(MESSAGE_LIMIT)
+    }
+
+    fn allows_everyone(&self) -> bool {
+        self.0.is_public.load(Ordering::Relaxed)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        let mut gateway = self.0.gateway.lock();
+        if gateway.is_none() {
+            *gateway = Some((self.0.start_gateway)(self.clone()));
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(task) = self.0.gateway.lock().take() {
+            task.abort();
+        }
+        self.set_status(ConnectionStatus::Deactivated);
+        Ok(())
+    }
+
+    fn channel_scopes(&self, channel: &str) -> Vec<Scope> {
+        match channel.parse().map(Snowflake) {
+            Ok(id) => self.scopes(id, self.0.cache.channel(id).and_then(|x| x.guild_id)),
+            Err(_) => vec![self.0.scope.clone()],
+        }
+    }
+
+    async fn send_message(&self, channel: &str, text: &str) -> Result<Option<String>> {
+        let sent = self.0.http.send_message(parse_id(channel)?, text).await?;
+        Ok(Some(sent.id.to_string()))
+    }
+
+    async fn reply(&self, channel: &str, message: &str, text: &str) -> Result<Option<String>> {
+        let sent = self.0.http.reply(parse_id(channel)?, parse_id(message)?, text).await?;
+        Ok(Some(sent.id.to_string()))
+    }
+
+    async fn edit_message(&self, channel: &str, message: &str, text: &str) -> Result<()> {
+        DiscordConnection::edit_message(self, parse_id(channel)?, parse_id(message)?, text).await?;
+        Ok(())
+    }
+}
+
+/// The implementation of [`Connection`] for Discord.
+pub(crate) struct DiscordConnectionImpl {
+    conn: DiscordConnection,
+}
+#[async_trait]
+impl <E: Events> Connection<E> for DiscordConnectionImpl {
+    async fn status(&self, _: &Handler<E>) -> ConnectionStatus {
+        self.conn.status()
+    }
+
+    async fn update_connection(&self, target: &Handler<E>) -> Result<()> {
+        self.conn.0.is_public.store(load_public(target)?, Ordering::Relaxed);
+        if load_token(target)? != self.conn.token() {
+            warn!("The Discord token has changed. The new token is used once the bot restarts.");
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self, _: &Handler<E>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_message(&self, _: &Handler<E>, channel: &str, message: &str) -> Result<()> {
+        self.conn.send_message(parse_id(channel)?, message).await?;
+        Ok(())
+    }
+
+    fn connector(&self) -> Option<Arc<dyn Connector>> {
+        Some(Arc::new(self.conn.clone()))
+    }
+}
+
+/// Creates connections to Discord.
+pub(crate) struct DiscordFactory {
+    pub module: ModuleInfo,
+}
+#[async_trait]
+impl <E: Events> ConnectionFactory<E> for DiscordFactory {
+    type Connection = DiscordConnectionImpl;
+
+    async fn create(
+        &self, target: &Handler<E>, _: ConnectionId, scope: Scope,
+    ) -> Result<DiscordConnectionImpl> {
+        let token = load_token(target)?;
+        let gateway_target = target.clone();
+        let conn = DiscordConnection(Arc::new(DiscordConnectionData {
+            module: self.module.clone(),
+            scope,
+            http: DiscordHttp::new(&token)?,
+            token,
+            cache: DiscordCache::default(),
+            bot_user: RwLock::new(None),
+            status: Mutex::new(ConnectionStatus::Disconnected),
+            is_public: AtomicBool::new(load_public(target)?),
+            gateway: Mutex::new(None),
+            start_gateway: Box::new(move |conn| {
+                let module = conn.module().clone();
+                let task = crate::gateway::run_gateway(gateway_target.clone(), conn);
+                gateway_target.get_service::<TaskManager>().spawn(&module, "discord_gateway", task)
+            }),
+        }));
+        Ok(DiscordConnectionImpl { conn })
+    }
+}
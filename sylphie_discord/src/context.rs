@@ -0,0 +1,89 @@
+use crate::ModDiscord;
+use crate::connection::DiscordConnection;
+use crate::model::*;
+use std::sync::Arc;
+use sylphie::commands::connector::*;
+use sylphie::database::config::ConfigManager;
+use sylphie::prelude::*;
+use sylphie::tasks::TaskManager;
+
+/// Information about a message received from Discord.
+///
+/// This can be retrieved from a command's context with [`DiscordContext::from_ctx`], or from a
+/// [`MessageReceivedEvent`] with [`IncomingMessage::platform_data`].
+pub struct DiscordContext {
+    connection: DiscordConnection,
+    message: Message,
+}
+impl DiscordContext {
+    /// Returns the Discord context of a command, or `None` if it was not sent from Discord.
+    pub fn from_ctx<E: Events>(ctx: &CommandCtx<E>) -> Option<&DiscordContext> {
+        ctx.message()?.platform_data()
+    }
+
+    /// Returns the connection the message was received on.
+    pub fn connection(&self) -> &DiscordConnection {
+        &self.connection
+    }
+
+    /// Returns the message.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Returns the user who sent the message.
+    pub fn author(&self) -> &User {
+        &self.message.author
+    }
+
+    /// Returns the channel the message was sent in, if the connection has seen it.
+    pub fn channel(&self) -> Option<Arc<Channel>> {
+        self.connection.cache().channel(self.message.channel_id)
+    }
+
+    /// Returns the guild the message was sent in, or `None` for direct messages.
+    pub fn guild(&self) -> Option<Arc<Guild>> {
+        self.message.guild_id.and_then(|id| self.connection.cache().guild(id))
+    }
+}
+
+async fn handle_message_async<E: Events>(
+    target: Handler<E>, conn: DiscordConnection, message: Message,
+) -> Result<()> {
+    let scopes = conn.scopes(message.channel_id, message.guild_id);
+    let bot_id = conn.bot_user().map(|x| x.id);
+    let is_direct = message.guild_id.is_none();
+    let config = target.get_service::<ConfigManager>();
+    let prefix = config.resolve(&target, &scopes, ModDiscord::CFG_DISCORD_PREFIX).await?;
+    let mentions = match bot_id {
+        Some(id) => vec![format!("<@{}>", id), format!("<@!{}>", id)],
+        None => Vec::new(),
+    };
+    let command = strip_command_prefix(&message.content, &prefix, &mentions, &[], is_direct)
+        .map(str::to_string);
+
+    let author = MessageAuthor::new(
+        format!("discord:{}", message.author.id),
+        format!("discord:{} ({})", message.author.id, message.author.username),
+    );
+    let mut incoming = IncomingMessage::new(
+        Arc::new(conn.clone()), message.channel_id.to_string(), author, message.content.clone(),
+    ).with_id(message.id.to_string()).with_direct(is_direct).with_scopes(scopes);
+    if let Some(command) = command {
+        incoming = incoming.with_command(command);
+    }
+    let incoming = incoming.with_platform_data(DiscordContext { connection: conn, message });
+    receive_message(&target, incoming).await
+}
+
+/// Handles a message received from the gateway, running it as a command if it is one.
+pub(crate) fn handle_message(
+    target: &Handler<impl Events>, conn: &DiscordConnection, message: Message,
+) {
+    let is_own = conn.bot_user().map_or(false, |x| x.id == message.author.id);
+    if is_own || message.author.bot || message.webhook_id.is_some() {
+        return
+    }
+    let task = handle_message_async(target.clone(), conn.clone(), message);
+    target.get_service::<TaskManager>().spawn(conn.module(), "discord_message", task);
+}
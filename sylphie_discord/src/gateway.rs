@@ -0,0 +1,286 @@
+//! The connection to Discord's gateway, which is how the bot receives messages.
+
+use crate::connection::DiscordConnection;
+use crate::model::*;
+use futures::{SinkExt, StreamExt};
+use serde::*;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use sylphie::connections::{Backoff, ConnectionStatus, log_disconnect};
+use sylphie::prelude::*;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const GATEWAY_URL: &str = "wss://gateway.discord.gg";
+const GATEWAY_PARAMS: &str = "?v=10&encoding=json";
+
+const INTENT_GUILDS: u64 = 1 << 0;
+const INTENT_GUILD_MESSAGES: u64 = 1 << 9;
+const INTENT_DIRECT_MESSAGES: u64 = 1 << 12;
+const INTENT_MESSAGE_CONTENT: u64 = 1 << 15;
+const INTENTS: u64 =
+    INTENT_GUILDS | INTENT_GUILD_MESSAGES | INTENT_DIRECT_MESSAGES | INTENT_MESSAGE_CONTENT;
+
+/// The delay before reconnecting after the first failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay before reconnecting.
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+const OP_DISPATCH: u8 = 0;
+const OP_HEARTBEAT: u8 = 1;
+const OP_IDENTIFY: u8 = 2;
+const OP_RESUME: u8 = 6;
+const OP_RECONNECT: u8 = 7;
+const OP_INVALID_SESSION: u8 = 9;
+const OP_HELLO: u8 = 10;
+const OP_HEARTBEAT_ACK: u8 = 11;
+
+#[derive(Deserialize)]
+struct Payload {
+    op: u8,
+    #[serde(default)]
+    d: Value,
+    #[serde(default)]
+    s: Option<u64>,
+    #[serde(default)]
+    t: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Hello {
+    heartbeat_interval: u64,
+}
+
+#[derive(Deserialize)]
+struct Ready {
+    user: User,
+    session_id: String,
+    resume_gateway_url: String,
+}
+
+#[derive(Deserialize)]
+struct GuildCreate {
+    #[serde(flatten)]
+    guild: Guild,
+    #[serde(default)]
+    channels: Vec<Channel>,
+    #[serde(default)]
+    threads: Vec<Channel>,
+}
+
+#[derive(Deserialize)]
+struct Deleted {
+    id: Snowflake,
+    #[serde(default)]
+    unavailable: bool,
+}
+
+/// A gateway session, which can be resumed after a disconnect without missing any events.
+struct Session {
+    id: String,
+    resume_url: String,
+}
+
+/// The state kept between connections to the gateway.
+#[derive(Default)]
+struct GatewayState {
+    session: Option<Session>,
+    sequence: Option<u64>,
+}
+
+/// Why a connection to the gateway ended.
+enum Disconnect {
+    /// The connection should be made again.
+    Reconnect,
+    /// The connection can never succeed, such as when the token is invalid.
+    Fatal(String),
+}
+
+/// Returns how to handle a close code sent by Discord.
+fn close_action(code: u16, state: &mut GatewayState) -> Disconnect {
+    match code {
+        4004 => Disconnect::Fatal("The Discord token is invalid.".to_string()),
+        4010 | 4011 | 4012 => Disconnect::Fatal(format!("Discord closed the gateway ({}).", code)),
+        4013 | 4014 => Disconnect::Fatal(
+            "The bot is not allowed the intents it needs. Enable the message content intent \
+             in the Discord developer portal.".to_string(),
+        ),
+        4007 | 4009 => {
+            state.session = None;
+            Disconnect::Reconnect
+        }
+        _ => Disconnect::Reconnect,
+    }
+}
+
+async fn handle_dispatch(
+    target: &Handler<impl Events>, conn: &DiscordConnection, state: &mut GatewayState,
+    event: &str, data: Value,
+) -> Result<()> {
+    let cache = conn.cache();
+    match event {
+        "READY" => {
+            let ready: Ready = serde_json::from_value(data)?;
+            info!("Connected to Discord as {}.", ready.user.username);
+            cache.clear();
+            conn.set_bot_user(ready.user);
+            state.session = Some(Session {
+                id: ready.session_id,
+                resume_url: ready.resume_gateway_url,
+            });
+            conn.set_status(ConnectionStatus::Connected);
+        }
+        "RESUMED" => {
+            debug!("Resumed the Discord gateway session.");
+            conn.set_status(ConnectionStatus::Connected);
+        }
+        "GUILD_CREATE" => {
+            let create: GuildCreate = serde_json::from_value(data)?;
+            let mut channels = create.channels;
+            channels.extend(create.threads);
+            cache.insert_guild(create.guild, channels);
+        }
+        "GUILD_UPDATE" => cache.update_guild(serde_json::from_value(data)?),
+        "GUILD_DELETE" => {
+            let deleted: Deleted = serde_json::from_value(data)?;
+            if !deleted.unavailable {
+                cache.remove_guild(deleted.id);
+            }
+        }
+        "CHANNEL_CREATE" | "CHANNEL_UPDATE" | "THREAD_CREATE" | "THREAD_UPDATE" =>
+            cache.insert_channel(serde_json::from_value(data)?),
+        "CHANNEL_DELETE" | "THREAD_DELETE" => {
+            let deleted: Deleted = serde_json::from_value(data)?;
+            cache.remove_channel(deleted.id);
+        }
+        "MESSAGE_CREATE" => {
+            let message: Message = serde_json::from_value(data)?;
+            cache.insert_user(&message.author);
+            crate::context::handle_message(target, conn, message);
+        }
+        _ => { }
+    }
+    Ok(())
+}
+
+/// Connects to the gateway and handles events until the connection ends.
+async fn connect_once(
+    target: &Handler<impl Events>, conn: &DiscordConnection, state: &mut GatewayState,
+) -> Result<Disconnect> {
+    let base = state.session.as_ref().map_or(GATEWAY_URL, |x| x.resume_url.as_str());
+    let url = format!("{}/{}", base.trim_end_matches('/'), GATEWAY_PARAMS);
+    let (socket, _) = tokio_tungstenite::connect_async(url.as_str()).await
+        .internal_err(|| "Could not connect to the Discord gateway.")?;
+    let (mut sink, mut stream) = socket.split();
+
+    let hello = loop {
+        match stream.next().await {
+            Some(Ok(WsMessage::Text(text))) => break serde_json::from_str::<Payload>(&text)?,
+            Some(Ok(_)) => { }
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(Disconnect::Reconnect),
+        }
+    };
+    ensure!(hello.op == OP_HELLO, "Discord sent opcode {} instead of Hello.", hello.op);
+    let hello: Hello = serde_json::from_value(hello.d)?;
+    let interval = Duration::from_millis(hello.heartbeat_interval);
+
+    let token = conn.token();
+    let login = match (&state.session, state.sequence) {
+        (Some(session), Some(sequence)) => json!({
+            "op": OP_RESUME,
+            "d": { "token": token, "session_id": session.id, "seq": sequence },
+        }),
+        _ => {
+            state.session = None;
+            state.sequence = None;
+            json!({
+                "op": OP_IDENTIFY,
+                "d": {
+                    "token": token,
+                    "intents": INTENTS,
+                    "properties": {
+                        "os": std::env::consts::OS,
+                        "browser": "sylphie",
+                        "device": "sylphie",
+                    },
+                },
+            })
+        }
+    };
+    sink.send(WsMessage::Text(login.to_string())).await?;
+
+    let start = tokio::time::Instant::now() + interval;
+    let mut heartbeat = tokio::time::interval_at(start, interval);
+    let mut is_acked = true;
+    loop {
+        let message = tokio::select! {
+            _ = heartbeat.tick() => {
+                if !is_acked {
+                    warn!("Discord did not acknowledge a heartbeat. Reconnecting.");
+                    return Ok(Disconnect::Reconnect)
+                }
+                is_acked = false;
+                let beat = json!({ "op": OP_HEARTBEAT, "d": state.sequence });
+                sink.send(WsMessage::Text(beat.to_string())).await?;
+                continue
+            }
+            message = stream.next() => message,
+        };
+        let payload: Payload = match message {
+            Some(Ok(WsMessage::Text(text))) => serde_json::from_str(&text)?,
+            Some(Ok(WsMessage::Close(frame))) => {
+                let code: u16 = frame.map_or(1000, |x| x.code.into());
+                debug!("Discord closed the gateway with code {}.", code);
+                return Ok(close_action(code, state))
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(Disconnect::Reconnect),
+        };
+        match payload.op {
+            OP_DISPATCH => {
+                if payload.s.is_some() {
+                    state.sequence = payload.s;
+                }
+                let event = payload.t.unwrap_or_default();
+                if let Err(e) = handle_dispatch(target, conn, state, &event, payload.d).await {
+                    warn!("Could not handle the Discord {} event: {}", event, e);
+                }
+            }
+            OP_HEARTBEAT => {
+                let beat = json!({ "op": OP_HEARTBEAT, "d": state.sequence });
+                sink.send(WsMessage::Text(beat.to_string())).await?;
+            }
+            OP_RECONNECT => return Ok(Disconnect::Reconnect),
+            OP_INVALID_SESSION => {
+                if !payload.d.as_bool().unwrap_or(false) {
+                    state.session = None;
+                }
+                return Ok(Disconnect::Reconnect)
+            }
+            OP_HEARTBEAT_ACK => is_acked = true,
+            _ => { }
+        }
+    }
+}
+
+/// Keeps a connection to the gateway open, reconnecting whenever it is lost.
+pub(crate) async fn run_gateway(
+    target: Handler<impl Events>, conn: DiscordConnection,
+) -> Result<()> {
+    let mut state = GatewayState::default();
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    loop {
+        let started = Instant::now();
+        let result = connect_once(&target, &conn, &mut state).await;
+        conn.set_status(ConnectionStatus::Disconnected);
+        match result {
+            Ok(Disconnect::Reconnect) => { }
+            Ok(Disconnect::Fatal(reason)) => cmd_error!("{} Not reconnecting.", reason),
+            Err(e) => log_disconnect("the Discord gateway", &e),
+        }
+
+        backoff.reset_if_stable(started.elapsed());
+        backoff.wait("Discord").await;
+    }
+}
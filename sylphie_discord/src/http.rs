@@ -0,0 +1,98 @@
+use crate::model::{Message, Snowflake};
+use reqwest::{Client, Method, StatusCode};
+use serde::*;
+use serde_json::{json, Value};
+use std::time::Duration;
+use sylphie::prelude::*;
+
+const API_BASE: &str = "https://discord.com/api/v10";
+const USER_AGENT: &str = "DiscordBot (https://github.com/Lymia/sylphie, 0.1.0)";
+
+/// How many times a request is retried after being rate limited.
+const MAX_RETRIES: u32 = 5;
+
+/// The longest message Discord accepts, in characters.
+pub const MESSAGE_LIMIT: usize = 2000;
+
+#[derive(Deserialize)]
+struct RateLimited {
+    retry_after: f64,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+/// A client for Discord's REST API.
+pub(crate) struct DiscordHttp {
+    client: Client,
+    authorization: String,
+}
+impl DiscordHttp {
+    pub fn new(token: &str) -> Result<DiscordHttp> {
+        let client = Client::builder().user_agent(USER_AGENT).build()?;
+        Ok(DiscordHttp { client, authorization: format!("Bot {}", token) })
+    }
+
+    async fn request<T: de::DeserializeOwned>(
+        &self, method: Method, path: &str, body: &Value,
+    ) -> Result<T> {
+        let url = format!("{}{}", API_BASE, path);
+        for _ in 0..MAX_RETRIES {
+            let response = self.client.request(method.clone(), &url)
+                .header("Authorization", &self.authorization)
+                .json(body)
+                .send().await?;
+            match response.status() {
+                status if status.is_success() => return Ok(response.json().await?),
+                StatusCode::TOO_MANY_REQUESTS => {
+                    let limited: RateLimited = response.json().await?;
+                    debug!("Rate limited by Discord for {} seconds.", limited.retry_after);
+                    tokio::time::delay_for(Duration::from_secs_f64(limited.retry_after)).await;
+                }
+                status => {
+                    let message = match response.json::<ApiError>().await {
+                        Ok(err) => err.message,
+                        Err(_) => status.to_string(),
+                    };
+                    match status {
+                        StatusCode::FORBIDDEN | StatusCode::NOT_FOUND =>
+                            cmd_error!("Discord refused the request: {}", message),
+                        _ => bail!("Discord request to {} failed: {}", path, message),
+                    }
+                }
+            }
+        }
+        bail!("Discord request to {} was rate limited too many times.", path)
+    }
+
+    /// Sends a message to a channel, without pinging anyone mentioned in it.
+    pub async fn send_message(&self, channel: Snowflake, content: &str) -> Result<Message> {
+        let path = format!("/channels/{}/messages", channel);
+        let body = json!({ "content": content, "allowed_mentions": { "parse": [] } });
+        self.request(Method::POST, &path, &body).await
+    }
+
+    /// Sends a message as a reply to another message, without pinging anyone.
+    pub async fn reply(
+        &self, channel: Snowflake, message: Snowflake, content: &str,
+    ) -> Result<Message> {
+        let path = format!("/channels/{}/messages", channel);
+        let body = json!({
+            "content": content,
+            "allowed_mentions": { "parse": [], "replied_user": false },
+            "message_reference": { "message_id": message, "fail_if_not_exists": false },
+        });
+        self.request(Method::POST, &path, &body).await
+    }
+
+    /// Replaces the text of a message the bot sent.
+    pub async fn edit_message(
+        &self, channel: Snowflake, message: Snowflake, content: &str,
+    ) -> Result<Message> {
+        let path = format!("/channels/{}/messages/{}", channel, message);
+        let body = json!({ "content": content, "allowed_mentions": { "parse": [] } });
+        self.request(Method::PATCH, &path, &body).await
+    }
+}
@@ -0,0 +1,67 @@
+//! Connects Sylphie bots to Discord.
+//!
+//! Adding [`ModDiscord`] to a bot adds the `discord` connection type. The bot token is read from
+//! the configuration file, and the connection is added from the terminal:
+//!
+//! ```toml
+//! owners = ["discord:123456789"]
+//!
+//! [discord]
+//! token = "..."
+//! public = false  # whether users other than the owners can run commands
+//! ```
+//!
+//! ```text
+//! connections add discord discord
+//! ```
+//!
+//! The bot needs the message content intent, which is enabled in the Discord developer portal.
+//!
+//! Messages that start with the `discord_prefix` configuration option or a mention of the bot
+//! are run as commands, as are all messages sent to the bot directly. Commands run in the scopes
+//! of the channel they were sent in, its category and its guild, from most to least specific,
+//! followed by the scope of the connection. Commands can find the message, author, channel and
+//! guild they were sent from with [`DiscordContext::from_ctx`]. Every message is also dispatched
+//! as a [`MessageReceivedEvent`](sylphie::commands::connector::MessageReceivedEvent), with a
+//! [`DiscordContext`] as its platform data.
+//!
+//! By default, only the users listed in `owners` can run commands, as the bot's commands can do
+//! anything, including shutting it down. Setting `public` allows anyone to run them.
+
+#[macro_use] extern crate tracing;
+
+use sylphie::connections::InitConnectionTypesEvent;
+use sylphie::database::config::*;
+use sylphie::prelude::*;
+
+mod connection;
+mod context;
+mod gateway;
+mod http;
+pub mod model;
+
+pub use connection::DiscordConnection;
+pub use context::DiscordContext;
+
+/// A module that can be added to a Sylphie bot to add Discord support.
+#[derive(Module)]
+pub struct ModDiscord {
+    #[module_info] info: ModuleInfo,
+}
+
+#[module_impl]
+impl ModDiscord {
+    /// The prefix for commands sent in Discord messages, or an empty string to only accept
+    /// commands that mention the bot.
+    #[config]
+    pub const CFG_DISCORD_PREFIX: ConfigKey<String> = config_option!(
+        Any, "discord_prefix 6975bba5-0cb6-4fd7-861d-7c996fad1937", || "!".to_string(),
+    );
+
+    #[event_handler]
+    fn init_connection_types(
+        &self, target: &Handler<impl Events>, ev: &mut InitConnectionTypesEvent,
+    ) -> Result<()> {
+        ev.add_type(target, "discord", connection::DiscordFactory { module: self.info.clone() })
+    }
+}
@@ -0,0 +1,240 @@
+//! The Discord objects the connector keeps track of.
+//!
+//! These contain only the fields the connector uses, and are deserialized from the JSON sent by
+//! Discord's gateway and REST API.
+
+use fxhash::FxHashMap;
+use parking_lot::RwLock;
+use serde::*;
+use serde::de::{Error as DeError, Visitor};
+use std::fmt;
+use std::sync::Arc;
+
+/// The ID of a Discord object, such as a user, guild or channel.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct Snowflake(pub u64);
+impl fmt::Display for Snowflake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+impl Serialize for Snowflake {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+impl <'de> Deserialize<'de> for Snowflake {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Discord sends IDs as strings, as they do not fit in a JavaScript number.
+        struct SnowflakeVisitor;
+        impl <'de> Visitor<'de> for SnowflakeVisitor {
+            type Value = Snowflake;
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a Discord ID")
+            }
+            fn visit_u64<E: DeError>(self, v: u64) -> Result<Snowflake, E> {
+                Ok(Snowflake(v))
+            }
+            fn visit_str<E: DeError>(self, v: &str) -> Result<Snowflake, E> {
+                v.parse().map(Snowflake).map_err(|_| E::custom("invalid Discord ID"))
+            }
+        }
+        deserializer.deserialize_any(SnowflakeVisitor)
+    }
+}
+
+/// A Discord user.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct User {
+    /// The ID of the user.
+    pub id: Snowflake,
+    /// The unique username of the user.
+    pub username: String,
+    /// The name shown for the user, if it differs from their username.
+    #[serde(default)]
+    pub global_name: Option<String>,
+    /// Whether the user is a bot.
+    #[serde(default)]
+    pub bot: bool,
+}
+impl User {
+    /// Returns the name shown for the user.
+    pub fn display_name(&self) -> &str {
+        self.global_name.as_deref().unwrap_or(&self.username)
+    }
+}
+
+/// The guild-specific information about the author of a message.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Member {
+    /// The nickname of the member in the guild, if they have one.
+    #[serde(default)]
+    pub nick: Option<String>,
+    /// The IDs of the roles the member has.
+    #[serde(default)]
+    pub roles: Vec<Snowflake>,
+}
+
+/// The kind of a Discord channel.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
+pub enum ChannelKind {
+    /// A text channel in a guild.
+    Text,
+    /// A direct message between the bot and a user.
+    Direct,
+    /// A direct message between several users.
+    Group,
+    /// A category, which contains other channels.
+    Category,
+    /// A thread, whose parent is a text channel.
+    Thread,
+    /// Any other kind of channel, such as a voice channel.
+    Other(u8),
+}
+impl <'de> Deserialize<'de> for ChannelKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match u8::deserialize(deserializer)? {
+            0 | 5 => ChannelKind::Text,
+            1 => ChannelKind::Direct,
+            3 => ChannelKind::Group,
+            4 => ChannelKind::Category,
+            10 | 11 | 12 => ChannelKind::Thread,
+            x => ChannelKind::Other(x),
+        })
+    }
+}
+
+/// A Discord channel.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct Channel {
+    /// The ID of the channel.
+    pub id: Snowflake,
+    /// The kind of the channel.
+    #[serde(rename = "type")]
+    pub kind: ChannelKind,
+    /// The guild the channel is in, or `None` for direct messages.
+    #[serde(default)]
+    pub guild_id: Option<Snowflake>,
+    /// The name of the channel. Direct messages have no name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The category the channel is in, or the channel a thread was started in.
+    #[serde(default)]
+    pub parent_id: Option<Snowflake>,
+}
+
+/// A Discord guild, which is shown as a server in Discord's interface.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct Guild {
+    /// The ID of the guild.
+    pub id: Snowflake,
+    /// The name of the guild.
+    pub name: String,
+    /// The ID of the user who owns the guild.
+    #[serde(default)]
+    pub owner_id: Option<Snowflake>,
+}
+
+/// A message sent in a Discord channel.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct Message {
+    /// The ID of the message.
+    pub id: Snowflake,
+    /// The channel the message was sent in.
+    pub channel_id: Snowflake,
+    /// The guild the message was sent in, or `None` for direct messages.
+    #[serde(default)]
+    pub guild_id: Option<Snowflake>,
+    /// The user who sent the message.
+    pub author: User,
+    /// The author's information in the guild, for messages sent in a guild.
+    #[serde(default)]
+    pub member: Option<Member>,
+    /// The text of the message.
+    pub content: String,
+    /// The webhook that sent the message, if it was sent by one.
+    #[serde(default)]
+    pub webhook_id: Option<Snowflake>,
+}
+
+/// The guilds, channels and users a Discord connection has seen.
+///
+/// Guilds and their channels are added as the gateway reports them, and users are added when
+/// they send a message.
+#[derive(Default)]
+pub struct DiscordCache {
+    guilds: RwLock<FxHashMap<Snowflake, Arc<Guild>>>,
+    channels: RwLock<FxHashMap<Snowflake, Arc<Channel>>>,
+    users: RwLock<FxHashMap<Snowflake, Arc<User>>>,
+}
+impl DiscordCache {
+    /// Returns a guild the bot is in.
+    pub fn guild(&self, id: Snowflake) -> Option<Arc<Guild>> {
+        self.guilds.read().get(&id).cloned()
+    }
+
+    /// Returns a channel the bot can see.
+    pub fn channel(&self, id: Snowflake) -> Option<Arc<Channel>> {
+        self.channels.read().get(&id).cloned()
+    }
+
+    /// Returns a user who has sent a message the bot has seen.
+    pub fn user(&self, id: Snowflake) -> Option<Arc<User>> {
+        self.users.read().get(&id).cloned()
+    }
+
+    /// Returns every guild the bot is in.
+    pub fn guilds(&self) -> Vec<Arc<Guild>> {
+        self.guilds.read().values().cloned().collect()
+    }
+
+    /// Returns every channel the bot can see in a guild.
+    pub fn guild_channels(&self, guild: Snowflake) -> Vec<Arc<Channel>> {
+        let channels = self.channels.read();
+        channels.values().filter(|x| x.guild_id == Some(guild)).cloned().collect()
+    }
+
+    pub(crate) fn insert_guild(&self, guild: Guild, channels: Vec<Channel>) {
+        let id = guild.id;
+        self.remove_guild(id);
+        self.guilds.write().insert(id, Arc::new(guild));
+        let mut cache = self.channels.write();
+        for mut channel in channels {
+            // channels sent with a guild do not include its ID.
+            channel.guild_id = Some(id);
+            cache.insert(channel.id, Arc::new(channel));
+        }
+    }
+
+    pub(crate) fn update_guild(&self, guild: Guild) {
+        self.guilds.write().insert(guild.id, Arc::new(guild));
+    }
+
+    pub(crate) fn remove_guild(&self, id: Snowflake) {
+        self.guilds.write().remove(&id);
+        self.channels.write().retain(|_, x| x.guild_id != Some(id));
+    }
+
+    pub(crate) fn insert_channel(&self, channel: Channel) {
+        self.channels.write().insert(channel.id, Arc::new(channel));
+    }
+
+    pub(crate) fn remove_channel(&self, id: Snowflake) {
+        self.channels.write().remove(&id);
+    }
+
+    pub(crate) fn insert_user(&self, user: &User) {
+        self.users.write().insert(user.id, Arc::new(user.clone()));
+    }
+
+    pub(crate) fn clear(&self) {
+        self.guilds.write().clear();
+        self.channels.write().clear();
+    }
+}
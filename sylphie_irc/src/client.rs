@@ -0,0 +1,282 @@
+//! The connection to a single IRC server.
+
+use crate::connection::{IrcConnection, IrcNetwork, OutgoingLine};
+use crate::proto::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sylphie::connections::{Backoff, ConnectionStatus, log_disconnect};
+use sylphie::prelude::*;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// The delay before reconnecting after the first failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay before reconnecting.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How long the server can be silent before the bot pings it, and then how long it has to
+/// answer.
+const PING_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The time each line adds to the flood control clock.
+const FLOOD_INTERVAL: Duration = Duration::from_millis(500);
+/// How far ahead of the current time the flood control clock can be before lines are delayed.
+const FLOOD_BURST: Duration = Duration::from_secs(4);
+
+/// The capabilities requested from the server, if it offers them.
+const WANTED_CAPS: &[&str] = &["account-tag", "extended-join", "multi-prefix"];
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+impl <T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Why a connection to the server ended.
+enum Disconnect {
+    /// The connection should be made again.
+    Reconnect,
+    /// The connection can never succeed, such as when the SASL credentials are wrong.
+    Fatal(String),
+}
+
+/// The state of a single connection to the server.
+struct Session {
+    available_caps: Vec<String>,
+    registered: bool,
+}
+
+/// Splits SASL PLAIN credentials into `AUTHENTICATE` lines of at most 400 bytes.
+fn sasl_lines(user: &str, password: &str) -> Vec<String> {
+    let encoded = base64::encode(format!("{}\0{}\0{}", user, user, password));
+    let mut lines: Vec<_> = encoded.as_bytes().chunks(400)
+        .map(|x| format_line("AUTHENTICATE", &[std::str::from_utf8(x).unwrap()]))
+        .collect();
+    if encoded.len() % 400 == 0 {
+        lines.push(format_line("AUTHENTICATE", &["+"]));
+    }
+    lines
+}
+
+fn handle_ctcp(network: &IrcNetwork, nick: &str, command: &str, args: &str) -> Result<()> {
+    let reply = match command {
+        "VERSION" => format!("Sylphie {}", env!("CARGO_PKG_VERSION")),
+        "PING" => args.to_string(),
+        "SOURCE" => "https://github.com/Lymia/sylphie".to_string(),
+        "CLIENTINFO" => "ACTION CLIENTINFO PING SOURCE VERSION".to_string(),
+        _ => return Ok(()),
+    };
+    network.send_line(format_line("NOTICE", &[nick, &format_ctcp(command, &reply)]))
+}
+
+/// Handles a line from the server, and returns why the connection should end, if it should.
+fn handle_line(
+    target: &Handler<impl Events>, conn: &IrcConnection, network: &Arc<IrcNetwork>,
+    session: &mut Session, message: IrcMessage,
+) -> Result<Option<Disconnect>> {
+    let config = &network.config;
+    match message.command.as_str() {
+        "PING" => network.send_line(format_line("PONG", &[message.param(0)]))?,
+        "ERROR" => {
+            warn!("The IRC network '{}' closed the connection: {}", network.name, message.param(0));
+            return Ok(Some(Disconnect::Reconnect))
+        }
+        "CAP" => match message.param(1) {
+            "LS" => {
+                let is_last = message.params.len() < 4 || message.param(2) != "*";
+                let caps = message.params.last().map_or("", |x| x.as_str());
+                for cap in caps.split(' ') {
+                    let name = cap.split('=').next().unwrap_or("");
+                    session.available_caps.push(name.to_string());
+                }
+                if is_last {
+                    let mut wanted: Vec<_> = WANTED_CAPS.iter()
+                        .filter(|x| session.available_caps.iter().any(|c| c == *x))
+                        .copied()
+                        .collect();
+                    if config.sasl().is_some() {
+                        if session.available_caps.iter().any(|x| x == "sasl") {
+                            wanted.push("sasl");
+                        } else {
+                            warn!("The IRC network '{}' does not support SASL.", network.name);
+                        }
+                    }
+                    if wanted.is_empty() {
+                        network.send_line(format_line("CAP", &["END"]))?;
+                    } else {
+                        network.send_line(format_line("CAP", &["REQ", &wanted.join(" ")]))?;
+                    }
+                }
+            }
+            "ACK" => {
+                let is_sasl = message.param(2).split(' ').any(|x| x == "sasl");
+                if is_sasl && config.sasl().is_some() {
+                    network.send_line(format_line("AUTHENTICATE", &["PLAIN"]))?;
+                } else {
+                    network.send_line(format_line("CAP", &["END"]))?;
+                }
+            }
+            "NAK" => network.send_line(format_line("CAP", &["END"]))?,
+            _ => { }
+        },
+        "AUTHENTICATE" if message.param(0) == "+" => {
+            if let Some((user, password)) = config.sasl() {
+                for line in sasl_lines(user, password) {
+                    network.send_line(line)?;
+                }
+            }
+        }
+        "903" => network.send_line(format_line("CAP", &["END"]))?,
+        "902" | "904" | "905" => return Ok(Some(Disconnect::Fatal(
+            format!("SASL authentication with the IRC network '{}' failed.", network.name),
+        ))),
+        "001" => {
+            session.registered = true;
+            network.set_nick(message.param(0));
+            network.set_status(ConnectionStatus::Connected);
+            info!("Connected to the IRC network '{}' as {}.", network.name, message.param(0));
+            for channel in &config.channels {
+                network.send_line(format_line("JOIN", &[channel]))?;
+            }
+        }
+        "433" if !session.registered => {
+            let nick = format!("{}_", network.nick());
+            network.set_nick(&nick);
+            network.send_line(format_line("NICK", &[&nick]))?;
+        }
+        "NICK" => if message.source_nick() == Some(network.nick().as_str()) {
+            network.set_nick(message.param(0));
+        },
+        "KICK" => if message.param(1) == network.nick() {
+            warn!("Kicked from {} on '{}': {}", message.param(0), network.name, message.param(2));
+            network.send_line(format_line("JOIN", &[message.param(0)]))?;
+        },
+        "PRIVMSG" => {
+            let nick = match message.source_nick() {
+                Some(nick) => nick.to_string(),
+                None => return Ok(None),
+            };
+            let ctcp = parse_ctcp(message.param(1))
+                .map(|(command, args)| (command.to_ascii_uppercase(), args.to_string()));
+            match ctcp {
+                Some((command, args)) if command != "ACTION" =>
+                    handle_ctcp(network, &nick, &command, &args)?,
+                _ => crate::context::handle_message(target, conn, network, message),
+            }
+        }
+        _ => { }
+    }
+    Ok(None)
+}
+
+/// Sends lines from a queue to the server, delaying them if too many are sent at once.
+async fn write_lines(
+    mut write: impl AsyncWrite + Unpin, mut receiver: mpsc::UnboundedReceiver<OutgoingLine>,
+) -> Result<()> {
+    let mut clock = tokio::time::Instant::now();
+    while let Some(OutgoingLine { line, sent }) = receiver.recv().await {
+        let now = tokio::time::Instant::now();
+        if clock < now {
+            clock = now;
+        }
+        if clock > now + FLOOD_BURST {
+            tokio::time::delay_until(clock - FLOOD_BURST).await;
+        }
+        write.write_all(line.as_bytes()).await?;
+        write.write_all(b"\r\n").await?;
+        if let Some(sent) = sent {
+            write.flush().await?;
+            let _ = sent.send(());
+        }
+        clock += FLOOD_INTERVAL;
+    }
+    Ok(())
+}
+
+/// Reads lines from the server and handles them until the connection ends.
+async fn read_lines(
+    target: &Handler<impl Events>, conn: &IrcConnection, network: &Arc<IrcNetwork>,
+    mut read: impl AsyncBufRead + Unpin,
+) -> Result<Disconnect> {
+    let mut session = Session { available_caps: Vec::new(), registered: false };
+    let mut buf = Vec::new();
+    let mut is_pinged = false;
+    loop {
+        let result = tokio::time::timeout(PING_TIMEOUT, read.read_until(b'\n', &mut buf)).await;
+        match result {
+            Err(_) if is_pinged => {
+                warn!("The IRC network '{}' stopped responding. Reconnecting.", network.name);
+                return Ok(Disconnect::Reconnect)
+            }
+            Err(_) => {
+                network.send_line(format_line("PING", &["sylphie"]))?;
+                is_pinged = true;
+                continue
+            }
+            Ok(Ok(0)) => return Ok(Disconnect::Reconnect),
+            Ok(Ok(_)) => { }
+            Ok(Err(e)) => return Err(e.into()),
+        }
+        is_pinged = false;
+        let line = String::from_utf8_lossy(&buf).into_owned();
+        buf.clear();
+        let message = match IrcMessage::parse(&line) {
+            Some(message) => message,
+            None => continue,
+        };
+        if let Some(disconnect) = handle_line(target, conn, network, &mut session, message)? {
+            return Ok(disconnect)
+        }
+    }
+}
+
+/// Connects to the server and handles lines until the connection ends.
+async fn connect_once(
+    target: &Handler<impl Events>, conn: &IrcConnection, network: &Arc<IrcNetwork>,
+) -> Result<Disconnect> {
+    let config = &network.config;
+    let stream = TcpStream::connect((config.server.as_str(), config.port())).await
+        .internal_err(|| format!("Could not connect to the IRC network '{}'.", network.name))?;
+    let stream: Box<dyn Transport> = if config.tls {
+        let tls = tokio_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+        Box::new(tls.connect(&config.server, stream).await?)
+    } else {
+        Box::new(stream)
+    };
+    let (read, write) = tokio::io::split(stream);
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    network.set_sender(Some(sender));
+    network.set_nick(&config.nick);
+    network.send_line(format_line("CAP", &["LS", "302"]))?;
+    if let Some(password) = &config.password {
+        network.send_line(format_line("PASS", &[password]))?;
+    }
+    network.send_line(format_line("NICK", &[&config.nick]))?;
+    network.send_line(format_line("USER", &[config.username(), "0", "*", config.realname()]))?;
+
+    tokio::select! {
+        result = read_lines(target, conn, network, BufReader::new(read)) => result,
+        result = write_lines(write, receiver) => result.map(|_| Disconnect::Reconnect),
+    }
+}
+
+/// Keeps a connection to a network open, reconnecting whenever it is lost.
+pub(crate) async fn run_network(
+    target: Handler<impl Events>, conn: IrcConnection, network: Arc<IrcNetwork>,
+) -> Result<()> {
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    let name = format!("the IRC network '{}'", network.name);
+    loop {
+        let started = Instant::now();
+        let result = connect_once(&target, &conn, &network).await;
+        network.set_sender(None);
+        network.set_status(ConnectionStatus::Disconnected);
+        match result {
+            Ok(Disconnect::Reconnect) => { }
+            Ok(Disconnect::Fatal(reason)) => cmd_error!("{} Not reconnecting.", reason),
+            Err(e) => log_disconnect(&name, &e),
+        }
+
+        backoff.reset_if_stable(started.elapsed());
+        backoff.wait(&name).await;
+    }
+}
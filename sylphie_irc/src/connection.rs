@@ -0,0 +1,384 @@
+use async_trait::*;
+use crate::proto::*;
+use parking_lot::{Mutex, RwLock};
+use serde::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use sylphie::commands::connector::Connector;
+use sylphie::config::Config;
+use sylphie::connections::*;
+use sylphie::prelude::*;
+use sylphie::tasks::{TaskHandle, TaskManager};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// The room left in a line for the prefix the server adds when relaying a message, other than
+/// the bot's nickname: the `:`, `!`, `@` and trailing space, a 10 character username and a 63
+/// character hostname.
+const PREFIX_ALLOWANCE: usize = 4 + 10 + 63;
+/// The shortest part a message is split into, even if a long nickname or target leaves less
+/// room in a line.
+const MIN_PART_LENGTH: usize = 64;
+
+/// How long the bot waits for its `QUIT` lines to be sent before disconnecting.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_tls() -> bool {
+    true
+}
+
+/// The settings of a network, from the `[irc.networks.<name>]` table of the configuration file.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct NetworkConfig {
+    pub server: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default = "default_tls")]
+    pub tls: bool,
+    pub nick: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub realname: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub sasl_username: Option<String>,
+    #[serde(default)]
+    pub sasl_password: Option<String>,
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+impl NetworkConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(if self.tls { 6697 } else { 6667 })
+    }
+
+    pub fn username(&self) -> &str {
+        self.username.as_ref().unwrap_or(&self.nick)
+    }
+
+    pub fn realname(&self) -> &str {
+        self.realname.as_ref().map_or("Sylphie", |x| x.as_str())
+    }
+
+    pub fn sasl(&self) -> Option<(&str, &str)> {
+        let password = self.sasl_password.as_ref()?;
+        Some((self.sasl_username.as_ref().unwrap_or(&self.nick).as_str(), password.as_str()))
+    }
+}
+
+/// Reads the networks to connect to from the configuration file.
+fn load_networks(target: &Handler<impl Events>) -> Result<BTreeMap<String, NetworkConfig>> {
+    let networks = target.get_service::<Config>()
+        .get::<BTreeMap<String, NetworkConfig>>("irc.networks")?
+        .unwrap_or_default();
+    if networks.is_empty() {
+        cmd_error!("No IRC networks are set. Add them as `[irc.networks.<name>]` tables in the \
+                    configuration file.");
+    }
+    for name in networks.keys() {
+        ensure!(!name.is_empty() && !name.contains('/'), "'{}' is not a valid network name.", name);
+    }
+    Ok(networks)
+}
+
+/// Reads whether users other than the owners can run commands from the configuration file.
+fn load_public(target: &Handler<impl Events>) -> Result<bool> {
+    Ok(target.get_service::<Config>().get("irc.public")?.unwrap_or(false))
+}
+
+/// Splits a channel passed to the connector into a network and a target.
+fn parse_channel(channel: &str) -> Result<(&str, &str)> {
+    match channel.find('/') {
+        Some(i) => Ok((&channel[..i], &channel[i + 1..])),
+        None => cmd_error!("'{}' is not an IRC channel. Use `<network>/<target>`.", channel),
+    }
+}
+
+/// A line queued to be sent to the server.
+pub(crate) struct OutgoingLine {
+    pub line: String,
+    /// Notified once the line has been written to the server.
+    pub sent: Option<oneshot::Sender<()>>,
+}
+
+/// One of the networks an IRC connection is connected to.
+pub(crate) struct IrcNetwork {
+    pub name: String,
+    pub config: NetworkConfig,
+    nick: RwLock<String>,
+    status: Mutex<ConnectionStatus>,
+    sender: Mutex<Option<UnboundedSender<OutgoingLine>>>,
+}
+impl IrcNetwork {
+    fn new(name: String, config: NetworkConfig) -> Self {
+        IrcNetwork {
+            nick: RwLock::new(config.nick.clone()),
+            name,
+            config,
+            status: Mutex::new(ConnectionStatus::Disconnected),
+            sender: Mutex::new(None),
+        }
+    }
+
+    /// Returns the bot's current nickname on this network.
+    pub fn nick(&self) -> String {
+        self.nick.read().clone()
+    }
+
+    pub fn set_nick(&self, nick: &str) {
+        *self.nick.write() = nick.to_string();
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        *self.status.lock()
+    }
+
+    pub fn set_status(&self, status: ConnectionStatus) {
+        *self.status.lock() = status;
+    }
+
+    /// Sets the queue lines are sent through, or clears it when the network disconnects.
+    pub fn set_sender(&self, sender: Option<UnboundedSender<OutgoingLine>>) {
+        *self.sender.lock() = sender;
+    }
+
+    fn queue_line(&self, line: OutgoingLine) -> Result<()> {
+        match &*self.sender.lock() {
+            Some(sender) if sender.send(line).is_ok() => Ok(()),
+            _ => cmd_error!("Not connected to the IRC network '{}'.", self.name),
+        }
+    }
+
+    /// Queues a line to be sent to the server.
+    pub fn send_line(&self, line: String) -> Result<()> {
+        self.queue_line(OutgoingLine { line, sent: None })
+    }
+
+    /// Queues a line to be sent to the server, returning a receiver that completes once the
+    /// line has been written.
+    pub fn send_line_notify(&self, line: String) -> Result<oneshot::Receiver<()>> {
+        let (sent, receiver) = oneshot::channel();
+        self.queue_line(OutgoingLine { line, sent: Some(sent) })?;
+        Ok(receiver)
+    }
+
+    /// Sends a message to a channel or user, splitting it into several lines if it is too long.
+    pub fn send_privmsg(&self, target: &str, text: &str) -> Result<()> {
+        let overhead = format_line("PRIVMSG", &[target, "x y"]).len() - 3;
+        let limit = LINE_LIMIT.saturating_sub(2 + PREFIX_ALLOWANCE + self.nick().len() + overhead)
+            .max(MIN_PART_LENGTH);
+        for part in split_lines(text, limit) {
+            self.send_line(format_line("PRIVMSG", &[target, part]))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the scopes of a channel or user on this network, from most to least specific.
+    pub fn scopes(&self, target: &str) -> Vec<Scope> {
+        let id = format!("{}/{}", self.name, target.to_ascii_lowercase());
+        let kind = if is_channel(target) { "channel" } else { "user" };
+        vec![
+            Scope::new(kind, ScopeArgs::String(id.into())),
+            Scope::new("server", ScopeArgs::String(self.name.clone().into())),
+        ]
+    }
+}
+
+struct IrcConnectionData {
+    module: ModuleInfo,
+    scope: Scope,
+    networks: BTreeMap<String, Arc<IrcNetwork>>,
+    is_public: AtomicBool,
+    tasks: Mutex<Vec<TaskHandle>>,
+    start_network: Box<dyn Fn(IrcConnection, Arc<IrcNetwork>) -> TaskHandle + Send + Sync>,
+}
+
+/// A connection to one or more IRC networks.
+///
+/// Channels are named as `<network>/<target>`, where the target is a channel such as `#sylphie`
+/// or the nickname of a user.
+#[derive(Clone)]
+pub struct IrcConnection(Arc<IrcConnectionData>);
+impl IrcConnection {
+    /// Returns the scope of this connection.
+    pub fn scope(&self) -> &Scope {
+        &self.0.scope
+    }
+
+    /// Returns the names of the networks this connection connects to.
+    pub fn networks(&self) -> impl Iterator<Item = &str> {
+        self.0.networks.keys().map(|x| x.as_str())
+    }
+
+    /// Returns the bot's current nickname on a network.
+    pub fn nick(&self, network: &str) -> Option<String> {
+        self.0.networks.get(network).map(|x| x.nick())
+    }
+
+    /// Returns the status of the connection to a network.
+    pub fn network_status(&self, network: &str) -> Option<ConnectionStatus> {
+        self.0.networks.get(network).map(|x| x.status())
+    }
+
+    /// Returns the status of the connection, which is partly connected if only some of the
+    /// networks are connected.
+    pub fn status(&self) -> ConnectionStatus {
+        let statuses: Vec<_> = self.0.networks.values().map(|x| x.status()).collect();
+        if statuses.iter().all(|x| *x == ConnectionStatus::Deactivated) {
+            ConnectionStatus::Deactivated
+        } else if statuses.iter().all(|x| *x == ConnectionStatus::Connected) {
+            ConnectionStatus::Connected
+        } else if statuses.iter().any(|x| *x == ConnectionStatus::Connected) {
+            ConnectionStatus::PartlyConnected
+        } else {
+            ConnectionStatus::Disconnected
+        }
+    }
+
+    /// Sends a raw line to a network, such as `MODE #sylphie +v nick`.
+    pub fn send_raw(&self, network: &str, line: &str) -> Result<()> {
+        self.network(network)?.send_line(line.replace(&['\r', '\n'][..], " "))
+    }
+
+    pub(crate) fn module(&self) -> &ModuleInfo {
+        &self.0.module
+    }
+
+    pub(crate) fn network(&self, name: &str) -> Result<&Arc<IrcNetwork>> {
+        self.0.networks.get(name).cmd_error(|| format!("No IRC network named '{}'.", name))
+    }
+}
+#[async_trait]
+impl Connector for IrcConnection {
+    fn platform(&self) -> &str {
+        "irc"
+    }
+
+    fn allows_everyone(&self) -> bool {
+        self.0.is_public.load(Ordering::Relaxed)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        let mut tasks = self.0.tasks.lock();
+        if tasks.is_empty() {
+            for network in self.0.networks.values() {
+                tasks.push((self.0.start_network)(self.clone(), network.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        for task in self.0.tasks.lock().drain(..) {
+            task.abort();
+        }
+        for network in self.0.networks.values() {
+            network.set_sender(None);
+            network.set_status(ConnectionStatus::Deactivated);
+        }
+        Ok(())
+    }
+
+    fn channel_scopes(&self, channel: &str) -> Vec<Scope> {
+        let mut scopes = match parse_channel(channel) {
+            Ok((network, target)) => match self.0.networks.get(network) {
+                Some(network) => network.scopes(target),
+                None => Vec::new(),
+            },
+            Err(_) => Vec::new(),
+        };
+        scopes.push(self.0.scope.clone());
+        scopes
+    }
+
+    async fn send_message(&self, channel: &str, text: &str) -> Result<Option<String>> {
+        let (network, target) = parse_channel(channel)?;
+        self.network(network)?.send_privmsg(target, text)?;
+        Ok(None)
+    }
+}
+
+/// The implementation of [`Connection`] for IRC.
+pub(crate) struct IrcConnectionImpl {
+    conn: IrcConnection,
+}
+#[async_trait]
+impl <E: Events> Connection<E> for IrcConnectionImpl {
+    async fn status(&self, _: &Handler<E>) -> ConnectionStatus {
+        self.conn.status()
+    }
+
+    async fn update_connection(&self, target: &Handler<E>) -> Result<()> {
+        self.conn.0.is_public.store(load_public(target)?, Ordering::Relaxed);
+        let networks = load_networks(target)?;
+        let unchanged = networks.len() == self.conn.0.networks.len() &&
+            networks.iter().all(|(name, config)| {
+                self.conn.0.networks.get(name).map_or(false, |x| x.config == *config)
+            });
+        if !unchanged {
+            warn!("The IRC networks have changed. The new settings are used once the bot \
+                   restarts.");
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self, _: &Handler<E>) -> Result<()> {
+        let mut pending = Vec::new();
+        for network in self.conn.0.networks.values() {
+            if let Ok(sent) = network.send_line_notify(format_line("QUIT", &["Shutting down."])) {
+                pending.push(sent);
+            }
+        }
+        // the networks are disconnected once this returns, which would drop the queued lines.
+        let deadline = tokio::time::Instant::now() + QUIT_TIMEOUT;
+        for sent in pending {
+            let _ = tokio::time::timeout_at(deadline, sent).await;
+        }
+        Ok(())
+    }
+
+    async fn send_message(&self, _: &Handler<E>, channel: &str, message: &str) -> Result<()> {
+        Connector::send_message(&self.conn, channel, message).await?;
+        Ok(())
+    }
+
+    fn connector(&self) -> Option<Arc<dyn Connector>> {
+        Some(Arc::new(self.conn.clone()))
+    }
+}
+
+/// Creates connections to IRC.
+pub(crate) struct IrcFactory {
+    pub module: ModuleInfo,
+}
+#[async_trait]
+impl <E: Events> ConnectionFactory<E> for IrcFactory {
+    type Connection = IrcConnectionImpl;
+
+    async fn create(
+        &self, target: &Handler<E>, _: ConnectionId, scope: Scope,
+    ) -> Result<IrcConnectionImpl> {
+        let networks = load_networks(target)?.into_iter()
+            .map(|(name, config)| (name.clone(), Arc::new(IrcNetwork::new(name, config))))
+            .collect();
+        let network_target = target.clone();
+        let conn = IrcConnection(Arc::new(IrcConnectionData {
+            module: self.module.clone(),
+            scope,
+            networks,
+            is_public: AtomicBool::new(load_public(target)?),
+            tasks: Mutex::new(Vec::new()),
+            start_network: Box::new(move |conn, network| {
+                let module = conn.module().clone();
+                let name = format!("irc_network_{}", network.name);
+                let task = crate::client::run_network(network_target.clone(), conn, network);
+                network_target.get_service::<TaskManager>().spawn(&module, &name, task)
+            }),
+        }));
+        Ok(IrcConnectionImpl { conn })
+    }
+}
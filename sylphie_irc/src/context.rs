@@ -0,0 +1,102 @@
+use crate::ModIrc;
+use crate::connection::{IrcConnection, IrcNetwork};
+use crate::proto::*;
+use std::sync::Arc;
+use sylphie::commands::connector::*;
+use sylphie::database::config::ConfigManager;
+use sylphie::prelude::*;
+use sylphie::tasks::TaskManager;
+
+/// Information about a message received from IRC.
+///
+/// This can be retrieved from a command's context with [`IrcContext::from_ctx`], or from a
+/// [`MessageReceivedEvent`] with [`IncomingMessage::platform_data`].
+pub struct IrcContext {
+    connection: IrcConnection,
+    network: String,
+    message: IrcMessage,
+}
+impl IrcContext {
+    /// Returns the IRC context of a command, or `None` if it was not sent from IRC.
+    pub fn from_ctx<E: Events>(ctx: &CommandCtx<E>) -> Option<&IrcContext> {
+        ctx.message()?.platform_data()
+    }
+
+    /// Returns the connection the message was received on.
+    pub fn connection(&self) -> &IrcConnection {
+        &self.connection
+    }
+
+    /// Returns the name of the network the message was received from.
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    /// Returns the raw message.
+    pub fn message(&self) -> &IrcMessage {
+        &self.message
+    }
+
+    /// Returns the nickname of the user who sent the message.
+    pub fn nick(&self) -> &str {
+        self.message.source_nick().unwrap_or("")
+    }
+
+    /// Returns the services account of the user who sent the message, if they are logged in and
+    /// the network supports the `account-tag` capability.
+    pub fn account(&self) -> Option<&str> {
+        self.message.tag("account").filter(|x| *x != "*")
+    }
+}
+
+async fn handle_message_async<E: Events>(
+    target: Handler<E>, conn: IrcConnection, network: Arc<IrcNetwork>, message: IrcMessage,
+) -> Result<()> {
+    let nick = message.source_nick().unwrap_or("").to_string();
+    let is_direct = !is_channel(message.param(0));
+    let channel = if is_direct { nick.clone() } else { message.param(0).to_string() };
+    let channel = format!("{}/{}", network.name, channel);
+
+    let id = match message.tag("account").filter(|x| *x != "*") {
+        Some(account) => format!("irc:{}:{}", network.name, account),
+        None => format!("irc:{}:{}", network.name, message.prefix.as_deref().unwrap_or(&nick)),
+    };
+    let author = MessageAuthor::new(id, format!("irc:{}:{}", network.name, nick));
+
+    let (text, command) = match parse_ctcp(message.param(1)) {
+        Some((_, action)) => (action.to_string(), None),
+        None => {
+            let text = message.param(1);
+            let scopes = conn.channel_scopes(&channel);
+            let config = target.get_service::<ConfigManager>();
+            let prefix = config.resolve(&target, &scopes, ModIrc::CFG_IRC_PREFIX).await?;
+            let command = strip_command_prefix(text, &prefix, &[], &[network.nick()], is_direct);
+            (text.to_string(), command.map(str::to_string))
+        }
+    };
+
+    let mut incoming = IncomingMessage::new(Arc::new(conn.clone()), channel, author, text)
+        .with_direct(is_direct);
+    if let Some(command) = command {
+        incoming = incoming.with_command(command);
+    }
+    let incoming = incoming.with_platform_data(IrcContext {
+        connection: conn,
+        network: network.name.clone(),
+        message,
+    });
+    receive_message(&target, incoming).await
+}
+
+/// Handles a `PRIVMSG` received from a network, running it as a command if it is one.
+pub(crate) fn handle_message(
+    target: &Handler<impl Events>, conn: &IrcConnection, network: &Arc<IrcNetwork>,
+    message: IrcMessage,
+) {
+    match message.source_nick() {
+        Some(nick) if !nick.eq_ignore_ascii_case(&network.nick()) => { }
+        _ => return,
+    }
+    let task = handle_message_async(target.clone(), conn.clone(), network.clone(), message);
+    target.get_service::<TaskManager>().spawn(conn.module(), "irc_message", task);
+}
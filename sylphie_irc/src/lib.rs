@@ -0,0 +1,79 @@
+//! Connects Sylphie bots to IRC.
+//!
+//! Adding [`ModIrc`] to a bot adds the `irc` connection type. One connection can connect to
+//! several networks, which are set in the configuration file:
+//!
+//! ```toml
+//! owners = ["irc:libera:lymia"]
+//!
+//! [irc]
+//! public = false  # whether users other than the owners can run commands
+//!
+//! [irc.networks.libera]
+//! server = "irc.libera.chat"
+//! port = 6697       # the default is 6697 with TLS, and 6667 without it
+//! tls = true
+//! nick = "sylphie"
+//! sasl_username = "sylphie"
+//! sasl_password = "..."
+//! channels = ["#sylphie"]
+//! ```
+//!
+//! ```text
+//! connections add irc irc
+//! ```
+//!
+//! Networks can also set `username`, `realname` and a server `password`. If `sasl_password` is
+//! set, the bot logs in with SASL PLAIN, and stops connecting to the network if it is rejected.
+//!
+//! Messages that start with the `irc_prefix` configuration option or are addressed to the bot,
+//! such as `sylphie: help`, are run as commands, as are all private messages. Long responses
+//! are split into several lines to fit in the IRC line length limit. Commands run in the scope
+//! of the channel they were sent in (or of the user, for private messages), then the scope of the
+//! network as a `server`, then the scope of the connection. Channels are named
+//! `<network>/<target>`, such as `libera/#sylphie`, for example in `log_alert_channel`.
+//!
+//! Owners are named `irc:<network>:<account>` by their services account, which requires the
+//! network to support the `account-tag` capability. Users who are not logged in are named by
+//! their full hostmask instead, as `irc:<network>:<nick>!<user>@<host>`. By default, only the
+//! owners can run commands. Setting `public` allows anyone to run them.
+//!
+//! The bot answers the `VERSION`, `PING`, `SOURCE` and `CLIENTINFO` CTCP requests. `ACTION`
+//! messages are passed on as messages, but are never run as commands.
+
+#[macro_use] extern crate tracing;
+
+use sylphie::connections::InitConnectionTypesEvent;
+use sylphie::database::config::*;
+use sylphie::prelude::*;
+
+mod client;
+mod connection;
+mod context;
+pub mod proto;
+
+pub use connection::IrcConnection;
+pub use context::IrcContext;
+
+/// A module that can be added to a Sylphie bot to add IRC support.
+#[derive(Module)]
+pub struct ModIrc {
+    #[module_info] info: ModuleInfo,
+}
+
+#[module_impl]
+impl ModIrc {
+    /// The prefix for commands sent in IRC messages, or an empty string to only accept commands
+    /// addressed to the bot.
+    #[config]
+    pub const CFG_IRC_PREFIX: ConfigKey<String> = config_option!(
+        Any, "irc_prefix 3f0b7f52-6d1e-4a7c-9a0e-2c85f1d4b6e3", || "!".to_string(),
+    );
+
+    #[event_handler]
+    fn init_connection_types(
+        &self, target: &Handler<impl Events>, ev: &mut InitConnectionTypesEvent,
+    ) -> Result<()> {
+        ev.add_type(target, "irc", connection::IrcFactory { module: self.info.clone() })
+    }
+}
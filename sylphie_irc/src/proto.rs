@@ -0,0 +1,226 @@
+//! Parsing and formatting of lines in the IRC protocol.
+
+/// The longest line the IRC protocol allows, in bytes, including the trailing CRLF.
+pub const LINE_LIMIT: usize = 512;
+
+/// A message in the IRC protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IrcMessage {
+    /// The IRCv3 tags of the message.
+    pub tags: Vec<(String, String)>,
+    /// The source of the message, such as `nick!user@host`.
+    pub prefix: Option<String>,
+    /// The command, in uppercase.
+    pub command: String,
+    /// The parameters of the command, including the trailing parameter.
+    pub params: Vec<String>,
+}
+impl IrcMessage {
+    /// Parses a line received from a server, or returns `None` if it has no command.
+    pub fn parse(line: &str) -> Option<IrcMessage> {
+        let mut rest = line.trim_end_matches(&['\r', '\n'][..]);
+
+        let mut tags = Vec::new();
+        if let Some(tail) = rest.strip_prefix('@') {
+            let (raw_tags, tail) = split_word(tail);
+            for tag in raw_tags.split(';').filter(|x| !x.is_empty()) {
+                match tag.find('=') {
+                    Some(i) => tags.push((tag[..i].to_string(), unescape_tag(&tag[i + 1..]))),
+                    None => tags.push((tag.to_string(), String::new())),
+                }
+            }
+            rest = tail;
+        }
+
+        let mut prefix = None;
+        if let Some(tail) = rest.strip_prefix(':') {
+            let (raw_prefix, tail) = split_word(tail);
+            prefix = Some(raw_prefix.to_string());
+            rest = tail;
+        }
+
+        let (command, mut rest) = split_word(rest);
+        if command.is_empty() {
+            return None
+        }
+        let mut params = Vec::new();
+        while !rest.is_empty() {
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break
+            }
+            let (param, tail) = split_word(rest);
+            params.push(param.to_string());
+            rest = tail;
+        }
+
+        Some(IrcMessage { tags, prefix, command: command.to_ascii_uppercase(), params })
+    }
+
+    /// Returns the value of a tag, if the message has it.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns a parameter, or an empty string if the message does not have it.
+    pub fn param(&self, i: usize) -> &str {
+        self.params.get(i).map_or("", |x| x.as_str())
+    }
+
+    /// Returns the nickname of the user who sent the message, if it was sent by a user.
+    pub fn source_nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_ref()?;
+        prefix.find('!').map(|i| &prefix[..i])
+    }
+}
+
+/// Splits the first word off a string, skipping the spaces after it.
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(' ') {
+        Some(i) => (&text[..i], text[i + 1..].trim_start_matches(' ')),
+        None => (text, ""),
+    }
+}
+
+/// Unescapes the value of an IRCv3 tag.
+fn unescape_tag(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(ch) => out.push(ch),
+            None => { }
+        }
+    }
+    out
+}
+
+/// Formats a line to send to a server, without the trailing CRLF.
+///
+/// Line breaks in the parameters are replaced with spaces, so a parameter cannot be used to send
+/// a second command.
+pub fn format_line(command: &str, params: &[&str]) -> String {
+    let mut line = command.to_string();
+    for (i, param) in params.iter().enumerate() {
+        let param = param.replace(&['\r', '\n'][..], " ");
+        line.push(' ');
+        let is_last = i == params.len() - 1;
+        if is_last && (param.is_empty() || param.contains(' ') || param.starts_with(':')) {
+            line.push(':');
+        }
+        line.push_str(&param);
+    }
+    line
+}
+
+/// Returns the command and arguments of a CTCP message, or `None` if the text is not one.
+pub fn parse_ctcp(text: &str) -> Option<(&str, &str)> {
+    let inner = text.strip_prefix('\x01')?;
+    let inner = inner.strip_suffix('\x01').unwrap_or(inner);
+    Some(split_word(inner))
+}
+
+/// Formats a CTCP message.
+pub fn format_ctcp(command: &str, args: &str) -> String {
+    if args.is_empty() {
+        format!("\x01{}\x01", command)
+    } else {
+        format!("\x01{} {}\x01", command, args)
+    }
+}
+
+/// Splits text into lines of at most `limit` bytes, at spaces where possible.
+///
+/// IRC messages cannot contain line breaks, so each line of the text is sent separately.
+///
+/// # Panics
+///
+/// Panics if `limit` is less than 4 bytes, as some characters would not fit in a line.
+pub fn split_lines(text: &str, limit: usize) -> Vec<&str> {
+    assert!(limit >= 4, "Lines cannot be split into parts of less than 4 bytes.");
+    let mut parts = Vec::new();
+    for line in text.lines() {
+        let mut rest = line.trim_end_matches('\r');
+        while rest.len() > limit {
+            let mut hard_split = limit;
+            while !rest.is_char_boundary(hard_split) {
+                hard_split -= 1;
+            }
+            let split = match rest[..hard_split].rfind(' ') {
+                Some(i) if i > 0 => i,
+                _ => hard_split,
+            };
+            parts.push(&rest[..split]);
+            rest = rest[split..].trim_start_matches(' ');
+        }
+        parts.push(rest);
+    }
+    parts.retain(|x| !x.trim().is_empty());
+    parts
+}
+
+/// Returns whether a message target is a channel rather than a user.
+pub fn is_channel(target: &str) -> bool {
+    target.starts_with(&['#', '&', '+', '!'][..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let message = IrcMessage::parse("PING :irc.example.com\r\n").unwrap();
+        assert_eq!(message.command, "PING");
+        assert_eq!(message.params, vec!["irc.example.com"]);
+        assert_eq!(message.prefix, None);
+
+        let line = "@account=lymia;msgid=a\\sb :nick!user@host privmsg #chan :hello  world";
+        let message = IrcMessage::parse(line).unwrap();
+        assert_eq!(message.tag("account"), Some("lymia"));
+        assert_eq!(message.tag("msgid"), Some("a b"));
+        assert_eq!(message.source_nick(), Some("nick"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, vec!["#chan", "hello  world"]);
+
+        let message = IrcMessage::parse(":server 001 bot Welcome").unwrap();
+        assert_eq!(message.source_nick(), None);
+        assert_eq!(message.params, vec!["bot", "Welcome"]);
+
+        assert_eq!(IrcMessage::parse(""), None);
+    }
+
+    #[test]
+    fn format_lines() {
+        assert_eq!(format_line("NICK", &["bot"]), "NICK bot");
+        assert_eq!(format_line("PRIVMSG", &["#chan", "hi there"]), "PRIVMSG #chan :hi there");
+        assert_eq!(format_line("PRIVMSG", &["#chan", ":)"]), "PRIVMSG #chan ::)");
+        assert_eq!(format_line("PRIVMSG", &["#chan", "a\r\nQUIT"]), "PRIVMSG #chan :a  QUIT");
+    }
+
+    #[test]
+    fn ctcp_messages() {
+        assert_eq!(parse_ctcp("\x01VERSION\x01"), Some(("VERSION", "")));
+        assert_eq!(parse_ctcp("\x01ACTION waves\x01"), Some(("ACTION", "waves")));
+        assert_eq!(parse_ctcp("\x01PING 123"), Some(("PING", "123")));
+        assert_eq!(parse_ctcp("hello"), None);
+        assert_eq!(format_ctcp("PING", "123"), "\x01PING 123\x01");
+    }
+
+    #[test]
+    fn split_long_lines() {
+        assert_eq!(split_lines("abc", 5), vec!["abc"]);
+        assert_eq!(split_lines("a\n\nb\r\n", 5), vec!["a", "b"]);
+        assert_eq!(split_lines("abc def ghi", 8), vec!["abc def", "ghi"]);
+        assert_eq!(split_lines("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(split_lines("ééé", 3), vec!["é", "é", "é"]);
+    }
+}
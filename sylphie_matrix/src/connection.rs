@@ -0,0 +1,280 @@
+use async_trait::*;
+use crate::http::{MatrixHttp, MESSAGE_LIMIT};
+use enumset::EnumSet;
+use fxhash::FxHashMap;
+use parking_lot::{Mutex, RwLock};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use sylphie::commands::connector::{Connector, ConnectorCapability};
+use sylphie::config::Config;
+use sylphie::connections::*;
+use sylphie::prelude::*;
+use sylphie::tasks::{TaskHandle, TaskManager};
+
+/// Reads a setting from the `[matrix]` table of the configuration file.
+fn load_setting(target: &Handler<impl Events>, key: &str) -> Result<Option<String>> {
+    let value = target.get_service::<Config>().get::<String>(&format!("matrix.{}", key))?;
+    Ok(value.map(|x| x.trim().to_string()).filter(|x| !x.is_empty()))
+}
+
+/// Reads a boolean setting from the `[matrix]` table of the configuration file.
+fn load_flag(target: &Handler<impl Events>, key: &str, default: bool) -> Result<bool> {
+    Ok(target.get_service::<Config>().get(&format!("matrix.{}", key))?.unwrap_or(default))
+}
+
+/// The account the bot logs in with, if it has no access token.
+pub(crate) struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// What the connection knows about a room the bot is in.
+#[derive(Default)]
+pub(crate) struct RoomInfo {
+    pub is_encrypted: bool,
+}
+
+/// The direct chats of the bot, from the `m.direct` account data, which maps the user ID of the
+/// other user in each chat to the chat's room IDs.
+pub(crate) type DirectRooms = FxHashMap<String, Vec<String>>;
+
+struct MatrixConnectionData {
+    module: ModuleInfo,
+    scope: Scope,
+    homeserver: String,
+    http: MatrixHttp,
+    credentials: Option<Credentials>,
+    user_id: RwLock<Option<String>>,
+    rooms: RwLock<FxHashMap<String, RoomInfo>>,
+    direct_rooms: RwLock<DirectRooms>,
+    status: Mutex<ConnectionStatus>,
+    is_public: AtomicBool,
+    auto_join: AtomicBool,
+    sync: Mutex<Option<TaskHandle>>,
+    start_sync: Box<dyn Fn(MatrixConnection) -> TaskHandle + Send + Sync>,
+}
+
+/// A connection to a Matrix homeserver.
+///
+/// Channels are identified by room IDs, such as `!abcdefg:matrix.org`.
+#[derive(Clone)]
+pub struct MatrixConnection(Arc<MatrixConnectionData>);
+impl MatrixConnection {
+    /// Returns the scope of this connection.
+    pub fn scope(&self) -> &Scope {
+        &self.0.scope
+    }
+
+    /// Returns the URL of the homeserver.
+    pub fn homeserver(&self) -> &str {
+        &self.0.homeserver
+    }
+
+    /// Returns the user ID of the bot, or `None` if it has not yet logged in.
+    pub fn user_id(&self) -> Option<String> {
+        self.0.user_id.read().clone()
+    }
+
+    /// Returns whether the connection is currently connected to the homeserver.
+    pub fn status(&self) -> ConnectionStatus {
+        *self.0.status.lock()
+    }
+
+    /// Returns the IDs of the rooms the bot is in.
+    pub fn rooms(&self) -> Vec<String> {
+        self.0.rooms.read().keys().cloned().collect()
+    }
+
+    /// Returns whether a room is a direct chat between the bot and one other user.
+    ///
+    /// Direct chats are the rooms listed in the bot's `m.direct` account data, to which rooms
+    /// are added when the bot accepts an invite to a direct chat.
+    pub fn is_direct(&self, room: &str) -> bool {
+        self.0.direct_rooms.read().values().any(|rooms| rooms.iter().any(|x| x == room))
+    }
+
+    /// Sends an event to a room, and returns its ID.
+    pub async fn send_event(
+        &self, room: &str, kind: &str, content: &serde_json::Value,
+    ) -> Result<String> {
+        self.0.http.send_event(room, kind, content).await
+    }
+
+    pub(crate) fn module(&self) -> &ModuleInfo {
+        &self.0.module
+    }
+
+    pub(crate) fn http(&self) -> &MatrixHttp {
+        &self.0.http
+    }
+
+    pub(crate) fn credentials(&self) -> Option<&Credentials> {
+        self.0.credentials.as_ref()
+    }
+
+    pub(crate) fn auto_join(&self) -> bool {
+        self.0.auto_join.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_user_id(&self, user_id: String) {
+        *self.0.user_id.write() = Some(user_id);
+    }
+
+    pub(crate) fn set_status(&self, status: ConnectionStatus) {
+        *self.0.status.lock() = status;
+    }
+
+    pub(crate) fn update_room(&self, room: &str, update: impl FnOnce(&mut RoomInfo)) {
+        update(self.0.rooms.write().entry(room.to_string()).or_default());
+    }
+
+    pub(crate) fn direct_rooms(&self) -> DirectRooms {
+        self.0.direct_rooms.read().clone()
+    }
+
+    pub(crate) fn set_direct_rooms(&self, direct_rooms: DirectRooms) {
+        *self.0.direct_rooms.write() = direct_rooms;
+    }
+
+    pub(crate) fn remove_room(&self, room: &str) {
+        self.0.rooms.write().remove(room);
+    }
+}
+#[async_trait]
+impl Connector for MatrixConnection {
+    fn platform(&self) -> &str {
+        "matrix"
+    }
+
+    fn capabilities(&self) -> EnumSet<ConnectorCapability> {
+        ConnectorCapability::EditMessages | ConnectorCapability::Replies
+    }
+
+    fn message_limit(&self) -> Option<usize> {
+        Some(MESSAGE_LIMIT)
+    }
+
+    fn allows_everyone(&self) -> bool {
+        self.0.is_public.load(Ordering::Relaxed)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        let mut sync = self.0.sync.lock();
+        if sync.is_none() {
+            *sync = Some((self.0.start_sync)(self.clone()));
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(task) = self.0.sync.lock().take() {
+            task.abort();
+        }
+        self.set_status(ConnectionStatus::Deactivated);
+        Ok(())
+    }
+
+    fn channel_scopes(&self, room: &str) -> Vec<Scope> {
+        vec![
+            Scope::new("channel", ScopeArgs::String(room.to_string().into())),
+            self.0.scope.clone(),
+        ]
+    }
+
+    async fn send_message(&self, room: &str, text: &str) -> Result<Option<String>> {
+        Ok(Some(self.0.http.send_notice(room, None, text).await?))
+    }
+
+    async fn reply(&self, room: &str, event: &str, text: &str) -> Result<Option<String>> {
+        Ok(Some(self.0.http.send_notice(room, Some(event), text).await?))
+    }
+
+    async fn edit_message(&self, room: &str, event: &str, text: &str) -> Result<()> {
+        self.0.http.edit_notice(room, event, text).await?;
+        Ok(())
+    }
+}
+
+/// The implementation of [`Connection`] for Matrix.
+pub(crate) struct MatrixConnectionImpl {
+    conn: MatrixConnection,
+}
+#[async_trait]
+impl <E: Events> Connection<E> for MatrixConnectionImpl {
+    async fn status(&self, _: &Handler<E>) -> ConnectionStatus {
+        self.conn.status()
+    }
+
+    async fn update_connection(&self, target: &Handler<E>) -> Result<()> {
+        self.conn.0.is_public.store(load_flag(target, "public", false)?, Ordering::Relaxed);
+        self.conn.0.auto_join.store(load_flag(target, "auto_join", true)?, Ordering::Relaxed);
+        if load_setting(target, "homeserver")?.as_deref() != Some(self.conn.homeserver()) {
+            warn!("The Matrix homeserver has changed. The new homeserver is used once the bot \
+                   restarts.");
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self, _: &Handler<E>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_message(&self, _: &Handler<E>, channel: &str, message: &str) -> Result<()> {
+        Connector::send_message(&self.conn, channel, message).await?;
+        Ok(())
+    }
+
+    fn connector(&self) -> Option<Arc<dyn Connector>> {
+        Some(Arc::new(self.conn.clone()))
+    }
+}
+
+/// Creates connections to Matrix.
+pub(crate) struct MatrixFactory {
+    pub module: ModuleInfo,
+}
+#[async_trait]
+impl <E: Events> ConnectionFactory<E> for MatrixFactory {
+    type Connection = MatrixConnectionImpl;
+
+    async fn create(
+        &self, target: &Handler<E>, _: ConnectionId, scope: Scope,
+    ) -> Result<MatrixConnectionImpl> {
+        let homeserver = match load_setting(target, "homeserver")? {
+            Some(homeserver) => homeserver,
+            None => cmd_error!("No Matrix homeserver is set. Set `homeserver` in the `[matrix]` \
+                                table of the configuration file."),
+        };
+        let access_token = load_setting(target, "access_token")?;
+        let credentials = match (load_setting(target, "user")?, load_setting(target, "password")?) {
+            (Some(user), Some(password)) => Some(Credentials { user, password }),
+            _ => None,
+        };
+        if access_token.is_none() && credentials.is_none() {
+            cmd_error!("No Matrix account is set. Set `access_token`, or `user` and `password`, \
+                        in the `[matrix]` table of the configuration file.");
+        }
+
+        let sync_target = target.clone();
+        let conn = MatrixConnection(Arc::new(MatrixConnectionData {
+            module: self.module.clone(),
+            scope,
+            http: MatrixHttp::new(&homeserver, access_token)?,
+            homeserver,
+            credentials,
+            user_id: RwLock::new(None),
+            rooms: RwLock::new(FxHashMap::default()),
+            direct_rooms: RwLock::new(FxHashMap::default()),
+            status: Mutex::new(ConnectionStatus::Disconnected),
+            is_public: AtomicBool::new(load_flag(target, "public", false)?),
+            auto_join: AtomicBool::new(load_flag(target, "auto_join", true)?),
+            sync: Mutex::new(None),
+            start_sync: Box::new(move |conn| {
+                let module = conn.module().clone();
+                let task = crate::sync::run_sync(sync_target.clone(), conn);
+                sync_target.get_service::<TaskManager>().spawn(&module, "matrix_sync", task)
+            }),
+        }));
+        Ok(MatrixConnectionImpl { conn })
+    }
+}
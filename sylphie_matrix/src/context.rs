@@ -0,0 +1,100 @@
+use crate::ModMatrix;
+use crate::connection::MatrixConnection;
+use crate::model::*;
+use std::sync::Arc;
+use sylphie::commands::connector::*;
+use sylphie::database::config::ConfigManager;
+use sylphie::prelude::*;
+use sylphie::tasks::TaskManager;
+
+/// Information about a message received from Matrix.
+///
+/// This can be retrieved from a command's context with [`MatrixContext::from_ctx`], or from a
+/// [`MessageReceivedEvent`] with [`IncomingMessage::platform_data`].
+pub struct MatrixContext {
+    connection: MatrixConnection,
+    room: String,
+    event: RoomEvent,
+}
+impl MatrixContext {
+    /// Returns the Matrix context of a command, or `None` if it was not sent from Matrix.
+    pub fn from_ctx<E: Events>(ctx: &CommandCtx<E>) -> Option<&MatrixContext> {
+        ctx.message()?.platform_data()
+    }
+
+    /// Returns the connection the message was received on.
+    pub fn connection(&self) -> &MatrixConnection {
+        &self.connection
+    }
+
+    /// Returns the ID of the room the message was sent in.
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    /// Returns the event of the message.
+    pub fn event(&self) -> &RoomEvent {
+        &self.event
+    }
+
+    /// Returns the user ID of the user who sent the message.
+    pub fn sender(&self) -> &str {
+        &self.event.sender
+    }
+}
+
+async fn handle_message_async<E: Events>(
+    target: Handler<E>, conn: MatrixConnection, room: String, event: RoomEvent, text: String,
+) -> Result<()> {
+    let scopes = conn.channel_scopes(&room);
+    let is_direct = conn.is_direct(&room);
+    let command = if event.msgtype() == Some("m.emote") {
+        None
+    } else {
+        let config = target.get_service::<ConfigManager>();
+        let prefix = config.resolve(&target, &scopes, ModMatrix::CFG_MATRIX_PREFIX).await?;
+        // clients usually mention a user by starting the message with their display name, which
+        // the bot does not track, so the full user ID and its localpart are accepted instead.
+        let names = match conn.user_id() {
+            Some(user_id) => {
+                let localpart = user_id.trim_start_matches('@').split(':').next().unwrap_or("");
+                let localpart = localpart.to_string();
+                vec![user_id, localpart]
+            }
+            None => Vec::new(),
+        };
+        strip_command_prefix(&text, &prefix, &[], &names, is_direct).map(str::to_string)
+    };
+
+    let author = MessageAuthor::new(format!("matrix:{}", event.sender), event.sender.clone());
+    let mut incoming = IncomingMessage::new(Arc::new(conn.clone()), room.clone(), author, text)
+        .with_id(event.event_id.clone())
+        .with_direct(is_direct)
+        .with_scopes(scopes);
+    if let Some(command) = command {
+        incoming = incoming.with_command(command);
+    }
+    let incoming = incoming.with_platform_data(MatrixContext { connection: conn, room, event });
+    receive_message(&target, incoming).await
+}
+
+/// Handles a message event received from the homeserver, running it as a command if it is one.
+///
+/// Notices are ignored, as by convention they are sent by bots, and so are edits.
+pub(crate) fn handle_message(
+    target: &Handler<impl Events>, conn: &MatrixConnection, room: &str, event: RoomEvent,
+) {
+    let is_own = conn.user_id().map_or(false, |x| x == event.sender);
+    let is_notice = event.msgtype() == Some("m.notice");
+    let is_edit = event.relation_type() == Some("m.replace");
+    if is_own || is_notice || is_edit {
+        return
+    }
+    let text = match event.body() {
+        Some(body) if event.is_reply() => strip_reply_fallback(body).to_string(),
+        Some(body) => body.to_string(),
+        None => return,
+    };
+    let task = handle_message_async(target.clone(), conn.clone(), room.to_string(), event, text);
+    target.get_service::<TaskManager>().spawn(conn.module(), "matrix_message", task);
+}
@@ -0,0 +1,206 @@
+use crate::model::*;
+use parking_lot::RwLock;
+use reqwest::{Client, Method, StatusCode, Url};
+use serde::*;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sylphie::prelude::*;
+
+const API_PREFIX: &[&str] = &["_matrix", "client", "v3"];
+
+/// How many times a request is retried after being rate limited.
+const MAX_RETRIES: u32 = 5;
+
+/// How long a sync request waits for new events, in milliseconds.
+pub const SYNC_TIMEOUT: u64 = 30000;
+
+/// How long a request can take, in addition to the time a sync request waits for events.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The longest message sent as a single event, in characters.
+///
+/// Matrix limits events to 64 KiB once encoded as JSON. A character takes up to 6 bytes when
+/// escaped, and edits contain the text twice, so this leaves about 5 KiB for the rest of the
+/// event even in the worst case.
+pub const MESSAGE_LIMIT: usize = 5000;
+
+#[derive(Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    errcode: String,
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    retry_after_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    access_token: String,
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+struct WhoAmI {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+struct EventId {
+    event_id: String,
+}
+
+/// A client for the Matrix client-server API.
+pub(crate) struct MatrixHttp {
+    client: Client,
+    homeserver: Url,
+    access_token: RwLock<Option<String>>,
+    txn_prefix: u64,
+    txn_counter: AtomicU64,
+}
+impl MatrixHttp {
+    pub fn new(homeserver: &str, access_token: Option<String>) -> Result<MatrixHttp> {
+        let homeserver = Url::parse(homeserver)
+            .cmd_error(|| format!("'{}' is not a valid homeserver URL.", homeserver))?;
+        ensure!(!homeserver.cannot_be_a_base(), "'{}' is not a valid homeserver URL.", homeserver);
+        let timeout = Duration::from_millis(SYNC_TIMEOUT) + REQUEST_TIMEOUT;
+        let client = Client::builder().timeout(timeout).build()?;
+        let txn_prefix = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        Ok(MatrixHttp {
+            client,
+            homeserver,
+            access_token: RwLock::new(access_token),
+            txn_prefix,
+            txn_counter: AtomicU64::new(0),
+        })
+    }
+
+    fn url(&self, path: &[&str]) -> Url {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut().unwrap().pop_if_empty().extend(API_PREFIX).extend(path);
+        url
+    }
+
+    async fn request<T: de::DeserializeOwned>(
+        &self, method: Method, path: &[&str], query: &[(&str, &str)], body: Option<&Value>,
+    ) -> Result<T> {
+        let url = self.url(path);
+        for _ in 0..MAX_RETRIES {
+            let mut request = self.client.request(method.clone(), url.clone()).query(query);
+            let token = self.access_token.read().clone();
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok(response.json().await?)
+            }
+            let err = match response.json::<ApiError>().await {
+                Ok(err) => err,
+                Err(_) => ApiError {
+                    errcode: String::new(),
+                    error: status.to_string(),
+                    retry_after_ms: None,
+                },
+            };
+            match status {
+                StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = err.retry_after_ms.unwrap_or(1000);
+                    debug!("Rate limited by the Matrix homeserver for {} ms.", delay);
+                    tokio::time::delay_for(Duration::from_millis(delay)).await;
+                }
+                StatusCode::UNAUTHORIZED if err.errcode == "M_UNKNOWN_TOKEN" =>
+                    cmd_error!("The Matrix access token is invalid: {}", err.error),
+                StatusCode::FORBIDDEN | StatusCode::NOT_FOUND =>
+                    cmd_error!("The Matrix homeserver refused the request: {}", err.error),
+                _ => bail!(
+                    "Matrix request to {} failed: {} {}", url.path(), err.errcode, err.error,
+                ),
+            }
+        }
+        bail!("Matrix request to {} was rate limited too many times.", url.path())
+    }
+
+    /// Returns whether an access token is set.
+    pub fn has_token(&self) -> bool {
+        self.access_token.read().is_some()
+    }
+
+    /// Logs in with a password, and returns the user ID of the bot.
+    pub async fn login(&self, user: &str, password: &str) -> Result<String> {
+        let body = json!({
+            "type": "m.login.password",
+            "identifier": { "type": "m.id.user", "user": user },
+            "password": password,
+            "initial_device_display_name": "Sylphie",
+        });
+        let login: LoginResponse = self.request(Method::POST, &["login"], &[], Some(&body)).await?;
+        *self.access_token.write() = Some(login.access_token);
+        Ok(login.user_id)
+    }
+
+    /// Returns the user ID the access token belongs to.
+    pub async fn whoami(&self) -> Result<String> {
+        let path = ["account", "whoami"];
+        Ok(self.request::<WhoAmI>(Method::GET, &path, &[], None).await?.user_id)
+    }
+
+    /// Waits for new events, and returns them.
+    pub async fn sync(&self, since: Option<&str>, filter: &str) -> Result<SyncResponse> {
+        let timeout = SYNC_TIMEOUT.to_string();
+        let mut query = vec![("timeout", timeout.as_str()), ("filter", filter)];
+        if let Some(since) = since {
+            query.push(("since", since));
+        }
+        self.request(Method::GET, &["sync"], &query, None).await
+    }
+
+    /// Joins a room the bot was invited to.
+    pub async fn join(&self, room: &str) -> Result<()> {
+        let path = ["rooms", room, "join"];
+        self.request::<Value>(Method::POST, &path, &[], Some(&json!({}))).await?;
+        Ok(())
+    }
+
+    /// Replaces part of the bot's account data, such as `m.direct`.
+    pub async fn set_account_data(&self, user_id: &str, kind: &str, content: &Value) -> Result<()> {
+        let path = ["user", user_id, "account_data", kind];
+        self.request::<Value>(Method::PUT, &path, &[], Some(content)).await?;
+        Ok(())
+    }
+
+    /// Sends an event to a room, and returns its ID.
+    pub async fn send_event(&self, room: &str, kind: &str, content: &Value) -> Result<String> {
+        let txn = self.txn_counter.fetch_add(1, Ordering::Relaxed);
+        let txn = format!("sylphie.{}.{}", self.txn_prefix, txn);
+        let path = ["rooms", room, "send", kind, txn.as_str()];
+        Ok(self.request::<EventId>(Method::PUT, &path, &[], Some(content)).await?.event_id)
+    }
+
+    /// Sends a notice to a room, optionally as a reply to another event.
+    pub async fn send_notice(
+        &self, room: &str, reply_to: Option<&str>, text: &str,
+    ) -> Result<String> {
+        let mut content = json!({ "msgtype": "m.notice", "body": text });
+        if let Some(event) = reply_to {
+            content["m.relates_to"] = json!({ "m.in_reply_to": { "event_id": event } });
+        }
+        self.send_event(room, "m.room.message", &content).await
+    }
+
+    /// Replaces the text of a notice the bot sent.
+    pub async fn edit_notice(&self, room: &str, event: &str, text: &str) -> Result<String> {
+        let content = json!({
+            "msgtype": "m.notice",
+            "body": format!("* {}", text),
+            "m.new_content": { "msgtype": "m.notice", "body": text },
+            "m.relates_to": { "rel_type": "m.replace", "event_id": event },
+        });
+        self.send_event(room, "m.room.message", &content).await
+    }
+}
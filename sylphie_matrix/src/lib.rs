@@ -0,0 +1,69 @@
+//! Connects Sylphie bots to Matrix.
+//!
+//! Adding [`ModMatrix`] to a bot adds the `matrix` connection type. The account is read from the
+//! configuration file, and the connection is added from the terminal:
+//!
+//! ```toml
+//! owners = ["matrix:@lymia:matrix.org"]
+//!
+//! [matrix]
+//! homeserver = "https://matrix.org"
+//! access_token = "..."  # or `user` and `password`, to log in with a password
+//! public = false        # whether users other than the owners can run commands
+//! auto_join = true      # whether to join rooms the bot is invited to
+//! ```
+//!
+//! ```text
+//! connections add matrix matrix
+//! ```
+//!
+//! Messages that start with the `matrix_prefix` configuration option or are addressed to the
+//! bot, such as `sylphie: help`, are run as commands, as are all messages in direct chats.
+//! Commands run in the scope of the room they were sent in, followed by the scope of the
+//! connection. Responses are sent as notices that reply to the command, and progress reports
+//! edit a single notice. Commands can find the room and event they were sent from with
+//! [`MatrixContext::from_ctx`].
+//!
+//! Owners are named as `matrix:<user ID>`. By default, only the owners can run commands.
+//! Setting `public` allows anyone to run them.
+//!
+//! End-to-end encryption is not supported, so the bot cannot read messages in encrypted rooms.
+//! It warns when it sees one.
+
+#[macro_use] extern crate tracing;
+
+use sylphie::connections::InitConnectionTypesEvent;
+use sylphie::database::config::*;
+use sylphie::prelude::*;
+
+mod connection;
+mod context;
+mod http;
+pub mod model;
+mod sync;
+
+pub use connection::MatrixConnection;
+pub use context::MatrixContext;
+
+/// A module that can be added to a Sylphie bot to add Matrix support.
+#[derive(Module)]
+pub struct ModMatrix {
+    #[module_info] info: ModuleInfo,
+}
+
+#[module_impl]
+impl ModMatrix {
+    /// The prefix for commands sent in Matrix messages, or an empty string to only accept
+    /// commands addressed to the bot.
+    #[config]
+    pub const CFG_MATRIX_PREFIX: ConfigKey<String> = config_option!(
+        Any, "matrix_prefix 8d4c2a19-5e7b-4f3a-b6d0-71e9c3a2f845", || "!".to_string(),
+    );
+
+    #[event_handler]
+    fn init_connection_types(
+        &self, target: &Handler<impl Events>, ev: &mut InitConnectionTypesEvent,
+    ) -> Result<()> {
+        ev.add_type(target, "matrix", connection::MatrixFactory { module: self.info.clone() })
+    }
+}
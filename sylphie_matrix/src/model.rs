@@ -0,0 +1,130 @@
+//! The parts of the Matrix client-server API the bot uses.
+
+use fxhash::FxHashMap;
+use serde::*;
+use serde_json::Value;
+
+/// The response to a sync request.
+#[derive(Deserialize, Debug)]
+pub(crate) struct SyncResponse {
+    pub next_batch: String,
+    #[serde(default)]
+    pub account_data: AccountData,
+    #[serde(default)]
+    pub rooms: Rooms,
+}
+
+/// The account data of the bot that changed in a sync response.
+#[derive(Deserialize, Default, Debug)]
+pub(crate) struct AccountData {
+    #[serde(default)]
+    pub events: Vec<AccountDataEvent>,
+}
+
+/// An event that sets part of the bot's account data, such as `m.direct`.
+#[derive(Deserialize, Debug)]
+pub(crate) struct AccountDataEvent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub content: Value,
+}
+
+/// The rooms with new events in a sync response.
+#[derive(Deserialize, Default, Debug)]
+pub(crate) struct Rooms {
+    #[serde(default)]
+    pub join: FxHashMap<String, JoinedRoom>,
+    #[serde(default)]
+    pub invite: FxHashMap<String, Value>,
+    #[serde(default)]
+    pub leave: FxHashMap<String, Value>,
+}
+
+/// The new events in a room the bot is in.
+#[derive(Deserialize, Default, Debug)]
+pub(crate) struct JoinedRoom {
+    #[serde(default)]
+    pub state: EventList,
+    #[serde(default)]
+    pub timeline: EventList,
+}
+
+/// A list of events.
+#[derive(Deserialize, Default, Debug)]
+pub(crate) struct EventList {
+    #[serde(default)]
+    pub events: Vec<RoomEvent>,
+}
+
+/// An event in a room.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct RoomEvent {
+    /// The type of the event, such as `m.room.message`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The ID of the event.
+    pub event_id: String,
+    /// The user ID of the user who sent the event.
+    pub sender: String,
+    /// The time the event was sent, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub origin_server_ts: u64,
+    /// The state key of the event, if it is a state event.
+    #[serde(default)]
+    pub state_key: Option<String>,
+    /// The content of the event.
+    #[serde(default)]
+    pub content: Value,
+}
+impl RoomEvent {
+    /// Returns the `msgtype` of a message event, such as `m.text`.
+    pub fn msgtype(&self) -> Option<&str> {
+        self.content.get("msgtype")?.as_str()
+    }
+
+    /// Returns the text of a message event.
+    pub fn body(&self) -> Option<&str> {
+        self.content.get("body")?.as_str()
+    }
+
+    /// Returns the type of the relation this event has to another event, such as `m.replace`
+    /// for edits.
+    pub fn relation_type(&self) -> Option<&str> {
+        self.content.get("m.relates_to")?.get("rel_type")?.as_str()
+    }
+
+    /// Returns whether this message event is a reply to another event.
+    pub fn is_reply(&self) -> bool {
+        self.content.get("m.relates_to").and_then(|x| x.get("m.in_reply_to")).is_some()
+    }
+}
+
+/// Removes the quote of the original message from the text of a reply.
+///
+/// Replies start with the original message as lines beginning with `> `, followed by an empty
+/// line.
+pub(crate) fn strip_reply_fallback(body: &str) -> &str {
+    let mut rest = body;
+    while rest.starts_with("> ") || rest.starts_with(">\n") {
+        rest = match rest.find('\n') {
+            Some(i) => &rest[i + 1..],
+            None => "",
+        };
+    }
+    rest.strip_prefix('\n').unwrap_or(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_fallbacks() {
+        assert_eq!(strip_reply_fallback("hello"), "hello");
+        assert_eq!(strip_reply_fallback("> <@a:b> hi\n> there\n\n!help"), "!help");
+        assert_eq!(strip_reply_fallback("> <@a:b> hi\n>\n> x\n\nok"), "ok");
+        assert_eq!(strip_reply_fallback("> quote"), "");
+    }
+}
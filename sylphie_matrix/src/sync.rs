@@ -0,0 +1,149 @@
+//! The sync loop, which is how the bot receives events from the homeserver.
+
+use crate::connection::{DirectRooms, MatrixConnection};
+use crate::model::*;
+use serde_json::Value;
+use std::time::Duration;
+use sylphie::connections::{Backoff, ConnectionStatus, is_fatal_error, log_disconnect};
+use sylphie::prelude::*;
+
+/// The delay before retrying after the first failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay before retrying.
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+/// The filter for sync requests, which leaves out presence and all account data other than the
+/// list of direct chats.
+const SYNC_FILTER: &str = concat!(
+    r#"{"presence":{"types":[]},"account_data":{"types":["m.direct"]},"#,
+    r#""room":{"timeline":{"limit":50}}}"#,
+);
+
+/// Logs in if needed, and finds the user ID of the bot.
+async fn login(conn: &MatrixConnection) -> Result<()> {
+    let user_id = match conn.credentials() {
+        Some(credentials) if !conn.http().has_token() =>
+            conn.http().login(&credentials.user, &credentials.password).await?,
+        _ => conn.http().whoami().await?,
+    };
+    info!("Logged in to Matrix as {}.", user_id);
+    conn.set_user_id(user_id);
+    Ok(())
+}
+
+/// Returns the user who invited the bot to a room, if the invite is for a direct chat.
+fn direct_invite_sender<'a>(invite: &'a Value, user_id: &str) -> Option<&'a str> {
+    let events = invite.get("invite_state")?.get("events")?.as_array()?;
+    let member = events.iter().find(|x| {
+        x.get("type").and_then(Value::as_str) == Some("m.room.member") &&
+            x.get("state_key").and_then(Value::as_str) == Some(user_id)
+    })?;
+    if member.get("content")?.get("is_direct")?.as_bool()? {
+        member.get("sender")?.as_str()
+    } else {
+        None
+    }
+}
+
+/// Adds a room to the bot's `m.direct` account data, as a direct chat with `user`.
+async fn add_direct_room(conn: &MatrixConnection, room: &str, user: &str) -> Result<()> {
+    let user_id = conn.user_id().internal_err(|| "Matrix user ID is not yet known.")?;
+    let mut direct_rooms = conn.direct_rooms();
+    direct_rooms.entry(user.to_string()).or_default().push(room.to_string());
+    let content = serde_json::to_value(&direct_rooms)?;
+    conn.http().set_account_data(&user_id, "m.direct", &content).await?;
+    conn.set_direct_rooms(direct_rooms);
+    Ok(())
+}
+
+/// Handles the events in a sync response.
+///
+/// The first sync returns recent history rather than new events, so messages in it are not
+/// handled.
+async fn handle_sync(
+    target: &Handler<impl Events>, conn: &MatrixConnection, sync: SyncResponse, is_initial: bool,
+) {
+    for event in &sync.account_data.events {
+        if event.kind == "m.direct" {
+            match serde_json::from_value::<DirectRooms>(event.content.clone()) {
+                Ok(direct_rooms) => conn.set_direct_rooms(direct_rooms),
+                Err(e) => warn!("The Matrix homeserver sent invalid direct chats: {}", e),
+            }
+        }
+    }
+    for (room, invite) in &sync.rooms.invite {
+        if conn.auto_join() {
+            info!("Joining the Matrix room {}.", room);
+            if let Err(e) = conn.http().join(room).await {
+                warn!("Could not join the Matrix room {}: {}", room, e);
+                continue
+            }
+            let user_id = conn.user_id().unwrap_or_default();
+            if let Some(sender) = direct_invite_sender(invite, &user_id) {
+                if let Err(e) = add_direct_room(conn, room, sender).await {
+                    warn!("Could not mark the Matrix room {} as a direct chat: {}", room, e);
+                }
+            }
+        }
+    }
+    for room in sync.rooms.leave.keys() {
+        conn.remove_room(room);
+    }
+    for (room, data) in sync.rooms.join {
+        let is_encrypted = data.state.events.iter().chain(&data.timeline.events)
+            .any(|x| x.kind == "m.room.encryption");
+        let mut is_newly_encrypted = false;
+        conn.update_room(&room, |info| {
+            is_newly_encrypted = is_encrypted && !info.is_encrypted;
+            info.is_encrypted |= is_encrypted;
+        });
+        if is_newly_encrypted {
+            warn!("The Matrix room {} is encrypted. Messages in it cannot be read.", room);
+        }
+        if is_initial {
+            continue
+        }
+        for event in data.timeline.events {
+            if event.kind == "m.room.message" {
+                crate::context::handle_message(target, conn, &room, event);
+            }
+        }
+    }
+}
+
+/// Syncs with the homeserver until the connection is closed.
+pub(crate) async fn run_sync(target: Handler<impl Events>, conn: MatrixConnection) -> Result<()> {
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    let mut since: Option<String> = None;
+    let mut is_logged_in = false;
+    loop {
+        let result = if is_logged_in {
+            let response = conn.http().sync(since.as_deref(), SYNC_FILTER).await;
+            match response {
+                Ok(sync) => {
+                    let is_initial = since.is_none();
+                    since = Some(sync.next_batch.clone());
+                    handle_sync(&target, &conn, sync, is_initial).await;
+                    conn.set_status(ConnectionStatus::Connected);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            login(&conn).await.map(|_| is_logged_in = true)
+        };
+
+        match result {
+            Ok(()) => backoff.reset(),
+            Err(e) if is_fatal_error(&e) => {
+                conn.set_status(ConnectionStatus::Disconnected);
+                return Err(e)
+            }
+            Err(e) => {
+                conn.set_status(ConnectionStatus::Disconnected);
+                log_disconnect("the Matrix homeserver", &e);
+                backoff.wait("the Matrix homeserver").await;
+            }
+        }
+    }
+}
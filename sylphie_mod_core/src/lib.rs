@@ -1,6 +1,7 @@
 use sylphie::commands::manager::CommandManager;
 use sylphie::database::config::*;
 use sylphie::prelude::*;
+use sylphie::stats::{CoreStats, format_uptime};
 use sylphie::utils::disambiguate::LookupResult;
 
 /// A module that can be added to a Sylphie bot to add core bot commands.
@@ -52,12 +53,41 @@ impl ModCore {
         Ok(())
     }
 
+    #[command]
+    async fn cmd_uptime(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        let uptime = ctx.handler().get_service::<CoreStats>().uptime();
+        ctx.respond(&format!("Uptime: {}", format_uptime(uptime))).await?;
+        Ok(())
+    }
+
+    #[command]
+    async fn cmd_stats(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        for line in ctx.handler().get_service::<CoreStats>().summary() {
+            ctx.respond(&line).await?;
+        }
+        Ok(())
+    }
+
     #[command]
     async fn cmd_shutdown(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
         ctx.handler().shutdown_bot();
         Ok(())
     }
 
+    #[command]
+    async fn cmd_restart(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        ctx.respond("Restarting...").await?;
+        ctx.handler().restart_bot();
+        Ok(())
+    }
+
+    #[command]
+    async fn cmd_reload(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        sylphie::core::reload_bot(ctx.handler()).await?;
+        ctx.respond("Reloaded the configuration file and settings.").await?;
+        Ok(())
+    }
+
     #[command]
     async fn cmd_show_config(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
         ctx.respond("Configuration options:").await?;
@@ -0,0 +1,174 @@
+//! Renders the pages of the dashboard.
+
+use std::fmt::Write;
+use sylphie::commands::manager::CommandManager;
+use sylphie::core::BotInfo;
+use sylphie::database::config::*;
+use sylphie::health::check_health;
+use sylphie::interface::Interface;
+use sylphie::module::ModuleManager;
+use sylphie::prelude::*;
+
+const STYLE: &str = "\
+    body { font-family: sans-serif; margin: 2em; }\
+    table { border-collapse: collapse; margin-bottom: 2em; }\
+    th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }\
+    pre { background: #f4f4f4; padding: 1em; overflow-x: auto; }\
+    .ok { color: green; } .degraded { color: orange; } .failed { color: red; }\
+";
+
+/// Escapes text for inclusion in HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title>\
+         <style>{}</style></head><body>{}</body></html>\n",
+        escape(title), STYLE, body,
+    )
+}
+
+pub fn unauthorized() -> String {
+    page(
+        "Unauthorized",
+        "<h1>Unauthorized</h1><p>A valid token is required.</p>\
+         <form method=\"post\" action=\"/login\">\
+         <input type=\"password\" name=\"token\" autocomplete=\"off\"> \
+         <input type=\"submit\" value=\"Log in\"></form>",
+    )
+}
+pub fn not_found() -> String {
+    page("Not Found", "<h1>Not Found</h1>")
+}
+pub fn server_error() -> String {
+    page("Error", "<h1>Internal Error</h1><p>See the error report for details.</p>")
+}
+
+/// Renders the dashboard itself.
+pub async fn dashboard(
+    target: &Handler<impl Events>, message: Option<&String>,
+) -> Result<String> {
+    let bot_name = target.get_service::<BotInfo>().bot_name().to_string();
+    let interface = target.get_service::<Interface>();
+    let mut body = String::new();
+    writeln!(body, "<h1>{}</h1>", escape(&bot_name))?;
+    if let Some(message) = message {
+        writeln!(body, "<p><strong>{}</strong></p>", escape(message))?;
+    }
+
+    // modules and their health
+    let health = check_health(target).await;
+    writeln!(
+        body, "<h2>Modules</h2><p>Bot health: <span class=\"{0}\">{0}</span></p>", health.status,
+    )?;
+    body.push_str("<table><tr><th>Module</th><th>Health</th><th>Details</th></tr>");
+    for module in target.get_service::<ModuleManager>().loaded_modules() {
+        let report = health.modules.iter().find(|x| x.module.name() == module.name());
+        match report {
+            Some(report) => writeln!(
+                body, "<tr><td>{0}</td><td class=\"{2}\">{2}</td><td>{1}</td></tr>",
+                escape(module.name()),
+                escape(report.report.message.as_deref().unwrap_or("")),
+                report.report.status,
+            )?,
+            None => writeln!(
+                body, "<tr><td>{}</td><td>-</td><td></td></tr>", escape(module.name()),
+            )?,
+        }
+    }
+    body.push_str("</table>");
+
+    // recent errors
+    body.push_str("<h2>Recent errors</h2>");
+    let errors = interface.recent_errors();
+    if errors.is_empty() {
+        body.push_str("<p>No errors have been reported.</p>");
+    } else {
+        body.push_str("<table><tr><th>Time</th><th>Error</th><th>Report</th></tr>");
+        for error in errors.iter().rev() {
+            writeln!(
+                body, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                error.time.format("%Y-%m-%d %H:%M:%S UTC"),
+                escape(&error.summary),
+                escape(&error.report_file.display().to_string()),
+            )?;
+        }
+        body.push_str("</table>");
+    }
+
+    // command statistics
+    body.push_str("<h2>Commands</h2>");
+    let stats = target.get_service::<CommandManager>().command_stats();
+    if stats.is_empty() {
+        body.push_str("<p>No commands have been executed.</p>");
+    } else {
+        body.push_str(
+            "<table><tr><th>Command</th><th>Executions</th><th>Errors</th>\
+             <th>Average time</th></tr>",
+        );
+        for (name, stats) in stats {
+            writeln!(
+                body, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{} ms</td></tr>",
+                escape(&name), stats.executions, stats.errors,
+                stats.average_time().as_millis(),
+            )?;
+        }
+        body.push_str("</table>");
+    }
+
+    // global configuration options
+    body.push_str("<h2>Settings</h2>");
+    body.push_str("<table><tr><th>Option</th><th>Value</th></tr>");
+    for option in &*target.get_service::<ConfigManager>().option_list() {
+        let flags = option.value.flags();
+        if !flags.contains(ConfigFlag::Global) && !flags.contains(ConfigFlag::Any) {
+            continue
+        }
+        let name = option.shortest_name.to_string();
+        let value = option.value.get_display(target, GLOBAL_SCOPE).await?;
+        writeln!(
+            body,
+            "<tr><td>{0}</td><td><form method=\"post\" action=\"/settings\">\
+             <input type=\"hidden\" name=\"option\" value=\"{0}\">\
+             <input type=\"text\" name=\"value\" value=\"{1}\"> \
+             <input type=\"submit\" value=\"Set\"></form></td></tr>",
+            escape(&name), escape(&value),
+        )?;
+    }
+    body.push_str("</table>");
+
+    // the end of the log
+    body.push_str("<h2>Log</h2><pre>");
+    for line in interface.recent_log_lines() {
+        writeln!(body, "{}", escape(&line))?;
+    }
+    body.push_str("</pre>");
+
+    Ok(page(&bot_name, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_html() {
+        assert_eq!(
+            escape("<a href=\"x\">&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;&lt;/a&gt;",
+        );
+    }
+}
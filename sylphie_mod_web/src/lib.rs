@@ -0,0 +1,43 @@
+#[macro_use] extern crate tracing;
+
+use std::net::SocketAddr;
+use sylphie::core::InitEvent;
+use sylphie::database::config::*;
+use sylphie::prelude::*;
+
+mod html;
+mod server;
+
+/// A module that can be added to a Sylphie bot to serve a web dashboard for administering it.
+///
+/// The dashboard shows the health of each module, recently reported errors, command statistics
+/// and the end of the log, and allows global configuration options to be changed. It is served
+/// on the address set by the `web_bind` configuration option, and requires the token stored in
+/// `web_dashboard.token` in the bot's root path, which is created when the dashboard is first
+/// started. The token is entered on the dashboard's login page, which stores it in a cookie, or
+/// sent in an `Authorization: Bearer` header.
+#[derive(Module)]
+pub struct ModWeb {
+    #[module_info] info: ModuleInfo,
+}
+
+#[module_impl]
+impl ModWeb {
+    #[config]
+    pub const CFG_WEB_BIND: ConfigKey<String> = config_option!(
+        Global, "web_bind 6b0c3d47-3b69-4b8e-9b52-94b7f0c1a2d5", || "127.0.0.1:8080".to_string(),
+    );
+
+    #[event_handler]
+    async fn start_server(&self, target: &Handler<impl Events>, _: &InitEvent) -> Result<()> {
+        let config = target.get_service::<ConfigManager>();
+        let bind = config.get(target, GLOBAL_SCOPE, Self::CFG_WEB_BIND).await?;
+        let addr: SocketAddr = match bind.parse() {
+            Ok(x) => x,
+            Err(_) => cmd_error!("Invalid address for the web dashboard: {}", bind),
+        };
+        let token = server::load_token(target)?;
+        self.spawn(target, "web_dashboard", server::serve(target.clone(), addr, token));
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use crate::html;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, LOCATION, SET_COOKIE};
+use hyper::service::{make_service_fn, service_fn};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use sylphie::core::BotInfo;
+use sylphie::database::config::*;
+use sylphie::prelude::*;
+use sylphie::tokens::{self, tokens_match};
+
+/// The cookie the token is stored in once a browser has logged in.
+const TOKEN_COOKIE: &str = "sylphie_web_token";
+
+/// Returns the token the dashboard authenticates with, creating it if needed.
+pub fn load_token(target: &Handler<impl Events>) -> Result<Arc<str>> {
+    let path = target.get_service::<BotInfo>().root_path().join("web_dashboard.token");
+    Ok(tokens::load_token(&path, "web dashboard")?.into())
+}
+
+struct WebState<E: Events> {
+    target: Handler<E>,
+    token: Arc<str>,
+}
+impl <E: Events> WebState<E> {
+    /// Checks the token sent with a request, either in an `Authorization: Bearer` header or in
+    /// the cookie set by `/login`.
+    ///
+    /// Tokens are never accepted in the URL, as URLs end up in browser history and server logs.
+    fn is_authorized(&self, req: &Request<Body>) -> bool {
+        let header = req.headers().get(AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("Bearer "));
+        let cookie = req.headers().get_all(COOKIE).iter()
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(';'))
+            .filter_map(|x| x.trim().strip_prefix(TOKEN_COOKIE)?.strip_prefix('='))
+            .next();
+        match header.or(cookie) {
+            Some(token) => tokens_match(token.trim(), &self.token),
+            None => false,
+        }
+    }
+}
+
+fn parse_params(data: &[u8]) -> HashMap<String, String> {
+    form_urlencoded::parse(data).into_owned().collect()
+}
+
+fn html_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+    response
+}
+
+fn redirect(location: &str) -> Result<Response<Body>> {
+    let mut response = html_response(StatusCode::SEE_OTHER, String::new());
+    response.headers_mut().insert(
+        LOCATION, location.parse().internal_err(|| "Invalid redirect location.")?,
+    );
+    Ok(response)
+}
+
+/// Checks the token entered on the login page, and stores it in a cookie if it is valid.
+fn login(
+    state: &WebState<impl Events>, params: &HashMap<String, String>,
+) -> Result<Response<Body>> {
+    let token = params.get("token").map_or("", |x| x.trim());
+    if !tokens_match(token, &state.token) {
+        warn!("Rejected a web dashboard login with an invalid token.");
+        return Ok(html_response(StatusCode::UNAUTHORIZED, html::unauthorized()))
+    }
+    let mut response = redirect("/")?;
+    let cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict", TOKEN_COOKIE, token);
+    response.headers_mut().insert(
+        SET_COOKIE, cookie.parse().internal_err(|| "Invalid token cookie.")?,
+    );
+    Ok(response)
+}
+
+async fn set_option(
+    target: &Handler<impl Events>, params: &HashMap<String, String>,
+) -> Result<()> {
+    let (name, value) = match (params.get("option"), params.get("value")) {
+        (Some(name), Some(value)) => (name, value),
+        _ => cmd_error!("No option or value was given."),
+    };
+    let options = target.get_service::<ConfigManager>().option_list();
+    let option = match options.iter().find(|x| &x.shortest_name.to_string() == name) {
+        Some(x) => x,
+        None => cmd_error!("No such configuration option '{}' exists.", name),
+    };
+    let flags = option.value.flags();
+    if !flags.contains(ConfigFlag::Global) && !flags.contains(ConfigFlag::Any) {
+        cmd_error!("'{}' can not be set globally.", name);
+    }
+    option.value.set_parse(target, GLOBAL_SCOPE, value).await?;
+    info!("Configuration option '{}' was set to '{}' from the web dashboard.", name, value);
+    Ok(())
+}
+
+async fn handle(state: &WebState<impl Events>, req: Request<Body>) -> Result<Response<Body>> {
+    let mut params = parse_params(req.uri().query().unwrap_or("").as_bytes());
+    let (parts, body) = req.into_parts();
+    if parts.method == Method::POST {
+        let body = hyper::body::to_bytes(body).await
+            .internal_err(|| "Could not read request body.")?;
+        params.extend(parse_params(&body));
+    }
+    let req = Request::from_parts(parts, Body::empty());
+
+    if req.method() == Method::POST && req.uri().path() == "/login" {
+        return login(state, &params)
+    }
+    if !state.is_authorized(&req) {
+        return Ok(html_response(StatusCode::UNAUTHORIZED, html::unauthorized()))
+    }
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/") => {
+            let page = html::dashboard(&state.target, params.get("message")).await?;
+            Ok(html_response(StatusCode::OK, page))
+        }
+        (&Method::POST, "/settings") => {
+            let message = match set_option(&state.target, &params).await {
+                Ok(()) => "Configuration option updated.".to_string(),
+                Err(e) => match e.error_kind() {
+                    ErrorKind::CommandError(e) => e.to_string(),
+                    _ => {
+                        e.report_error();
+                        "An internal error occurred. See the error report for details."
+                            .to_string()
+                    }
+                },
+            };
+            let location = format!(
+                "/?{}",
+                form_urlencoded::Serializer::new(String::new())
+                    .append_pair("message", &message)
+                    .finish(),
+            );
+            redirect(&location)
+        }
+        _ => Ok(html_response(StatusCode::NOT_FOUND, html::not_found())),
+    }
+}
+
+/// Serves the dashboard until the bot is shut down.
+pub async fn serve<E: Events>(target: Handler<E>, addr: SocketAddr, token: Arc<str>) -> Result<()> {
+    let state = Arc::new(WebState { target, token });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move {
+                    let response = match handle(&state, req).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            e.report_error();
+                            html_response(StatusCode::INTERNAL_SERVER_ERROR, html::server_error())
+                        }
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)
+        .internal_err(|| format!("Could not bind the web dashboard to {}.", addr))?
+        .serve(make_service);
+    info!("Web dashboard listening on http://{}/", addr);
+    server.await.internal_err(|| "Web dashboard server failed.")?;
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use async_trait::*;
+use crate::http::{MESSAGE_LIMIT, TelegramHttp};
+use crate::model::*;
+use enumset::EnumSet;
+use parking_lot::{Mutex, RwLock};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use sylphie::commands::connector::{Connector, ConnectorCapability, split_message};
+use sylphie::config::Config;
+use sylphie::connections::*;
+use sylphie::prelude::*;
+use sylphie::tasks::{TaskHandle, TaskManager};
+
+/// How the bot receives updates from Telegram.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) enum UpdateMode {
+    /// The bot repeatedly asks Telegram for new updates.
+    Polling,
+    /// Telegram sends updates to a web server run by the bot.
+    Webhook { url: String, bind: SocketAddr, secret: String },
+}
+
+/// Reads a setting from the `[telegram]` table of the configuration file.
+fn load_setting(target: &Handler<impl Events>, key: &str) -> Result<Option<String>> {
+    let value = target.get_service::<Config>().get::<String>(&format!("telegram.{}", key))?;
+    Ok(value.map(|x| x.trim().to_string()).filter(|x| !x.is_empty()))
+}
+
+/// Reads the bot token from the configuration file.
+fn load_token(target: &Handler<impl Events>) -> Result<String> {
+    match load_setting(target, "token")? {
+        Some(token) => Ok(token),
+        None => cmd_error!("No Telegram token is set. Set `token` in the `[telegram]` table of \
+                            the configuration file."),
+    }
+}
+
+/// Reads whether users other than the owners can run commands from the configuration file.
+fn load_public(target: &Handler<impl Events>) -> Result<bool> {
+    Ok(target.get_service::<Config>().get("telegram.public")?.unwrap_or(false))
+}
+
+/// Reads how updates are received from the configuration file. Webhooks are used if
+/// `webhook_url` is set.
+fn load_mode(target: &Handler<impl Events>) -> Result<UpdateMode> {
+    let url = match load_setting(target, "webhook_url")? {
+        Some(url) => url,
+        None => return Ok(UpdateMode::Polling),
+    };
+    let bind = load_setting(target, "webhook_bind")?.unwrap_or("127.0.0.1:8443".to_string());
+    let bind = bind.parse()
+        .cmd_error(|| format!("Invalid address for the Telegram webhook: {}", bind))?;
+    let secret = match load_setting(target, "webhook_secret")? {
+        Some(secret) => secret,
+        None => cmd_error!("No Telegram webhook secret is set. Set `webhook_secret` in the \
+                            `[telegram]` table of the configuration file."),
+    };
+    let is_valid = secret.len() <= 256 &&
+        secret.bytes().all(|x| x.is_ascii_alphanumeric() || x == b'_' || x == b'-');
+    ensure!(is_valid, "The Telegram webhook secret can only contain letters, numbers, \
+                       `_` and `-`, and can be up to 256 characters long.");
+    Ok(UpdateMode::Webhook { url, bind, secret })
+}
+
+/// Parses a chat or message ID passed to the connector.
+fn parse_id(id: &str) -> Result<i64> {
+    id.parse().cmd_error(|| format!("'{}' is not a Telegram ID.", id))
+}
+
+struct TelegramConnectionData {
+    module: ModuleInfo,
+    scope: Scope,
+    token: String,
+    mode: UpdateMode,
+    http: TelegramHttp,
+    bot_user: RwLock<Option<Arc<User>>>,
+    status: Mutex<ConnectionStatus>,
+    is_public: AtomicBool,
+    updates: Mutex<Option<TaskHandle>>,
+    start_updates: Box<dyn Fn(TelegramConnection) -> TaskHandle + Send + Sync>,
+}
+
+/// A connection to the Telegram Bot API.
+///
+/// Channels are identified by chat IDs, such as `-1001234567890`.
+#[derive(Clone)]
+pub struct TelegramConnection(Arc<TelegramConnectionData>);
+impl TelegramConnection {
+    /// Returns the scope of this connection.
+    pub fn scope(&self) -> &Scope {
+        &self.0.scope
+    }
+
+    /// Returns the bot's own user, or `None` if it has not yet connected.
+    pub fn bot_user(&self) -> Option<Arc<User>> {
+        self.0.bot_user.read().clone()
+    }
+
+    /// Returns whether the connection is currently receiving updates from Telegram.
+    pub fn status(&self) -> ConnectionStatus {
+        *self.0.status.lock()
+    }
+
+    /// Sends a message to a chat, splitting it into several messages if it is too long.
+    ///
+    /// Returns the last message sent, or `None` if the message was empty.
+    pub async fn send_message(&self, chat: i64, text: &str) -> Result<Option<Message>> {
+        let mut last = None;
+        for part in split_message(text, MESSAGE_LIMIT) {
+            last = Some(self.0.http.send_message(chat, None, part).await?);
+        }
+        Ok(last)
+    }
+
+    pub(crate) fn module(&self) -> &ModuleInfo {
+        &self.0.module
+    }
+
+    pub(crate) fn token(&self) -> &str {
+        &self.0.token
+    }
+
+    pub(crate) fn mode(&self) -> &UpdateMode {
+        &self.0.mode
+    }
+
+    pub(crate) fn http(&self) -> &TelegramHttp {
+        &self.0.http
+    }
+
+    pub(crate) fn set_bot_user(&self, user: User) {
+        *self.0.bot_user.write() = Some(Arc::new(user));
+    }
+
+    pub(crate) fn set_status(&self, status: ConnectionStatus) {
+        *self.0.status.lock() = status;
+    }
+}
+#[async_trait]
+impl Connector for TelegramConnection {
+    fn platform(&self) -> &str {
+        "telegram"
+    }
+
+    fn capabilities(&self) -> EnumSet<ConnectorCapability> {
+        ConnectorCapability::EditMessages | ConnectorCapability::Replies
+    }
+
+    fn message_limit(&self) -> Option<usize> {
+        Some(MESSAGE_LIMIT)
+    }
+
+    fn allows_everyone(&self) -> bool {
+        self.0.is_public.load(Ordering::Relaxed)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        let mut updates = self.0.updates.lock();
+        if updates.is_none() {
+            *updates = Some((self.0.start_updates)(self.clone()));
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        if let Some(task) = self.0.updates.lock().take() {
+            task.abort();
+        }
+        self.set_status(ConnectionStatus::Deactivated);
+        Ok(())
+    }
+
+    fn channel_scopes(&self, chat: &str) -> Vec<Scope> {
+        let mut scopes = Vec::new();
+        if let Ok(id) = chat.parse::<i64>() {
+            scopes.push(Scope::new("channel", ScopeArgs::Long(id as u64)));
+        }
+        scopes.push(self.0.scope.clone());
+        scopes
+    }
+
+    async fn send_message(&self, chat: &str, text: &str) -> Result<Option<String>> {
+        let sent = self.0.http.send_message(parse_id(chat)?, None, text).await?;
+        Ok(Some(sent.message_id.to_string()))
+    }
+
+    async fn reply(&self, chat: &str, message: &str, text: &str) -> Result<Option<String>> {
+        let reply_to = Some(parse_id(message)?);
+        let sent = self.0.http.send_message(parse_id(chat)?, reply_to, text).await?;
+        Ok(Some(sent.message_id.to_string()))
+    }
+
+    async fn edit_message(&self, chat: &str, message: &str, text: &str) -> Result<()> {
+        self.0.http.edit_message(parse_id(chat)?, parse_id(message)?, text).await
+    }
+}
+
+/// The implementation of [`Connection`] for Telegram.
+pub(crate) struct TelegramConnectionImpl {
+    conn: TelegramConnection,
+}
+#[async_trait]
+impl <E: Events> Connection<E> for TelegramConnectionImpl {
+    async fn status(&self, _: &Handler<E>) -> ConnectionStatus {
+        self.conn.status()
+    }
+
+    async fn update_connection(&self, target: &Handler<E>) -> Result<()> {
+        self.conn.0.is_public.store(load_public(target)?, Ordering::Relaxed);
+        if load_token(target)? != self.conn.token() || load_mode(target)? != *self.conn.mode() {
+            warn!("The Telegram settings have changed. The new settings are used once the bot \
+                   restarts.");
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self, _: &Handler<E>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_message(&self, _: &Handler<E>, channel: &str, message: &str) -> Result<()> {
+        self.conn.send_message(parse_id(channel)?, message).await?;
+        Ok(())
+    }
+
+    fn connector(&self) -> Option<Arc<dyn Connector>> {
+        Some(Arc::new(self.conn.clone()))
+    }
+}
+
+/// Creates connections to Telegram.
+pub(crate) struct TelegramFactory {
+    pub module: ModuleInfo,
+}
+#[async_trait]
+impl <E: Events> ConnectionFactory<E> for TelegramFactory {
+    type Connection = TelegramConnectionImpl;
+
+    async fn create(
+        &self, target: &Handler<E>, _: ConnectionId, scope: Scope,
+    ) -> Result<TelegramConnectionImpl> {
+        let token = load_token(target)?;
+        let updates_target = target.clone();
+        let conn = TelegramConnection(Arc::new(TelegramConnectionData {
+            module: self.module.clone(),
+            scope,
+            http: TelegramHttp::new(&token)?,
+            token,
+            mode: load_mode(target)?,
+            bot_user: RwLock::new(None),
+            status: Mutex::new(ConnectionStatus::Disconnected),
+            is_public: AtomicBool::new(load_public(target)?),
+            updates: Mutex::new(None),
+            start_updates: Box::new(move |conn| {
+                let module = conn.module().clone();
+                let task = crate::updates::run_updates(updates_target.clone(), conn);
+                updates_target.get_service::<TaskManager>().spawn(&module, "telegram_updates", task)
+            }),
+        }));
+        Ok(TelegramConnectionImpl { conn })
+    }
+}
@@ -0,0 +1,124 @@
+use crate::connection::TelegramConnection;
+use crate::model::*;
+use std::sync::Arc;
+use sylphie::commands::connector::*;
+use sylphie::prelude::*;
+use sylphie::tasks::TaskManager;
+
+/// Information about a message received from Telegram.
+///
+/// This can be retrieved from a command's context with [`TelegramContext::from_ctx`], or from a
+/// [`MessageReceivedEvent`] with [`IncomingMessage::platform_data`].
+pub struct TelegramContext {
+    connection: TelegramConnection,
+    message: Message,
+}
+impl TelegramContext {
+    /// Returns the Telegram context of a command, or `None` if it was not sent from Telegram.
+    pub fn from_ctx<E: Events>(ctx: &CommandCtx<E>) -> Option<&TelegramContext> {
+        ctx.message()?.platform_data()
+    }
+
+    /// Returns the connection the message was received on.
+    pub fn connection(&self) -> &TelegramConnection {
+        &self.connection
+    }
+
+    /// Returns the message.
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Returns the chat the message was sent in.
+    pub fn chat(&self) -> &Chat {
+        &self.message.chat
+    }
+}
+
+/// Returns the command in a message, or `None` if the message is not a command.
+///
+/// Commands start with `/`. In groups, they can name the bot they are meant for, as in
+/// `/help@SylphieBot`, and commands for other bots are ignored. All messages in private chats
+/// are run as commands, with or without the `/`.
+fn parse_command(text: &str, bot_username: Option<&str>, is_direct: bool) -> Option<String> {
+    let text = text.trim_start();
+    let fallback = if is_direct { Some(text.to_string()) } else { None };
+    let rest = match text.strip_prefix('/') {
+        Some(rest) => rest,
+        None => return fallback,
+    };
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (word, args) = rest.split_at(end);
+    let name = match word.find('@') {
+        Some(at) => {
+            let target = &word[at + 1..];
+            let is_for_bot = bot_username.map_or(false, |x| x.eq_ignore_ascii_case(target));
+            if !is_for_bot {
+                return None
+            }
+            &word[..at]
+        }
+        None => word,
+    };
+    if name.is_empty() {
+        return fallback
+    }
+    Some(format!("{}{}", name, args))
+}
+
+async fn handle_message_async<E: Events>(
+    target: Handler<E>, conn: TelegramConnection, message: Message, user: User, text: String,
+) -> Result<()> {
+    let chat = message.chat.id.to_string();
+    let is_direct = message.chat.is_private();
+    let bot_username = conn.bot_user().and_then(|x| x.username.clone());
+    let command = parse_command(&text, bot_username.as_deref(), is_direct);
+
+    let author = MessageAuthor::new(format!("telegram:{}", user.id), user.display_name());
+    let mut incoming = IncomingMessage::new(Arc::new(conn.clone()), chat, author, text)
+        .with_id(message.message_id.to_string())
+        .with_direct(is_direct);
+    if let Some(command) = command {
+        incoming = incoming.with_command(command);
+    }
+    let incoming = incoming.with_platform_data(TelegramContext { connection: conn, message });
+    receive_message(&target, incoming).await
+}
+
+/// Handles a message received from Telegram, running it as a command if it is one.
+///
+/// Messages from bots, including the bot itself, and messages posted as a channel are ignored.
+pub(crate) fn handle_message(
+    target: &Handler<impl Events>, conn: &TelegramConnection, message: Message,
+) {
+    let user = match &message.from {
+        Some(user) if !user.is_bot => user.clone(),
+        _ => return,
+    };
+    let text = match &message.text {
+        Some(text) => text.clone(),
+        None => return,
+    };
+    let task = handle_message_async(target.clone(), conn.clone(), message, user, text);
+    target.get_service::<TaskManager>().spawn(conn.module(), "telegram_message", task);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bot_commands() {
+        let bot = Some("SylphieBot");
+        let parse = |text, is_direct| parse_command(text, bot, is_direct);
+        assert_eq!(parse("/help", false).as_deref(), Some("help"));
+        assert_eq!(parse("/help@SylphieBot roll 1d6", false).as_deref(), Some("help roll 1d6"));
+        assert_eq!(parse("/help@sylphiebot", false).as_deref(), Some("help"));
+        assert_eq!(parse("/help@OtherBot", false), None);
+        assert_eq!(parse("hello", false), None);
+        assert_eq!(parse("hello", true).as_deref(), Some("hello"));
+        assert_eq!(parse("/help@OtherBot", true), None);
+        assert_eq!(parse("/", false), None);
+        assert_eq!(parse_command("/help@SylphieBot", None, false), None);
+    }
+}
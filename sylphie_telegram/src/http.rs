@@ -0,0 +1,150 @@
+use crate::model::*;
+use reqwest::{Client, StatusCode};
+use serde::*;
+use serde_json::{json, Value};
+use std::time::Duration;
+use sylphie::prelude::*;
+
+const API_BASE: &str = "https://api.telegram.org";
+
+/// How many times a request is retried after being rate limited.
+const MAX_RETRIES: u32 = 5;
+
+/// How long a `getUpdates` request waits for new updates, in seconds.
+pub const POLL_TIMEOUT: u64 = 30;
+
+/// How long a request can take, in addition to the time a `getUpdates` request waits.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The longest message sent, in characters.
+///
+/// Telegram allows 4096 UTF-16 code units, so this leaves room for characters outside the basic
+/// multilingual plane, which count twice.
+pub const MESSAGE_LIMIT: usize = 2048;
+
+/// The updates the bot receives.
+const ALLOWED_UPDATES: &[&str] = &["message"];
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    #[serde(default)]
+    result: Option<T>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Deserialize)]
+struct ResponseParameters {
+    #[serde(default)]
+    retry_after: Option<u64>,
+}
+
+/// A client for the Telegram Bot API.
+pub(crate) struct TelegramHttp {
+    client: Client,
+    base: String,
+}
+impl TelegramHttp {
+    pub fn new(token: &str) -> Result<TelegramHttp> {
+        let timeout = Duration::from_secs(POLL_TIMEOUT) + REQUEST_TIMEOUT;
+        let client = Client::builder().timeout(timeout).build()?;
+        Ok(TelegramHttp { client, base: format!("{}/bot{}", API_BASE, token) })
+    }
+
+    async fn request<T: de::DeserializeOwned>(&self, method: &str, body: &Value) -> Result<T> {
+        let url = format!("{}/{}", self.base, method);
+        for _ in 0..MAX_RETRIES {
+            // errors from reqwest include the URL, which contains the token.
+            let response = self.client.post(&url).json(body).send().await
+                .map_err(|e| e.without_url())?;
+            let status = response.status();
+            let response: ApiResponse<T> = response.json().await
+                .map_err(|e| e.without_url())
+                .internal_err(|| format!("Telegram sent an invalid response to {}.", method))?;
+            if response.ok {
+                match response.result {
+                    Some(result) => return Ok(result),
+                    None => bail!("Telegram sent no result for {}.", method),
+                }
+            }
+            let description = response.description.unwrap_or_else(|| status.to_string());
+            match status {
+                StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = response.parameters.and_then(|x| x.retry_after).unwrap_or(1);
+                    debug!("Rate limited by Telegram for {} seconds.", delay);
+                    tokio::time::delay_for(Duration::from_secs(delay)).await;
+                }
+                StatusCode::UNAUTHORIZED | StatusCode::NOT_FOUND =>
+                    cmd_error!("The Telegram token is invalid: {}", description),
+                StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN =>
+                    cmd_error!("Telegram refused the request: {}", description),
+                _ => bail!("Telegram request {} failed: {}", method, description),
+            }
+        }
+        bail!("Telegram request {} was rate limited too many times.", method)
+    }
+
+    /// Returns the bot's own user.
+    pub async fn get_me(&self) -> Result<User> {
+        self.request("getMe", &json!({})).await
+    }
+
+    /// Waits for updates after `offset`, and returns them.
+    pub async fn get_updates(&self, offset: Option<i64>) -> Result<Vec<Update>> {
+        let body = json!({
+            "offset": offset,
+            "timeout": POLL_TIMEOUT,
+            "allowed_updates": ALLOWED_UPDATES,
+        });
+        self.request("getUpdates", &body).await
+    }
+
+    /// Sets the URL updates are sent to, discarding any updates that are waiting.
+    pub async fn set_webhook(&self, url: &str, secret: &str) -> Result<()> {
+        let body = json!({
+            "url": url,
+            "secret_token": secret,
+            "allowed_updates": ALLOWED_UPDATES,
+            "drop_pending_updates": true,
+        });
+        self.request::<bool>("setWebhook", &body).await?;
+        Ok(())
+    }
+
+    /// Removes the webhook, so updates can be received with `getUpdates`, discarding any updates
+    /// that are waiting.
+    pub async fn delete_webhook(&self) -> Result<()> {
+        self.request::<bool>("deleteWebhook", &json!({ "drop_pending_updates": true })).await?;
+        Ok(())
+    }
+
+    /// Sends a message to a chat, optionally as a reply to another message.
+    pub async fn send_message(
+        &self, chat: i64, reply_to: Option<i64>, text: &str,
+    ) -> Result<Message> {
+        let mut body = json!({ "chat_id": chat, "text": text });
+        if let Some(message) = reply_to {
+            body["reply_parameters"] = json!({
+                "message_id": message,
+                "allow_sending_without_reply": true,
+            });
+        }
+        self.request("sendMessage", &body).await
+    }
+
+    /// Replaces the text of a message the bot sent.
+    pub async fn edit_message(&self, chat: i64, message: i64, text: &str) -> Result<()> {
+        let body = json!({ "chat_id": chat, "message_id": message, "text": text });
+        match self.request::<Value>("editMessageText", &body).await {
+            Ok(_) => Ok(()),
+            // Telegram refuses edits that do not change the text.
+            Err(e) => match e.error_kind() {
+                ErrorKind::CommandError(msg) if msg.contains("message is not modified") => Ok(()),
+                _ => Err(e),
+            },
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Connects Sylphie bots to Telegram.
+//!
+//! Adding [`ModTelegram`] to a bot adds the `telegram` connection type. The bot's token, from
+//! [@BotFather](https://t.me/BotFather), is read from the configuration file, and the connection
+//! is added from the terminal:
+//!
+//! ```toml
+//! owners = ["telegram:123456789"]
+//!
+//! [telegram]
+//! token = "..."
+//! public = false  # whether users other than the owners can run commands
+//! ```
+//!
+//! ```text
+//! connections add telegram telegram
+//! ```
+//!
+//! By default, the bot polls Telegram for new messages. Setting `webhook_url` makes Telegram
+//! send them to a web server run by the bot instead, which must be reachable at that URL over
+//! HTTPS, usually through a reverse proxy:
+//!
+//! ```toml
+//! [telegram]
+//! webhook_url = "https://bot.example.com/telegram"
+//! webhook_bind = "127.0.0.1:8443"  # the address the web server listens on
+//! webhook_secret = "..."           # checked on every request, using only `A-Z a-z 0-9 _ -`
+//! ```
+//!
+//! Messages that start with `/` are run as commands, as are all messages in private chats. In
+//! groups, commands that name a bot, such as `/help@SylphieBot`, are only run by that bot.
+//! Telegram only shows groups' messages to bots that are administrators or have privacy mode
+//! disabled, besides commands and replies to the bot. Commands run in the scope of the chat they
+//! were sent in, followed by the scope of the connection. Responses reply to the command, and
+//! progress reports edit a single message. Commands can find the message they were sent from
+//! with [`TelegramContext::from_ctx`].
+//!
+//! Owners are named as `telegram:<user ID>`, using the numeric ID of the user rather than their
+//! username, which can be changed. By default, only the owners can run commands. Setting
+//! `public` allows anyone to run them.
+
+#[macro_use] extern crate tracing;
+
+use sylphie::connections::InitConnectionTypesEvent;
+use sylphie::prelude::*;
+
+mod connection;
+mod context;
+mod http;
+pub mod model;
+mod updates;
+
+pub use connection::TelegramConnection;
+pub use context::TelegramContext;
+
+/// A module that can be added to a Sylphie bot to add Telegram support.
+#[derive(Module)]
+pub struct ModTelegram {
+    #[module_info] info: ModuleInfo,
+}
+
+#[module_impl]
+impl ModTelegram {
+    #[event_handler]
+    fn init_connection_types(
+        &self, target: &Handler<impl Events>, ev: &mut InitConnectionTypesEvent,
+    ) -> Result<()> {
+        ev.add_type(target, "telegram", connection::TelegramFactory { module: self.info.clone() })
+    }
+}
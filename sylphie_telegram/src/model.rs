@@ -0,0 +1,84 @@
+//! The types of the Telegram Bot API the bot uses.
+
+use serde::*;
+
+/// A Telegram user or bot.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct User {
+    /// The ID of the user.
+    pub id: i64,
+    /// Whether the user is a bot.
+    #[serde(default)]
+    pub is_bot: bool,
+    /// The first name of the user.
+    pub first_name: String,
+    /// The last name of the user, if they have one.
+    #[serde(default)]
+    pub last_name: Option<String>,
+    /// The username of the user, if they have one.
+    #[serde(default)]
+    pub username: Option<String>,
+}
+impl User {
+    /// Returns the name of the user, as shown in logs.
+    pub fn display_name(&self) -> String {
+        match &self.username {
+            Some(username) => format!("@{}", username),
+            None => match &self.last_name {
+                Some(last_name) => format!("{} {}", self.first_name, last_name),
+                None => self.first_name.clone(),
+            },
+        }
+    }
+}
+
+/// A chat, which is a private chat, a group or a channel.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct Chat {
+    /// The ID of the chat. Private chats have the ID of the user, and groups and channels have
+    /// negative IDs.
+    pub id: i64,
+    /// The type of the chat, which is `private`, `group`, `supergroup` or `channel`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The title of a group or channel.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// The username of the chat, if it has one.
+    #[serde(default)]
+    pub username: Option<String>,
+}
+impl Chat {
+    /// Returns whether this is a private chat with a single user.
+    pub fn is_private(&self) -> bool {
+        self.kind == "private"
+    }
+}
+
+/// A message.
+#[derive(Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct Message {
+    /// The ID of the message, which is unique within its chat.
+    pub message_id: i64,
+    /// The user who sent the message, or `None` for messages in channels.
+    #[serde(default)]
+    pub from: Option<User>,
+    /// The chat the message was sent in.
+    pub chat: Chat,
+    /// The time the message was sent, as a Unix timestamp.
+    pub date: i64,
+    /// The text of the message, if it is a text message.
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+/// An update received from the Bot API.
+#[derive(Deserialize, Debug)]
+pub(crate) struct Update {
+    pub update_id: i64,
+    #[serde(default)]
+    pub message: Option<Message>,
+}
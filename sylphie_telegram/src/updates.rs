@@ -0,0 +1,153 @@
+//! Receives updates from Telegram, either by long polling or through a webhook.
+
+use crate::connection::{TelegramConnection, UpdateMode};
+use crate::model::*;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use sylphie::connections::{Backoff, ConnectionStatus, is_fatal_error, log_disconnect};
+use sylphie::prelude::*;
+use sylphie::tokens::tokens_match;
+
+/// The delay before retrying after the first failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest delay before retrying.
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+/// The header Telegram sends the webhook secret in.
+const SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// Finds the bot's own user, and removes the webhook if updates are polled for.
+async fn login(conn: &TelegramConnection) -> Result<()> {
+    let user = conn.http().get_me().await?;
+    info!("Logged in to Telegram as {}.", user.display_name());
+    conn.set_bot_user(user);
+    if *conn.mode() == UpdateMode::Polling {
+        conn.http().delete_webhook().await?;
+    }
+    Ok(())
+}
+
+fn handle_update(target: &Handler<impl Events>, conn: &TelegramConnection, update: Update) {
+    if let Some(message) = update.message {
+        crate::context::handle_message(target, conn, message);
+    }
+}
+
+/// Waits for a batch of updates and handles them.
+async fn poll(
+    target: &Handler<impl Events>, conn: &TelegramConnection, offset: &mut Option<i64>,
+) -> Result<()> {
+    let updates = conn.http().get_updates(*offset).await?;
+    conn.set_status(ConnectionStatus::Connected);
+    for update in updates {
+        *offset = Some(update.update_id + 1);
+        handle_update(target, conn, update);
+    }
+    Ok(())
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Handles a request sent to the webhook.
+///
+/// Requests without the secret are refused, as anyone who can reach the webhook could otherwise
+/// send the bot commands.
+async fn handle_request<E: Events>(
+    target: &Handler<E>, conn: &TelegramConnection, secret: &str, req: Request<Body>,
+) -> Result<Response<Body>> {
+    if req.method() != Method::POST {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED))
+    }
+    let is_authorized = req.headers().get(SECRET_HEADER)
+        .and_then(|x| x.to_str().ok())
+        .map_or(false, |x| tokens_match(x, secret));
+    if !is_authorized {
+        return Ok(empty_response(StatusCode::UNAUTHORIZED))
+    }
+    let body = hyper::body::to_bytes(req.into_body()).await
+        .internal_err(|| "Could not read the Telegram webhook request body.")?;
+    match serde_json::from_slice::<Update>(&body) {
+        Ok(update) => handle_update(target, conn, update),
+        Err(e) => {
+            warn!("Telegram sent an invalid update: {}", e);
+            return Ok(empty_response(StatusCode::BAD_REQUEST))
+        }
+    }
+    Ok(empty_response(StatusCode::OK))
+}
+
+/// Runs the webhook server and registers it with Telegram, until the server fails.
+async fn serve<E: Events>(
+    target: &Handler<E>, conn: &TelegramConnection, url: &str, addr: SocketAddr, secret: &str,
+) -> Result<()> {
+    let (service_target, service_conn) = (target.clone(), conn.clone());
+    let service_secret: Arc<str> = secret.into();
+    let make_service = make_service_fn(move |_| {
+        let (target, conn, secret) =
+            (service_target.clone(), service_conn.clone(), service_secret.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let (target, conn, secret) = (target.clone(), conn.clone(), secret.clone());
+                async move {
+                    let response = match handle_request(&target, &conn, &secret, req).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            warn!("Could not handle a Telegram webhook request: {}", e);
+                            empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+                        }
+                    };
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)
+        .internal_err(|| format!("Could not bind the Telegram webhook to {}.", addr))?
+        .serve(make_service);
+    conn.http().set_webhook(url, secret).await?;
+    info!("Telegram webhook listening on {}.", addr);
+    conn.set_status(ConnectionStatus::Connected);
+    server.await.internal_err(|| "Telegram webhook server failed.")?;
+    bail!("Telegram webhook server stopped.")
+}
+
+/// Receives updates from Telegram until the connection is closed.
+pub(crate) async fn run_updates(
+    target: Handler<impl Events>, conn: TelegramConnection,
+) -> Result<()> {
+    let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+    let mut offset = None;
+    let mut is_logged_in = false;
+    loop {
+        let result = if !is_logged_in {
+            login(&conn).await.map(|_| is_logged_in = true)
+        } else {
+            match conn.mode() {
+                UpdateMode::Polling => poll(&target, &conn, &mut offset).await,
+                UpdateMode::Webhook { url, bind, secret } =>
+                    serve(&target, &conn, url, *bind, secret).await,
+            }
+        };
+
+        match result {
+            Ok(()) => backoff.reset(),
+            Err(e) if is_fatal_error(&e) => {
+                conn.set_status(ConnectionStatus::Disconnected);
+                return Err(e)
+            }
+            Err(e) => {
+                conn.set_status(ConnectionStatus::Disconnected);
+                log_disconnect("Telegram", &e);
+                backoff.wait("Telegram").await;
+            }
+        }
+    }
+}